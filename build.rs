@@ -0,0 +1,71 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Dependencies whose resolved versions change what the bench measures
+const TRACKED_DEPS: &[(&str, &str)] = &[
+    ("sparse-merkle-tree", "SMT_BENCH_DEP_SMT"),
+    ("gw-store", "SMT_BENCH_DEP_GW_STORE"),
+    ("gw-db", "SMT_BENCH_DEP_GW_DB"),
+];
+
+// Every resolved version of the package in Cargo.lock, with the git
+// revision or registry it came from, e.g. "0.5.2@git:e9d4be78a772"
+fn resolved(lock: &str, name: &str) -> String {
+    let mut versions = vec![];
+    for package in lock.split("[[package]]") {
+        let field = |field: &str| {
+            package.lines().find_map(|line| {
+                let value = line.strip_prefix(field)?.trim().strip_prefix('=')?;
+                Some(value.trim().trim_matches('"').to_string())
+            })
+        };
+        if field("name ").as_deref() != Some(name) {
+            continue;
+        }
+        let version = field("version ").unwrap_or_default();
+        let source = match field("source ") {
+            Some(source) if source.starts_with("git+") => match source.rsplit_once('#') {
+                Some((_, rev)) => format!("git:{}", &rev[..rev.len().min(12)]),
+                None => "git".to_string(),
+            },
+            Some(source) if source.starts_with("registry+") => "crates.io".to_string(),
+            Some(source) => source,
+            None => "path".to_string(),
+        };
+        versions.push(format!("{}@{}", version, source));
+    }
+    if versions.is_empty() {
+        "unknown".to_string()
+    } else {
+        versions.join("+")
+    }
+}
+
+// Records the profile and opt-level of the build, the enabled features and
+// the resolved versions of the tree and store crates, so that reports can
+// say what produced their numbers
+fn main() {
+    let profile = env::var("PROFILE").unwrap_or_else(|_| "unknown".to_string());
+    let opt_level = env::var("OPT_LEVEL").unwrap_or_else(|_| "unknown".to_string());
+    println!("cargo:rustc-env=SMT_BENCH_PROFILE={}", profile);
+    println!("cargo:rustc-env=SMT_BENCH_OPT_LEVEL={}", opt_level);
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(name, _)| {
+            let feature = name.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=SMT_BENCH_FEATURES={}", features.join(","));
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let lock_path = Path::new(&manifest_dir).join("Cargo.lock");
+    let lock = fs::read_to_string(&lock_path).unwrap_or_default();
+    for (name, var) in TRACKED_DEPS {
+        println!("cargo:rustc-env={}={}", var, resolved(&lock, name));
+    }
+    println!("cargo:rerun-if-changed={}", lock_path.display());
+    println!("cargo:rerun-if-changed=build.rs");
+}
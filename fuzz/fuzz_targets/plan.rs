@@ -0,0 +1,89 @@
+#![no_main]
+
+// The bench is a binary crate, so the stores are pulled in by path
+#[path = "../../src/history.rs"]
+#[allow(dead_code)]
+mod history;
+#[path = "../../src/old.rs"]
+#[allow(dead_code)]
+mod old;
+#[path = "../../src/trie.rs"]
+#[allow(dead_code)]
+mod trie;
+#[path = "../../src/utils.rs"]
+#[allow(dead_code)]
+mod utils;
+
+use gw_store::Store as GwStore;
+use libfuzzer_sys::fuzz_target;
+use old::CountingStore;
+use rand_chacha::{
+    rand_core::{RngCore, SeedableRng},
+    ChaCha20Rng,
+};
+use sparse_merkle_tree::{blake2b::Blake2bHasher, SparseMerkleTree, H256};
+use trie::TrieStore;
+
+// Keys are drawn from a small pool so that plans hit existing keys, which
+// covers updates, deletes and delete-then-reinsert of the same key
+const KEY_POOL: usize = 64;
+const MAX_BATCH: usize = 32;
+
+fn random_h256(rng: &mut ChaCha20Rng) -> H256 {
+    let mut buf = [0u8; 32];
+    rng.fill_bytes(&mut buf);
+    buf.into()
+}
+
+// Every step of the plan is 3 bytes: operation, batch size and seed
+fuzz_target!(|data: &[u8]| {
+    let mut rng = ChaCha20Rng::from_seed([0u8; 32]);
+    let pool: Vec<H256> = (0..KEY_POOL).map(|_| random_h256(&mut rng)).collect();
+
+    let old_store = GwStore::open_tmp().unwrap();
+    let trie_store = GwStore::open_tmp().unwrap();
+    let mut old_root = H256::zero();
+    let mut trie_root = H256::zero();
+    for step in data.chunks_exact(3) {
+        let batch_size = step[1] as usize % MAX_BATCH + 1;
+        let mut rng = ChaCha20Rng::from_seed([step[2]; 32]);
+        let keys: Vec<H256> = (0..batch_size)
+            .map(|_| pool[rng.next_u32() as usize % KEY_POOL])
+            .collect();
+
+        let old_tx = old_store.begin_transaction();
+        let trie_tx = trie_store.begin_transaction();
+        let mut old_smt: SparseMerkleTree<Blake2bHasher, H256, _> =
+            SparseMerkleTree::new(old_root, CountingStore::new(&old_tx));
+        let mut trie_smt: SparseMerkleTree<Blake2bHasher, H256, _> =
+            SparseMerkleTree::new(trie_root, TrieStore::new(&trie_tx));
+        match step[0] % 3 {
+            0 | 1 => {
+                // update_all expects distinct keys
+                let mut pairs: Vec<(H256, H256)> = vec![];
+                for key in keys {
+                    if pairs.iter().all(|(k, _)| *k != key) {
+                        let value = if step[0] % 3 == 0 {
+                            random_h256(&mut rng)
+                        } else {
+                            H256::zero()
+                        };
+                        pairs.push((key, value));
+                    }
+                }
+                old_smt.update_all(pairs.clone()).unwrap();
+                trie_smt.update_all(pairs).unwrap();
+            }
+            _ => {
+                for key in &keys {
+                    assert_eq!(old_smt.get(key).unwrap(), trie_smt.get(key).unwrap());
+                }
+            }
+        }
+        assert_eq!(old_smt.root(), trie_smt.root());
+        old_root = *old_smt.root();
+        trie_root = *trie_smt.root();
+        old_tx.commit().unwrap();
+        trie_tx.commit().unwrap();
+    }
+});
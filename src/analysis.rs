@@ -0,0 +1,789 @@
+use crate::{
+    build_info,
+    config::Config,
+    phase::PhaseKind,
+    rocksdb_options,
+    runner::{PhaseReport, RoundReport},
+};
+use std::time::Duration;
+
+// Rounds required on each side of a level shift
+const MIN_SEGMENT: usize = 5;
+// Ratio between the means of two segments reported as a level shift
+const MIN_SHIFT: f64 = 1.5;
+// Standard errors the means of two segments must differ by, so that noise
+// in short series is not reported
+const MIN_SIGNIFICANCE: f64 = 4.0;
+// Rounds slower than this many times the phase median are spikes
+const SPIKE_FACTOR: f64 = 3.0;
+// Correlation between the tombstones and the read latency of read rounds,
+// or between a RocksDB stat and the latency spikes, reported as a finding
+const MIN_CORRELATION: f64 = 0.5;
+// Relative distance from its value at the end of the series the rolling
+// mean of the steady state stays within, over windows of MIN_SEGMENT rounds
+const STEADY_TOLERANCE: f64 = 0.1;
+
+// Indices where a new level of the series starts, found by binary
+// segmentation: the series is split where the squared deviations from the
+// means of both sides are lowest, the split is kept when the means differ
+// enough, and both sides are searched again.
+pub fn level_shifts(series: &[f64]) -> Vec<usize> {
+    let mut shifts = vec![];
+    segment(series, 0, &mut shifts);
+    shifts.sort_unstable();
+    shifts
+}
+
+fn segment(series: &[f64], offset: usize, shifts: &mut Vec<usize>) {
+    let n = series.len();
+    if n < 2 * MIN_SEGMENT {
+        return;
+    }
+    let mut sums = vec![0.0; n + 1];
+    for (i, value) in series.iter().enumerate() {
+        sums[i + 1] = sums[i] + value;
+    }
+    // Squared deviations of both sides, minus the sum of squares common to
+    // every split
+    let cost = |split: usize| {
+        let left = sums[split];
+        let right = sums[n] - left;
+        -(left * left / split as f64) - right * right / (n - split) as f64
+    };
+    let split = (MIN_SEGMENT..=n - MIN_SEGMENT)
+        .min_by(|a, b| cost(*a).total_cmp(&cost(*b)))
+        .unwrap();
+    let (left, right) = series.split_at(split);
+    if !is_shift(left, right) {
+        return;
+    }
+    shifts.push(offset + split);
+    segment(left, offset, shifts);
+    segment(right, offset + split, shifts);
+}
+
+fn is_shift(left: &[f64], right: &[f64]) -> bool {
+    let (left_mean, right_mean) = (mean(left), mean(right));
+    let (low, high) = if left_mean < right_mean {
+        (left_mean, right_mean)
+    } else {
+        (right_mean, left_mean)
+    };
+    if high <= 0.0 || high < low * MIN_SHIFT {
+        return false;
+    }
+    let error = (variance(left) / left.len() as f64 + variance(right) / right.len() as f64).sqrt();
+    high - low > error * MIN_SIGNIFICANCE
+}
+
+// Index the steady state at the end of the series starts at: the longest
+// stretch running to the end whose rolling mean stays within
+// STEADY_TOLERANCE of the last one. A series too short for two windows is
+// steady throughout.
+pub fn steady_start(series: &[f64]) -> usize {
+    let n = series.len();
+    if n < 2 * MIN_SEGMENT {
+        return 0;
+    }
+    let rolling = |start: usize| mean(&series[start..start + MIN_SEGMENT]);
+    let end = rolling(n - MIN_SEGMENT);
+    let mut start = n - MIN_SEGMENT;
+    while start > 0 && (rolling(start - 1) - end).abs() <= end.abs() * STEADY_TOLERANCE {
+        start -= 1;
+    }
+    start
+}
+
+// Indices of the values above factor times the median
+pub fn spikes(series: &[f64], factor: f64) -> Vec<usize> {
+    let median = median(series);
+    if median <= 0.0 {
+        return vec![];
+    }
+    (0..series.len())
+        .filter(|i| series[*i] > median * factor)
+        .collect()
+}
+
+fn mean(series: &[f64]) -> f64 {
+    series.iter().sum::<f64>() / series.len().max(1) as f64
+}
+
+fn variance(series: &[f64]) -> f64 {
+    let mean = mean(series);
+    series.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / series.len().max(1) as f64
+}
+
+// Pearson correlation of two series of the same length, 0 when either is
+// constant
+pub fn correlation(xs: &[f64], ys: &[f64]) -> f64 {
+    let (x_mean, y_mean) = (mean(xs), mean(ys));
+    let covariance = xs
+        .iter()
+        .zip(ys)
+        .map(|(x, y)| (x - x_mean) * (y - y_mean))
+        .sum::<f64>()
+        / xs.len().max(1) as f64;
+    let spread = (variance(xs) * variance(ys)).sqrt();
+    if spread > 0.0 {
+        covariance / spread
+    } else {
+        0.0
+    }
+}
+
+fn median(series: &[f64]) -> f64 {
+    if series.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = series.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    sorted[sorted.len() / 2]
+}
+
+// Throughput and latency of a stretch of rounds
+pub struct Throughput {
+    pub ops_per_sec: f64,
+    pub mean_round: Duration,
+}
+
+impl Throughput {
+    fn new(rounds: &[&RoundReport]) -> Self {
+        let ops: usize = rounds.iter().map(|r| r.ops).sum();
+        let elapsed: Duration = rounds.iter().map(|r| r.elapsed).sum();
+        Self {
+            ops_per_sec: ops as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            mean_round: elapsed / rounds.len().max(1) as u32,
+        }
+    }
+}
+
+pub struct SteadyState {
+    pub phase: String,
+    // First and last round of the steady state, 1-based like the progress
+    // lines
+    pub first: usize,
+    pub last: usize,
+    // Whether --steady-after placed the window rather than the detection
+    pub overridden: bool,
+    pub overall: Throughput,
+    pub steady: Throughput,
+}
+
+// The steady state of every phase of more than one round, detected on the
+// time per op, or starting after round steady_after of each phase. Rolled
+// back rounds count in neither throughput.
+pub fn steady_states(reports: &[PhaseReport], steady_after: Option<usize>) -> Vec<SteadyState> {
+    let mut states = vec![];
+    for report in reports {
+        let (numbers, rounds): (Vec<usize>, Vec<&RoundReport>) = report
+            .rounds
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| !r.rolled_back)
+            .map(|(i, r)| (i + 1, r))
+            .unzip();
+        if rounds.len() < 2 {
+            continue;
+        }
+        let start = match steady_after {
+            Some(after) => numbers
+                .iter()
+                .position(|number| *number > after)
+                .unwrap_or(rounds.len() - 1),
+            None => {
+                let series: Vec<f64> = rounds
+                    .iter()
+                    .map(|r| r.elapsed.as_secs_f64() / r.ops.max(1) as f64)
+                    .collect();
+                steady_start(&series)
+            }
+        };
+        states.push(SteadyState {
+            phase: report.phase.to_string(),
+            first: numbers[start],
+            last: numbers[rounds.len() - 1],
+            overridden: steady_after.is_some(),
+            overall: Throughput::new(&rounds),
+            steady: Throughput::new(&rounds[start..]),
+        });
+    }
+    states
+}
+
+// What the load rounds of a run cost, initializing the tree the other
+// phases measure
+pub struct InitCost {
+    pub rounds: usize,
+    pub keys: usize,
+    pub elapsed: Duration,
+    pub reads: usize,
+    pub writes: usize,
+    pub written_bytes: usize,
+}
+
+// None when the run loaded nothing, e.g. with --read-only
+pub fn init_cost(reports: &[PhaseReport]) -> Option<InitCost> {
+    let rounds: Vec<&RoundReport> = reports
+        .iter()
+        .flat_map(|report| &report.rounds)
+        .filter(|round| round.init)
+        .collect();
+    if rounds.is_empty() {
+        return None;
+    }
+    Some(InitCost {
+        rounds: rounds.len(),
+        keys: rounds.iter().map(|r| r.ops).sum(),
+        elapsed: rounds.iter().map(|r| r.elapsed).sum(),
+        reads: rounds.iter().map(|r| r.reads).sum(),
+        writes: rounds.iter().map(|r| r.writes).sum(),
+        written_bytes: rounds.iter().map(|r| r.written_bytes).sum(),
+    })
+}
+
+pub struct Finding {
+    pub phase: String,
+    // Round the finding starts at, 1-based like the progress lines
+    pub round: usize,
+    pub message: String,
+}
+
+// Series of a phase searched for level shifts, as (name, value of a round)
+type Series = (&'static str, fn(&RoundReport) -> f64);
+
+const SERIES: &[Series] = &[
+    ("round latency", |r| r.elapsed.as_secs_f64()),
+    ("reads per key", |r| r.reads as f64 / r.ops.max(1) as f64),
+    ("written bytes per key", |r| {
+        r.written_bytes as f64 / r.ops.max(1) as f64
+    }),
+];
+
+// Looks for level shifts and latency spikes within each phase. Shifts
+// between phases are expected, the workload changes there.
+pub fn findings(reports: &[PhaseReport]) -> Vec<Finding> {
+    let mut findings = vec![];
+    for report in reports {
+        // Rolled back rounds did no work, numbers keep their place in the
+        // phase
+        let (numbers, rounds): (Vec<usize>, Vec<&RoundReport>) = report
+            .rounds
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| !r.rolled_back)
+            .map(|(i, r)| (i + 1, r))
+            .unzip();
+        let phase = report.phase.to_string();
+        for (name, value) in SERIES {
+            let series: Vec<f64> = rounds.iter().map(|r| value(r)).collect();
+            if series.iter().all(|v| *v == 0.0) {
+                continue;
+            }
+            let shifts = level_shifts(&series);
+            let mut start = 0;
+            for (i, shift) in shifts.iter().enumerate() {
+                let end = shifts.get(i + 1).copied().unwrap_or(series.len());
+                let before = mean(&series[start..*shift]);
+                let after = mean(&series[*shift..end]);
+                let change = if after > before {
+                    format!("grew {:.2}x", after / before.max(f64::EPSILON))
+                } else {
+                    format!("dropped {:.2}x", before / after.max(f64::EPSILON))
+                };
+                findings.push(Finding {
+                    phase: phase.clone(),
+                    round: numbers[*shift],
+                    message: format!(
+                        "{} {} after round {} ({} leaves)",
+                        name,
+                        change,
+                        numbers[shift - 1],
+                        rounds[shift - 1].leaves_label()
+                    ),
+                });
+                start = *shift;
+            }
+        }
+        let latency: Vec<f64> = rounds.iter().map(|r| r.elapsed.as_secs_f64()).collect();
+        let spikes = if latency.len() >= 2 * MIN_SEGMENT {
+            spikes(&latency, SPIKE_FACTOR)
+        } else {
+            vec![]
+        };
+        if let Some(first) = spikes.first() {
+            findings.push(Finding {
+                phase: phase.clone(),
+                round: numbers[*first],
+                message: format!(
+                    "{} rounds took over {}x the median round, first at round {}",
+                    spikes.len(),
+                    SPIKE_FACTOR,
+                    numbers[*first]
+                ),
+            });
+        }
+    }
+    findings.extend(tombstone_correlation(reports));
+    findings.extend(spike_correlation(reports, "L0 files", |r| r.l0_files));
+    findings.extend(spike_correlation(
+        reports,
+        "pending compaction bytes",
+        |r| r.pending_compaction_bytes,
+    ));
+    findings
+}
+
+// What compacting after the delete phase deletes did to the reads of the
+// same keys, read before the compaction and compacted after it
+pub fn compaction_finding(
+    deletes: &PhaseReport,
+    read: &PhaseReport,
+    compacted: &PhaseReport,
+) -> Finding {
+    let per_key = |report: &PhaseReport| {
+        let ops: usize = report.rounds.iter().map(|r| r.ops).sum();
+        report.elapsed().as_secs_f64() / ops.max(1) as f64
+    };
+    let tombstones = |report: &PhaseReport| {
+        report
+            .rounds
+            .last()
+            .and_then(|r| r.tombstones)
+            .map_or("n/a".to_string(), |n| n.to_string())
+    };
+    let (before, after) = (per_key(read), per_key(compacted));
+    // Reads that got no faster owe nothing to the tombstones
+    let attributed = if before > after {
+        format!(
+            "{:?} ({:.1}%)",
+            Duration::from_secs_f64(before - after),
+            (before - after) * 100.0 / before
+        )
+    } else {
+        "none of it".to_string()
+    };
+    Finding {
+        phase: deletes.phase.to_string(),
+        round: deletes.rounds.len(),
+        message: format!(
+            "compacting after the phase took tombstones from {} to {} and read time per key from {:?} to {:?}, {} attributable to tombstones",
+            tombstones(read),
+            tombstones(compacted),
+            Duration::from_secs_f64(before),
+            Duration::from_secs_f64(after),
+            attributed
+        ),
+    }
+}
+
+// How the time per key of every read round of the run follows the
+// tombstones counted before it, reported at the round with the most
+// tombstones
+fn tombstone_correlation(reports: &[PhaseReport]) -> Option<Finding> {
+    let mut points = vec![];
+    let mut before = None;
+    for report in reports {
+        for (i, round) in report.rounds.iter().enumerate() {
+            if report.phase.kind == PhaseKind::Read && !round.rolled_back && round.ops > 0 {
+                if let Some(tombstones) = before {
+                    let latency = round.elapsed.as_secs_f64() / round.ops as f64;
+                    points.push((tombstones, latency, report, i + 1));
+                }
+            }
+            before = round.tombstones;
+        }
+    }
+    if points.len() < MIN_SEGMENT {
+        return None;
+    }
+    let tombstones: Vec<f64> = points.iter().map(|p| p.0 as f64).collect();
+    let latency: Vec<f64> = points.iter().map(|p| p.1).collect();
+    let r = correlation(&tombstones, &latency);
+    if r.abs() < MIN_CORRELATION {
+        return None;
+    }
+    let fewest = points.iter().map(|p| p.0).min().unwrap();
+    let (most, _, report, round) = points.iter().max_by_key(|p| p.0).unwrap();
+    Some(Finding {
+        phase: report.phase.to_string(),
+        round: *round,
+        message: format!(
+            "read time per key correlates with tombstones over {} read rounds (r = {:.2}, {} to {} tombstones)",
+            points.len(),
+            r,
+            fewest,
+            most
+        ),
+    })
+}
+
+// How the latency spikes of every phase long enough to find them in follow
+// a RocksDB stat sampled before the round, e.g. the L0 files stalling reads
+// or writes. Correlates the stat with whether the round spiked, reported at
+// the first spike.
+fn spike_correlation(
+    reports: &[PhaseReport],
+    name: &str,
+    stat: fn(&RoundReport) -> Option<u64>,
+) -> Option<Finding> {
+    let mut points = vec![];
+    let mut before = None;
+    for report in reports {
+        let latency: Vec<f64> = report
+            .rounds
+            .iter()
+            .filter(|r| !r.rolled_back)
+            .map(|r| r.elapsed.as_secs_f64())
+            .collect();
+        let searched = latency.len() >= 2 * MIN_SEGMENT;
+        let spiked = if searched {
+            spikes(&latency, SPIKE_FACTOR)
+        } else {
+            vec![]
+        };
+        let mut live = 0;
+        for (i, round) in report.rounds.iter().enumerate() {
+            if !round.rolled_back {
+                if let Some(value) = before.filter(|_| searched) {
+                    points.push((value, spiked.contains(&live), report, i + 1));
+                }
+                live += 1;
+            }
+            before = stat(round);
+        }
+    }
+    let spiking = points.iter().filter(|p| p.1).count();
+    if points.len() < 2 * MIN_SEGMENT || spiking == 0 {
+        return None;
+    }
+    let values: Vec<f64> = points.iter().map(|p| p.0 as f64).collect();
+    let spiked: Vec<f64> = points.iter().map(|p| if p.1 { 1.0 } else { 0.0 }).collect();
+    let r = correlation(&values, &spiked);
+    // Only a stat that is higher before the spikes explains them
+    if r < MIN_CORRELATION {
+        return None;
+    }
+    let mean_before = |spike: bool| {
+        let values: Vec<f64> = points
+            .iter()
+            .filter(|p| p.1 == spike)
+            .map(|p| p.0 as f64)
+            .collect();
+        mean(&values)
+    };
+    let (_, _, report, round) = points.iter().find(|p| p.1).unwrap();
+    Some(Finding {
+        phase: report.phase.to_string(),
+        round: *round,
+        message: format!(
+            "latency spikes correlate with {} over {} rounds (r = {:.2}, {:.0} before the {} spikes, {:.0} before the other rounds)",
+            name,
+            points.len(),
+            r,
+            mean_before(true),
+            spiking,
+            mean_before(false)
+        ),
+    })
+}
+
+// cache_curve is the --cache-advisor hit rate by cache size in tries, empty
+// without it. The text output reports init with the load phases.
+pub fn print_findings(
+    findings: &[Finding],
+    steady: &[SteadyState],
+    cache_curve: &[(usize, f64)],
+    init: Option<&InitCost>,
+    config: &Config,
+) {
+    if config.json {
+        let findings: Vec<String> = findings
+            .iter()
+            .map(|f| {
+                format!(
+                    "{{\"phase\": \"{}\", \"round\": {}, \"message\": \"{}\"}}",
+                    f.phase, f.round, f.message
+                )
+            })
+            .collect();
+        let deps: Vec<String> = build_info::DEPS
+            .iter()
+            .map(|(name, version)| format!("\"{}\": \"{}\"", name, version))
+            .collect();
+        let steady: Vec<String> = steady
+            .iter()
+            .map(|s| {
+                format!(
+                    "{{\"phase\": \"{}\", \"first_round\": {}, \"last_round\": {}, \"overridden\": {}, \"overall_ops_per_sec\": {:.1}, \"steady_ops_per_sec\": {:.1}, \"overall_mean_round_ns\": {}, \"steady_mean_round_ns\": {}}}",
+                    s.phase,
+                    s.first,
+                    s.last,
+                    s.overridden,
+                    s.overall.ops_per_sec,
+                    s.steady.ops_per_sec,
+                    s.overall.mean_round.as_nanos(),
+                    s.steady.mean_round.as_nanos()
+                )
+            })
+            .collect();
+        let cache_curve: Vec<String> = cache_curve
+            .iter()
+            .map(|(tries, hit_rate)| {
+                format!("{{\"tries\": {}, \"hit_rate\": {:.4}}}", tries, hit_rate)
+            })
+            .collect();
+        let rocksdb_options = match &config.rocksdb_options_file {
+            Some(path) => format!(
+                "{{\"file\": \"{}\", \"hash\": \"{}\"}}",
+                path.display(),
+                rocksdb_options::hash(path).unwrap_or_default()
+            ),
+            None => "null".to_string(),
+        };
+        let init = match init {
+            Some(init) => format!(
+                "{{\"rounds\": {}, \"keys\": {}, \"elapsed_ns\": {}, \"reads\": {}, \"writes\": {}, \"written_bytes\": {}}}",
+                init.rounds,
+                init.keys,
+                init.elapsed.as_nanos(),
+                init.reads,
+                init.writes,
+                init.written_bytes
+            ),
+            None => "null".to_string(),
+        };
+        println!(
+            "{{\"build\": {{\"profile\": \"{}\", \"opt_level\": \"{}\", \"features\": \"{}\", \"deps\": {{{}}}}}, \"init\": {}, \"findings\": [{}], \"steady_state\": [{}], \"cache_curve\": [{}], \"rocksdb_options\": {}}}",
+            build_info::PROFILE,
+            build_info::OPT_LEVEL,
+            build_info::FEATURES,
+            deps.join(", "),
+            init,
+            findings.join(", "),
+            steady.join(", "),
+            cache_curve.join(", "),
+            rocksdb_options
+        );
+        return;
+    }
+    if !steady.is_empty() {
+        println!("Steady state:");
+    }
+    for s in steady {
+        println!(
+            "  Phase {}: rounds {}-{} ({}), {:.0} ops/s, mean round {:?}; overall {:.0} ops/s, mean round {:?}",
+            s.phase,
+            s.first,
+            s.last,
+            if s.overridden { "--steady-after" } else { "detected" },
+            s.steady.ops_per_sec,
+            s.steady.mean_round,
+            s.overall.ops_per_sec,
+            s.overall.mean_round
+        );
+    }
+    if findings.is_empty() {
+        println!("Findings: none");
+        return;
+    }
+    println!("Findings:");
+    for finding in findings {
+        println!("  Phase {}: {}", finding.phase, finding.message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phase::Phase;
+
+    // Deterministic noise of up to +-10% around level
+    fn noisy(level: f64, i: usize) -> f64 {
+        level * (1.0 + ((i * 7919) % 13) as f64 / 60.0 - 0.1)
+    }
+
+    // Level shift detection must find the known changepoints of synthetic
+    // series, give or take a round, and nothing in a flat noisy one. The
+    // steady state of each must start at its last level, give or take the
+    // rounds a rolling window straddles, and end a warm-up that decays.
+    #[test]
+    fn level_shifts_and_steady_states() -> Result<(), String> {
+        let cases: [(Vec<f64>, Vec<usize>); 4] = [
+            ((0..100).map(|i| noisy(1.0, i)).collect(), vec![]),
+            (
+                (0..100)
+                    .map(|i| noisy(if i < 40 { 1.0 } else { 2.0 }, i))
+                    .collect(),
+                vec![40],
+            ),
+            (
+                (0..100)
+                    .map(|i| noisy(if i < 70 { 3.0 } else { 1.0 }, i))
+                    .collect(),
+                vec![70],
+            ),
+            (
+                (0..120)
+                    .map(|i| noisy([1.0, 3.0, 1.2][i / 40], i))
+                    .collect(),
+                vec![40, 80],
+            ),
+        ];
+        for (series, expected) in &cases {
+            let shifts = level_shifts(series);
+            let close = shifts.len() == expected.len()
+                && shifts
+                    .iter()
+                    .zip(expected)
+                    .all(|(s, e)| s.abs_diff(*e) <= 1);
+            if !close {
+                return Err(format!(
+                    "found shifts {:?} instead of {:?}",
+                    shifts, expected
+                ));
+            }
+            let steady = steady_start(series);
+            let last = expected.last().copied().unwrap_or(0);
+            if steady.abs_diff(last) > 2 {
+                return Err(format!(
+                    "steady state starts at {} instead of {}",
+                    steady, last
+                ));
+            }
+        }
+        // Rounds slow down while the tree grows, less and less
+        let warm_up: Vec<f64> = (0..100)
+            .map(|i| noisy(1.0 + 2.0 * (-(i as f64) / 10.0).exp(), i))
+            .collect();
+        let steady = steady_start(&warm_up);
+        if !(20..=45).contains(&steady) {
+            return Err(format!(
+                "steady state of the warm-up starts at {} instead of 20 to 45",
+                steady
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn latency_spikes() -> Result<(), String> {
+        let series: Vec<f64> = (0..100)
+            .map(|i| noisy(if [10, 50, 70].contains(&i) { 5.0 } else { 1.0 }, i))
+            .collect();
+        let found = spikes(&series, 3.0);
+        if found != [10, 50, 70] {
+            return Err(format!("found spikes {:?} instead of [10, 50, 70]", found));
+        }
+        Ok(())
+    }
+
+    // Read rounds slowing down with tombstones must show up in the findings
+    // while a phase whose reads do not must not. A compaction that halves
+    // the time per key must be reported as half of it attributable to
+    // tombstones.
+    #[test]
+    fn tombstones() -> Result<(), String> {
+        let linear: Vec<f64> = (0..10).map(|i| i as f64).collect();
+        if (correlation(&linear, &linear) - 1.0).abs() > 1e-9
+            || correlation(&linear, &[2.0; 10]) != 0.0
+        {
+            return Err("correlation of a line with itself or a constant is off".to_string());
+        }
+
+        let phase = |kind, rounds: Vec<RoundReport>| PhaseReport {
+            phase: Phase::new(kind, rounds.len(), 100),
+            rounds,
+        };
+        let round = |tombstones: u64, micros: u64| RoundReport {
+            ops: 100,
+            elapsed: Duration::from_micros(micros),
+            tombstones: Some(tombstones),
+            ..Default::default()
+        };
+        let slowing = |slows: bool| {
+            vec![
+                phase(PhaseKind::Delete, vec![round(1000, 500)]),
+                phase(
+                    PhaseKind::Read,
+                    (0..10)
+                        .map(|i| {
+                            round(
+                                1000 - 100 * i,
+                                if slows { 300 - 20 * i } else { 200 + i % 2 * 5 },
+                            )
+                        })
+                        .collect(),
+                ),
+            ]
+        };
+        let mentions = |reports: &[PhaseReport]| {
+            findings(reports)
+                .iter()
+                .any(|f| f.message.contains("tombstones"))
+        };
+        if !mentions(&slowing(true)) {
+            return Err("reads slowing with tombstones were not reported".to_string());
+        }
+        if mentions(&slowing(false)) {
+            return Err("reads unaffected by tombstones were reported".to_string());
+        }
+
+        let deletes = phase(PhaseKind::Delete, vec![round(1000, 500)]);
+        let read = phase(PhaseKind::Read, vec![round(1000, 400); 5]);
+        let compacted = phase(PhaseKind::Read, vec![round(0, 200); 5]);
+        let finding = compaction_finding(&deletes, &read, &compacted);
+        if !finding.message.contains("from 1000 to 0") || !finding.message.contains("(50.0%)") {
+            return Err(format!("compaction finding: {}", finding.message));
+        }
+        Ok(())
+    }
+
+    // Spikes following the rounds that left many L0 files behind must be
+    // reported as correlating with them, spikes the L0 files do not explain
+    // and rounds without the stat must not.
+    #[test]
+    fn spike_stats() -> Result<(), String> {
+        let spiked = [5, 12, 17];
+        let rounds = |aligned: bool, sampled: bool| {
+            let rounds = (0..20)
+                .map(|i| {
+                    let spike = spiked.contains(&i);
+                    // The stat after a round is the one before the next
+                    let many = if aligned {
+                        spiked.contains(&(i + 1))
+                    } else {
+                        i % 2 == 0
+                    };
+                    RoundReport {
+                        ops: 100,
+                        elapsed: Duration::from_micros(if spike { 1_000 } else { 100 + i % 3 }),
+                        l0_files: Some(if many { 12 } else { 2 }).filter(|_| sampled),
+                        ..Default::default()
+                    }
+                })
+                .collect();
+            vec![PhaseReport {
+                phase: Phase::new(PhaseKind::Update, 20, 100),
+                rounds,
+            }]
+        };
+        let mentions = |reports: &[PhaseReport]| {
+            findings(reports)
+                .iter()
+                .find(|f| f.message.contains("L0 files"))
+                .map(|f| (f.round, f.message.clone()))
+        };
+        match mentions(&rounds(true, true)) {
+            Some((6, message)) if message.contains("12 before the 3 spikes") => {}
+            found => return Err(format!("spikes after many L0 files: {:?}", found)),
+        }
+        if let Some(found) = mentions(&rounds(false, true)) {
+            return Err(format!("spikes the L0 files do not explain: {:?}", found));
+        }
+        if let Some(found) = mentions(&rounds(true, false)) {
+            return Err(format!("rounds without L0 files: {:?}", found));
+        }
+        Ok(())
+    }
+}
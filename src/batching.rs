@@ -0,0 +1,249 @@
+use crate::{
+    config::{Config, OutputFormat, StoreKind},
+    markdown,
+    old::CountingStore,
+    random_h256,
+    seed::SeedTree,
+    store_config,
+    trie::TrieStore,
+    CountingSmt, TrieSmt,
+};
+use gw_db::RocksDB;
+use gw_store::Store as GwStore;
+use rand_chacha::rand_core::RngCore;
+use sparse_merkle_tree::{blake2b::Blake2bHasher, traits::Store, SparseMerkleTree, H256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Snapshot keys inserted per transaction while building a snapshot
+const SNAPSHOT_CHUNK: usize = 10_000;
+
+pub(crate) fn open(config: &Config, path: &Path) -> GwStore {
+    GwStore::new(RocksDB::open(
+        &store_config(config, path.to_path_buf()),
+        config.columns,
+    ))
+}
+
+// RocksDB keeps a closed database in plain files of one directory
+pub(crate) fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        fs::copy(entry.path(), to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+// What applying one batch one way cost
+pub(crate) struct Measurement {
+    pub(crate) root: H256,
+    pub(crate) elapsed: Duration,
+    pub(crate) reads: usize,
+    pub(crate) writes: usize,
+    pub(crate) written_bytes: usize,
+}
+
+fn apply<S: Store<H256>>(
+    smt: &mut SparseMerkleTree<Blake2bHasher, H256, S>,
+    pairs: Vec<(H256, H256)>,
+    batched: bool,
+) {
+    if batched {
+        smt.update_all(pairs).unwrap();
+    } else {
+        for (key, value) in pairs {
+            smt.update(key, value).unwrap();
+        }
+    }
+}
+
+// Applies pairs to the tree of root in the database at path through the
+// store of kind, in one transaction, and measures it commit included
+pub(crate) fn measure(
+    config: &Config,
+    kind: StoreKind,
+    path: &Path,
+    root: H256,
+    pairs: Vec<(H256, H256)>,
+    batched: bool,
+) -> Measurement {
+    let store = open(config, path);
+    let tx = store.begin_transaction();
+    let start = Instant::now();
+    let (root, reads, writes, written_bytes) = match kind {
+        StoreKind::Trie => {
+            let trie = TrieStore::with_layout(&tx, config.trie_layout)
+                .with_columns(config.branch_col, config.leaf_col)
+                .molecule_wrap(config.trie_molecule_wrap)
+                .truncate_leaf_keys(config.leaf_key_bytes);
+            let mut smt = TrieSmt::new(root, trie);
+            apply(&mut smt, pairs, batched);
+            let trie = smt.store();
+            (
+                *smt.root(),
+                trie.reads(),
+                trie.writes(),
+                trie.written_bytes(),
+            )
+        }
+        StoreKind::Counting => {
+            let counting = CountingStore::new(&tx).with_columns(config.branch_col, config.leaf_col);
+            let mut smt = CountingSmt::new(root, counting);
+            apply(&mut smt, pairs, batched);
+            let counting = smt.store();
+            (
+                *smt.root(),
+                counting.reads(),
+                counting.writes(),
+                counting.written_bytes(),
+            )
+        }
+    };
+    tx.commit().unwrap();
+    Measurement {
+        root,
+        elapsed: start.elapsed(),
+        reads,
+        writes,
+        written_bytes,
+    }
+}
+
+// Builds the tree of the snapshot pairs at path through the store of kind
+// and returns its root
+fn build_snapshot(config: &Config, kind: StoreKind, path: &Path, pairs: &[(H256, H256)]) -> H256 {
+    let mut root = H256::zero();
+    for chunk in pairs.chunks(SNAPSHOT_CHUNK) {
+        root = measure(config, kind, path, root, chunk.to_vec(), true).root;
+    }
+    root
+}
+
+// A batch of distinct keys, half of them keys of the snapshot and the
+// others fresh, a twentieth of them set to zero
+fn batch(seeds: &SeedTree, size: usize, snapshot: &[(H256, H256)]) -> Vec<(H256, H256)> {
+    let mut rng = seeds.child(&format!("batch-{}", size)).rng();
+    let mut pairs = BTreeMap::new();
+    while pairs.len() < size {
+        let key = if rng.next_u64().is_multiple_of(2) && !snapshot.is_empty() {
+            snapshot[rng.next_u64() as usize % snapshot.len()].0
+        } else {
+            random_h256(&mut rng)
+        };
+        let value = if rng.next_u64().is_multiple_of(20) {
+            H256::zero()
+        } else {
+            random_h256(&mut rng)
+        };
+        pairs.insert(key, value);
+    }
+    pairs.into_iter().collect()
+}
+
+// Applies the same batches of every --batch-sizes size to copies of a
+// snapshot tree of --snapshot-keys keys, once with a call to update per key
+// and once with one update_all, through each store. Both must end with the
+// same root. Reports the time, reads, writes and bytes of each.
+pub fn run(config: &Config) {
+    let mut dir = config.db_path.as_os_str().to_owned();
+    dir.push("-update-vs-batch");
+    let dir = PathBuf::from(dir);
+    if dir.exists() {
+        panic!(
+            "{} already exists, --experiment update-vs-batch needs a fresh directory",
+            dir.display()
+        );
+    }
+    let seeds = SeedTree::new(config.seed, config.verbose).child("update-vs-batch");
+    let snapshot: Vec<(H256, H256)> = {
+        let mut rng = seeds.child("snapshot").rng();
+        (0..config.snapshot_keys)
+            .map(|_| (random_h256(&mut rng), random_h256(&mut rng)))
+            .collect()
+    };
+    println!(
+        "Update vs batch: batches of {:?} keys on a snapshot of {} keys, in {}",
+        config.batch_sizes,
+        config.snapshot_keys,
+        dir.display()
+    );
+
+    let mut rows = vec![];
+    for kind in [StoreKind::Trie, StoreKind::Counting] {
+        let name = format!("{:?}", kind).to_lowercase();
+        let snapshot_path = dir.join(format!("{}-snapshot", name));
+        let root = build_snapshot(config, kind, &snapshot_path, &snapshot);
+        for &size in &config.batch_sizes {
+            let pairs = batch(&seeds, size, &snapshot);
+            let mut measured = vec![];
+            for batched in [false, true] {
+                let path = dir.join(format!(
+                    "{}-{}",
+                    name,
+                    if batched { "batched" } else { "single" }
+                ));
+                copy_dir(&snapshot_path, &path).unwrap();
+                measured.push(measure(config, kind, &path, root, pairs.clone(), batched));
+                fs::remove_dir_all(&path).unwrap();
+            }
+            let (single, batched) = (&measured[0], &measured[1]);
+            if single.root != batched.root {
+                eprintln!(
+                    "{} store, batch of {}: update ended with root {:?}, update_all with {:?}",
+                    name, size, single.root, batched.root
+                );
+                std::process::exit(1);
+            }
+            println!(
+                "  {} store, batch of {}: update {:?}, update_all {:?}",
+                name, size, single.elapsed, batched.elapsed
+            );
+            for (method, m) in [("update", single), ("update_all", batched)] {
+                rows.push(vec![
+                    name.clone(),
+                    size.to_string(),
+                    method.to_string(),
+                    format!("{:.3}", m.elapsed.as_secs_f64() * 1e3),
+                    m.reads.to_string(),
+                    m.writes.to_string(),
+                    format!("{:.2}", m.written_bytes as f64 / (1 << 20) as f64),
+                    format!(
+                        "{:.2}x",
+                        single.elapsed.as_secs_f64() / m.elapsed.as_secs_f64().max(f64::EPSILON)
+                    ),
+                ]);
+            }
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+
+    let header: Vec<String> = [
+        "Store",
+        "Batch",
+        "Method",
+        "ms",
+        "Reads",
+        "Writes",
+        "Written MiB",
+        "Speedup",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    println!();
+    if config.output == OutputFormat::Markdown {
+        markdown::print_preamble(config);
+        markdown::print_table(&header, &rows);
+        return;
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        println!(
+            "{}",
+            row.iter().map(|c| format!("{:>13}", c)).collect::<String>()
+        );
+    }
+}
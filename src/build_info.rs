@@ -0,0 +1,82 @@
+use crate::config::Command;
+
+// Cargo profile and opt-level the binary was built with, from build.rs
+pub const PROFILE: &str = env!("SMT_BENCH_PROFILE");
+pub const OPT_LEVEL: &str = env!("SMT_BENCH_OPT_LEVEL");
+// Enabled cargo features, comma separated
+pub const FEATURES: &str = env!("SMT_BENCH_FEATURES");
+// Versions of the tree and store crates resolved in Cargo.lock, a package
+// resolved more than once lists every version joined by +
+pub const DEPS: &[(&str, &str)] = &[
+    ("sparse-merkle-tree", env!("SMT_BENCH_DEP_SMT")),
+    ("gw-store", env!("SMT_BENCH_DEP_GW_STORE")),
+    ("gw-db", env!("SMT_BENCH_DEP_GW_DB")),
+];
+
+pub fn label() -> String {
+    let features = if FEATURES.is_empty() {
+        "none"
+    } else {
+        FEATURES
+    };
+    format!(
+        "{}, opt-level {}, features: {}",
+        PROFILE, OPT_LEVEL, features
+    )
+}
+
+pub fn deps_label() -> String {
+    let deps: Vec<String> = DEPS
+        .iter()
+        .map(|(name, version)| format!("{} {}", name, version))
+        .collect();
+    deps.join(", ")
+}
+
+// Commands whose output is timings, which a debug build makes meaningless
+fn measures_time(command: Command) -> bool {
+    matches!(
+        command,
+        Command::Bench | Command::VerifySizes | Command::PackBench
+    )
+}
+
+// Refuses timing commands in a build with debug assertions unless
+// --allow-debug is given, inspection commands always run
+pub fn guard(command: Command, allow_debug: bool, debug_build: bool) -> Result<(), String> {
+    if debug_build && !allow_debug && measures_time(command) {
+        return Err(format!(
+            "Refusing to benchmark with a debug build ({}), build with --release or pass --allow-debug",
+            label()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A debug build must refuse the timing commands unless --allow-debug is
+    // given, and run everything else.
+    #[test]
+    fn debug_build_guard() -> Result<(), String> {
+        for command in [Command::Bench, Command::VerifySizes, Command::PackBench] {
+            if guard(command, false, true).is_ok() {
+                return Err(format!("{:?} ran in a debug build", command));
+            }
+            if guard(command, true, true).is_err() {
+                return Err(format!("{:?} refused with --allow-debug", command));
+            }
+            if guard(command, false, false).is_err() {
+                return Err(format!("{:?} refused in a release build", command));
+            }
+        }
+        for command in [Command::Dot, Command::Path, Command::HistoryGet] {
+            if guard(command, false, true).is_err() {
+                return Err(format!("{:?} refused in a debug build", command));
+            }
+        }
+        Ok(())
+    }
+}
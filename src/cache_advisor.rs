@@ -0,0 +1,272 @@
+use crate::trie::TRIE_SIZE;
+use gw_db::{
+    error::Error,
+    iter::{DBIter, IteratorMode},
+    schema::Col,
+};
+use gw_store::traits::KVStore;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+// Sampled accesses kept before the sampling rate halves, bounding the trace
+// of a long run to a few MiB
+const MAX_TRACE: usize = 1 << 20;
+
+// Trie blob reads of a run, sampled by key: an access is kept when the hash
+// of its key is at most threshold, so every access to a sampled trie is
+// kept and the reuse between them stays intact. Stack distances of the
+// sample scale to the whole trace by the sampling rate.
+pub struct AccessTrace {
+    threshold: u64,
+    accesses: Vec<u64>,
+    // Accesses seen, sampled or not
+    pub total: usize,
+}
+
+impl AccessTrace {
+    pub fn new() -> Self {
+        Self {
+            threshold: u64::MAX,
+            accesses: vec![],
+            total: 0,
+        }
+    }
+
+    pub fn record(&mut self, key: &[u8]) {
+        self.total += 1;
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        if hash > self.threshold {
+            return;
+        }
+        self.accesses.push(hash);
+        if self.accesses.len() > MAX_TRACE {
+            self.threshold /= 2;
+            let threshold = self.threshold;
+            self.accesses.retain(|hash| *hash <= threshold);
+        }
+    }
+
+    // Share of the keys whose accesses are kept
+    pub fn rate(&self) -> f64 {
+        (self.threshold as f64 + 1.0) / (u64::MAX as f64 + 1.0)
+    }
+
+    pub fn curve(&self) -> Vec<(usize, f64)> {
+        hit_rate_curve(&self.accesses, self.rate())
+    }
+}
+
+// Fenwick tree over trace positions, counting the positions marked
+struct Fenwick(Vec<i64>);
+
+impl Fenwick {
+    fn add(&mut self, mut i: usize, delta: i64) {
+        i += 1;
+        while i < self.0.len() {
+            self.0[i] += delta;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    // Marks at positions below i
+    fn prefix(&self, mut i: usize) -> i64 {
+        let mut sum = 0;
+        while i > 0 {
+            sum += self.0[i];
+            i -= i & i.wrapping_neg();
+        }
+        sum
+    }
+}
+
+// Mattson's stack distance of every access: its position in an LRU stack
+// of the keys, 1 for the key accessed last, None for the first access to a
+// key. An LRU cache of n keys hits exactly the accesses at distance n or
+// less. The distance is one more than the distinct keys accessed since the
+// previous access to the same key, counted as the positions holding the
+// latest access to their key.
+pub fn stack_distances(trace: &[u64]) -> Vec<Option<usize>> {
+    let mut latest = Fenwick(vec![0; trace.len() + 1]);
+    let mut last: HashMap<u64, usize> = HashMap::new();
+    trace
+        .iter()
+        .enumerate()
+        .map(|(i, key)| {
+            let distance = last.insert(*key, i).map(|previous| {
+                let between = latest.prefix(i) - latest.prefix(previous + 1);
+                latest.add(previous, -1);
+                between as usize + 1
+            });
+            latest.add(i, 1);
+            distance
+        })
+        .collect()
+}
+
+// Hit rate of an LRU cache of every power of two of tries up to the
+// distinct tries of the trace, from a trace sampled at rate
+pub fn hit_rate_curve(trace: &[u64], rate: f64) -> Vec<(usize, f64)> {
+    if trace.is_empty() {
+        return vec![];
+    }
+    let distances = stack_distances(trace);
+    let scaled: Vec<f64> = distances
+        .iter()
+        .flatten()
+        .map(|distance| *distance as f64 / rate)
+        .collect();
+    let cold = distances.iter().filter(|d| d.is_none()).count();
+    let distinct = (cold as f64 / rate).ceil() as usize;
+    let mut curve = vec![];
+    let mut size = 1;
+    loop {
+        let hits = scaled.iter().filter(|d| **d <= size as f64).count();
+        curve.push((size, hits as f64 / trace.len() as f64));
+        if size >= distinct {
+            break;
+        }
+        size *= 2;
+    }
+    curve
+}
+
+// KVStore decorator recording the gets of one column in an AccessTrace,
+// passing everything through unchanged without one
+pub struct TraceStore<DB: KVStore> {
+    inner: DB,
+    trace: Option<(Rc<RefCell<AccessTrace>>, Col)>,
+}
+
+impl<DB: KVStore> TraceStore<DB> {
+    pub fn new(inner: DB, trace: Option<Rc<RefCell<AccessTrace>>>, col: Col) -> Self {
+        Self {
+            inner,
+            trace: trace.map(|trace| (trace, col)),
+        }
+    }
+}
+
+impl<DB: KVStore> KVStore for TraceStore<DB> {
+    fn get(&self, col: Col, key: &[u8]) -> Option<Box<[u8]>> {
+        if let Some((trace, traced_col)) = &self.trace {
+            if *traced_col == col {
+                trace.borrow_mut().record(key);
+            }
+        }
+        self.inner.get(col, key)
+    }
+
+    fn get_iter(&self, col: Col, mode: IteratorMode) -> DBIter<'_> {
+        self.inner.get_iter(col, mode)
+    }
+
+    fn insert_raw(&self, col: Col, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.inner.insert_raw(col, key, value)
+    }
+
+    fn delete(&self, col: Col, key: &[u8]) -> Result<(), Error> {
+        self.inner.delete(col, key)
+    }
+}
+
+pub fn print_curve(trace: &AccessTrace, curve: &[(usize, f64)]) {
+    if curve.is_empty() {
+        return;
+    }
+    println!(
+        "Cache advisor: {} trie reads, {:.2}% of the tries sampled, LRU hit rate by cache size:",
+        trace.total,
+        trace.rate() * 100.0
+    );
+    for &(tries, hit_rate) in curve {
+        println!(
+            "  {:>10} tries ({:>10.1} MiB): {:>6.2}%",
+            tries,
+            (tries * TRIE_SIZE) as f64 / (1 << 20) as f64,
+            hit_rate * 100.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stack distances and hit rates of small traces worked out by hand, and a
+    // sampled trace of a long cyclic scan staying within its bound and scaling
+    // back to the cache size the scan needs
+    #[test]
+    fn stack_distances_and_hit_rates() -> Result<(), String> {
+        let cases: [(&[u64], &[Option<usize>]); 4] = [
+            (
+                &[1, 2, 3, 1, 2, 3],
+                &[None, None, None, Some(3), Some(3), Some(3)],
+            ),
+            (&[1, 1, 2, 1], &[None, Some(1), None, Some(2)]),
+            (
+                &[1, 2, 2, 1, 3, 1],
+                &[None, None, Some(1), Some(2), None, Some(2)],
+            ),
+            (
+                &[4, 3, 2, 1, 1, 4],
+                &[None, None, None, None, Some(1), Some(4)],
+            ),
+        ];
+        for (trace, expected) in cases {
+            let distances = stack_distances(trace);
+            if distances != expected {
+                return Err(format!(
+                    "trace {:?} has distances {:?}, expected {:?}",
+                    trace, distances, expected
+                ));
+            }
+        }
+        let curve = hit_rate_curve(&[1, 2, 3, 1, 2, 3], 1.0);
+        if curve != [(1, 0.0), (2, 0.0), (4, 0.5)] {
+            return Err(format!("cyclic trace of 3 keys has curve {:?}", curve));
+        }
+        let curve = hit_rate_curve(&[1, 1, 2, 1], 1.0);
+        if curve != [(1, 0.25), (2, 0.5)] {
+            return Err(format!("trace [1, 1, 2, 1] has curve {:?}", curve));
+        }
+        // Accessing the tries twice at distance 3 in a half sample means
+        // distance 6 in the whole trace
+        let curve = hit_rate_curve(&[1, 2, 3, 1, 2, 3], 0.5);
+        if curve != [(1, 0.0), (2, 0.0), (4, 0.0), (8, 0.5)] {
+            return Err(format!("half sampled trace has curve {:?}", curve));
+        }
+
+        // Just over MAX_TRACE accesses, so the rate halves once
+        let keys = 1024u32;
+        let mut trace = AccessTrace::new();
+        for _ in 0..1030 {
+            for key in 0..keys {
+                trace.record(&key.to_le_bytes());
+            }
+        }
+        if trace.rate() >= 1.0 {
+            return Err(format!("{} accesses were never sampled", trace.total));
+        }
+        // A scan over more keys than the cache holds never hits in LRU, one
+        // holding them all hits on every pass but the first
+        let curve = trace.curve();
+        let small = curve.iter().find(|(tries, _)| *tries == keys as usize / 4);
+        let whole = curve.last();
+        if small.is_none_or(|(_, hit_rate)| *hit_rate > 0.05)
+            || whole.is_none_or(|(_, hit_rate)| *hit_rate < 0.95)
+        {
+            return Err(format!(
+                "cyclic scan of {} keys sampled at {} has curve {:?}",
+                keys,
+                trace.rate(),
+                curve
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{
+    compaction, config::Config, footprint::dir_size, random_h256, seed::SeedTree,
+    trie::trie_occupancy, trie::TrieStore, TrieSmt,
+};
+use gw_db::{iter::IteratorMode, RocksDB};
+use gw_store::{traits::KVStore, transaction::StoreTransaction, Store as GwStore};
+use rand_chacha::rand_core::RngCore;
+use sparse_merkle_tree::H256;
+
+// Bytes at the bottom of the key that differ between the keys of a block,
+// the rest is shared so the block fills the same rounded tries
+const LOCAL_BYTES: usize = 2;
+
+// Size of the database after a cycle's deletes
+struct Cycle {
+    tries_loaded: usize,
+    disk_bytes: u64,
+    sst_bytes: Option<u64>,
+    // Delete tombstones of the tree's columns after the cycle
+    tombstones: Option<u64>,
+}
+
+fn trie_store<'a>(config: &Config, tx: &'a StoreTransaction) -> TrieStore<'a, StoreTransaction> {
+    TrieStore::with_layout(tx, config.trie_layout)
+        .with_columns(config.branch_col, config.leaf_col)
+        .molecule_wrap(config.trie_molecule_wrap)
+        .truncate_leaf_keys(config.leaf_key_bytes)
+}
+
+// Writes the pairs in one transaction and returns the new root
+fn commit(config: &Config, store: &GwStore, root: H256, pairs: Vec<(H256, H256)>) -> H256 {
+    let tx = store.begin_transaction();
+    let mut smt = TrieSmt::new(root, trie_store(config, &tx));
+    let root = *smt.update_all(pairs).unwrap();
+    tx.commit().unwrap();
+    root
+}
+
+// Counts the trie blobs of the branch column and panics on the first one
+// holding no branch
+fn check_tries(config: &Config, store: &GwStore, cycle: usize, when: &str) -> usize {
+    let tx = store.begin_transaction();
+    let mut tries = 0;
+    for (key, value) in tx.get_iter(config.branch_col, IteratorMode::Start) {
+        if trie_occupancy(&value) == 0 {
+            panic!(
+                "Cycle {} {}: trie {:02x?} holds no branch but was not deleted",
+                cycle, when, key
+            );
+        }
+        tries += 1;
+    }
+    tries
+}
+
+// Whether the tree's columns hold nothing
+pub fn tree_empty(config: &Config, store: &GwStore) -> bool {
+    let tx = store.begin_transaction();
+    [config.branch_col, config.leaf_col]
+        .iter()
+        .all(|col| tx.get_iter(*col, IteratorMode::Start).next().is_none())
+}
+
+// Panics unless the tree's columns hold nothing, for experiments that
+// account for every key they find or start from an empty tree
+pub fn require_empty(config: &Config, store: &GwStore, option: &str) {
+    if !tree_empty(config, store) {
+        panic!(
+            "{} needs an empty database, {} holds a tree",
+            option,
+            config.db_path.display()
+        );
+    }
+}
+
+// Validates the reclamation of empty tries: every cycle inserts --churn-keys
+// keys sharing all but their lowest LOCAL_BYTES bytes, deletes them all, and
+// checks that the tree is empty again and no empty trie blob was left
+// behind. Each cycle picks new keys in the same region. The database size
+// after each cycle shows whether deleted tries keep costing space.
+pub fn run(config: &Config, store: &GwStore, db: &RocksDB) {
+    require_empty(config, store, "--experiment churn");
+    let mut rng = SeedTree::new(config.seed, config.verbose)
+        .child("churn")
+        .rng();
+    let region: [u8; 32] = random_h256(&mut rng).into();
+
+    println!(
+        "Churn: {} cycles of {} keys sharing {} bytes",
+        config.churn_cycles,
+        config.churn_keys,
+        32 - LOCAL_BYTES
+    );
+    let mut cycles = vec![];
+    for cycle in 1..=config.churn_cycles {
+        let pairs: Vec<(H256, H256)> = (0..config.churn_keys)
+            .map(|_| {
+                let mut key = region;
+                let mut local = [0u8; LOCAL_BYTES];
+                rng.fill_bytes(&mut local);
+                key[..LOCAL_BYTES].copy_from_slice(&local);
+                (H256::from(key), random_h256(&mut rng))
+            })
+            .collect();
+        let deletes = pairs.iter().map(|(key, _)| (*key, H256::zero())).collect();
+
+        let root = commit(config, store, H256::zero(), pairs);
+        let tries_loaded = check_tries(config, store, cycle, "after inserting");
+        let root = commit(config, store, root, deletes);
+        if root != H256::zero() {
+            panic!("Cycle {}: root {:?} after deleting every key", cycle, root);
+        }
+        let tries_left = check_tries(config, store, cycle, "after deleting");
+        if tries_left > 0 {
+            panic!(
+                "Cycle {}: {} tries left after deleting every key",
+                cycle, tries_left
+            );
+        }
+        let result = Cycle {
+            tries_loaded,
+            disk_bytes: dir_size(&config.db_path),
+            sst_bytes: db
+                .property_int_value(config.branch_col, "rocksdb.total-sst-files-size")
+                .ok()
+                .flatten(),
+            tombstones: compaction::tombstones(db, config),
+        };
+        if config.verbose {
+            println!(
+                "  Cycle {}: {} tries while loaded, {} bytes on disk, branch SST bytes: {}, tombstones: {}",
+                cycle,
+                result.tries_loaded,
+                result.disk_bytes,
+                result
+                    .sst_bytes
+                    .map_or("n/a".to_string(), |bytes| bytes.to_string()),
+                result
+                    .tombstones
+                    .map_or("n/a".to_string(), |n| n.to_string())
+            );
+        }
+        cycles.push(result);
+    }
+    print_growth(&cycles);
+}
+
+// Growth of the database per cycle over both halves of the experiment.
+// Without reclamation every cycle leaves its tries behind and the growth
+// stays the same, with it the growth of the second half drops to what
+// RocksDB keeps of deleted data until compaction.
+fn print_growth(cycles: &[Cycle]) {
+    let slope = |cycles: &[Cycle]| match (cycles.first(), cycles.last()) {
+        (Some(first), Some(last)) if cycles.len() > 1 => {
+            (last.disk_bytes as f64 - first.disk_bytes as f64) / (cycles.len() - 1) as f64
+        }
+        _ => 0.0,
+    };
+    let half = cycles.len() / 2;
+    let (first, second) = (slope(&cycles[..half]), slope(&cycles[half..]));
+    println!(
+        "Churn: no empty trie left behind, max tries while loaded: {}",
+        cycles.iter().map(|c| c.tries_loaded).max().unwrap_or(0)
+    );
+    println!(
+        "  Disk bytes: {} after cycle 1, {} after cycle {}",
+        cycles.first().map_or(0, |c| c.disk_bytes),
+        cycles.last().map_or(0, |c| c.disk_bytes),
+        cycles.len()
+    );
+    if let Some(last) = cycles.last().and_then(|c| c.tombstones) {
+        println!(
+            "  Tombstones: {} after cycle 1, {} after cycle {}",
+            cycles[0]
+                .tombstones
+                .map_or("n/a".to_string(), |n| n.to_string()),
+            last,
+            cycles.len()
+        );
+    }
+    println!(
+        "  Growth per cycle: {:.0} bytes over the first half, {:.0} over the second ({})",
+        first,
+        second,
+        if second < first / 2.0 || second <= 0.0 {
+            "flattening"
+        } else {
+            "linear"
+        }
+    );
+}
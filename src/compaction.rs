@@ -0,0 +1,108 @@
+use crate::{
+    config::Config,
+    footprint,
+    phase::{Phase, PhaseKind},
+};
+use gw_db::{
+    internal::ops::{GetColumnFamilys, SetOptions},
+    RocksDB,
+};
+
+// Turns RocksDB's automatic compactions of the tree's columns off or back
+// on. gw_db has no setter for column options, so this goes through the
+// rocksdb handle underneath it.
+pub fn set_auto_compactions(db: &RocksDB, config: &Config, enabled: bool) {
+    let inner = db.inner();
+    let value = if enabled { "false" } else { "true" };
+    for (name, col) in footprint::columns(config) {
+        let cf = inner
+            .cf_handle(&col.to_string())
+            .unwrap_or_else(|| panic!("The {} column {} does not exist", name, col));
+        if let Err(err) = inner.set_options_cf(cf, &[("disable_auto_compactions", value)]) {
+            panic!(
+                "Setting disable_auto_compactions={} on the {} column failed: {}",
+                value, name, err
+            );
+        }
+    }
+}
+
+// Bytes RocksDB estimates it has to rewrite to bring the tree's columns back
+// into shape, None when it reported none of them
+pub fn pending_bytes(db: &RocksDB, config: &Config) -> Option<u64> {
+    footprint::columns(config)
+        .into_iter()
+        .filter_map(|(_, col)| {
+            db.property_int_value(col, "rocksdb.estimate-pending-compaction-bytes")
+                .ok()
+                .flatten()
+        })
+        .reduce(|a, b| a + b)
+}
+
+// Files in level 0 of the tree's columns, which every read may have to
+// check. None when RocksDB reported none of them.
+pub fn l0_files(db: &RocksDB, config: &Config) -> Option<u64> {
+    footprint::columns(config)
+        .into_iter()
+        .filter_map(|(_, col)| {
+            db.property_int_value(col, "rocksdb.num-files-at-level0")
+                .ok()
+                .flatten()
+        })
+        .reduce(|a, b| a + b)
+}
+
+// Reads timed before and after the compaction of --compact-after-deletes
+pub fn tombstone_probe() -> Phase {
+    Phase::new(PhaseKind::Read, 5, 1_000)
+}
+
+// Delete tombstones in the tree's columns: the deletes still in memtables
+// and the deleted keys of the SST files. None when RocksDB reported none of
+// them.
+pub fn tombstones(db: &RocksDB, config: &Config) -> Option<u64> {
+    footprint::columns(config)
+        .into_iter()
+        .flat_map(|(_, col)| {
+            let property = |name| db.property_int_value(col, name).ok().flatten();
+            let sst = db
+                .property_value(col, "rocksdb.aggregated-table-properties")
+                .ok()
+                .flatten()
+                .and_then(|properties| deleted_keys(&properties));
+            vec![
+                property("rocksdb.num-deletes-active-mem-table"),
+                property("rocksdb.num-deletes-imm-mem-tables"),
+                sst,
+            ]
+        })
+        .flatten()
+        .reduce(|a, b| a + b)
+}
+
+// The "# deleted keys=N" entry of RocksDB's table properties string
+pub(crate) fn deleted_keys(properties: &str) -> Option<u64> {
+    properties
+        .split(';')
+        .find_map(|entry| entry.trim().strip_prefix("# deleted keys="))
+        .and_then(|n| n.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tombstones must be read from RocksDB's table properties
+    #[test]
+    fn deleted_keys_from_properties() -> Result<(), String> {
+        let properties = "# data blocks=12; # entries=4000; # deletions=0; # deleted keys=1532; # merge operands=0; # range deletions=0";
+        if deleted_keys(properties) != Some(1532) {
+            return Err(format!("read {:?} deleted keys", deleted_keys(properties)));
+        }
+        if deleted_keys("# data blocks=12; # entries=4000").is_some() {
+            return Err("deleted keys read from properties without them".to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1286 @@
+use crate::{
+    growth::{self, BASELINE_MAX_LEAVES},
+    path::parse_h256,
+    phase::{parse_phases, Phase, PhaseKind, RoundSizes},
+    profile::Profile,
+    replica::parse_target,
+    schedule::{ScheduledChange, Setting},
+    tiered::TierSplit,
+    trie::{TrieLayout, LEAF_KEY_SIZE},
+};
+use gw_db::schema::Col;
+use sparse_merkle_tree::H256;
+use std::env;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Bench,
+    // Prints the stored tree as a Graphviz DOT graph
+    Dot,
+    // Prints every stored branch along the path of one key
+    Path,
+    // Prints the value a key had at the end of a round, from the leaf history
+    HistoryGet,
+    // Times proof verification including the hashing of leaf preimages of
+    // every --value-sizes size
+    VerifySizes,
+    // Times pack_key against writing the packed key bytes directly
+    PackBench,
+    // Writes a reproducible file of key/value pairs for --keys-file
+    GenCorpus,
+    // Compares the molecule and byte-packed encodings of stored branches
+    EncodingSizes,
+    // Prints a results file written with --output binary
+    ShowResults,
+    // Applies a dump of --dump-writes to a fresh database, timed
+    ReplayWrites,
+    // Rebuilds the branches of a damaged database from its leaves
+    Repair,
+    // Checks a compiled proof file against a root, key and value without a
+    // database
+    VerifyProof,
+    // Replays the rounds of a --lineage file from the write dump of the run
+    VerifyLineage,
+}
+
+// How the progress lines compute reads, writes and written bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsMode {
+    // Totals since the start of the run
+    Cumulative,
+    // Change since the previous progress line
+    Delta,
+    // Mean per round since the previous progress line
+    PerRound,
+}
+
+impl FromStr for StatsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cumulative" => Ok(StatsMode::Cumulative),
+            "delta" => Ok(StatsMode::Delta),
+            "per-round" => Ok(StatsMode::PerRound),
+            _ => Err(format!("Unknown stats mode: {}", s)),
+        }
+    }
+}
+
+// When a round builds its SparseMerkleTree again from the current root
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmtLifetime {
+    // After every chunk of keys passed to update_all
+    PerChunk,
+    // Once per transaction, as without --smt-lifetime
+    PerRound,
+}
+
+impl FromStr for SmtLifetime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "per-chunk" => Ok(SmtLifetime::PerChunk),
+            "per-round" => Ok(SmtLifetime::PerRound),
+            // The tree borrows the store of one transaction, and every round
+            // commits its own
+            "per-run" => Err(
+                "per-run is not supported, the tree cannot outlive the transaction of its round"
+                    .to_string(),
+            ),
+            _ => Err(format!("Unknown SMT lifetime: {}", s)),
+        }
+    }
+}
+
+// How the --profile-compare and verify-sizes tables are printed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    // GitHub-flavored Markdown, for pasting into issues and PRs
+    Markdown,
+    // The text output, plus every round in a compact versioned file for
+    // archiving, see results.rs
+    Binary,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "markdown" => Ok(OutputFormat::Markdown),
+            "binary" => Ok(OutputFormat::Binary),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+// Naive approach timed next to the incremental updates of --growth-profile
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Baseline {
+    // The whole tree rebuilt from its leaves with one update_all into a
+    // fresh in-memory store after every timed round
+    Recompute,
+}
+
+impl FromStr for Baseline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "recompute" => Ok(Baseline::Recompute),
+            _ => Err(format!("Unknown baseline: {}", s)),
+        }
+    }
+}
+
+// What a correctness monitor firing does: contract violations, commit
+// verification mismatches and proofs that do not verify
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnViolation {
+    // Panic at once, the run's numbers being worthless from there
+    Abort,
+    // Record the violation in the round's report and go on, exiting
+    // non-zero at the end
+    Tally,
+}
+
+impl FromStr for OnViolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(OnViolation::Abort),
+            "tally" => Ok(OnViolation::Tally),
+            _ => Err(format!("Unknown violation mode: {}", s)),
+        }
+    }
+}
+
+// Named set of scenarios --suite runs, see suite.rs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Suite {
+    Standard,
+}
+
+impl FromStr for Suite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standard" => Ok(Suite::Standard),
+            _ => Err(format!("Unknown suite: {}", s)),
+        }
+    }
+}
+
+// When the OS page cache of the database files is dropped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropCaches {
+    BetweenRounds,
+    BetweenPhases,
+    // Every read phase runs again on the same keys after the whole OS page
+    // cache was dropped, which needs root
+    BeforeReads,
+}
+
+impl FromStr for DropCaches {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "between-rounds" => Ok(DropCaches::BetweenRounds),
+            "between-phases" => Ok(DropCaches::BetweenPhases),
+            "before-reads" => Ok(DropCaches::BeforeReads),
+            _ => Err(format!("Unknown cache drop point: {}", s)),
+        }
+    }
+}
+
+// When RocksDB's automatic compactions are held off, chosen with
+// --pause-compaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PauseCompaction {
+    // Off while a round is timed, back on between rounds
+    DuringRounds,
+}
+
+impl FromStr for PauseCompaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "during-rounds" => Ok(PauseCompaction::DuringRounds),
+            _ => Err(format!("Unknown compaction pause: {}", s)),
+        }
+    }
+}
+
+// Runs replacing the phases, chosen with --experiment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Experiment {
+    // Fills one region of the tree and empties it again, see churn::run
+    Churn,
+    // Two threads committing to the tree at once, see conflict::run
+    WriteConflict,
+    // Commits and simulated crashes, see durability::run
+    Durability,
+    // The same batches applied with update and with update_all, see
+    // batching::run
+    UpdateVsBatch,
+    // Branches of one trie read, changed and written back, see rmw::run
+    TrieRmw,
+    // Rounds applied again in another order after a revert, see
+    // reorder::run
+    Reorder,
+    // The same rounds through TrieStore and CountingStore, roots compared
+    // every round, see differential::run
+    Differential,
+    // The phases on a tree split over two databases by height, see
+    // tiered::run
+    Tiered,
+}
+
+impl FromStr for Experiment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "churn" => Ok(Experiment::Churn),
+            "write-conflict" => Ok(Experiment::WriteConflict),
+            "durability" => Ok(Experiment::Durability),
+            "update-vs-batch" => Ok(Experiment::UpdateVsBatch),
+            "trie-rmw" => Ok(Experiment::TrieRmw),
+            "reorder" => Ok(Experiment::Reorder),
+            "differential" => Ok(Experiment::Differential),
+            "tiered" => Ok(Experiment::Tiered),
+            _ => Err(format!("Unknown experiment: {}", s)),
+        }
+    }
+}
+
+// Store implementation used to read an existing database
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreKind {
+    Trie,
+    Counting,
+}
+
+impl FromStr for StoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trie" => Ok(StoreKind::Trie),
+            "counting" => Ok(StoreKind::Counting),
+            _ => Err(format!("Unknown store: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub command: Command,
+    pub db_path: PathBuf,
+    // Root seed of all random streams
+    pub seed: u64,
+    pub verbose: bool,
+    // Print every derived random stream at the end of the run
+    pub audit_seeds: bool,
+    // Phases executed in order against the same database
+    pub phases: Vec<Phase>,
+    // Number of random get_leaf calls issued over the inserted keys after the
+    // update phase, 0 disables the phase.
+    pub leaf_gets: usize,
+    // Print an aggregated progress line every K rounds, 0 disables it
+    pub report_every: usize,
+    pub stats_mode: StatsMode,
+    // When the tree is built again from the current root. Set, update_all
+    // is fed keys in chunks whatever the policy, so policies differ only
+    // in the rebuilds.
+    pub smt_lifetime: Option<SmtLifetime>,
+    // Distribution the key count of each update and delete round is drawn
+    // from, replacing the batch size of the phase
+    pub round_sizes: Option<RoundSizes>,
+    // Render a live dashboard instead of progress lines, needs the tui feature
+    pub tui: bool,
+    // Leaves re-read through a fresh transaction after each commit, 0
+    // disables the verification
+    pub verify_commit: usize,
+    // Keys are blake2b hashes of random preimages of this many bytes, 0 uses
+    // raw random 32-byte keys
+    pub key_preimage_size: usize,
+    // Node order within TrieStore blobs
+    pub trie_layout: TrieLayout,
+    // Store trie blobs wrapped in a molecule Bytes, as scanners of godwoken
+    // databases expect of every branch column value
+    pub trie_molecule_wrap: bool,
+    // Store leaves under this many leading bytes of their key instead of all
+    // 32, an experimental layout older databases and tools cannot read
+    pub leaf_key_bytes: Option<usize>,
+    // Maximum number of branches emitted by the dot command
+    pub max_nodes: usize,
+    // Open the database with RocksDB's read-only open, which takes no lock,
+    // for pointing bench, dot and path at a godwoken node's database while
+    // the node keeps running. Only the --branch-col and --leaf-col column
+    // families are opened, and reads see the database as of the open. bench
+    // only runs read and prove-same phases, a single read phase by default.
+    pub read_only: bool,
+    // Columns holding branches and leaves. The bench itself uses 0 and 1, a
+    // godwoken node keeps its account SMT in the columns named
+    // COLUMN_ACCOUNT_SMT_BRANCH and COLUMN_ACCOUNT_SMT_LEAF in gw_db::schema.
+    // Opening a node's database for writing also needs --columns set to
+    // gw_db::schema::COLUMNS, since RocksDB refuses to open a database with
+    // column families missing, --read-only does not.
+    pub branch_col: Col,
+    pub leaf_col: Col,
+    // Number of column families the database is opened with
+    pub columns: u32,
+    // RocksDB block cache size in MiB, None keeps gw_db's default
+    pub block_cache_mb: Option<usize>,
+    // RocksDB options file the database is opened with, checked against
+    // the options RocksDB reports applying
+    pub rocksdb_options_file: Option<PathBuf>,
+    // Store used to read the database by the path command and by bench with
+    // --read-only
+    pub store: StoreKind,
+    // Key whose path the path command prints, or whose history history-get
+    // queries
+    pub key: Option<H256>,
+    // Print the path command's output and the bench findings as JSON
+    pub json: bool,
+    // Round of every phase after which its steady state starts, instead of
+    // the detected one
+    pub steady_after: Option<usize>,
+    // Threads generating the keys and values of load rounds, insertion stays
+    // serial
+    pub init_threads: usize,
+    // Keys of a load round applied per update_all, None applying them with
+    // one update each
+    pub init_chunk: Option<usize>,
+    // Record the previous value of every changed leaf per round
+    pub leaf_history: bool,
+    pub history_col: Col,
+    // Rounds of leaf history kept after the run, older records are trimmed
+    pub history_retention: Option<u64>,
+    // Round queried by the history-get command
+    pub round: Option<u64>,
+    // RocksDB background jobs, None keeps the RocksDB default
+    pub max_background_compactions: Option<u32>,
+    pub max_background_flushes: Option<u32>,
+    // Print RocksDB's compaction statistics at the end of the bench
+    pub compaction_stats: bool,
+    // Start rounds, phases or a second run of the read phases with a cold
+    // page cache
+    pub drop_caches: Option<DropCaches>,
+    // Fraction of store reads and writes failing with an injected error
+    pub fault_rate: f64,
+    // Time the root() call after each update separately
+    pub time_root: bool,
+    // Validate the store in a temp database before benchmarking
+    pub self_test: bool,
+    // Distinct leaves tracked exactly before the leaf count switches to an
+    // estimate
+    pub exact_leaf_limit: usize,
+    // Warn when a transaction buffers more than this many bytes of keys and
+    // values, and with split_commits commit early at that point instead
+    pub max_staged_bytes: Option<usize>,
+    pub split_commits: bool,
+    // Workloads run on fresh databases and compared instead of the phases
+    pub profile_compare: Vec<Profile>,
+    // Check every store read of the bench against a model of its writes
+    pub check_contract: bool,
+    // What a violation of the contract, a commit verification or a proof
+    // does, and with tally the ndjson file the violations go to
+    pub on_violation: OnViolation,
+    pub violations_out: Option<PathBuf>,
+    // Leaf preimage sizes in bytes and tree size of the verify-sizes command
+    pub value_sizes: Vec<usize>,
+    pub verify_leaves: usize,
+    // Panic when a load or update round leaves the root unchanged
+    pub assert_root_changes: bool,
+    // Panic when a write round stores more values than this per key
+    pub max_writes_per_update: Option<f64>,
+    // Store and path of a database every committed batch is replayed to
+    pub replicate_to: Option<(StoreKind, PathBuf)>,
+    // Load and update rounds write keys sharing all but their lowest byte
+    pub rewrite_storm: bool,
+    // Fresh keys count up from zero instead of being random, so every round
+    // writes next to the keys of the previous one
+    pub sorted_keys: bool,
+    pub output: OutputFormat,
+    // File of --output binary instead of results.bin in the database path
+    pub results_out: Option<PathBuf>,
+    // Settings changed between rounds, given with --at
+    pub schedule: Vec<ScheduledChange>,
+    // Compact the tree's columns fully after the run and report the bytes
+    // each live leaf costs
+    pub final_compact: bool,
+    // Flush and compact the tree's columns after the run and report the
+    // bytes the database directory takes on disk, and the replica's
+    pub disk_size: bool,
+    // After every delete phase, time reads before and after compacting the
+    // tree's columns, to measure what the tombstones cost them
+    pub compact_after_deletes: bool,
+    // Run timing commands even in a debug build
+    pub allow_debug: bool,
+    // Track the bench's transactions and store, and fail on any still alive
+    // at exit or opened over a live transaction
+    pub debug_handles: bool,
+    pub experiment: Option<Experiment>,
+    // Keys per cycle and cycles of --experiment churn
+    pub churn_keys: usize,
+    pub churn_cycles: usize,
+    // Share of each writer's keys the other one updates too, and the batches
+    // and batch size of each writer of --experiment write-conflict
+    pub conflict_overlap: f64,
+    pub conflict_batches: usize,
+    pub conflict_batch_size: usize,
+    // Iterations and updates per iteration of --experiment durability, and
+    // the share of iterations ending in a crash and a reopen
+    pub durability_iterations: usize,
+    pub durability_batch_size: usize,
+    pub crash_rate: f64,
+    // Keys of the tree every batch of --experiment update-vs-batch starts
+    // from, and the sizes of the batches
+    pub snapshot_keys: usize,
+    pub batch_sizes: Vec<usize>,
+    // Read-modify-write cycles of --experiment trie-rmw
+    pub rmw_iterations: usize,
+    // Rounds and keys per round of --experiment reorder
+    pub reorder_rounds: usize,
+    pub reorder_batch_size: usize,
+    // Rounds and keys per round of --experiment differential, and whether a
+    // diverging round is bisected down to the keys responsible
+    pub differential_rounds: usize,
+    pub differential_batch_size: usize,
+    pub bisect_divergence: bool,
+    // Also apply the rounds of --experiment differential through a TrieStore
+    // writing merge operands for changed slots instead of whole blobs
+    pub trie_merge_op: bool,
+    // Split height and database paths of --experiment tiered
+    pub tier_split: Option<TierSplit>,
+    // Pair count and output file of gen-corpus
+    pub corpus_count: Option<usize>,
+    pub corpus_out: Option<PathBuf>,
+    // Corpus the load and update rounds take their pairs from
+    pub keys_file: Option<PathBuf>,
+    // Physical store operations allowed per second, to emulate slow disks
+    pub iops_limit: Option<u64>,
+    // With --profile-compare every profile runs once without and once with
+    // the pause
+    pub pause_compaction: Option<PauseCompaction>,
+    // Core the bench runs on. Built with the numa feature, memory is also
+    // allocated on the core's NUMA node only.
+    pub pin_core: Option<usize>,
+    // Shares of absent keys in the proofs of a prove-mix round, one proof
+    // per share
+    pub proof_mix: Vec<f64>,
+    // Branches encoding-sizes reads at most
+    pub sample_branches: usize,
+    // Print the wall time of every stage of the run at the end
+    pub stage_times: bool,
+    // Exit without running when the database was last written by a run of
+    // the same fingerprint
+    pub skip_if_run: bool,
+    // Go on with the run that left its checkpoint in the database instead
+    // of refusing a database that holds a tree
+    pub resume: bool,
+    // Sample which phase marker the bench is in while profiling and print
+    // the counts at the end
+    pub profile_phases: bool,
+    // Run the smoke phases this many times on fresh databases and compare
+    // every counter of every round
+    pub determinism_check: Option<usize>,
+    // Trace the trie blob reads of the rounds and print the hit rate an LRU
+    // cache of each size would have had
+    pub cache_advisor: bool,
+    // Count inserts replacing a leaf by a different value, at the cost of a
+    // read before every leaf insert
+    pub strict_leaf: bool,
+    // Panic on any store access outside the columns the tree lives in, or
+    // with tolerate count it and skip the write
+    pub guard_columns: bool,
+    pub tolerate: bool,
+    // Options each taking every one of their values, the bench running once
+    // per combination, and the combinations run at once
+    pub grid: Vec<(String, Vec<String>)>,
+    pub grid_parallel: usize,
+    // Results file show-results prints, write dump replay-writes applies,
+    // or proof file verify-proof checks
+    pub input_file: Option<PathBuf>,
+    // File the inserts and deletes of every committed transaction are
+    // recorded to
+    pub dump_writes: Option<PathBuf>,
+    // File the root after every committed round is written to, as hex one
+    // per line, for diffing runs of different builds or machines
+    pub emit_roots: Option<PathBuf>,
+    // File every round is recorded to with the roots before and after it,
+    // one JSON object per line, which verify-lineage checks against the
+    // write dump of --workload
+    pub lineage: Option<PathBuf>,
+    pub workload: Option<PathBuf>,
+    // Scenarios run one after the other, each on a fresh database, with a
+    // report of all of them at the end
+    pub suite: Option<Suite>,
+    // What repair does: drop every branch and insert every leaf again, a
+    // chunk of leaves per transaction, then check the root against
+    // --expect-root or the root recorded with the database
+    pub rebuild_branches: bool,
+    pub rebuild_chunk: usize,
+    pub expect_root: Option<H256>,
+    // Root and value verify-proof checks the proof of --key against
+    pub proof_root: Option<H256>,
+    pub proof_value: Option<H256>,
+    // Grow an empty tree in large batches and time a fixed batch of updates
+    // at every checkpoint size
+    pub growth_profile: bool,
+    pub growth_checkpoints: Vec<usize>,
+    pub growth_batch: usize,
+    // --workload scan: grow an empty tree to every size and time ordered
+    // iteration of the leaf column at each, over all of it and over random
+    // ranges of a share of the key space
+    pub scan: bool,
+    pub scan_sizes: Vec<usize>,
+    pub scan_range_width: f64,
+    // Baseline --growth-profile also times, and whether it runs past its
+    // size limit
+    pub baseline: Option<Baseline>,
+    pub force: bool,
+    // Address of the HTTP API that starts, follows and stops runs, needs the
+    // remote feature
+    pub serve: Option<String>,
+    // With --store proxy:PATH, the socket a proxy server listens on for the
+    // phases to run through, needs the proxy feature, and the puts and
+    // deletes the client sends before waiting for their replies
+    pub proxy_socket: Option<PathBuf>,
+    pub proxy_pipeline: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            command: Command::Bench,
+            db_path: PathBuf::from("./store2.db"),
+            seed: 0,
+            verbose: false,
+            audit_seeds: false,
+            phases: vec![
+                Phase::new(PhaseKind::Load, 1, 200),
+                Phase::new(PhaseKind::Update, 1, 10000),
+            ],
+            leaf_gets: 0,
+            report_every: 1,
+            stats_mode: StatsMode::Delta,
+            smt_lifetime: None,
+            round_sizes: None,
+            tui: false,
+            verify_commit: 0,
+            key_preimage_size: 0,
+            trie_layout: TrieLayout::default(),
+            trie_molecule_wrap: false,
+            leaf_key_bytes: None,
+            max_nodes: 1000,
+            read_only: false,
+            branch_col: 0,
+            leaf_col: 1,
+            columns: 10,
+            block_cache_mb: None,
+            rocksdb_options_file: None,
+            store: StoreKind::Trie,
+            key: None,
+            json: false,
+            steady_after: None,
+            init_threads: 1,
+            init_chunk: None,
+            leaf_history: false,
+            history_col: 2,
+            history_retention: None,
+            round: None,
+            max_background_compactions: None,
+            max_background_flushes: None,
+            compaction_stats: false,
+            drop_caches: None,
+            fault_rate: 0.0,
+            time_root: false,
+            self_test: true,
+            exact_leaf_limit: 1_000_000,
+            max_staged_bytes: None,
+            split_commits: false,
+            profile_compare: vec![],
+            check_contract: false,
+            on_violation: OnViolation::Abort,
+            violations_out: None,
+            value_sizes: vec![32, 256, 4096, 65536],
+            verify_leaves: 10_000,
+            assert_root_changes: false,
+            max_writes_per_update: None,
+            replicate_to: None,
+            rewrite_storm: false,
+            sorted_keys: false,
+            output: OutputFormat::Text,
+            results_out: None,
+            schedule: vec![],
+            final_compact: false,
+            disk_size: false,
+            compact_after_deletes: false,
+            allow_debug: false,
+            debug_handles: false,
+            experiment: None,
+            churn_keys: 1000,
+            churn_cycles: 50,
+            conflict_overlap: 0.5,
+            conflict_batches: 20,
+            conflict_batch_size: 500,
+            durability_iterations: 200,
+            durability_batch_size: 500,
+            crash_rate: 0.2,
+            snapshot_keys: 10_000,
+            batch_sizes: vec![10, 100, 1000],
+            rmw_iterations: 10_000,
+            reorder_rounds: 10,
+            reorder_batch_size: 1_000,
+            differential_rounds: 10,
+            differential_batch_size: 1_000,
+            bisect_divergence: false,
+            trie_merge_op: false,
+            tier_split: None,
+            corpus_count: None,
+            corpus_out: None,
+            keys_file: None,
+            iops_limit: None,
+            pause_compaction: None,
+            pin_core: None,
+            proof_mix: vec![0.3],
+            sample_branches: 10_000,
+            stage_times: false,
+            skip_if_run: false,
+            resume: false,
+            profile_phases: false,
+            determinism_check: None,
+            cache_advisor: false,
+            strict_leaf: false,
+            guard_columns: false,
+            tolerate: false,
+            grid: vec![],
+            grid_parallel: 1,
+            input_file: None,
+            dump_writes: None,
+            emit_roots: None,
+            lineage: None,
+            workload: None,
+            suite: None,
+            rebuild_branches: false,
+            rebuild_chunk: 10_000,
+            expect_root: None,
+            proof_root: None,
+            proof_value: None,
+            growth_profile: false,
+            growth_checkpoints: vec![1_000, 10_000, 100_000, 1_000_000],
+            growth_batch: 1_000,
+            scan: false,
+            scan_sizes: vec![1_000, 10_000, 100_000],
+            scan_range_width: 0.01,
+            baseline: None,
+            force: false,
+            serve: None,
+            proxy_socket: None,
+            proxy_pipeline: 1,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args() -> Self {
+        Self::parse(env::args().skip(1))
+    }
+
+    // Parses the arguments after the program name, a later option
+    // overriding an earlier one
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Self {
+        let mut config = Config::default();
+        let mut phases_given = false;
+        let mut args = args.into_iter().peekable();
+        if let Some(command) = args.next_if(|arg| !arg.starts_with('-')) {
+            config.command = match command.as_str() {
+                "bench" => Command::Bench,
+                "dot" => Command::Dot,
+                "path" => Command::Path,
+                "history-get" => Command::HistoryGet,
+                "verify-sizes" => Command::VerifySizes,
+                "pack-bench" => Command::PackBench,
+                "gen-corpus" => Command::GenCorpus,
+                "encoding-sizes" => Command::EncodingSizes,
+                "show-results" => Command::ShowResults,
+                "replay-writes" => Command::ReplayWrites,
+                "repair" => Command::Repair,
+                "verify-proof" => Command::VerifyProof,
+                "verify-lineage" => Command::VerifyLineage,
+                _ => panic!("Unknown command: {}", command),
+            };
+        }
+        if matches!(
+            config.command,
+            Command::ShowResults | Command::ReplayWrites | Command::VerifyProof
+        ) {
+            config.input_file = args.next_if(|arg| !arg.starts_with('-')).map(PathBuf::from);
+        }
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--db-path" => config.db_path = parse_value(&arg, args.next()),
+                "--max-nodes" => config.max_nodes = parse_value(&arg, args.next()),
+                "--stage-times" => config.stage_times = true,
+                "--skip-if-run" => config.skip_if_run = true,
+                "--resume" => config.resume = true,
+                "--profile-phases" => config.profile_phases = true,
+                "--cache-advisor" => config.cache_advisor = true,
+                "--strict-leaf" => config.strict_leaf = true,
+                "--guard-columns" => config.guard_columns = true,
+                "--tolerate" => config.tolerate = true,
+                "--grid" => {
+                    while let Some(axis) = args.next_if(|arg| !arg.starts_with("--")) {
+                        config
+                            .grid
+                            .push(parse_axis(&axis).unwrap_or_else(|err| panic!("{}", err)));
+                    }
+                    if config.grid.is_empty() {
+                        panic!("--grid requires at least one option=value,... axis");
+                    }
+                }
+                "--suite" => config.suite = Some(parse_value(&arg, args.next())),
+                "--grid-parallel" => config.grid_parallel = parse_value(&arg, args.next()),
+                "--determinism-check" => {
+                    config.determinism_check = Some(parse_value(&arg, args.next()))
+                }
+                "--sample" => config.sample_branches = parse_value(&arg, args.next()),
+                "--seed" => config.seed = parse_value(&arg, args.next()),
+                "-v" | "--verbose" => config.verbose = true,
+                "--audit-seeds" => config.audit_seeds = true,
+                "--phases" => {
+                    let value: String = parse_value(&arg, args.next());
+                    config.phases = parse_phases(&value).unwrap_or_else(|err| panic!("{}", err));
+                    phases_given = true;
+                }
+                "--leaf-gets" => config.leaf_gets = parse_value(&arg, args.next()),
+                "--report-every" => config.report_every = parse_value(&arg, args.next()),
+                "--stats-mode" => config.stats_mode = parse_value(&arg, args.next()),
+                "--smt-lifetime" => config.smt_lifetime = Some(parse_value(&arg, args.next())),
+                "--round-size-distribution" => {
+                    config.round_sizes = Some(parse_value(&arg, args.next()))
+                }
+                "--verify-commit" => {
+                    let value: String = parse_value(&arg, args.next());
+                    let sample = value.strip_prefix("sample=").unwrap_or(&value);
+                    config.verify_commit = parse_value(&arg, Some(sample.to_string()));
+                }
+                // --app-key-size names it after what the preimage models, an
+                // application key hashed to the tree key
+                "--key-preimage-size" | "--app-key-size" => {
+                    config.key_preimage_size = parse_value(&arg, args.next())
+                }
+                "--trie-layout" => config.trie_layout = parse_value(&arg, args.next()),
+                "--trie-molecule-wrap" => config.trie_molecule_wrap = true,
+                "--leaf-key-bytes" => config.leaf_key_bytes = Some(parse_value(&arg, args.next())),
+                "--read-only" => config.read_only = true,
+                "--branch-col" => config.branch_col = parse_value(&arg, args.next()),
+                "--leaf-col" => config.leaf_col = parse_value(&arg, args.next()),
+                "--columns" => config.columns = parse_value(&arg, args.next()),
+                "--block-cache-mb" => config.block_cache_mb = Some(parse_value(&arg, args.next())),
+                "--rocksdb-options-file" => {
+                    config.rocksdb_options_file = Some(parse_value(&arg, args.next()))
+                }
+                "--store" => {
+                    let value: String = parse_value(&arg, args.next());
+                    match value.strip_prefix("proxy:") {
+                        Some(socket) => {
+                            if !cfg!(feature = "proxy") {
+                                panic!("--store proxy requires building with the proxy feature");
+                            }
+                            config.proxy_socket = Some(PathBuf::from(socket));
+                        }
+                        None => config.store = parse_value(&arg, Some(value)),
+                    }
+                }
+                "--proxy-pipeline" => config.proxy_pipeline = parse_value(&arg, args.next()),
+                "--key" => {
+                    let value: String = parse_value(&arg, args.next());
+                    config.key = Some(parse_h256(&value).unwrap_or_else(|err| panic!("{}", err)));
+                }
+                "--json" => config.json = true,
+                "--steady-after" => config.steady_after = Some(parse_value(&arg, args.next())),
+                "--output" => config.output = parse_value(&arg, args.next()),
+                "--results-out" => config.results_out = Some(parse_value(&arg, args.next())),
+                "--at" => {
+                    let value: String = parse_value(&arg, args.next());
+                    let change = value.parse().unwrap_or_else(|err| panic!("{}", err));
+                    config.schedule.push(change);
+                }
+                "--init-threads" => config.init_threads = parse_value(&arg, args.next()),
+                "--init-chunk" => config.init_chunk = Some(parse_value(&arg, args.next())),
+                "--leaf-history" => config.leaf_history = true,
+                "--history-col" => config.history_col = parse_value(&arg, args.next()),
+                "--history-retention" => {
+                    config.history_retention = Some(parse_value(&arg, args.next()))
+                }
+                "--round" => config.round = Some(parse_value(&arg, args.next())),
+                "--max-background-compactions" => {
+                    config.max_background_compactions = Some(parse_value(&arg, args.next()))
+                }
+                "--max-background-flushes" => {
+                    config.max_background_flushes = Some(parse_value(&arg, args.next()))
+                }
+                "--compaction-stats" => config.compaction_stats = true,
+                "--final-compact" => config.final_compact = true,
+                "--disk-size" => config.disk_size = true,
+                "--compact-after-deletes" => config.compact_after_deletes = true,
+                "--allow-debug" => config.allow_debug = true,
+                "--debug-handles" => config.debug_handles = true,
+                "--experiment" => config.experiment = Some(parse_value(&arg, args.next())),
+                "--churn-keys" => config.churn_keys = parse_value(&arg, args.next()),
+                "--churn-cycles" => config.churn_cycles = parse_value(&arg, args.next()),
+                "--overlap" => config.conflict_overlap = parse_value(&arg, args.next()),
+                "--conflict-batches" => config.conflict_batches = parse_value(&arg, args.next()),
+                "--conflict-batch-size" => {
+                    config.conflict_batch_size = parse_value(&arg, args.next())
+                }
+                "--iterations" => config.durability_iterations = parse_value(&arg, args.next()),
+                "--durability-batch-size" => {
+                    config.durability_batch_size = parse_value(&arg, args.next())
+                }
+                "--crash-rate" => config.crash_rate = parse_value(&arg, args.next()),
+                "--snapshot-keys" => config.snapshot_keys = parse_value(&arg, args.next()),
+                "--rmw-iterations" => config.rmw_iterations = parse_value(&arg, args.next()),
+                "--reorder-rounds" => config.reorder_rounds = parse_value(&arg, args.next()),
+                "--reorder-batch-size" => {
+                    config.reorder_batch_size = parse_value(&arg, args.next())
+                }
+                "--differential-rounds" => {
+                    config.differential_rounds = parse_value(&arg, args.next())
+                }
+                "--differential-batch-size" => {
+                    config.differential_batch_size = parse_value(&arg, args.next())
+                }
+                "--bisect-divergence" => config.bisect_divergence = true,
+                "--trie-merge-op" => config.trie_merge_op = true,
+                "--tier-split" => config.tier_split = Some(parse_value(&arg, args.next())),
+                "--batch-sizes" => {
+                    let value: String = parse_value(&arg, args.next());
+                    config.batch_sizes = value
+                        .split(',')
+                        .map(|size| parse_value(&arg, Some(size.trim().to_string())))
+                        .collect();
+                }
+                "--count" => config.corpus_count = Some(parse_value(&arg, args.next())),
+                "--out" => config.corpus_out = Some(parse_value(&arg, args.next())),
+                "--keys-file" => config.keys_file = Some(parse_value(&arg, args.next())),
+                "--dump-writes" => config.dump_writes = Some(parse_value(&arg, args.next())),
+                "--emit-roots" => config.emit_roots = Some(parse_value(&arg, args.next())),
+                "--lineage" => config.lineage = Some(parse_value(&arg, args.next())),
+                // A write dump for verify-lineage, or the scan workload
+                "--workload" => {
+                    let value: String = parse_value(&arg, args.next());
+                    if value == "scan" {
+                        config.scan = true;
+                    } else {
+                        config.workload = Some(PathBuf::from(value));
+                    }
+                }
+                "--scan-sizes" => {
+                    let value: String = parse_value(&arg, args.next());
+                    config.scan_sizes = value
+                        .split(',')
+                        .map(|size| parse_value(&arg, Some(size.trim().to_string())))
+                        .collect();
+                }
+                "--scan-range-width" => config.scan_range_width = parse_value(&arg, args.next()),
+                "--iops-limit" => config.iops_limit = Some(parse_value(&arg, args.next())),
+                "--pin-core" => config.pin_core = Some(parse_value(&arg, args.next())),
+                "--pause-compaction" => {
+                    config.pause_compaction = Some(parse_value(&arg, args.next()))
+                }
+                "--drop-caches" => config.drop_caches = Some(parse_value(&arg, args.next())),
+                "--fault-rate" => {
+                    config.fault_rate = parse_value(&arg, args.next());
+                    if !(0.0..=1.0).contains(&config.fault_rate) {
+                        panic!("--fault-rate must be between 0 and 1");
+                    }
+                }
+                "--time-root" => config.time_root = true,
+                "--self-test" => config.self_test = true,
+                "--no-self-test" => config.self_test = false,
+                "--exact-leaf-limit" => config.exact_leaf_limit = parse_value(&arg, args.next()),
+                "--max-staged-bytes" => {
+                    config.max_staged_bytes = Some(parse_value(&arg, args.next()))
+                }
+                "--split-commits" => config.split_commits = true,
+                "--check-contract" => config.check_contract = true,
+                "--on-violation" => config.on_violation = parse_value(&arg, args.next()),
+                "--violations-out" => config.violations_out = Some(parse_value(&arg, args.next())),
+                "--value-sizes" => {
+                    let value: String = parse_value(&arg, args.next());
+                    config.value_sizes = value
+                        .split(',')
+                        .map(|size| parse_value(&arg, Some(size.trim().to_string())))
+                        .collect();
+                }
+                "--proof-mix" => {
+                    let value: String = parse_value(&arg, args.next());
+                    config.proof_mix = value
+                        .split(',')
+                        .map(|mix| parse_value(&arg, Some(mix.trim().to_string())))
+                        .collect();
+                }
+                "--verify-leaves" => config.verify_leaves = parse_value(&arg, args.next()),
+                "--assert-root-changes" => config.assert_root_changes = true,
+                "--max-writes-per-update" => {
+                    config.max_writes_per_update = Some(parse_value(&arg, args.next()))
+                }
+                "--rewrite-storm" => config.rewrite_storm = true,
+                "--sorted-keys" => config.sorted_keys = true,
+                "--rebuild-branches" => config.rebuild_branches = true,
+                "--growth-profile" => config.growth_profile = true,
+                "--root" => {
+                    let value: String = parse_value(&arg, args.next());
+                    config.proof_root =
+                        Some(parse_h256(&value).unwrap_or_else(|err| panic!("{}", err)));
+                }
+                "--value" => {
+                    let value: String = parse_value(&arg, args.next());
+                    config.proof_value =
+                        Some(parse_h256(&value).unwrap_or_else(|err| panic!("{}", err)));
+                }
+                "--checkpoints" => {
+                    let value: String = parse_value(&arg, args.next());
+                    config.growth_checkpoints = value
+                        .split(',')
+                        .map(|size| parse_value(&arg, Some(size.trim().to_string())))
+                        .collect();
+                }
+                "--growth-batch" => config.growth_batch = parse_value(&arg, args.next()),
+                "--baseline" => config.baseline = Some(parse_value(&arg, args.next())),
+                "--force" => config.force = true,
+                "--rebuild-chunk" => config.rebuild_chunk = parse_value(&arg, args.next()),
+                "--expect-root" => {
+                    let value: String = parse_value(&arg, args.next());
+                    config.expect_root =
+                        Some(parse_h256(&value).unwrap_or_else(|err| panic!("{}", err)));
+                }
+                "--replicate-to" => {
+                    let value: String = parse_value(&arg, args.next());
+                    config.replicate_to =
+                        Some(parse_target(&value).unwrap_or_else(|err| panic!("{}", err)));
+                }
+                "--profile-compare" => {
+                    let value: String = parse_value(&arg, args.next());
+                    config.profile_compare = value
+                        .split(',')
+                        .map(|p| p.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .unwrap_or_else(|err| panic!("{}", err));
+                    if config.profile_compare.len() < 2 {
+                        panic!("--profile-compare needs at least two profiles");
+                    }
+                }
+                "--tui" => {
+                    if !cfg!(feature = "tui") {
+                        panic!("--tui requires building with the tui feature");
+                    }
+                    config.tui = true;
+                }
+                "--serve" => {
+                    if !cfg!(feature = "remote") {
+                        panic!("--serve requires building with the remote feature");
+                    }
+                    config.serve = Some(parse_value(&arg, args.next()));
+                }
+                _ => panic!("Unknown argument: {}", arg),
+            }
+        }
+        if config.split_commits && config.max_staged_bytes.is_none() {
+            panic!("--split-commits requires --max-staged-bytes");
+        }
+        if let Some(path) = &config.rocksdb_options_file {
+            if !path.is_file() {
+                panic!("--rocksdb-options-file {} is not a file", path.display());
+            }
+        }
+        if config.tolerate && !config.guard_columns {
+            panic!("--tolerate requires --guard-columns");
+        }
+        if config.rewrite_storm {
+            let scheduled = config
+                .schedule
+                .iter()
+                .find_map(|change| match change.setting {
+                    Setting::BatchSize(size) if size > 256 => Some(size),
+                    _ => None,
+                });
+            if let Some(size) = scheduled {
+                panic!(
+                    "--rewrite-storm allows at most 256 keys per round, --at sets {}",
+                    size
+                );
+            }
+            // Only 256 keys differ in nothing but the lowest byte
+            if let Some(phase) = config.phases.iter().find(|p| {
+                matches!(p.kind, PhaseKind::Load | PhaseKind::Update) && p.batch_size > 256
+            }) {
+                panic!(
+                    "--rewrite-storm allows at most 256 keys per round, {} has more",
+                    phase
+                );
+            }
+            if let Some(sizes) = config.round_sizes {
+                if sizes.max().is_none_or(|max| max > 256) {
+                    panic!("--rewrite-storm allows at most 256 keys per round, --round-size-distribution can draw more");
+                }
+            }
+        }
+        if config.read_only && !config.profile_compare.is_empty() {
+            panic!("--read-only does not allow --profile-compare");
+        }
+        if config
+            .proof_mix
+            .iter()
+            .any(|mix| !(0.0..=1.0).contains(mix))
+        {
+            panic!("--proof-mix shares must be between 0 and 1");
+        }
+        if !(0.0..=1.0).contains(&config.conflict_overlap) {
+            panic!("--overlap must be between 0 and 1");
+        }
+        if config.determinism_check.is_some_and(|runs| runs < 2) {
+            panic!("--determinism-check needs at least 2 runs to compare");
+        }
+        if !(0.0..=1.0).contains(&config.crash_rate) {
+            panic!("--crash-rate must be between 0 and 1");
+        }
+        if config.grid_parallel == 0 {
+            panic!("--grid-parallel must be above 0");
+        }
+        if config.iops_limit == Some(0) {
+            panic!("--iops-limit must be above 0");
+        }
+        if config.rewrite_storm && config.keys_file.is_some() {
+            panic!("--rewrite-storm crafts its own keys, it does not allow --keys-file");
+        }
+        if config.sorted_keys && (config.rewrite_storm || config.keys_file.is_some()) {
+            panic!("--sorted-keys does not allow --rewrite-storm or --keys-file");
+        }
+        if config.suite.is_some() && !config.grid.is_empty() {
+            panic!("--suite does not allow --grid");
+        }
+        if config.history_retention.is_some() && !config.leaf_history {
+            panic!("--history-retention requires --leaf-history");
+        }
+        if config.command == Command::Repair && !config.rebuild_branches {
+            panic!("repair requires --rebuild-branches, the only repair there is");
+        }
+        if config.growth_checkpoints.is_empty()
+            || config.growth_checkpoints.windows(2).any(|w| w[0] >= w[1])
+        {
+            panic!("--checkpoints must be ascending tree sizes");
+        }
+        if config.batch_sizes.is_empty() || config.batch_sizes.contains(&0) {
+            panic!("--batch-sizes must be sizes above 0");
+        }
+        if config.growth_batch == 0 {
+            panic!("--growth-batch must be above 0");
+        }
+        if config.scan {
+            if config.command != Command::Bench || config.read_only {
+                panic!("--workload scan only runs with bench, without --read-only");
+            }
+            if config.scan_sizes.is_empty()
+                || config.scan_sizes[0] == 0
+                || config.scan_sizes.windows(2).any(|w| w[0] >= w[1])
+            {
+                panic!("--scan-sizes must be ascending tree sizes above 0");
+            }
+            if !(config.scan_range_width > 0.0 && config.scan_range_width <= 1.0) {
+                panic!(
+                    "--scan-range-width must be a share of the key space, above 0 and at most 1"
+                );
+            }
+        }
+        if config.init_chunk == Some(0) {
+            panic!("--init-chunk must be above 0");
+        }
+        if config.baseline.is_some() {
+            if !config.growth_profile {
+                panic!("--baseline only applies to --growth-profile");
+            }
+            let leaves = growth::final_leaves(&config);
+            if leaves > BASELINE_MAX_LEAVES && !config.force {
+                panic!(
+                    "--baseline recompute rebuilds the whole tree every round, {} leaves is above its limit of {}, --force runs it anyway",
+                    leaves, BASELINE_MAX_LEAVES
+                );
+            }
+        }
+        // A round is replayed from the leaves its transactions wrote, which
+        // only the dump records, and only under their full keys
+        if config.command == Command::Bench
+            && config.lineage.is_some()
+            && (config.dump_writes.is_none() || config.leaf_key_bytes.is_some())
+        {
+            panic!("--lineage requires --dump-writes and does not allow --leaf-key-bytes");
+        }
+        if config.command == Command::VerifyLineage
+            && (config.lineage.is_none() || config.workload.is_none())
+        {
+            panic!("verify-lineage requires --lineage and --workload");
+        }
+        if config.violations_out.is_some() && config.on_violation != OnViolation::Tally {
+            panic!("--violations-out requires --on-violation tally");
+        }
+        if config.proxy_pipeline == 0 {
+            panic!("--proxy-pipeline must be above 0");
+        }
+        if config.proxy_socket.is_some()
+            && (config.command != Command::Bench || config.read_only || config.experiment.is_some())
+        {
+            panic!(
+                "--store proxy only runs the phases of bench, without --read-only or --experiment"
+            );
+        }
+        if config.rmw_iterations == 0 {
+            panic!("--rmw-iterations must be above 0");
+        }
+        // Two rounds after the snapshot, or there is no other order
+        if config.reorder_rounds < 4 || config.reorder_batch_size == 0 {
+            panic!("--reorder-rounds must be at least 4 and --reorder-batch-size above 0");
+        }
+        if config.differential_rounds == 0 || config.differential_batch_size == 0 {
+            panic!("--differential-rounds and --differential-batch-size must be above 0");
+        }
+        if config.bisect_divergence && config.experiment != Some(Experiment::Differential) {
+            panic!("--bisect-divergence only works with --experiment differential");
+        }
+        // The operator materializes plain blobs
+        if config.trie_merge_op
+            && (config.experiment != Some(Experiment::Differential) || config.trie_molecule_wrap)
+        {
+            panic!("--trie-merge-op only works with --experiment differential, without --trie-molecule-wrap");
+        }
+        if (config.experiment == Some(Experiment::Tiered)) != config.tier_split.is_some() {
+            panic!("--experiment tiered and --tier-split go together");
+        }
+        if let Some(split) = &config.tier_split {
+            if split.fast == split.slow {
+                panic!("--tier-split needs two different paths");
+            }
+        }
+        if let Some(bytes) = config.leaf_key_bytes {
+            if !(1..LEAF_KEY_SIZE).contains(&bytes) {
+                panic!(
+                    "--leaf-key-bytes must be between 1 and {}",
+                    LEAF_KEY_SIZE - 1
+                );
+            }
+            // Everything else reads the leaf column as full keys
+            if config.command != Command::Bench
+                || config.store != StoreKind::Trie
+                || config.leaf_history
+                || config.strict_leaf
+                || config.read_only
+            {
+                panic!("--leaf-key-bytes only works with bench on the trie store, without --leaf-history, --strict-leaf or --read-only");
+            }
+        }
+        if config.read_only && config.growth_profile {
+            panic!("--read-only does not allow --growth-profile");
+        }
+        if config.rebuild_chunk == 0 {
+            panic!("--rebuild-chunk must be above 0");
+        }
+        if config.read_only && config.experiment.is_some() {
+            panic!("--read-only does not allow --experiment");
+        }
+        if config.read_only && config.final_compact {
+            panic!("--read-only does not allow --final-compact");
+        }
+        if config.read_only && config.disk_size {
+            panic!("--read-only does not allow --disk-size");
+        }
+        // The rebuilt key pool holds full keys, the phase counter must match
+        // the phases given, and the files and replica a run writes from its
+        // start cannot be continued
+        if config.resume
+            && (config.read_only
+                || config.leaf_key_bytes.is_some()
+                || config.compact_after_deletes
+                || config.drop_caches == Some(DropCaches::BeforeReads)
+                || config.replicate_to.is_some()
+                || config.lineage.is_some())
+        {
+            panic!("--resume does not allow --read-only, --leaf-key-bytes, --compact-after-deletes, --drop-caches before-reads, --replicate-to or --lineage");
+        }
+        if config.read_only {
+            if !matches!(
+                config.command,
+                Command::Bench | Command::Dot | Command::Path
+            ) {
+                panic!("--read-only only runs bench, dot and path");
+            }
+            if !phases_given {
+                config.phases = vec![Phase::new(PhaseKind::Read, 1, 1000)];
+            }
+            if let Some(phase) = config
+                .phases
+                .iter()
+                .find(|p| !matches!(p.kind, PhaseKind::Read | PhaseKind::ProveSame))
+            {
+                panic!(
+                    "--read-only only runs read and prove-same phases, not {}",
+                    phase
+                );
+            }
+            // The read-only bench has no runner to feed these
+            if config.tui
+                || config.leaf_history
+                || config.replicate_to.is_some()
+                || config.dump_writes.is_some()
+                || config.emit_roots.is_some()
+                || config.lineage.is_some()
+            {
+                panic!("--read-only does not allow --tui, --leaf-history, --replicate-to, --dump-writes, --emit-roots or --lineage");
+            }
+            // Nothing is written into the node's directory
+            if config.output == OutputFormat::Binary && config.results_out.is_none() {
+                panic!("--read-only with --output binary needs --results-out");
+            }
+        }
+        config
+    }
+}
+
+// An axis of --grid, an option name without its dashes and the values it
+// takes, e.g. seed=0,1
+fn parse_axis(s: &str) -> Result<(String, Vec<String>), String> {
+    let (name, values) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid grid axis {}, expected option=value,...", s))?;
+    let values: Vec<String> = values.split(',').map(|v| v.trim().to_string()).collect();
+    if name.is_empty() || values.iter().any(|v| v.is_empty()) {
+        return Err(format!("Invalid grid axis {}", s));
+    }
+    Ok((name.to_string(), values))
+}
+
+fn parse_value<T>(name: &str, value: Option<String>) -> T
+where
+    T: FromStr,
+    T::Err: Debug,
+{
+    let value = value.unwrap_or_else(|| panic!("{} requires a value", name));
+    value
+        .parse()
+        .unwrap_or_else(|err| panic!("Invalid value {} for {}: {:?}", value, name, err))
+}
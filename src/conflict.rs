@@ -0,0 +1,258 @@
+use crate::{churn, config::Config, random_h256, seed::SeedTree, trie::TrieStore, TrieSmt};
+use gw_store::{transaction::StoreTransaction, Store as GwStore};
+use rand_chacha::{rand_core::RngCore, ChaCha20Rng};
+use sparse_merkle_tree::H256;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Keys each writer draws its updates from
+const KEYS_PER_WRITER: usize = 10_000;
+// Attempts a batch gets before its writer gives it up
+const MAX_ATTEMPTS: usize = 100;
+
+// The tree every writer commits to. gw_db's optimistic transactions only
+// refuse a commit when a key the transaction wrote changed after it first
+// touched it, which does not cover a tree built on a root another writer
+// replaced meanwhile, so writers also compare the version they started at.
+struct Shared {
+    root: H256,
+    // Bumped by every commit
+    version: u64,
+    // Value of every key as of the last commit, to validate the tree
+    values: BTreeMap<H256, H256>,
+}
+
+#[derive(Default)]
+struct WriterStats {
+    batches: usize,
+    keys: usize,
+    // Batches recomputed because another writer committed first
+    stale: usize,
+    // Commits gw_db refused, and the distinct errors it returned
+    refused: usize,
+    errors: Vec<String>,
+    // Batches given up after MAX_ATTEMPTS
+    failed: usize,
+}
+
+impl WriterStats {
+    fn record(&mut self, error: String) {
+        if !self.errors.contains(&error) {
+            self.errors.push(error);
+        }
+    }
+
+    fn add(&mut self, other: WriterStats) {
+        self.batches += other.batches;
+        self.keys += other.keys;
+        self.stale += other.stale;
+        self.refused += other.refused;
+        self.failed += other.failed;
+        for error in other.errors {
+            self.record(error);
+        }
+    }
+}
+
+fn trie_store<'a>(config: &Config, tx: &'a StoreTransaction) -> TrieStore<'a, StoreTransaction> {
+    TrieStore::with_layout(tx, config.trie_layout)
+        .with_columns(config.branch_col, config.leaf_col)
+        .molecule_wrap(config.trie_molecule_wrap)
+        .truncate_leaf_keys(config.leaf_key_bytes)
+}
+
+// Commits batches of updates to keys drawn from keys, retrying each batch
+// on a fresh transaction until it lands on the current root
+fn write(
+    config: &Config,
+    store: &GwStore,
+    shared: &Mutex<Shared>,
+    keys: &[H256],
+    batches: usize,
+    mut rng: ChaCha20Rng,
+) -> WriterStats {
+    let mut stats = WriterStats::default();
+    for _ in 0..batches {
+        // One value per key, update_all keeps an arbitrary one of duplicates
+        let pairs: Vec<(H256, H256)> = (0..config.conflict_batch_size)
+            .map(|_| {
+                let key = keys[rng.next_u64() as usize % keys.len()];
+                (key, random_h256(&mut rng))
+            })
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .collect();
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            if attempts > MAX_ATTEMPTS {
+                stats.failed += 1;
+                break;
+            }
+            let (root, version) = {
+                let shared = shared.lock().unwrap();
+                (shared.root, shared.version)
+            };
+            let tx = store.begin_transaction();
+            let mut smt = TrieSmt::new(root, trie_store(config, &tx));
+            let root = match smt.update_all(pairs.clone()) {
+                Ok(root) => *root,
+                Err(err) => {
+                    stats.record(err.to_string());
+                    continue;
+                }
+            };
+            let mut shared = shared.lock().unwrap();
+            if shared.version != version {
+                stats.stale += 1;
+                continue;
+            }
+            match tx.commit() {
+                Ok(()) => {
+                    shared.root = root;
+                    shared.version += 1;
+                    shared.values.extend(pairs.iter().cloned());
+                    stats.batches += 1;
+                    stats.keys += pairs.len();
+                    break;
+                }
+                Err(err) => {
+                    stats.refused += 1;
+                    stats.record(err.to_string());
+                }
+            }
+        }
+    }
+    stats
+}
+
+fn print_writers(label: &str, stats: &WriterStats, elapsed: Duration) {
+    println!(
+        "  {}: {} batches ({} keys) in {:?}, {:.0} keys/s, {} retries on a stale root, {} commits refused by gw_db, {} batches given up",
+        label,
+        stats.batches,
+        stats.keys,
+        elapsed,
+        stats.keys as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        stats.stale,
+        stats.refused,
+        stats.failed
+    );
+    for error in &stats.errors {
+        println!("    Error: {}", error);
+    }
+}
+
+// The committed root must be the one a fresh tree of the committed values
+// gets, and every committed value must read back under it
+fn validate(config: &Config, store: &GwStore, shared: &Shared) -> Result<(), String> {
+    let map_err = |err: sparse_merkle_tree::error::Error| err.to_string();
+    let pairs: Vec<(H256, H256)> = shared
+        .values
+        .iter()
+        .map(|(key, value)| (*key, *value))
+        .collect();
+    let fresh = GwStore::open_tmp().map_err(|err| err.to_string())?;
+    let fresh_tx = fresh.begin_transaction();
+    let mut rebuilt = TrieSmt::new(H256::zero(), trie_store(config, &fresh_tx));
+    rebuilt.update_all(pairs.clone()).map_err(map_err)?;
+    if *rebuilt.root() != shared.root {
+        return Err(format!(
+            "root {:?} differs from {:?} rebuilt from the {} committed keys",
+            shared.root,
+            rebuilt.root(),
+            pairs.len()
+        ));
+    }
+    let tx = store.begin_transaction();
+    let smt = TrieSmt::new(shared.root, trie_store(config, &tx));
+    for (key, value) in pairs {
+        let actual = smt.get(&key).map_err(map_err)?;
+        if actual != value {
+            return Err(format!(
+                "key {:?} reads {:?}, {:?} was committed",
+                key, actual, value
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Runs the same amount of updates once with a single writer and once split
+// between two threads committing concurrently, with --overlap of their key
+// ranges shared, then validates the tree the commits left.
+pub fn run(config: &Config, store: &GwStore) {
+    churn::require_empty(config, store, "--experiment write-conflict");
+    let seeds = SeedTree::new(config.seed, config.verbose).child("write-conflict");
+    let shared_keys = (KEYS_PER_WRITER as f64 * config.conflict_overlap).round() as usize;
+    let mut rng = seeds.child("keys").rng();
+    let keys: Vec<H256> = (0..2 * KEYS_PER_WRITER - shared_keys)
+        .map(|_| random_h256(&mut rng))
+        .collect();
+    let ranges = [
+        &keys[..KEYS_PER_WRITER],
+        &keys[KEYS_PER_WRITER - shared_keys..],
+    ];
+    println!(
+        "Write conflict: 2 writers of {} batches of {} keys, {} keys each, {} shared",
+        config.conflict_batches, config.conflict_batch_size, KEYS_PER_WRITER, shared_keys
+    );
+
+    let shared = Mutex::new(Shared {
+        root: H256::zero(),
+        version: 0,
+        values: BTreeMap::new(),
+    });
+    let start = Instant::now();
+    let single = write(
+        config,
+        store,
+        &shared,
+        &keys,
+        2 * config.conflict_batches,
+        seeds.child("single").rng(),
+    );
+    let single_elapsed = start.elapsed();
+    print_writers("Single writer", &single, single_elapsed);
+
+    let start = Instant::now();
+    let mut concurrent = WriterStats::default();
+    thread::scope(|scope| {
+        let writers: Vec<_> = ranges
+            .iter()
+            .enumerate()
+            .map(|(i, range)| {
+                let rng = seeds.child(&format!("writer-{}", i)).rng();
+                let shared = &shared;
+                scope.spawn(move || {
+                    write(config, store, shared, range, config.conflict_batches, rng)
+                })
+            })
+            .collect();
+        for writer in writers {
+            concurrent.add(writer.join().unwrap());
+        }
+    });
+    let concurrent_elapsed = start.elapsed();
+    print_writers("Two writers", &concurrent, concurrent_elapsed);
+    let throughput = |stats: &WriterStats, elapsed: Duration| {
+        stats.keys as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+    };
+    println!(
+        "  Combined throughput: {:.2}x the single writer",
+        throughput(&concurrent, concurrent_elapsed)
+            / throughput(&single, single_elapsed).max(f64::EPSILON)
+    );
+
+    let shared = shared.into_inner().unwrap();
+    if let Err(err) = validate(config, store, &shared) {
+        panic!("Write conflict left an inconsistent tree: {}", err);
+    }
+    println!(
+        "  Consistency: root {:?} matches a rebuild of the {} committed keys, all read back",
+        shared.root,
+        shared.values.len()
+    );
+}
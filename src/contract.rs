@@ -0,0 +1,316 @@
+use crate::violations::{self, Monitor};
+use sparse_merkle_tree::{
+    error::Error as SMTError,
+    traits::Store,
+    tree::{BranchKey, BranchNode},
+    H256,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::rc::Rc;
+
+// What a store must return for every key it has been asked about, None
+// meaning the key was removed or never written.
+#[derive(Default)]
+pub struct ContractModel {
+    branches: HashMap<BranchKey, Option<BranchNode>>,
+    leaves: HashMap<H256, Option<H256>>,
+    // Every write to the database went through the model, so a key it does
+    // not know must be absent. Otherwise the first read of such a key is
+    // taken as its value.
+    complete: bool,
+}
+
+impl ContractModel {
+    pub fn new(complete: bool) -> Self {
+        Self {
+            complete,
+            ..Default::default()
+        }
+    }
+
+    fn merge(&mut self, other: ContractModel) {
+        self.branches.extend(other.branches);
+        self.leaves.extend(other.leaves);
+    }
+}
+
+// Store decorator shadowing every write in a ContractModel and panicking as
+// soon as a read disagrees with it, enabled with --check-contract, or
+// raising the violation with its monitor when it has one. Writes of
+// the transaction are kept apart until commit_model, so a rolled back
+// transaction does not change the model.
+pub struct ContractCheckStore<S> {
+    inner: S,
+    model: Option<Rc<RefCell<ContractModel>>>,
+    pending: RefCell<ContractModel>,
+    monitor: Option<Rc<Monitor>>,
+}
+
+impl<S: Store<H256>> ContractCheckStore<S> {
+    // A None model passes every call through unchecked
+    pub fn new(inner: S, model: Option<Rc<RefCell<ContractModel>>>) -> Self {
+        Self {
+            inner,
+            model,
+            pending: RefCell::default(),
+            monitor: None,
+        }
+    }
+
+    pub fn with_monitor(mut self, monitor: Rc<Monitor>) -> Self {
+        self.monitor = Some(monitor);
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    // Makes the writes of the transaction part of the model, called when it
+    // commits
+    pub fn commit_model(&self) {
+        if let Some(model) = &self.model {
+            model.borrow_mut().merge(self.pending.take());
+        }
+    }
+}
+
+// Compares a read against the pending writes, then the model, learning the
+// value when neither knows the key and the model is not complete. A
+// mismatch leaves the model as it was, the store being the one in error.
+fn check_read<K, V>(
+    kind: &str,
+    key: &K,
+    actual: &Option<V>,
+    pending: &mut HashMap<K, Option<V>>,
+    model: &HashMap<K, Option<V>>,
+    complete: bool,
+    monitor: Option<&Monitor>,
+) where
+    K: Clone + Debug + Eq + Hash,
+    V: Clone + Debug + PartialEq,
+{
+    let expected = match pending.get(key).or_else(|| model.get(key)) {
+        Some(expected) => expected.clone(),
+        None if complete => None,
+        None => {
+            pending.insert(key.clone(), actual.clone());
+            return;
+        }
+    };
+    if *actual != expected {
+        let details = format!(
+            "Store contract violated: {} {:?} read back {:?}, expected {:?}",
+            kind, key, actual, expected
+        );
+        match monitor {
+            Some(monitor) => {
+                monitor.raise(violations::CONTRACT, Some(format!("{:?}", key)), details)
+            }
+            None => panic!("{}", details),
+        }
+    }
+}
+
+impl<S: Store<H256>> Store<H256> for ContractCheckStore<S> {
+    fn get_branch(&self, branch_key: &BranchKey) -> Result<Option<BranchNode>, SMTError> {
+        let branch = self.inner.get_branch(branch_key)?;
+        if let Some(model) = &self.model {
+            let model = model.borrow();
+            check_read(
+                "branch",
+                branch_key,
+                &branch,
+                &mut self.pending.borrow_mut().branches,
+                &model.branches,
+                model.complete,
+                self.monitor.as_deref(),
+            );
+        }
+        Ok(branch)
+    }
+
+    fn get_leaf(&self, leaf_key: &H256) -> Result<Option<H256>, SMTError> {
+        let leaf = self.inner.get_leaf(leaf_key)?;
+        if let Some(model) = &self.model {
+            let model = model.borrow();
+            check_read(
+                "leaf",
+                leaf_key,
+                &leaf,
+                &mut self.pending.borrow_mut().leaves,
+                &model.leaves,
+                model.complete,
+                self.monitor.as_deref(),
+            );
+        }
+        Ok(leaf)
+    }
+
+    fn insert_branch(&mut self, branch_key: BranchKey, branch: BranchNode) -> Result<(), SMTError> {
+        if self.model.is_some() {
+            self.inner
+                .insert_branch(branch_key.clone(), branch.clone())?;
+            self.pending
+                .get_mut()
+                .branches
+                .insert(branch_key, Some(branch));
+            return Ok(());
+        }
+        self.inner.insert_branch(branch_key, branch)
+    }
+
+    fn insert_leaf(&mut self, leaf_key: H256, leaf: H256) -> Result<(), SMTError> {
+        self.inner.insert_leaf(leaf_key, leaf)?;
+        if self.model.is_some() {
+            self.pending.get_mut().leaves.insert(leaf_key, Some(leaf));
+        }
+        Ok(())
+    }
+
+    fn remove_branch(&mut self, branch_key: &BranchKey) -> Result<(), SMTError> {
+        self.inner.remove_branch(branch_key)?;
+        if self.model.is_some() {
+            self.pending
+                .get_mut()
+                .branches
+                .insert(branch_key.clone(), None);
+        }
+        Ok(())
+    }
+
+    fn remove_leaf(&mut self, leaf_key: &H256) -> Result<(), SMTError> {
+        self.inner.remove_leaf(leaf_key)?;
+        if self.model.is_some() {
+            self.pending.get_mut().leaves.insert(*leaf_key, None);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        old::CountingStore,
+        random_h256,
+        seed::SeedTree,
+        tiered::{Tier, TieredStore},
+        trie::{TrieLayout, TrieStore},
+    };
+    use gw_store::Store as GwStore;
+    use sparse_merkle_tree::{blake2b::Blake2bHasher, SparseMerkleTree};
+    use std::panic::{self, AssertUnwindSafe};
+
+    // Every backend, run through ContractCheckStore, must read back exactly
+    // what was written over several committed rounds of inserts, overwrites and
+    // removals, including keys it never held.
+    #[test]
+    fn store_contract() -> Result<(), String> {
+        let mut rng = SeedTree::new(0, false).child("check-store-contract").rng();
+        let pool: Vec<H256> = (0..40).map(|_| random_h256(&mut rng)).collect();
+        let rounds: Vec<Vec<(H256, H256)>> = (0..4)
+            .map(|round| {
+                pool.iter()
+                    .enumerate()
+                    .filter(|(i, _)| (i + round) % 3 != 0)
+                    .map(|(i, key)| {
+                        let value = if (i + round) % 4 == 0 {
+                            H256::zero()
+                        } else {
+                            random_h256(&mut rng)
+                        };
+                        (*key, value)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut backends: Vec<String> = TrieLayout::ALL
+            .iter()
+            .map(|layout| format!("{:?} TrieStore", layout))
+            .collect();
+        backends.push("CountingStore".to_string());
+        // Branches of heights below 200 on a store of their own
+        backends.push("tiered TrieStore".to_string());
+        let mut tier_puts = [0; 2];
+        for (i, backend) in backends.iter().enumerate() {
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let slow = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let model = Rc::new(RefCell::new(ContractModel::new(true)));
+            let mut root = H256::zero();
+            for pairs in &rounds {
+                let tx = store.begin_transaction();
+                let slow_tx = slow.begin_transaction();
+                let result =
+                    panic::catch_unwind(AssertUnwindSafe(|| match TrieLayout::ALL.get(i) {
+                        Some(layout) => {
+                            let trie = TrieStore::with_layout(&tx, *layout);
+                            contract_round(
+                                ContractCheckStore::new(trie, Some(model.clone())),
+                                root,
+                                pairs,
+                            )
+                        }
+                        None if i == TrieLayout::ALL.len() => {
+                            let counting = CountingStore::new(&tx);
+                            contract_round(
+                                ContractCheckStore::new(counting, Some(model.clone())),
+                                root,
+                                pairs,
+                            )
+                        }
+                        None => {
+                            let tiered = TieredStore::new(&tx, &slow_tx, 0, 200);
+                            let root = contract_round(
+                                ContractCheckStore::new(
+                                    TrieStore::new(&tiered),
+                                    Some(model.clone()),
+                                ),
+                                root,
+                                pairs,
+                            );
+                            tier_puts[0] += tiered.stats(Tier::Fast).puts;
+                            tier_puts[1] += tiered.stats(Tier::Slow).puts;
+                            root
+                        }
+                    }));
+                root = match result {
+                    Ok(result) => result.map_err(|err| format!("{}: {}", backend, err))?,
+                    Err(_) => return Err(format!("{} violated the store contract", backend)),
+                };
+                tx.commit().map_err(|err| err.to_string())?;
+                slow_tx.commit().map_err(|err| err.to_string())?;
+            }
+        }
+        if tier_puts.contains(&0) {
+            return Err(format!(
+                "tiered TrieStore wrote {:?} blobs to its fast and slow tiers, both must be used",
+                tier_puts
+            ));
+        }
+        Ok(())
+    }
+
+    fn contract_round<S: Store<H256>>(
+        store: ContractCheckStore<S>,
+        root: H256,
+        pairs: &[(H256, H256)],
+    ) -> Result<H256, String> {
+        let map_err = |err: sparse_merkle_tree::error::Error| err.to_string();
+        let mut smt: SparseMerkleTree<Blake2bHasher, H256, _> = SparseMerkleTree::new(root, store);
+        smt.update_all(pairs.to_vec()).map_err(map_err)?;
+        for (key, value) in pairs {
+            if smt.get(key).map_err(map_err)? != *value {
+                return Err(format!("leaf {:?} lost", key));
+            }
+        }
+        let keys: Vec<H256> = pairs.iter().map(|(key, _)| *key).collect();
+        smt.merkle_proof(keys).map_err(map_err)?;
+        smt.store().commit_model();
+        Ok(*smt.root())
+    }
+}
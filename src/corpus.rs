@@ -0,0 +1,111 @@
+use crate::{config::Config, runner::random_pairs, seed::SeedTree};
+use sparse_merkle_tree::H256;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// A corpus file is MAGIC, the pair count and the seed as little endian
+// u64s, then every pair as its 32 key bytes followed by its 32 value bytes
+const MAGIC: &[u8; 8] = b"SMTCORP1";
+const HEADER_SIZE: usize = MAGIC.len() + 8 + 8;
+const PAIR_SIZE: usize = 64;
+// Pairs generated and written at a time
+const CHUNK: usize = 100_000;
+
+// Writes --count pairs drawn from --seed to --out, the same stream of keys
+// and values for the same seed and --key-preimage-size
+pub fn generate(config: &Config) -> io::Result<()> {
+    let path = config
+        .corpus_out
+        .as_ref()
+        .expect("gen-corpus requires --out");
+    let count = config.corpus_count.expect("gen-corpus requires --count");
+    let mut rng = SeedTree::new(config.seed, config.verbose)
+        .child("corpus")
+        .rng();
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&(count as u64).to_le_bytes())?;
+    out.write_all(&config.seed.to_le_bytes())?;
+    let mut written = 0;
+    while written < count {
+        let chunk = CHUNK.min(count - written);
+        let (pairs, _) = random_pairs(&mut rng, chunk, config.key_preimage_size);
+        for (key, value) in pairs {
+            out.write_all(key.as_slice())?;
+            out.write_all(value.as_slice())?;
+        }
+        written += chunk;
+    }
+    out.flush()?;
+    println!(
+        "Wrote {} pairs of seed {} to {}",
+        count,
+        config.seed,
+        path.display()
+    );
+    Ok(())
+}
+
+// Pairs of a corpus file, handed out in order by the load and update rounds
+// of --keys-file
+pub struct Corpus {
+    data: Vec<u8>,
+    pub count: usize,
+    pub seed: u64,
+    next: usize,
+}
+
+impl Corpus {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut data =
+            fs::read(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+        if data.len() < HEADER_SIZE || &data[..MAGIC.len()] != MAGIC {
+            return Err(format!("{} is not a corpus file", path.display()));
+        }
+        let u64_at = |offset: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&data[offset..offset + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let count = u64_at(MAGIC.len()) as usize;
+        let seed = u64_at(MAGIC.len() + 8);
+        let pairs_size = data.len() - HEADER_SIZE;
+        if pairs_size != count * PAIR_SIZE {
+            return Err(format!(
+                "{} declares {} pairs but holds {} bytes of pairs, truncated or corrupted",
+                path.display(),
+                count,
+                pairs_size
+            ));
+        }
+        data.drain(..HEADER_SIZE);
+        Ok(Self {
+            data,
+            count,
+            seed,
+            next: 0,
+        })
+    }
+
+    // The next count pairs, panicking when the corpus runs out
+    pub fn take(&mut self, count: usize) -> Vec<(H256, H256)> {
+        if self.next + count > self.count {
+            panic!(
+                "--keys-file holds {} pairs, the run needs more than that",
+                self.count
+            );
+        }
+        let h256 = |bytes: &[u8]| {
+            let mut buf = [0u8; 32];
+            buf.copy_from_slice(bytes);
+            H256::from(buf)
+        };
+        let pairs = self.data[self.next * PAIR_SIZE..(self.next + count) * PAIR_SIZE]
+            .chunks(PAIR_SIZE)
+            .map(|pair| (h256(&pair[..32]), h256(&pair[32..])))
+            .collect();
+        self.next += count;
+        pairs
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{
+    config::Config,
+    handles,
+    phase::parse_phases,
+    runner::{RoundReport, Runner},
+    seed::SeedTree,
+};
+use gw_store::Store as GwStore;
+use sparse_merkle_tree::H256;
+
+// Phases of every run, small enough to repeat a few times in a row
+const SMOKE_PHASES: &str = "load:2000;update:5x500;delete:2x200;read:2x200;prove-update:2x20";
+
+// Every field of a round that follows from the configuration and the seed
+// alone. Timings, and the replica and compaction figures that depend on
+// them, are left out.
+fn counters(round: &RoundReport) -> Vec<(&'static str, String)> {
+    vec![
+        ("ops", round.ops.to_string()),
+        ("reads", round.reads.to_string()),
+        ("writes", round.writes.to_string()),
+        ("written_bytes", round.written_bytes.to_string()),
+        ("history_reads", round.history_reads.to_string()),
+        ("history_writes", round.history_writes.to_string()),
+        ("history_bytes", round.history_bytes.to_string()),
+        ("faults", round.faults.to_string()),
+        ("rolled_back", round.rolled_back.to_string()),
+        ("failed_ops", round.failed_ops.to_string()),
+        ("leaves", round.leaves.to_string()),
+        ("staged_bytes", round.staged_bytes.to_string()),
+        ("commits", round.commits.to_string()),
+        ("storm_writes", round.storm_writes.to_string()),
+        ("storm_bytes", round.storm_bytes.to_string()),
+        ("strict_violations", round.strict_violations.to_string()),
+        ("conversions", round.conversions.to_string()),
+        ("converted_bytes", round.converted_bytes.to_string()),
+        ("proof_reads", round.proof_reads.to_string()),
+        ("reproof_reads", round.reproof_reads.to_string()),
+        ("physical_ops", round.physical_ops.to_string()),
+        ("proof_sizes", format!("{:?}", round.proof_sizes)),
+    ]
+}
+
+struct Run {
+    rounds: Vec<Vec<RoundReport>>,
+    // Root after every phase
+    roots: Vec<H256>,
+}
+
+fn run_once(config: &Config) -> Run {
+    let store = handles::track(handles::STORE, GwStore::open_tmp().unwrap());
+    let mut runner = Runner::new(config, &store, SeedTree::new(config.seed, false));
+    let mut run = Run {
+        rounds: vec![],
+        roots: vec![],
+    };
+    for phase in &config.phases {
+        run.rounds.push(runner.run_phase(phase).rounds);
+        run.roots.push(runner.root());
+    }
+    runner.finish();
+    run
+}
+
+// The first difference of run from expected, described
+fn first_difference(config: &Config, expected: &Run, run: &Run) -> Option<String> {
+    for (p, phase) in config.phases.iter().enumerate() {
+        let (expected_rounds, rounds) = (&expected.rounds[p], &run.rounds[p]);
+        if expected_rounds.len() != rounds.len() {
+            return Some(format!(
+                "phase {} ran {} rounds instead of {}",
+                phase,
+                rounds.len(),
+                expected_rounds.len()
+            ));
+        }
+        for (r, (expected_round, round)) in expected_rounds.iter().zip(rounds).enumerate() {
+            for ((field, expected_value), (_, value)) in
+                counters(expected_round).into_iter().zip(counters(round))
+            {
+                if expected_value != value {
+                    return Some(format!(
+                        "phase {} round {}: {} is {}, the first run had {}",
+                        phase,
+                        r + 1,
+                        field,
+                        value,
+                        expected_value
+                    ));
+                }
+            }
+        }
+        if expected.roots[p] != run.roots[p] {
+            return Some(format!(
+                "phase {}: root {:?}, the first run had {:?}",
+                phase, run.roots[p], expected.roots[p]
+            ));
+        }
+    }
+    None
+}
+
+// With --debug-handles, a run must leave no transaction or store alive and
+// never open a transaction over a live one
+fn check_handles(run: usize) {
+    if handles::enabled() && !handles::report(&format!("after run {}", run)) {
+        eprintln!("Run {} leaked handles", run);
+        std::process::exit(1);
+    }
+}
+
+// Runs the smoke phases runs times, each against a fresh temporary
+// database, and compares every counter of every round and the root after
+// every phase with the first run. Exits with an error on the first
+// difference.
+pub fn run(config: &Config, runs: usize) {
+    let config = Config {
+        phases: parse_phases(SMOKE_PHASES).unwrap(),
+        report_every: 0,
+        tui: false,
+        verbose: false,
+        guard_columns: true,
+        ..config.clone()
+    };
+    println!(
+        "Determinism check: {} runs of {} with seed {}",
+        runs, SMOKE_PHASES, config.seed
+    );
+    let expected = run_once(&config);
+    check_handles(1);
+    for i in 2..=runs {
+        let run = run_once(&config);
+        check_handles(i);
+        if let Some(difference) = first_difference(&config, &expected, &run) {
+            eprintln!("Run {} differs from run 1: {}", i, difference);
+            std::process::exit(1);
+        }
+        println!("  Run {} matches run 1", i);
+    }
+    println!(
+        "  All {} runs match: {} rounds, final root {:?}",
+        runs,
+        expected.rounds.iter().map(|r| r.len()).sum::<usize>(),
+        expected.roots.last().unwrap()
+    );
+}
@@ -0,0 +1,427 @@
+use crate::{
+    batching::{measure, open, Measurement},
+    config::{Config, StoreKind},
+    dot::hex,
+    merge_op::{self, MergeOpDb},
+    old::CountingStore,
+    path, random_h256,
+    reorder::Snapshot,
+    seed::SeedTree,
+    trie::TrieStore,
+};
+use rand_chacha::rand_core::RngCore;
+use sparse_merkle_tree::{traits::Store, tree::BranchKey, H256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Keys of the last round whose paths are read back from every database
+const READ_KEYS: usize = 100;
+
+// How a side writes its tree
+#[derive(Clone, Copy)]
+enum Writer {
+    // Through a store on gw_db, rewriting whole TrieStore blobs or single
+    // CountingStore branches
+    Store(StoreKind),
+    // Through a TrieStore writing slot operands, see merge_op
+    TrieMergeOp,
+}
+
+// One of the databases the rounds are applied to, and what applying them
+// cost so far
+struct Side {
+    writer: Writer,
+    name: &'static str,
+    path: PathBuf,
+    root: H256,
+    // The database before the current round, with --bisect-divergence
+    snapshot: Option<Snapshot>,
+    elapsed: Duration,
+    reads: usize,
+    writes: usize,
+    written_bytes: usize,
+    // Reading the paths of READ_KEYS keys after the last round
+    read_elapsed: Duration,
+}
+
+impl Side {
+    fn new(writer: Writer, name: &'static str, dir: &Path) -> Self {
+        Self {
+            writer,
+            name,
+            path: dir.join(name),
+            root: H256::zero(),
+            snapshot: None,
+            elapsed: Duration::default(),
+            reads: 0,
+            writes: 0,
+            written_bytes: 0,
+            read_elapsed: Duration::default(),
+        }
+    }
+
+    fn measure(&self, config: &Config, root: H256, pairs: &[(H256, H256)]) -> Measurement {
+        match self.writer {
+            Writer::Store(kind) => measure(config, kind, &self.path, root, pairs.to_vec(), true),
+            Writer::TrieMergeOp => merge_op::measure(config, &self.path, root, pairs.to_vec()),
+        }
+    }
+
+    fn apply(&mut self, config: &Config, pairs: &[(H256, H256)]) {
+        let measured = self.measure(config, self.root, pairs);
+        self.root = measured.root;
+        self.elapsed += measured.elapsed;
+        self.reads += measured.reads;
+        self.writes += measured.writes;
+        self.written_bytes += measured.written_bytes;
+    }
+
+    // Puts the database back to the snapshot before the round and applies
+    // pairs on the root it had then, returning the new root
+    fn replay(&self, config: &Config, root: H256, pairs: &[(H256, H256)]) -> H256 {
+        self.snapshot
+            .as_ref()
+            .expect("replaying a round needs its snapshot")
+            .restore(&self.path);
+        self.measure(config, root, pairs).root
+    }
+
+    // Opens the database and hands f the store the side writes it with
+    fn read<R>(&self, config: &Config, f: impl FnOnce(&dyn Store<H256>) -> R) -> R {
+        match self.writer {
+            Writer::Store(kind) => {
+                let db = open(config, &self.path);
+                let tx = db.begin_transaction();
+                match kind {
+                    StoreKind::Trie => f(&TrieStore::with_layout(&tx, config.trie_layout)
+                        .with_columns(config.branch_col, config.leaf_col)
+                        .molecule_wrap(config.trie_molecule_wrap)
+                        .truncate_leaf_keys(config.leaf_key_bytes)),
+                    StoreKind::Counting => f(
+                        &CountingStore::new(&tx).with_columns(config.branch_col, config.leaf_col)
+                    ),
+                }
+            }
+            Writer::TrieMergeOp => {
+                let db = MergeOpDb::open(config, &self.path);
+                let tx = db.begin_transaction();
+                f(&merge_op::trie_store(config, &tx))
+            }
+        }
+    }
+}
+
+// Reads every branch along the path of each key and its leaf, what a proof
+// of the key reads, timed
+fn time_reads(store: &dyn Store<H256>, keys: &[H256]) -> Duration {
+    let start = Instant::now();
+    for key in keys {
+        for height in (0..=u8::MAX).rev() {
+            store
+                .get_branch(&BranchKey::new(height, key.parent_path(height)))
+                .unwrap();
+        }
+        store.get_leaf(key).unwrap();
+    }
+    start.elapsed()
+}
+
+// The --differential-rounds rounds of --differential-batch-size pairs. The
+// first round inserts fresh keys, later ones insert fresh keys, update
+// keys written before and delete others, a third each, and no key is
+// written twice in a round.
+fn workload(config: &Config, seeds: &SeedTree) -> Vec<Vec<(H256, H256)>> {
+    let mut rng = seeds.child("workload").rng();
+    let mut written = vec![];
+    (0..config.differential_rounds)
+        .map(|_| {
+            let mut kept = vec![];
+            let pairs = (0..config.differential_batch_size)
+                .map(|_| {
+                    let pick = rng.next_u64() % 3;
+                    if pick == 0 || written.is_empty() {
+                        let key = random_h256(&mut rng);
+                        kept.push(key);
+                        return (key, random_h256(&mut rng));
+                    }
+                    let i = rng.next_u64() as usize % written.len();
+                    let key = written.swap_remove(i);
+                    if pick == 1 {
+                        kept.push(key);
+                        (key, random_h256(&mut rng))
+                    } else {
+                        (key, H256::zero())
+                    }
+                })
+                .collect();
+            written.extend(kept);
+            pairs
+        })
+        .collect()
+}
+
+// Narrows pairs, which diverge, down to a subset that still diverges and
+// none of whose pairs can be left out: keeps the half that diverges on its
+// own while either does, then leaves out one pair at a time
+pub(crate) fn narrow(
+    pairs: &[(H256, H256)],
+    mut diverges: impl FnMut(&[(H256, H256)]) -> bool,
+) -> Vec<(H256, H256)> {
+    let mut culprit = pairs.to_vec();
+    while culprit.len() > 1 {
+        let (first, second) = culprit.split_at(culprit.len() / 2);
+        if diverges(first) {
+            culprit = first.to_vec();
+        } else if diverges(second) {
+            culprit = second.to_vec();
+        } else {
+            break;
+        }
+    }
+    let mut i = 0;
+    while culprit.len() > 1 && i < culprit.len() {
+        let mut without = culprit.clone();
+        without.remove(i);
+        if diverges(&without) {
+            culprit = without;
+        } else {
+            i += 1;
+        }
+    }
+    culprit
+}
+
+// Bisects the diverging round of pairs, which sides started from roots,
+// and prints the keys responsible with the branches along their paths that
+// differ from the first database after applying only those keys
+fn bisect(config: &Config, sides: &[Side], roots: &[H256], pairs: &[(H256, H256)]) {
+    // Every side is replayed, so all of them are left with subset applied
+    let replay = |subset: &[(H256, H256)]| -> Vec<H256> {
+        sides
+            .iter()
+            .zip(roots)
+            .map(|(side, root)| side.replay(config, *root, subset))
+            .collect()
+    };
+    let culprit = narrow(pairs, |subset| {
+        let replayed = replay(subset);
+        replayed.iter().any(|root| *root != replayed[0])
+    });
+    // The last replay may have been of another subset
+    let replayed = replay(&culprit);
+    println!(
+        "Bisected to {} of the {} keys of the round:",
+        culprit.len(),
+        pairs.len()
+    );
+    for (key, value) in &culprit {
+        println!("  {} set to {}", hex(key), hex(value));
+    }
+
+    let out = &mut std::io::stdout();
+    for (side, root) in sides.iter().zip(&replayed).skip(1) {
+        if *root == replayed[0] {
+            continue;
+        }
+        println!(
+            "Branches of {} differing from {}:",
+            side.name, sides[0].name
+        );
+        sides[0].read(config, |first| {
+            side.read(config, |other| {
+                for (key, _) in &culprit {
+                    path::write_path_diff((sides[0].name, first), (side.name, other), key, out)
+                        .unwrap();
+                }
+            })
+        });
+    }
+}
+
+// Applies the same rounds to a database through TrieStore and to another
+// through CountingStore, and with --trie-merge-op to a third through
+// TrieStore writing merge operands, each round in one transaction, and
+// checks that all end every round with the same root. With
+// --bisect-divergence the databases are snapshotted before every round,
+// and a diverging round is narrowed down to the keys responsible. Reports
+// the branch I/O of each and the time reading paths back takes.
+pub fn run(config: &Config) {
+    let mut dir = config.db_path.as_os_str().to_owned();
+    dir.push("-differential");
+    let dir = PathBuf::from(dir);
+    if dir.exists() {
+        panic!(
+            "{} already exists, --experiment differential needs a fresh directory",
+            dir.display()
+        );
+    }
+    let seeds = SeedTree::new(config.seed, config.verbose).child("differential");
+    let rounds = workload(config, &seeds);
+    let mut sides = vec![
+        Side::new(Writer::Store(StoreKind::Trie), "trie", &dir),
+        Side::new(Writer::Store(StoreKind::Counting), "counting", &dir),
+    ];
+    if config.trie_merge_op {
+        sides.push(Side::new(Writer::TrieMergeOp, "trie-merge", &dir));
+    }
+    println!(
+        "Differential: {} rounds of {} keys through {}, in {}",
+        config.differential_rounds,
+        config.differential_batch_size,
+        sides
+            .iter()
+            .map(|side| side.name)
+            .collect::<Vec<_>>()
+            .join(", "),
+        dir.display()
+    );
+    if config.bisect_divergence {
+        println!("  The databases are copied before every round to bisect a divergence");
+    }
+
+    for side in &mut sides {
+        // Creates the database, so the first round has one to snapshot
+        side.apply(config, &[]);
+    }
+    for (round, pairs) in rounds.iter().enumerate() {
+        let roots: Vec<H256> = sides.iter().map(|side| side.root).collect();
+        for side in &mut sides {
+            if config.bisect_divergence {
+                let snapshot = dir.join(format!("{}-snapshot", side.name));
+                side.snapshot = Some(Snapshot::take(&side.path, snapshot));
+            }
+            side.apply(config, pairs);
+        }
+        if sides.iter().any(|side| side.root != sides[0].root) {
+            eprintln!(
+                "Round {} diverged, roots: {}",
+                round + 1,
+                sides
+                    .iter()
+                    .map(|side| format!("{} {}", side.name, hex(&side.root)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            if config.bisect_divergence {
+                bisect(config, &sides, &roots, pairs);
+            } else {
+                eprintln!("  --bisect-divergence narrows the round down to the keys responsible");
+            }
+            eprintln!("The databases are left in {}", dir.display());
+            std::process::exit(1);
+        }
+    }
+    let keys: Vec<H256> = rounds
+        .last()
+        .map_or(&[][..], |pairs| &pairs[..])
+        .iter()
+        .take(READ_KEYS)
+        .map(|(key, _)| *key)
+        .collect();
+    for side in &mut sides {
+        side.read_elapsed = side.read(config, |store| time_reads(store, &keys));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+
+    for side in &sides {
+        println!(
+            "  {:<10} {:?}, {} reads, {} writes, {:.1} MiB written, paths of {} keys read in {:?}",
+            side.name,
+            side.elapsed,
+            side.reads,
+            side.writes,
+            side.written_bytes as f64 / (1 << 20) as f64,
+            keys.len(),
+            side.read_elapsed
+        );
+    }
+    if let Some(merge) = sides
+        .iter()
+        .find(|side| matches!(side.writer, Writer::TrieMergeOp))
+    {
+        let rewrite = &sides[0];
+        println!(
+            "  Merge operands: {:.1}% of the bytes rewriting tries writes, paths read in {:.2}x its time",
+            merge.written_bytes as f64 * 100.0 / rewrite.written_bytes.max(1) as f64,
+            merge.read_elapsed.as_secs_f64() / rewrite.read_elapsed.as_secs_f64().max(f64::EPSILON)
+        );
+    }
+    println!(
+        "  Every round ended with the same root in all, last {}",
+        hex(&sides[0].root)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    // Bisection must end with exactly the keys a divergence needs, whether
+    // one key causes it or two keys in different halves only together
+    #[test]
+    fn narrowed_divergence() -> Result<(), String> {
+        let seeds = SeedTree::new(11, false).child("check-differential");
+        let mut rng = seeds.rng();
+        let pairs: Vec<(H256, H256)> = (0..37)
+            .map(|_| (random_h256(&mut rng), random_h256(&mut rng)))
+            .collect();
+        for culprits in [vec![5], vec![3, 30], vec![0, 17, 36]] {
+            let keys: Vec<H256> = culprits.iter().map(|i| pairs[*i].0).collect();
+            let mut replays = 0;
+            let narrowed = narrow(&pairs, |subset| {
+                replays += 1;
+                keys.iter()
+                    .all(|key| subset.iter().any(|(other, _)| other == key))
+            });
+            let narrowed: Vec<H256> = narrowed.iter().map(|(key, _)| *key).collect();
+            if narrowed != keys {
+                return Err(format!(
+                    "pairs {:?} narrowed to {} keys, not to those",
+                    culprits,
+                    narrowed.len()
+                ));
+            }
+            if replays > 2 * pairs.len() {
+                return Err(format!(
+                    "{} replays to narrow {} pairs",
+                    replays,
+                    pairs.len()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Rounds after the first must mix inserts, updates and deletes, and never
+    // write a key twice in a round
+    #[test]
+    fn differential_workload() -> Result<(), String> {
+        let config = Config {
+            differential_rounds: 4,
+            differential_batch_size: 30,
+            ..Default::default()
+        };
+        let rounds = workload(&config, &SeedTree::new(3, false));
+        let mut written = BTreeSet::new();
+        for (i, pairs) in rounds.iter().enumerate() {
+            let keys: BTreeSet<H256> = pairs.iter().map(|(key, _)| *key).collect();
+            if keys.len() != pairs.len() {
+                return Err(format!("round {} writes a key twice", i + 1));
+            }
+            let deletes = pairs.iter().filter(|(_, value)| value.is_zero()).count();
+            let updates = keys.intersection(&written).count() - deletes;
+            if i > 0 && (deletes == 0 || updates == 0) {
+                return Err(format!(
+                    "round {} has {} updates and {} deletes",
+                    i + 1,
+                    updates,
+                    deletes
+                ));
+            }
+            written.extend(keys);
+        }
+        Ok(())
+    }
+}
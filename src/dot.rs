@@ -0,0 +1,97 @@
+use sparse_merkle_tree::{
+    merge::MergeValue,
+    traits::Store,
+    tree::{BranchKey, BranchNode},
+    H256,
+};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+pub(crate) fn hex(h: &H256) -> String {
+    h.as_slice().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn node_id(key: &BranchKey) -> String {
+    format!("b{}_{}", key.height, hex(&key.node_key))
+}
+
+// Writes the branch structure reachable from the root as a Graphviz digraph,
+// visiting at most max_nodes branches breadth first. Children whose merge
+// value is MergeWithZero are drawn dashed.
+pub fn write_dot<S: Store<H256>>(
+    store: &S,
+    max_nodes: usize,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(out, "digraph smt {{")?;
+    writeln!(out, "  node [shape=box];")?;
+
+    let mut queue = VecDeque::new();
+    queue.push_back((BranchKey::new(u8::MAX, H256::zero()), false));
+    let mut visited = 0;
+    while let Some((key, merge_with_zero)) = queue.pop_front() {
+        if visited == max_nodes {
+            writeln!(out, "  truncated [shape=plaintext, label=\"...\"];")?;
+            writeln!(out, "  // stopped after {} nodes", max_nodes)?;
+            break;
+        }
+        let branch: BranchNode = match store.get_branch(&key) {
+            Ok(Some(branch)) => branch,
+            Ok(None) => continue,
+            Err(err) => {
+                writeln!(out, "  // error reading {:?}: {}", key, err)?;
+                continue;
+            }
+        };
+        visited += 1;
+        let style = if merge_with_zero {
+            ", style=dashed"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "  {} [label=\"h={}\"{}];",
+            node_id(&key),
+            key.height,
+            style
+        )?;
+
+        for (right, value) in [(false, &branch.left), (true, &branch.right)] {
+            if value.is_zero() {
+                continue;
+            }
+            let mut child_key = key.node_key;
+            if right {
+                child_key.set_bit(key.height);
+            }
+            let edge_label = if right { "R" } else { "L" };
+            if key.height == 0 {
+                writeln!(
+                    out,
+                    "  leaf_{} [shape=ellipse, label=\"leaf\"];",
+                    hex(&child_key)
+                )?;
+                writeln!(
+                    out,
+                    "  {} -> leaf_{} [label=\"{}\"];",
+                    node_id(&key),
+                    hex(&child_key),
+                    edge_label
+                )?;
+            } else {
+                let child = BranchKey::new(key.height - 1, child_key);
+                writeln!(
+                    out,
+                    "  {} -> {} [label=\"{}\"];",
+                    node_id(&key),
+                    node_id(&child),
+                    edge_label
+                )?;
+                let is_mwz = matches!(value, MergeValue::MergeWithZero { .. });
+                queue.push_back((child, is_mwz));
+            }
+        }
+    }
+    writeln!(out, "}}")
+}
@@ -0,0 +1,71 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+// Asks the kernel to evict the page cache of every SST file under path, so
+// the next reads have to go to disk. Returns how many files were advised;
+// failures are reported but do not stop the bench.
+pub fn drop_os_cache(path: &Path) -> usize {
+    let mut dropped = 0;
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                eprintln!("Warning: cannot list {}: {}", dir.display(), err);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "sst") {
+                match fadvise_dontneed(&path) {
+                    Ok(()) => dropped += 1,
+                    Err(err) => {
+                        eprintln!("Warning: cannot drop cache of {}: {}", path.display(), err)
+                    }
+                }
+            }
+        }
+    }
+    dropped
+}
+
+// Flushes dirty pages and drops the whole OS page cache, clean dentries
+// and inodes included. Writing drop_caches needs root.
+#[cfg(target_os = "linux")]
+pub fn drop_system_cache() -> io::Result<()> {
+    unsafe { libc::sync() };
+    fs::write("/proc/sys/vm/drop_caches", "3")
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drop_system_cache() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "/proc/sys/vm/drop_caches only exists on linux",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn fadvise_dontneed(path: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = File::open(path)?;
+    let ret = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fadvise_dontneed(path: &Path) -> io::Result<()> {
+    File::open(path)?;
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "posix_fadvise is only used on linux",
+    ))
+}
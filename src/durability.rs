@@ -0,0 +1,174 @@
+use crate::{config::Config, random_h256, seed::SeedTree, store_config, trie::TrieStore, TrieSmt};
+use gw_db::RocksDB;
+use gw_store::{traits::KVStore, Store as GwStore};
+use rand_chacha::{rand_core::RngCore, ChaCha20Rng};
+use sparse_merkle_tree::{
+    blake2b::Blake2bHasher, merge::merge, traits::Store, tree::BranchKey, H256,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Keys the iterations draw their updates from, so later ones overwrite and
+// delete what earlier ones committed
+const KEY_POOL: usize = 20_000;
+
+// Where the experiment keeps the database it drops and reopens, next to
+// --db-path, which stays open for the whole process
+fn crash_path(config: &Config) -> PathBuf {
+    let mut path = config.db_path.clone().into_os_string();
+    path.push("-durability");
+    path.into()
+}
+
+fn open(config: &Config, path: &Path) -> GwStore {
+    GwStore::new(RocksDB::open(
+        &store_config(config, path.to_path_buf()),
+        config.columns,
+    ))
+}
+
+fn trie_store<'a, DB: KVStore>(config: &Config, db: &'a DB) -> TrieStore<'a, DB> {
+    TrieStore::with_layout(db, config.trie_layout)
+        .with_columns(config.branch_col, config.leaf_col)
+        .molecule_wrap(config.trie_molecule_wrap)
+        .truncate_leaf_keys(config.leaf_key_bytes)
+}
+
+fn random_batch(config: &Config, rng: &mut ChaCha20Rng, keys: &[H256]) -> Vec<(H256, H256)> {
+    (0..config.durability_batch_size)
+        .map(|_| {
+            let key = keys[rng.next_u64() as usize % keys.len()];
+            // One in ten updates deletes its key
+            let value = if rng.next_u64().is_multiple_of(10) {
+                H256::zero()
+            } else {
+                random_h256(rng)
+            };
+            (key, value)
+        })
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .collect()
+}
+
+// The root the persisted top branch hashes to, zero without one
+fn persisted_root(config: &Config, store: &GwStore) -> Result<H256, String> {
+    let tx = store.begin_transaction();
+    let top = trie_store(config, &tx)
+        .get_branch(&BranchKey::new(u8::MAX, H256::zero()))
+        .map_err(|err| err.to_string())?;
+    Ok(top.map_or(H256::zero(), |branch| {
+        merge::<Blake2bHasher>(u8::MAX, &H256::zero(), &branch.left, &branch.right)
+            .hash::<Blake2bHasher>()
+    }))
+}
+
+// After a reopen the store must hold the last committed root and every value
+// committed under it, and nothing of the transaction the crash dropped
+fn verify(
+    config: &Config,
+    store: &GwStore,
+    root: H256,
+    values: &BTreeMap<H256, H256>,
+) -> Result<(), String> {
+    let persisted = persisted_root(config, store)?;
+    if persisted != root {
+        return Err(format!(
+            "persisted root {:?}, last committed {:?}",
+            persisted, root
+        ));
+    }
+    let tx = store.begin_transaction();
+    let smt = TrieSmt::new(root, trie_store(config, &tx));
+    for (key, value) in values {
+        let actual = smt.get(key).map_err(|err| err.to_string())?;
+        if actual != *value {
+            return Err(format!(
+                "key {:?} reads {:?}, {:?} was committed",
+                key, actual, value
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Fuzzes what a commit of TrieStore leaves on disk: every iteration commits
+// a random batch, then with --crash-rate stages another batch without
+// committing it, drops the database and reopens it, and checks that the
+// persisted root and values are those of the last commit.
+pub fn run(config: &Config) {
+    let path = crash_path(config);
+    if path.exists() {
+        panic!(
+            "{} already exists, --experiment durability needs a fresh database",
+            path.display()
+        );
+    }
+    let seeds = SeedTree::new(config.seed, config.verbose).child("durability");
+    let keys: Vec<H256> = {
+        let mut rng = seeds.child("keys").rng();
+        (0..KEY_POOL).map(|_| random_h256(&mut rng)).collect()
+    };
+    let mut rng = seeds.child("iterations").rng();
+    println!(
+        "Durability: {} iterations of {} updates, crash rate {}, database {}",
+        config.durability_iterations,
+        config.durability_batch_size,
+        config.crash_rate,
+        path.display()
+    );
+
+    let mut store = open(config, &path);
+    let mut root = H256::zero();
+    let mut values = BTreeMap::new();
+    let mut reopens = 0;
+    for iteration in 1..=config.durability_iterations {
+        let pairs = random_batch(config, &mut rng, &keys);
+        let tx = store.begin_transaction();
+        let mut smt = TrieSmt::new(root, trie_store(config, &tx));
+        root = *smt.update_all(pairs.clone()).unwrap();
+        tx.commit().unwrap();
+        for (key, value) in pairs {
+            if value.is_zero() {
+                values.remove(&key);
+            } else {
+                values.insert(key, value);
+            }
+        }
+
+        let sample = rng.next_u64() as f64 / u64::MAX as f64;
+        if sample >= config.crash_rate {
+            continue;
+        }
+        // The crash hits in the middle of the next round
+        {
+            let pending = random_batch(config, &mut rng, &keys);
+            let tx = store.begin_transaction();
+            let mut smt = TrieSmt::new(root, trie_store(config, &tx));
+            smt.update_all(pending).unwrap();
+        }
+        drop(store);
+        store = open(config, &path);
+        reopens += 1;
+        if let Err(err) = verify(config, &store, root, &values) {
+            panic!("Iteration {}, reopen {}: {}", iteration, reopens, err);
+        }
+        if config.verbose {
+            println!(
+                "  Iteration {}: reopened, root {:?}, {} keys",
+                iteration,
+                root,
+                values.len()
+            );
+        }
+    }
+    drop(store);
+    fs::remove_dir_all(&path).unwrap();
+    println!(
+        "  {} reopen cycles, the persisted root matched the last commit every time, final root {:?} with {} keys",
+        reopens,
+        root,
+        values.len()
+    );
+}
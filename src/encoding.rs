@@ -0,0 +1,107 @@
+use crate::{
+    config::Config,
+    trie::{trie_branches, unwrap_blob, wrap_blob, NODE_SIZE, TRIE_SIZE, WRAP_HEADER_SIZE},
+    utils::pack_branch,
+};
+use gw_db::iter::IteratorMode;
+use gw_store::{traits::KVStore, Store as GwStore};
+use gw_types::prelude::*;
+use sparse_merkle_tree::merge::MergeValue;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// Bytes a child needs at least: a type byte and the hash of a value, or a
+// type byte, the zero count, the base node and the zero bits
+fn minimal_size(value: &MergeValue) -> usize {
+    match value {
+        MergeValue::Value(_) => 1 + 32,
+        MergeValue::MergeWithZero { .. } => 1 + 1 + 32 + 32,
+    }
+}
+
+// Reads up to --sample branches out of the trie blobs of --db-path and
+// compares what one branch costs in the molecule encoding of old.rs with
+// its slot in a trie.rs blob, apart from how either store groups them.
+// The minimal size is what a per-node encoding without padding would take.
+// Also times wrapping the sampled blobs in a molecule Bytes and unwrapping
+// them again, what --trie-molecule-wrap adds to every write and read.
+pub fn compare_sizes(config: &Config, store: &GwStore) {
+    let tx = store.begin_transaction();
+    let mut tries = 0;
+    let mut wrapped_tries = 0;
+    let mut wrap_elapsed = Duration::ZERO;
+    let mut unwrap_elapsed = Duration::ZERO;
+    let mut branches = 0;
+    let mut molecule_bytes = 0;
+    let mut minimal_bytes = 0;
+    let mut zero_merges = 0;
+    for (_, blob) in tx.get_iter(config.branch_col, IteratorMode::Start) {
+        tries += 1;
+        let raw = unwrap_blob(&blob);
+        if raw.len() != blob.len() {
+            wrapped_tries += 1;
+        }
+        let start = Instant::now();
+        let wrapped = black_box(wrap_blob(raw));
+        wrap_elapsed += start.elapsed();
+        let start = Instant::now();
+        black_box(unwrap_blob(&wrapped));
+        unwrap_elapsed += start.elapsed();
+        for branch in trie_branches(&blob) {
+            molecule_bytes += pack_branch(&branch).as_slice().len();
+            for child in [&branch.left, &branch.right] {
+                minimal_bytes += minimal_size(child);
+                if matches!(child, MergeValue::MergeWithZero { .. }) {
+                    zero_merges += 1;
+                }
+            }
+            branches += 1;
+            if branches == config.sample_branches {
+                break;
+            }
+        }
+        if branches == config.sample_branches {
+            break;
+        }
+    }
+    if branches == 0 {
+        println!(
+            "No trie blobs in {}, populate it with the bench first",
+            config.db_path.display()
+        );
+        return;
+    }
+
+    let per_branch = |bytes: usize| bytes as f64 / branches as f64;
+    println!(
+        "Encoding sizes of {} branches from {} tries:",
+        branches, tries
+    );
+    println!(
+        "  molecule (old.rs): {:.1} bytes per branch",
+        per_branch(molecule_bytes)
+    );
+    println!(
+        "  byte-packed (trie.rs): {} bytes per branch slot",
+        NODE_SIZE
+    );
+    println!(
+        "  minimal: {:.1} bytes per branch, molecule adds {:.1}, the trie slot {:.1}",
+        per_branch(minimal_bytes),
+        per_branch(molecule_bytes) - per_branch(minimal_bytes),
+        NODE_SIZE as f64 - per_branch(minimal_bytes)
+    );
+    println!(
+        "  Children merged with zero: {:.1}%",
+        zero_merges as f64 * 100.0 / (2 * branches) as f64
+    );
+    println!(
+        "  molecule Bytes wrapper: {} bytes per trie, {:.3}% of a blob, wrap {:.0} ns, unwrap {:.0} ns per trie, {} of the {} tries stored wrapped",
+        WRAP_HEADER_SIZE,
+        WRAP_HEADER_SIZE as f64 * 100.0 / TRIE_SIZE as f64,
+        wrap_elapsed.as_nanos() as f64 / tries as f64,
+        unwrap_elapsed.as_nanos() as f64 / tries as f64,
+        wrapped_tries,
+        tries
+    );
+}
@@ -0,0 +1,81 @@
+use rand_chacha::{rand_core::RngCore, ChaCha20Rng};
+use sparse_merkle_tree::{
+    error::Error as SMTError,
+    traits::Store,
+    tree::{BranchKey, BranchNode},
+    H256,
+};
+use std::cell::{Cell, RefCell};
+
+// Store decorator failing a random fraction of reads and writes with
+// SMTError::Store, to exercise the error paths of the harness.
+pub struct FaultyStore<S> {
+    inner: S,
+    rate: f64,
+    rng: RefCell<ChaCha20Rng>,
+    faults: Cell<usize>,
+}
+
+impl<S: Store<H256>> FaultyStore<S> {
+    pub fn new(inner: S, rate: f64, rng: ChaCha20Rng) -> Self {
+        Self {
+            inner,
+            rate,
+            rng: RefCell::new(rng),
+            faults: Cell::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    // Number of errors injected so far
+    pub fn faults(&self) -> usize {
+        self.faults.get()
+    }
+
+    fn maybe_fail(&self, op: &str) -> Result<(), SMTError> {
+        if self.rate <= 0.0 {
+            return Ok(());
+        }
+        let sample = self.rng.borrow_mut().next_u64() as f64 / u64::MAX as f64;
+        if sample < self.rate {
+            self.faults.set(self.faults.get() + 1);
+            return Err(SMTError::Store(format!("injected {} fault", op)));
+        }
+        Ok(())
+    }
+}
+
+impl<S: Store<H256>> Store<H256> for FaultyStore<S> {
+    fn get_branch(&self, branch_key: &BranchKey) -> Result<Option<BranchNode>, SMTError> {
+        self.maybe_fail("get_branch")?;
+        self.inner.get_branch(branch_key)
+    }
+
+    fn get_leaf(&self, leaf_key: &H256) -> Result<Option<H256>, SMTError> {
+        self.maybe_fail("get_leaf")?;
+        self.inner.get_leaf(leaf_key)
+    }
+
+    fn insert_branch(&mut self, branch_key: BranchKey, branch: BranchNode) -> Result<(), SMTError> {
+        self.maybe_fail("insert_branch")?;
+        self.inner.insert_branch(branch_key, branch)
+    }
+
+    fn insert_leaf(&mut self, leaf_key: H256, leaf: H256) -> Result<(), SMTError> {
+        self.maybe_fail("insert_leaf")?;
+        self.inner.insert_leaf(leaf_key, leaf)
+    }
+
+    fn remove_branch(&mut self, branch_key: &BranchKey) -> Result<(), SMTError> {
+        self.maybe_fail("remove_branch")?;
+        self.inner.remove_branch(branch_key)
+    }
+
+    fn remove_leaf(&mut self, leaf_key: &H256) -> Result<(), SMTError> {
+        self.maybe_fail("remove_leaf")?;
+        self.inner.remove_leaf(leaf_key)
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{
+    build_info,
+    config::{Config, OutputFormat},
+    rocksdb_options,
+};
+use gw_common::blake2b::new_blake2b;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+
+// Bytes of the blake2b hash kept, enough to tell runs on one machine apart
+const FINGERPRINT_BYTES: usize = 8;
+
+// The configuration with everything that only decides where results go
+// or how they are printed reset, so runs differing only in those match
+fn canonical_config(config: &Config) -> String {
+    let mut config = config.clone();
+    config.db_path = PathBuf::new();
+    config.corpus_out = None;
+    config.results_out = None;
+    config.verbose = false;
+    config.audit_seeds = false;
+    config.tui = false;
+    config.json = false;
+    config.compaction_stats = false;
+    config.output = OutputFormat::Text;
+    config.stage_times = false;
+    config.skip_if_run = false;
+    // A resumed run is the run it resumes
+    config.resume = false;
+    config.profile_phases = false;
+    // The options file counts by its content, see fingerprint
+    config.rocksdb_options_file = None;
+    format!("{:?}", config)
+}
+
+// CPU model, core count and memory size, what makes timings of one machine
+// incomparable with another's
+fn hardware_context() -> String {
+    let field = |file: &str, name: &str| {
+        fs::read_to_string(file)
+            .ok()
+            .and_then(|content| {
+                content
+                    .lines()
+                    .find(|line| line.starts_with(name))
+                    .and_then(|line| line.split_once(':'))
+                    .map(|(_, value)| value.trim().to_string())
+            })
+            .unwrap_or_default()
+    };
+    format!(
+        "cpu={};cores={};memory={}",
+        field("/proc/cpuinfo", "model name"),
+        thread::available_parallelism().map_or(0, |n| n.get()),
+        field("/proc/meminfo", "MemTotal")
+    )
+}
+
+// Identifies a run by its configuration, the build and dependencies it ran
+// with and the machine it ran on. Stable across runs of the same binary.
+pub fn fingerprint(config: &Config) -> String {
+    let mut hasher = new_blake2b();
+    hasher.update(canonical_config(config).as_bytes());
+    hasher.update(build_info::label().as_bytes());
+    hasher.update(build_info::deps_label().as_bytes());
+    hasher.update(hardware_context().as_bytes());
+    if let Some(path) = &config.rocksdb_options_file {
+        let hash = rocksdb_options::hash(path).unwrap_or_else(|err| panic!("{}", err));
+        hasher.update(hash.as_bytes());
+    }
+    let mut hash = [0u8; 32];
+    hasher.finalize(&mut hash);
+    hash[..FINGERPRINT_BYTES]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
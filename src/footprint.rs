@@ -0,0 +1,252 @@
+use crate::config::Config;
+use gw_db::{schema::Col, RocksDB};
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often the background compactions are polled after compact_range
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// On-disk size of one column after --final-compact
+pub struct ColumnFootprint {
+    pub name: &'static str,
+    pub keys: Option<u64>,
+    pub sst_bytes: Option<u64>,
+}
+
+// Name and column of every column the tree is stored in
+pub fn columns(config: &Config) -> Vec<(&'static str, Col)> {
+    let mut columns = vec![("branch", config.branch_col), ("leaf", config.leaf_col)];
+    if config.leaf_history {
+        columns.push(("history", config.history_col));
+    }
+    columns
+}
+
+// Compacts every column of the tree over its whole key range, then waits
+// until RocksDB runs no background compaction for them either, so the SST
+// sizes read afterwards are settled. Returns the time it all took.
+pub fn compact(db: &RocksDB, config: &Config) -> Duration {
+    let start = Instant::now();
+    let columns = columns(config);
+    for (name, col) in &columns {
+        if let Err(err) = db.compact_range(*col, None, None) {
+            panic!("Compacting the {} column failed: {}", name, err);
+        }
+    }
+    let busy = |col: Col| {
+        [
+            "rocksdb.compaction-pending",
+            "rocksdb.num-running-compactions",
+        ]
+        .iter()
+        .any(|property| matches!(db.property_int_value(col, property), Ok(Some(n)) if n > 0))
+    };
+    while columns.iter().any(|(_, col)| busy(*col)) {
+        thread::sleep(POLL_INTERVAL);
+    }
+    start.elapsed()
+}
+
+pub fn footprint(db: &RocksDB, config: &Config) -> Vec<ColumnFootprint> {
+    columns(config)
+        .into_iter()
+        .map(|(name, col)| {
+            let property = |property| db.property_int_value(col, property).ok().flatten();
+            ColumnFootprint {
+                name,
+                keys: property("rocksdb.estimate-num-keys"),
+                sst_bytes: property("rocksdb.total-sst-files-size"),
+            }
+        })
+        .collect()
+}
+
+// SST bytes of all columns together, None when RocksDB reported none of them
+pub fn total_bytes(footprint: &[ColumnFootprint]) -> Option<u64> {
+    footprint
+        .iter()
+        .filter_map(|column| column.sst_bytes)
+        .reduce(|a, b| a + b)
+}
+
+// Prints the keys and bytes of every column and what each live leaf costs
+pub fn print_footprint(footprint: &[ColumnFootprint], leaves: usize, leaves_label: &str) {
+    let per_leaf = |bytes: Option<u64>| match bytes {
+        Some(bytes) if leaves > 0 => format!("{:.1}", bytes as f64 / leaves as f64),
+        _ => "n/a".to_string(),
+    };
+    let opt = |value: Option<u64>| value.map_or("n/a".to_string(), |v| v.to_string());
+    println!(
+        "Footprint after final compaction, {} live leaves:",
+        leaves_label
+    );
+    for column in footprint {
+        println!(
+            "  {} column: {} keys (est.), {} bytes, {} bytes per leaf",
+            column.name,
+            opt(column.keys),
+            opt(column.sst_bytes),
+            per_leaf(column.sst_bytes)
+        );
+    }
+    let total = total_bytes(footprint);
+    println!(
+        "  Total: {} bytes, {} bytes per leaf",
+        opt(total),
+        per_leaf(total)
+    );
+}
+
+// Bytes of the files under a database directory: SST files, write-ahead
+// logs and the rest, MANIFEST, OPTIONS and the info LOG among them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiskSize {
+    pub sst: u64,
+    pub log: u64,
+    pub other: u64,
+}
+
+impl DiskSize {
+    pub fn total(&self) -> u64 {
+        self.sst + self.log + self.other
+    }
+}
+
+// Walks path recursively and sums the size of every file by kind
+pub fn disk_size(path: &Path) -> DiskSize {
+    let mut size = DiskSize::default();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let bytes = match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => {
+                    dirs.push(entry.path());
+                    continue;
+                }
+                Ok(metadata) => metadata.len(),
+                Err(_) => continue,
+            };
+            match entry.path().extension().and_then(|ext| ext.to_str()) {
+                Some("sst") => size.sst += bytes,
+                Some("log") => size.log += bytes,
+                _ => size.other += bytes,
+            }
+        }
+    }
+    size
+}
+
+// Size of every file under path, write-ahead log included
+pub fn dir_size(path: &Path) -> u64 {
+    disk_size(path).total()
+}
+
+// Prints what the database directory of name takes on disk and what each
+// live leaf costs of it
+pub fn print_disk_size(name: &str, path: &Path, leaves: usize) {
+    let size = disk_size(path);
+    let mib = |bytes: u64| bytes as f64 / (1 << 20) as f64;
+    println!(
+        "On disk, {} {}: {:.1} MiB ({:.1} MiB SST, {:.1} MiB log, {:.1} MiB other), {}",
+        name,
+        path.display(),
+        mib(size.total()),
+        mib(size.sst),
+        mib(size.log),
+        mib(size.other),
+        if leaves > 0 {
+            format!("{:.1} bytes per leaf", size.total() as f64 / leaves as f64)
+        } else {
+            "no live leaves".to_string()
+        }
+    );
+}
+
+// Block cache hits and misses since the database was opened, from RocksDB's
+// statistics. The property is database wide, any column reads it. None when
+// the statistics are off.
+pub fn block_cache_tickers(db: &RocksDB, config: &Config) -> Option<(u64, u64)> {
+    let stats = db
+        .property_value(config.branch_col, "rocksdb.options-statistics")
+        .ok()
+        .flatten()?;
+    Some((
+        ticker(&stats, "rocksdb.block.cache.hit")?,
+        ticker(&stats, "rocksdb.block.cache.miss")?,
+    ))
+}
+
+// The "<name> COUNT : N" line of RocksDB's statistics string
+pub(crate) fn ticker(stats: &str, name: &str) -> Option<u64> {
+    stats.lines().find_map(|line| {
+        let count = line.trim().strip_prefix(name)?.trim_start();
+        count.strip_prefix("COUNT :")?.trim().parse().ok()
+    })
+}
+
+// Largest resident set the process had so far, in bytes
+pub fn peak_rss() -> Option<u64> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+    // Linux reports kibibytes
+    Some(usage.ru_maxrss as u64 * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The size of a database directory must count the files of its
+    // subdirectories, SST files and write-ahead logs apart from the rest, the
+    // info LOG included in the rest.
+    #[test]
+    fn disk_size_of_a_directory() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("smt-bench-disk-size-{}", std::process::id()));
+        let result = (|| {
+            std::fs::create_dir_all(dir.join("archive")).map_err(|err| err.to_string())?;
+            for (name, bytes) in [
+                ("000010.sst", 300),
+                ("archive/000007.sst", 200),
+                ("000011.log", 50),
+                ("LOG", 7),
+                ("MANIFEST-000005", 3),
+            ] {
+                std::fs::write(dir.join(name), vec![0u8; bytes]).map_err(|err| err.to_string())?;
+            }
+            let size = disk_size(&dir);
+            let expected = DiskSize {
+                sst: 500,
+                log: 50,
+                other: 10,
+            };
+            if size != expected || dir_size(&dir) != 560 {
+                return Err(format!("measured {:?}, expected {:?}", size, expected));
+            }
+            Ok(())
+        })();
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+
+    // A ticker must be read from its own line of the statistics string, not
+    // from a longer ticker it prefixes
+    #[test]
+    fn statistics_tickers() -> Result<(), String> {
+        let stats = "rocksdb.block.cache.miss COUNT : 7\n\
+                     rocksdb.block.cache.hit.bytes COUNT : 99\n\
+                     rocksdb.block.cache.hit COUNT : 42\n";
+        let found = [
+            ticker(stats, "rocksdb.block.cache.hit"),
+            ticker(stats, "rocksdb.block.cache.miss"),
+            ticker(stats, "rocksdb.block.cache.add"),
+        ];
+        if found != [Some(42), Some(7), None] {
+            return Err(format!("read tickers {:?}", found));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{
+    config::Config,
+    footprint,
+    phase::{Phase, PhaseKind},
+    runner::{RoundReport, Runner},
+    seed::SeedTree,
+    store_config,
+};
+use gw_db::RocksDB;
+use gw_store::Store as GwStore;
+use sparse_merkle_tree::H256;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+// One combination of the --grid values, as the options appended to the
+// command line of the grid
+struct Cell {
+    values: Vec<String>,
+    args: Vec<String>,
+}
+
+// The command line without --grid, --grid-parallel, --suite and --serve,
+// which every cell of a grid, scenario of a suite and remote run starts from
+pub(crate) fn base_args() -> Vec<String> {
+    let mut args = env::args().skip(1).peekable();
+    let mut base = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--grid" => while args.next_if(|arg| !arg.starts_with("--")).is_some() {},
+            "--grid-parallel" | "--suite" | "--serve" => {
+                args.next();
+            }
+            _ => base.push(arg),
+        }
+    }
+    base
+}
+
+// The cross product of the axes, the last axis varying fastest. A value of
+// on or off gives or leaves out an option taking no value.
+fn cells(grid: &[(String, Vec<String>)]) -> Vec<Cell> {
+    let mut cells = vec![Cell {
+        values: vec![],
+        args: vec![],
+    }];
+    for (name, values) in grid {
+        cells = cells
+            .into_iter()
+            .flat_map(|cell| {
+                values.iter().map(move |value| {
+                    let mut args = cell.args.clone();
+                    match value.as_str() {
+                        "on" => args.push(format!("--{}", name)),
+                        "off" => {}
+                        _ => args.extend([format!("--{}", name), value.clone()]),
+                    }
+                    let mut values = cell.values.clone();
+                    values.push(value.clone());
+                    Cell { values, args }
+                })
+            })
+            .collect();
+    }
+    cells
+}
+
+pub(crate) fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+// Metrics of the rounds a run on the database in path did, ending at root
+pub(crate) fn cell_metrics(
+    rounds: &[RoundReport],
+    path: &Path,
+    root: H256,
+) -> Vec<(&'static str, String)> {
+    let ops: usize = rounds.iter().map(|r| r.ops).sum();
+    let elapsed: Duration = rounds.iter().map(|r| r.elapsed).sum();
+    vec![
+        ("ops", ops.to_string()),
+        ("rounds", rounds.len().to_string()),
+        ("elapsed_secs", format!("{:.6}", elapsed.as_secs_f64())),
+        (
+            "ops_per_sec",
+            format!(
+                "{:.1}",
+                ops as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+            ),
+        ),
+        (
+            "mean_round_secs",
+            format!("{:.6}", elapsed.as_secs_f64() / rounds.len().max(1) as f64),
+        ),
+        (
+            "reads",
+            rounds.iter().map(|r| r.reads).sum::<usize>().to_string(),
+        ),
+        (
+            "writes",
+            rounds.iter().map(|r| r.writes).sum::<usize>().to_string(),
+        ),
+        (
+            "written_bytes",
+            rounds
+                .iter()
+                .map(|r| r.written_bytes)
+                .sum::<usize>()
+                .to_string(),
+        ),
+        (
+            "leaves",
+            rounds.last().map(|r| r.leaves_label()).unwrap_or_default(),
+        ),
+        ("disk_bytes", footprint::dir_size(path).to_string()),
+        ("root", format!("{:?}", root)),
+    ]
+}
+
+// Metrics of a cell, or the message it failed with
+pub(crate) type CellResult = Result<Vec<(&'static str, String)>, String>;
+
+// Runs the phases of the cell's configuration on a fresh database in path
+// and returns its metrics
+pub(crate) fn run_cell(args: Vec<String>, path: &Path) -> CellResult {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut config = Config::parse(args);
+        // Cells running at once would interleave their progress lines
+        config.report_every = 0;
+        config.tui = false;
+        let db = RocksDB::open(&store_config(&config, path.to_path_buf()), config.columns);
+        let store = GwStore::new(db);
+        let mut runner = Runner::new(&config, &store, SeedTree::new(config.seed, false));
+        let mut phases = config.phases.clone();
+        if config.leaf_gets > 0 {
+            phases.push(Phase::new(PhaseKind::Read, 1, config.leaf_gets));
+        }
+        let rounds: Vec<_> = phases
+            .iter()
+            .flat_map(|phase| runner.run_phase(phase).rounds)
+            .collect();
+        runner.finish();
+        cell_metrics(&rounds, path, runner.root())
+    }))
+    .map_err(|payload| panic_message(&*payload))
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+// Runs the bench once per combination of the --grid values, each on a fresh
+// database in a directory next to --db-path and up to --grid-parallel of
+// them at once, and writes one CSV row per cell and metric. A failing cell
+// gets an error row instead of stopping the grid.
+pub fn run(config: &Config) {
+    let mut dir = config.db_path.as_os_str().to_owned();
+    dir.push("-grid");
+    let dir = PathBuf::from(dir);
+    if dir.exists() {
+        panic!(
+            "{} already exists, --grid needs a fresh directory",
+            dir.display()
+        );
+    }
+    fs::create_dir_all(&dir).unwrap();
+    let base = base_args();
+    let cells = cells(&config.grid);
+    println!(
+        "Grid: {} cells, {} at a time, in {}",
+        cells.len(),
+        config.grid_parallel,
+        dir.display()
+    );
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<CellResult>>> = Mutex::new(cells.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..config.grid_parallel.min(cells.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some(cell) = cells.get(i) else {
+                    break;
+                };
+                let path = dir.join(format!("cell-{}", i));
+                let mut args = base.clone();
+                args.extend(cell.args.iter().cloned());
+                args.extend(["--db-path".to_string(), path.display().to_string()]);
+                let result = run_cell(args, &path);
+                // One line per cell, whole, whichever thread finishes first
+                let label: Vec<String> = config
+                    .grid
+                    .iter()
+                    .zip(&cell.values)
+                    .map(|((name, _), value)| format!("{}={}", name, value))
+                    .collect();
+                let status = match &result {
+                    Ok(metrics) => format!("{} ops in {}s", metrics[0].1, metrics[2].1),
+                    Err(err) => format!("failed: {}", err),
+                };
+                println!(
+                    "  Cell {}/{} ({}): {}",
+                    i + 1,
+                    cells.len(),
+                    label.join(" "),
+                    status
+                );
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    let path = dir.join("grid.csv");
+    let mut out = io::BufWriter::new(fs::File::create(&path).unwrap());
+    let names: Vec<String> = config
+        .grid
+        .iter()
+        .map(|(name, _)| csv_field(name))
+        .collect();
+    writeln!(out, "cell,{},metric,value", names.join(",")).unwrap();
+    let mut failed = 0;
+    for (i, (cell, result)) in cells.iter().zip(results.into_inner().unwrap()).enumerate() {
+        let values: Vec<String> = cell.values.iter().map(|v| csv_field(v)).collect();
+        let rows = match result.unwrap() {
+            Ok(metrics) => metrics,
+            Err(err) => {
+                failed += 1;
+                vec![("error", err)]
+            }
+        };
+        for (metric, value) in rows {
+            writeln!(
+                out,
+                "{},{},{},{}",
+                i,
+                values.join(","),
+                metric,
+                csv_field(&value)
+            )
+            .unwrap();
+        }
+    }
+    out.flush().unwrap();
+    println!(
+        "  {} cells, {} failed, results in {}",
+        cells.len(),
+        failed,
+        path.display()
+    );
+}
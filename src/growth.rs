@@ -0,0 +1,215 @@
+use crate::{
+    churn,
+    config::{Baseline, Config, OutputFormat},
+    dot::hex,
+    markdown,
+    phase::{Phase, PhaseKind},
+    runner::{RoundReport, Runner},
+    seed::SeedTree,
+};
+use gw_store::Store as GwStore;
+use sparse_merkle_tree::{
+    blake2b::Blake2bHasher, default_store::DefaultStore, SparseMerkleTree, H256,
+};
+use std::time::{Duration, Instant};
+
+type MemorySMT = SparseMerkleTree<Blake2bHasher, H256, DefaultStore<H256>>;
+
+// Keys inserted per update_all while growing the tree to a checkpoint
+const GROW_BATCH: usize = 50_000;
+// Rounds of --growth-batch updates timed at every checkpoint
+const MEASURED_ROUNDS: usize = 5;
+// Leaves --baseline recompute rebuilds the tree from at most without
+// --force, the rebuild after every round making the run quadratic
+pub(crate) const BASELINE_MAX_LEAVES: usize = 100_000;
+
+// Leaves the tree ends with, after the rounds timed at the last checkpoint
+pub(crate) fn final_leaves(config: &Config) -> usize {
+    config.growth_checkpoints.last().copied().unwrap_or(0) + MEASURED_ROUNDS * config.growth_batch
+}
+
+// Cost of the updates timed at one checkpoint, per updated key
+struct Checkpoint {
+    keys: usize,
+    micros: f64,
+    reads: f64,
+    writes: f64,
+    bytes: f64,
+    // Time of a whole round, and of rebuilding the tree after it with
+    // --baseline recompute
+    round_micros: f64,
+    recompute_micros: Option<f64>,
+}
+
+impl Checkpoint {
+    fn new(keys: usize, rounds: &[RoundReport], recompute: Option<Duration>) -> Self {
+        let ops = rounds.iter().map(|r| r.ops).sum::<usize>().max(1) as f64;
+        let sum = |f: fn(&RoundReport) -> usize| rounds.iter().map(f).sum::<usize>() as f64 / ops;
+        Self {
+            keys,
+            micros: rounds.iter().map(|r| r.elapsed.as_secs_f64()).sum::<f64>() * 1e6 / ops,
+            reads: sum(|r| r.reads),
+            writes: sum(|r| r.writes),
+            bytes: sum(|r| r.written_bytes),
+            round_micros: rounds.iter().map(|r| r.elapsed.as_secs_f64()).sum::<f64>() * 1e6
+                / rounds.len().max(1) as f64,
+            recompute_micros: recompute
+                .map(|elapsed| elapsed.as_secs_f64() * 1e6 / rounds.len().max(1) as f64),
+        }
+    }
+
+    // Stays flat while the cost grows with the depth of the tree
+    fn per_log2(&self) -> f64 {
+        self.micros / (self.keys.max(2) as f64).log2()
+    }
+}
+
+// Keys the committed rounds inserted, each update writing a fresh key
+fn inserted(rounds: &[RoundReport]) -> usize {
+    rounds
+        .iter()
+        .filter(|r| !r.rolled_back)
+        .map(|r| r.ops)
+        .sum()
+}
+
+// Rebuilds the tree of runner from all its leaves with one update_all into
+// an empty in-memory store, which must end with the root the incremental
+// updates did. Reading the leaves back is not timed.
+fn recompute(runner: &Runner) -> Duration {
+    let leaves = runner.leaves();
+    let count = leaves.len();
+    let start = Instant::now();
+    let mut smt = MemorySMT::new(H256::zero(), DefaultStore::default());
+    smt.update_all(leaves).unwrap();
+    let elapsed = start.elapsed();
+    if *smt.root() != runner.root() {
+        panic!(
+            "Recomputing the tree from its {} leaves gave root {}, the incremental updates {}",
+            count,
+            hex(smt.root()),
+            hex(&runner.root())
+        );
+    }
+    elapsed
+}
+
+// Grows an empty tree to every --checkpoints size in batches of GROW_BATCH
+// keys, and at each times MEASURED_ROUNDS rounds of --growth-batch fresh
+// keys. The keys of the timed rounds stay in the tree and count towards the
+// next checkpoint. With --baseline recompute the tree is also rebuilt from
+// scratch after every timed round.
+pub fn run(config: &Config, store: &GwStore) {
+    churn::require_empty(config, store, "--growth-profile");
+    let config = Config {
+        report_every: 0,
+        ..config.clone()
+    };
+    println!(
+        "Growth profile: {} rounds of {} updates at {} tree sizes",
+        MEASURED_ROUNDS,
+        config.growth_batch,
+        config.growth_checkpoints.len()
+    );
+    let mut runner = Runner::new(&config, store, SeedTree::new(config.seed, config.verbose));
+    let mut keys = 0;
+    let mut checkpoints = vec![];
+    for &checkpoint in &config.growth_checkpoints {
+        let start = Instant::now();
+        while keys < checkpoint {
+            let batch = GROW_BATCH.min(checkpoint - keys);
+            let report = runner.run_phase(&Phase::new(PhaseKind::Update, 1, batch));
+            keys += inserted(&report.rounds);
+        }
+        let (rounds, recomputed) = match config.baseline {
+            None => {
+                let report = runner.run_phase(&Phase::new(
+                    PhaseKind::Update,
+                    MEASURED_ROUNDS,
+                    config.growth_batch,
+                ));
+                (report.rounds, None)
+            }
+            Some(Baseline::Recompute) => {
+                let mut rounds = vec![];
+                let mut recomputed = Duration::default();
+                for _ in 0..MEASURED_ROUNDS {
+                    let report =
+                        runner.run_phase(&Phase::new(PhaseKind::Update, 1, config.growth_batch));
+                    rounds.extend(report.rounds);
+                    recomputed += recompute(&runner);
+                }
+                (rounds, Some(recomputed))
+            }
+        };
+        let measured = Checkpoint::new(keys, &rounds, recomputed);
+        print!(
+            "  {} keys: grown in {:?}, {:.1} us per update",
+            keys,
+            start.elapsed(),
+            measured.micros
+        );
+        match measured.recompute_micros {
+            Some(micros) => println!(
+                ", recompute {:.0} us per round against {:.0} ({:.1}x)",
+                micros,
+                measured.round_micros,
+                micros / measured.round_micros.max(f64::EPSILON)
+            ),
+            None => println!(),
+        }
+        keys += inserted(&rounds);
+        checkpoints.push(measured);
+    }
+    runner.finish();
+
+    let mut header: Vec<String> = [
+        "Keys",
+        "us/update",
+        "Reads/update",
+        "Writes/update",
+        "Bytes/update",
+        "us/log2(keys)",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    if config.baseline.is_some() {
+        header.extend(
+            ["us/round", "Recompute us", "Recompute x"]
+                .iter()
+                .map(|s| s.to_string()),
+        );
+    }
+    let rows: Vec<Vec<String>> = checkpoints
+        .iter()
+        .map(|c| {
+            let mut row = vec![
+                c.keys.to_string(),
+                format!("{:.1}", c.micros),
+                format!("{:.1}", c.reads),
+                format!("{:.1}", c.writes),
+                format!("{:.0}", c.bytes),
+                format!("{:.2}", c.per_log2()),
+            ];
+            if let Some(micros) = c.recompute_micros {
+                row.push(format!("{:.0}", c.round_micros));
+                row.push(format!("{:.0}", micros));
+                row.push(format!("{:.1}x", micros / c.round_micros.max(f64::EPSILON)));
+            }
+            row
+        })
+        .collect();
+    println!();
+    if config.output == OutputFormat::Markdown {
+        markdown::print_preamble(&config);
+        markdown::print_table(&header, &rows);
+        return;
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        println!(
+            "{}",
+            row.iter().map(|c| format!("{:>15}", c)).collect::<String>()
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{config::Config, footprint};
+use gw_db::{
+    error::Error,
+    iter::{DBIter, IteratorMode},
+    schema::Col,
+};
+use gw_store::traits::KVStore;
+use std::cell::Cell;
+use std::rc::Rc;
+
+// Columns the bench stores the tree in with config: the branch and leaf
+// columns, and the history column with --leaf-history
+pub fn allowed_columns(config: &Config) -> Vec<Col> {
+    footprint::columns(config)
+        .into_iter()
+        .map(|(_, col)| col)
+        .collect()
+}
+
+// KVStore decorator checking that every get, iterator, insert and delete
+// goes to an allowed column, so data written to the wrong column cannot
+// skew the size metrics unnoticed. An access elsewhere panics, or with
+// tolerate is counted in violations and, for a write, not done. Without an
+// allow-list it passes everything through.
+pub struct ColumnGuardStore<DB: KVStore> {
+    inner: DB,
+    allowed: Option<Vec<Col>>,
+    tolerate: bool,
+    // Shared with the caller, so the count outlives the transaction
+    violations: Rc<Cell<usize>>,
+}
+
+impl<DB: KVStore> ColumnGuardStore<DB> {
+    pub fn new(inner: DB, allowed: Option<Vec<Col>>) -> Self {
+        Self {
+            inner,
+            allowed,
+            tolerate: false,
+            violations: Rc::default(),
+        }
+    }
+
+    pub fn tolerate(mut self, violations: Rc<Cell<usize>>) -> Self {
+        self.tolerate = true;
+        self.violations = violations;
+        self
+    }
+
+    // Whether op may go to col
+    fn check(&self, op: &str, col: Col) -> bool {
+        match &self.allowed {
+            Some(allowed) if !allowed.contains(&col) => {
+                if !self.tolerate {
+                    panic!(
+                        "{} of column {}, the store only uses columns {:?}",
+                        op, col, allowed
+                    );
+                }
+                self.violations.set(self.violations.get() + 1);
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+impl<DB: KVStore> KVStore for ColumnGuardStore<DB> {
+    fn get(&self, col: Col, key: &[u8]) -> Option<Box<[u8]>> {
+        self.check("get", col);
+        self.inner.get(col, key)
+    }
+
+    fn get_iter(&self, col: Col, mode: IteratorMode) -> DBIter<'_> {
+        self.check("iterator", col);
+        self.inner.get_iter(col, mode)
+    }
+
+    fn insert_raw(&self, col: Col, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        if !self.check("insert", col) {
+            return Ok(());
+        }
+        self.inner.insert_raw(col, key, value)
+    }
+
+    fn delete(&self, col: Col, key: &[u8]) -> Result<(), Error> {
+        if !self.check("delete", col) {
+            return Ok(());
+        }
+        self.inner.delete(col, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        iops::IopsLimitedStore,
+        phase::{Phase, PhaseKind},
+        runner::{tests::serial, Runner},
+        seed::SeedTree,
+    };
+    use gw_store::Store as GwStore;
+    use std::panic::{self, AssertUnwindSafe};
+
+    // A write misrouted to a column the tree does not live in must panic, or
+    // with --tolerate be counted and never reach the store, and every phase
+    // must keep to the columns of the tree, with and without --leaf-history
+    #[test]
+    fn column_guard() -> Result<(), String> {
+        let _serial = serial();
+        let config = Config::default();
+        let misrouted = config.history_col;
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let tx = store.begin_transaction();
+        let guarded = ColumnGuardStore::new(
+            IopsLimitedStore::new(&tx, None),
+            Some(allowed_columns(&config)),
+        );
+        if panic::catch_unwind(AssertUnwindSafe(|| {
+            guarded.insert_raw(misrouted, b"key", b"value")
+        }))
+        .is_ok()
+        {
+            return Err(format!("a write to column {} went through", misrouted));
+        }
+        let violations = Rc::new(Cell::new(0));
+        let tolerant = ColumnGuardStore::new(
+            IopsLimitedStore::new(&tx, None),
+            Some(allowed_columns(&config)),
+        )
+        .tolerate(violations.clone());
+        tolerant
+            .insert_raw(misrouted, b"key", b"value")
+            .map_err(|err| err.to_string())?;
+        tolerant
+            .insert_raw(config.leaf_col, b"key", b"value")
+            .map_err(|err| err.to_string())?;
+        tx.commit().map_err(|err| err.to_string())?;
+        if violations.get() != 1 {
+            return Err(format!(
+                "{} violations counted for one misrouted write",
+                violations.get()
+            ));
+        }
+        let tx = store.begin_transaction();
+        if tx.get(misrouted, b"key").is_some() {
+            return Err(format!("a tolerated write reached column {}", misrouted));
+        }
+        if tx.get(config.leaf_col, b"key").is_none() {
+            return Err("a write to the leaf column was lost".to_string());
+        }
+
+        let phases = [
+            Phase::new(PhaseKind::Load, 1, 100),
+            Phase::new(PhaseKind::Update, 2, 50),
+            Phase::new(PhaseKind::Delete, 1, 30),
+            Phase::new(PhaseKind::Read, 1, 30),
+            Phase::new(PhaseKind::ProveUpdate, 1, 5),
+            Phase::new(PhaseKind::ProveHistory, 1, 5),
+        ];
+        for leaf_history in [false, true] {
+            let config = Config {
+                report_every: 0,
+                guard_columns: true,
+                tolerate: true,
+                leaf_history,
+                verify_commit: 10,
+                ..Default::default()
+            };
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let mut runner = Runner::new(&config, &store, SeedTree::new(0, false));
+            for phase in &phases {
+                let report = runner.run_phase(phase);
+                let violations: usize = report.rounds.iter().map(|r| r.column_violations).sum();
+                if violations > 0 {
+                    return Err(format!(
+                        "{} accesses outside the tree columns in {}, leaf history {}",
+                        violations, phase, leaf_history
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
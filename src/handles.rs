@@ -0,0 +1,177 @@
+use gw_db::{
+    error::Error,
+    iter::{DBIter, IteratorMode},
+    schema::Col,
+};
+use gw_store::traits::KVStore;
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// Registry of --debug-handles: every tracked handle alive, by the order it
+// was created in, with its kind and where it was created
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static LIVE: Mutex<BTreeMap<u64, (&'static str, Backtrace)>> = Mutex::new(BTreeMap::new());
+// Transactions opened while another tracked one was still alive
+static OVERLAPS: AtomicUsize = AtomicUsize::new(0);
+
+pub const TRANSACTION: &str = "transaction";
+pub const STORE: &str = "store";
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+// A transaction or store registered while it is alive. Without
+// --debug-handles it only wraps the value.
+pub struct Tracked<T> {
+    inner: T,
+    id: Option<u64>,
+}
+
+// Registers inner as a live handle of kind, capturing where it is created.
+// A transaction opened while another is alive is reported at once, the
+// earlier one was neither committed nor dropped before it.
+pub fn track<T>(kind: &'static str, inner: T) -> Tracked<T> {
+    if !enabled() {
+        return Tracked { inner, id: None };
+    }
+    let backtrace = Backtrace::force_capture();
+    let mut live = LIVE.lock().unwrap();
+    if kind == TRANSACTION {
+        if let Some((id, (_, opened))) = live.iter().find(|(_, (k, _))| *k == TRANSACTION) {
+            OVERLAPS.fetch_add(1, Ordering::SeqCst);
+            eprintln!(
+                "Warning: transaction opened while transaction #{} is alive\nOpened at:\n{}\nAlive since:\n{}",
+                id, backtrace, opened
+            );
+        }
+    }
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    live.insert(id, (kind, backtrace));
+    Tracked {
+        inner,
+        id: Some(id),
+    }
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> Drop for Tracked<T> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            LIVE.lock().unwrap().remove(&id);
+        }
+    }
+}
+
+impl<T: KVStore> KVStore for Tracked<T> {
+    fn get(&self, col: Col, key: &[u8]) -> Option<Box<[u8]>> {
+        self.inner.get(col, key)
+    }
+
+    fn get_iter(&self, col: Col, mode: IteratorMode) -> DBIter<'_> {
+        self.inner.get_iter(col, mode)
+    }
+
+    fn insert_raw(&self, col: Col, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.inner.insert_raw(col, key, value)
+    }
+
+    fn delete(&self, col: Col, key: &[u8]) -> Result<(), Error> {
+        self.inner.delete(col, key)
+    }
+}
+
+// Prints every handle still alive with where it was created, and the
+// overlapping transactions seen so far. Returns whether there was neither.
+pub fn report(when: &str) -> bool {
+    let live = LIVE.lock().unwrap();
+    for (id, (kind, backtrace)) in live.iter() {
+        eprintln!(
+            "Leaked {} #{} {}, created at:\n{}",
+            kind, id, when, backtrace
+        );
+    }
+    let overlaps = OVERLAPS.load(Ordering::SeqCst);
+    if !live.is_empty() || overlaps > 0 {
+        eprintln!(
+            "--debug-handles: {} handles alive {}, {} transactions opened over a live one",
+            live.len(),
+            when,
+            overlaps
+        );
+    }
+    live.is_empty() && overlaps == 0
+}
+
+// Created first in main so it is dropped last: at exit with
+// --debug-handles, fails the process if a handle is still alive
+pub struct ExitCheck;
+
+impl Drop for ExitCheck {
+    fn drop(&mut self) {
+        if enabled() && !report("at exit") {
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::tests::serial;
+
+    // Turns tracking off and forgets every handle and overlap
+    fn reset() {
+        ENABLED.store(false, Ordering::SeqCst);
+        LIVE.lock().unwrap().clear();
+        OVERLAPS.store(0, Ordering::SeqCst);
+    }
+
+    // The --debug-handles registry must see nothing left after handles are
+    // dropped in order, and report a transaction opened over a live one and a
+    // handle that is never dropped.
+    #[test]
+    fn registry() -> Result<(), String> {
+        let _serial = serial();
+        enable();
+        let result = (|| {
+            drop(track(TRANSACTION, ()));
+            let store = track(STORE, ());
+            drop(track(TRANSACTION, ()));
+            drop(store);
+            if !report("after dropping every handle") {
+                return Err("handles dropped in order reported".to_string());
+            }
+            let first = track(TRANSACTION, ());
+            let second = track(TRANSACTION, ());
+            drop((second, first));
+            if report("after overlapping transactions") {
+                return Err("overlapping transactions not reported".to_string());
+            }
+            reset();
+            enable();
+            std::mem::forget(track(STORE, ()));
+            if report("after forgetting a store") {
+                return Err("forgotten store not reported".to_string());
+            }
+            Ok(())
+        })();
+        reset();
+        result
+    }
+}
@@ -0,0 +1,232 @@
+use gw_db::{
+    iter::{Direction, IteratorMode},
+    schema::Col,
+};
+use gw_store::traits::KVStore;
+use sparse_merkle_tree::{error::Error as SMTError, H256};
+
+// History records are keyed by the leaf key followed by the big endian round,
+// so the records of one key are adjacent and ordered by round.
+fn history_key(leaf_key: &H256, round: u64) -> Vec<u8> {
+    let mut key = leaf_key.as_slice().to_vec();
+    key.extend_from_slice(&round.to_be_bytes());
+    key
+}
+
+// Holds the horizon of the last trim. Shorter than any history key, so
+// history_get never finds it.
+const HORIZON_KEY: &[u8] = b"horizon";
+
+// Keeps the value a leaf had before it was first overwritten or removed in a
+// round, a zero value meaning the leaf did not exist. Stores call record
+// before changing a leaf when --leaf-history is on.
+pub struct LeafHistory {
+    col: Col,
+    round: u64,
+    pub reads: usize,
+    pub writes: usize,
+    // Bytes of keys and values written to the history column
+    pub bytes: usize,
+}
+
+impl LeafHistory {
+    pub fn new(col: Col, round: u64) -> Self {
+        Self {
+            col,
+            round,
+            reads: 0,
+            writes: 0,
+            bytes: 0,
+        }
+    }
+
+    pub fn record<DB: KVStore>(
+        &mut self,
+        store: &DB,
+        leaf_col: Col,
+        leaf_key: &H256,
+    ) -> Result<(), SMTError> {
+        let key = history_key(leaf_key, self.round);
+        self.reads += 1;
+        // Only the value from before the round counts, not intermediate ones
+        if store.get(self.col, &key).is_some() {
+            return Ok(());
+        }
+        self.reads += 1;
+        let old = match store.get(leaf_col, leaf_key.as_slice()) {
+            Some(slice) if slice.len() == 32 => slice.as_ref().to_vec(),
+            Some(_) => return Err(SMTError::Store("get corrupted leaf".to_string())),
+            None => vec![0u8; 32],
+        };
+        self.writes += 1;
+        self.bytes += key.len() + old.len();
+        store
+            .insert_raw(self.col, &key, &old)
+            .map_err(|err| SMTError::Store(format!("insert error {}", err)))
+    }
+}
+
+// Returns the value of a leaf at the end of the given round: the value
+// recorded by the first overwrite after it, or the current leaf if it has not
+// changed since.
+pub fn history_get<DB: KVStore>(
+    store: &DB,
+    history_col: Col,
+    leaf_col: Col,
+    leaf_key: &H256,
+    round: u64,
+) -> Option<H256> {
+    let start = history_key(leaf_key, round.saturating_add(1));
+    let record = store
+        .get_iter(history_col, IteratorMode::From(&start, Direction::Forward))
+        .find(|(key, _)| key.as_ref() >= start.as_slice())
+        .filter(|(key, _)| key.starts_with(leaf_key.as_slice()));
+    let value = match record {
+        Some((_, value)) => value.to_vec(),
+        None => store.get(leaf_col, leaf_key.as_slice())?.to_vec(),
+    };
+    if value.len() != 32 {
+        return None;
+    }
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(&value);
+    let value = H256::from(buf);
+    if value.is_zero() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+// Deletes the records of the rounds up to horizon, which history_get only
+// reads for rounds before it, and records the horizon. Returns the number of
+// records deleted.
+pub fn trim<DB: KVStore>(store: &DB, history_col: Col, horizon: u64) -> Result<usize, String> {
+    let horizon = horizon.max(self::horizon(store, history_col).unwrap_or(0));
+    let trimmed: Vec<Box<[u8]>> = store
+        .get_iter(history_col, IteratorMode::Start)
+        .map(|(key, _)| key)
+        // Big endian rounds compare like the numbers
+        .filter(|key| key.len() == 40 && key[32..] <= horizon.to_be_bytes()[..])
+        .collect();
+    for key in &trimmed {
+        store
+            .delete(history_col, key)
+            .map_err(|err| err.to_string())?;
+    }
+    store
+        .insert_raw(history_col, HORIZON_KEY, &horizon.to_be_bytes())
+        .map_err(|err| err.to_string())?;
+    Ok(trimmed.len())
+}
+
+// First round history_get still answers for after a trim, None before any
+pub fn horizon<DB: KVStore>(store: &DB, history_col: Col) -> Option<u64> {
+    let value = store.get(history_col, HORIZON_KEY)?;
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(value.get(..8)?);
+    Some(u64::from_be_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{old::CountingStore, trie::TrieStore};
+    use gw_store::Store as GwStore;
+    use sparse_merkle_tree::traits::Store;
+
+    // Sets key to value in round through either store, a zero value removing
+    // the key
+    fn set<DB: KVStore>(
+        tx: &DB,
+        counting: bool,
+        round: u64,
+        key: H256,
+        value: H256,
+    ) -> Result<(), String> {
+        let result = match (counting, value.is_zero()) {
+            (true, true) => CountingStore::new(tx)
+                .with_history(2, round)
+                .remove_leaf(&key),
+            (true, false) => CountingStore::new(tx)
+                .with_history(2, round)
+                .insert_leaf(key, value),
+            (false, true) => TrieStore::new(tx).with_history(2, round).remove_leaf(&key),
+            (false, false) => TrieStore::new(tx)
+                .with_history(2, round)
+                .insert_leaf(key, value),
+        };
+        result.map_err(|err| err.to_string())
+    }
+
+    fn check<DB: KVStore>(
+        tx: &DB,
+        key: &H256,
+        expected: &[(u64, Option<H256>)],
+    ) -> Result<(), String> {
+        for (round, expected) in expected {
+            let value = history_get(tx, 2, 1, key, *round);
+            if value != *expected {
+                return Err(format!(
+                    "round {} reads back as {:?} instead of {:?}",
+                    round, value, expected
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // A key written in round 1, overwritten in round 2 and removed in round 3
+    // must read back with the value it had at the end of each round, through
+    // either store.
+    #[test]
+    fn leaf_history() -> Result<(), String> {
+        let key = H256::from([4u8; 32]);
+        let (v1, v2) = (H256::from([1u8; 32]), H256::from([2u8; 32]));
+        for counting in [false, true] {
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let tx = store.begin_transaction();
+            set(&tx, counting, 1, key, v1)?;
+            set(&tx, counting, 2, key, v2)?;
+            set(&tx, counting, 3, key, H256::zero())?;
+            check(
+                &tx,
+                &key,
+                &[(0, None), (1, Some(v1)), (2, Some(v2)), (3, None)],
+            )?;
+        }
+        Ok(())
+    }
+
+    // Trimming to a horizon must delete the records up to it, keep every
+    // round from it on reading back as before, and never move the horizon
+    // back.
+    #[test]
+    fn trimmed_history() -> Result<(), String> {
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let tx = store.begin_transaction();
+        let key = H256::from([4u8; 32]);
+        let values: Vec<H256> = (1..=4u8).map(|v| H256::from([v; 32])).collect();
+        for (round, value) in values.iter().enumerate() {
+            set(&tx, false, round as u64 + 1, key, *value)?;
+        }
+        let trimmed = trim(&tx, 2, 2)?;
+        if trimmed != 2 {
+            return Err(format!("trimmed {} records, expected 2", trimmed));
+        }
+        check(
+            &tx,
+            &key,
+            &[
+                (2, Some(values[1])),
+                (3, Some(values[2])),
+                (4, Some(values[3])),
+            ],
+        )?;
+        trim(&tx, 2, 1)?;
+        if horizon(&tx, 2) != Some(2) {
+            return Err(format!("horizon {:?} after trimming to 1", horizon(&tx, 2)));
+        }
+        Ok(())
+    }
+}
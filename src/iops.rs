@@ -0,0 +1,100 @@
+use gw_db::{
+    error::Error,
+    iter::{DBIter, IteratorMode},
+    schema::Col,
+};
+use gw_store::traits::KVStore;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Seconds of operations the bucket holds when idle, so short bursts are not
+// throttled below the rate
+const BURST_SECS: f64 = 0.01;
+
+// Token bucket of --iops-limit, shared by every transaction of the run.
+// Each physical store operation takes one token, refilled at the limit.
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled: Instant,
+    // Operations taken and time spent waiting for tokens since the start
+    pub ops: usize,
+    pub throttled: Duration,
+}
+
+impl TokenBucket {
+    pub fn new(limit: u64) -> Self {
+        let rate = limit as f64;
+        let capacity = (rate * BURST_SECS).max(1.0);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            refilled: Instant::now(),
+            ops: 0,
+            throttled: Duration::default(),
+        }
+    }
+
+    // Takes a token, sleeping until one is refilled when the bucket is empty
+    fn take(&mut self) {
+        let now = Instant::now();
+        let refill = now.duration_since(self.refilled).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.capacity);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            let wait = Duration::from_secs_f64((1.0 - self.tokens) / self.rate);
+            thread::sleep(wait);
+            let slept = self.refilled.elapsed();
+            self.throttled += slept;
+            self.tokens += slept.as_secs_f64() * self.rate;
+            self.refilled = Instant::now();
+        }
+        self.tokens -= 1.0;
+        self.ops += 1;
+    }
+}
+
+// KVStore decorator holding every get, iterator, insert and delete to the
+// rate of a TokenBucket, passing them through unchanged without one
+pub struct IopsLimitedStore<'a, DB: KVStore> {
+    inner: &'a DB,
+    bucket: Option<Rc<RefCell<TokenBucket>>>,
+}
+
+impl<'a, DB: KVStore> IopsLimitedStore<'a, DB> {
+    pub fn new(inner: &'a DB, bucket: Option<Rc<RefCell<TokenBucket>>>) -> Self {
+        Self { inner, bucket }
+    }
+
+    fn take(&self) {
+        if let Some(bucket) = &self.bucket {
+            bucket.borrow_mut().take();
+        }
+    }
+}
+
+impl<'a, DB: KVStore> KVStore for IopsLimitedStore<'a, DB> {
+    fn get(&self, col: Col, key: &[u8]) -> Option<Box<[u8]>> {
+        self.take();
+        self.inner.get(col, key)
+    }
+
+    fn get_iter(&self, col: Col, mode: IteratorMode) -> DBIter<'_> {
+        self.take();
+        self.inner.get_iter(col, mode)
+    }
+
+    fn insert_raw(&self, col: Col, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.take();
+        self.inner.insert_raw(col, key, value)
+    }
+
+    fn delete(&self, col: Col, key: &[u8]) -> Result<(), Error> {
+        self.take();
+        self.inner.delete(col, key)
+    }
+}
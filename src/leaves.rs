@@ -0,0 +1,119 @@
+use sparse_merkle_tree::H256;
+use std::collections::HashSet;
+
+// Hash functions used by the counting Bloom filter
+const BLOOM_HASHES: usize = 7;
+// Counters allocated per key of the exact limit when switching to the filter
+const BLOOM_COUNTERS_PER_KEY: usize = 16;
+
+enum Membership {
+    Exact(HashSet<H256>),
+    // Counting Bloom filter, deletions decrement the counters of the key
+    Bloom(Vec<u8>),
+}
+
+// Tracks the number of distinct non-zero leaves written by the run. The count
+// is exact until more than `exact_limit` keys are present, after that
+// membership goes through a counting Bloom filter to bound memory, and false
+// positives make the count an estimate which slightly undercounts inserts.
+pub struct LeafCounter {
+    membership: Membership,
+    exact_limit: usize,
+    count: usize,
+}
+
+impl LeafCounter {
+    pub fn new(exact_limit: usize) -> Self {
+        Self {
+            membership: Membership::Exact(HashSet::new()),
+            exact_limit,
+            count: 0,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_estimate(&self) -> bool {
+        matches!(self.membership, Membership::Bloom(_))
+    }
+
+    // Accounts for a batch of writes, zero values delete the key
+    pub fn record(&mut self, pairs: &[(H256, H256)]) {
+        for (key, value) in pairs {
+            if value.is_zero() {
+                self.remove(key);
+            } else {
+                self.insert(key);
+            }
+        }
+        if let Membership::Exact(keys) = &self.membership {
+            if keys.len() > self.exact_limit {
+                let mut counters = vec![0u8; (self.exact_limit * BLOOM_COUNTERS_PER_KEY).max(1)];
+                for key in keys {
+                    for i in bloom_indices(key, counters.len()) {
+                        counters[i] = counters[i].saturating_add(1);
+                    }
+                }
+                self.membership = Membership::Bloom(counters);
+            }
+        }
+    }
+
+    fn insert(&mut self, key: &H256) {
+        match &mut self.membership {
+            Membership::Exact(keys) => {
+                if keys.insert(*key) {
+                    self.count += 1;
+                }
+            }
+            Membership::Bloom(counters) => {
+                let indices = bloom_indices(key, counters.len());
+                if indices.iter().any(|&i| counters[i] == 0) {
+                    for i in indices {
+                        counters[i] = counters[i].saturating_add(1);
+                    }
+                    self.count += 1;
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &H256) {
+        match &mut self.membership {
+            Membership::Exact(keys) => {
+                if keys.remove(key) {
+                    self.count -= 1;
+                }
+            }
+            Membership::Bloom(counters) => {
+                let indices = bloom_indices(key, counters.len());
+                if indices.iter().all(|&i| counters[i] > 0) {
+                    for i in indices {
+                        // Saturated counters can no longer be decremented
+                        // safely
+                        if counters[i] < u8::MAX {
+                            counters[i] -= 1;
+                        }
+                    }
+                    self.count = self.count.saturating_sub(1);
+                }
+            }
+        }
+    }
+}
+
+// Keys are hashes or random bytes already, so double hashing over two 64-bit
+// words of the key spreads them well enough.
+fn bloom_indices(key: &H256, len: usize) -> Vec<usize> {
+    let bytes = key.as_slice();
+    let mut h1 = [0u8; 8];
+    let mut h2 = [0u8; 8];
+    h1.copy_from_slice(&bytes[0..8]);
+    h2.copy_from_slice(&bytes[8..16]);
+    let (h1, h2) = (u64::from_le_bytes(h1), u64::from_le_bytes(h2) | 1);
+    (0..BLOOM_HASHES as u64)
+        .map(|i| (h1.wrapping_add(i.wrapping_mul(h2)) % len as u64) as usize)
+        .collect()
+}
@@ -0,0 +1,308 @@
+use crate::{
+    config::Config,
+    dot::hex,
+    path::parse_h256,
+    trie::TrieStore,
+    writes::{self, Record},
+    TrieSmt,
+};
+use gw_db::schema::Col;
+use gw_store::Store as GwStore;
+use sparse_merkle_tree::H256;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+// One round of a run as a line of a lineage file: the run-wide round
+// number, the roots before and after it, and how many transactions of the
+// write dump were committed by its end
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub round: u64,
+    pub parent_root: H256,
+    pub new_root: H256,
+    pub transactions: usize,
+}
+
+impl Entry {
+    pub(crate) fn to_json(&self) -> String {
+        format!(
+            "{{\"round\": {}, \"parent_root\": \"{}\", \"new_root\": \"{}\", \"transactions\": {}}}",
+            self.round,
+            hex(&self.parent_root),
+            hex(&self.new_root),
+            self.transactions
+        )
+    }
+
+    // Reads back a line written by to_json
+    pub(crate) fn parse(line: &str) -> Result<Self, String> {
+        let fields: BTreeMap<&str, &str> = line
+            .trim()
+            .trim_start_matches('{')
+            .trim_end_matches('}')
+            .split(", ")
+            .filter_map(|field| {
+                let (name, value) = field.split_once(": ")?;
+                Some((name.trim_matches('"'), value.trim_matches('"')))
+            })
+            .collect();
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .copied()
+                .ok_or_else(|| format!("no {} in {}", name, line))
+        };
+        let number = |name: &str| {
+            field(name)?
+                .parse::<u64>()
+                .map_err(|err| format!("{}: {}", name, err))
+        };
+        Ok(Self {
+            round: number("round")?,
+            parent_root: parse_h256(field("parent_root")?)?,
+            new_root: parse_h256(field("new_root")?)?,
+            transactions: number("transactions")? as usize,
+        })
+    }
+}
+
+// The lineage file of --lineage. Written through at every round, a crashed
+// run leaves every round it finished in the file.
+pub struct LineageFile {
+    out: BufWriter<File>,
+}
+
+impl LineageFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        writeln!(self.out, "{}", entry.to_json())?;
+        self.out.flush()
+    }
+}
+
+// The leaf a write of the leaf column stores, keys and values being 32
+// bytes and a delete storing zero
+fn leaf(key: &[u8], value: Option<&[u8]>) -> Result<(H256, H256), String> {
+    let h256 = |bytes: &[u8]| {
+        if bytes.len() != 32 {
+            return Err(format!("a {} byte leaf key or value", bytes.len()));
+        }
+        let mut h = [0u8; 32];
+        h.copy_from_slice(bytes);
+        Ok(H256::from(h))
+    };
+    Ok((h256(key)?, value.map_or(Ok(H256::zero()), h256)?))
+}
+
+// Replays the rounds of the lineage file from an empty tree in a temporary
+// store, each the leaves its transactions of the write dump wrote to
+// leaf_col, and checks that every round starts from the root the previous
+// one ended with and ends with the root it records. Both files are read a
+// line and a transaction at a time. Returns the rounds and the last root.
+pub(crate) fn check(
+    lineage: &Path,
+    workload: &Path,
+    leaf_col: Col,
+) -> Result<(usize, H256), String> {
+    let input = File::open(lineage).map_err(|err| format!("{}: {}", lineage.display(), err))?;
+    let mut transactions = writes::open(workload)?;
+    let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+    let mut root = H256::zero();
+    let mut replayed = 0;
+    let mut rounds = 0;
+    for (i, line) in BufReader::new(input).lines().enumerate() {
+        let line = line.map_err(|err| err.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = Entry::parse(&line).map_err(|err| format!("line {}: {}", i + 1, err))?;
+        if entry.parent_root != root {
+            return Err(format!(
+                "round {} starts from root {}, the rounds before it end with {}{}",
+                entry.round,
+                hex(&entry.parent_root),
+                hex(&root),
+                if rounds == 0 {
+                    ", the replay starts from an empty tree"
+                } else {
+                    ""
+                }
+            ));
+        }
+        if entry.transactions < replayed {
+            return Err(format!(
+                "round {} ends after {} transactions, the rounds before it after {}",
+                entry.round, entry.transactions, replayed
+            ));
+        }
+        // Later writes of a leaf replace earlier ones
+        let mut leaves = BTreeMap::new();
+        while replayed < entry.transactions {
+            let records = transactions.next().ok_or_else(|| {
+                format!(
+                    "round {} ends after {} transactions, the workload has {}",
+                    entry.round, entry.transactions, replayed
+                )
+            })??;
+            for record in records {
+                let (key, value) = match &record {
+                    Record::Put(col, key, value) if *col == leaf_col => leaf(key, Some(value))?,
+                    Record::Delete(col, key) if *col == leaf_col => leaf(key, None)?,
+                    _ => continue,
+                };
+                leaves.insert(key, value);
+            }
+            replayed += 1;
+        }
+        if !leaves.is_empty() {
+            let tx = store.begin_transaction();
+            let mut smt = TrieSmt::new(root, TrieStore::new(&tx));
+            smt.update_all(leaves.into_iter().collect())
+                .map_err(|err| format!("round {}: {}", entry.round, err))?;
+            root = *smt.root();
+            tx.commit().map_err(|err| err.to_string())?;
+        }
+        if root != entry.new_root {
+            return Err(format!(
+                "round {} replays to root {}, the lineage records {}",
+                entry.round,
+                hex(&root),
+                hex(&entry.new_root)
+            ));
+        }
+        rounds += 1;
+    }
+    let left = transactions.count();
+    if left > 0 {
+        return Err(format!(
+            "the workload has {} transactions after the last round",
+            left
+        ));
+    }
+    Ok((rounds, root))
+}
+
+// The verify-lineage command: checks the --lineage file of a run against
+// the write dump of --workload
+pub fn verify(config: &Config) {
+    let lineage = config
+        .lineage
+        .as_ref()
+        .expect("verify-lineage requires --lineage");
+    let workload = config
+        .workload
+        .as_ref()
+        .expect("verify-lineage requires --workload");
+    match check(lineage, workload, config.leaf_col) {
+        Ok((rounds, root)) => println!(
+            "Lineage of {} rounds verified against {}, ending with root {}",
+            rounds,
+            workload.display(),
+            hex(&root)
+        ),
+        Err(err) => {
+            eprintln!("Lineage {} does not verify: {}", lineage.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        phase::{Phase, PhaseKind},
+        runner::{tests::serial, Runner},
+        seed::SeedTree,
+    };
+
+    // The lineage of a run with injected faults must replay from its write
+    // dump, rounds rolled back included. A lineage with a root changed, a
+    // round missing or more rounds than the dump has transactions for must
+    // not.
+    #[test]
+    fn root_lineage() -> Result<(), String> {
+        let _serial = serial();
+        let dir = std::env::temp_dir();
+        let dump = dir.join(format!("smt-bench-lineage-{}.bin", std::process::id()));
+        let path = dir.join(format!("smt-bench-lineage-{}.ndjson", std::process::id()));
+        let config = Config {
+            report_every: 0,
+            fault_rate: 0.00005,
+            dump_writes: Some(dump.clone()),
+            lineage: Some(path.clone()),
+            ..Default::default()
+        };
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let mut runner = Runner::new(&config, &store, SeedTree::new(0, false));
+        for phase in [
+            Phase::new(PhaseKind::Load, 2, 20),
+            Phase::new(PhaseKind::Update, 2, 10),
+            Phase::new(PhaseKind::Read, 1, 5),
+            Phase::new(PhaseKind::Delete, 1, 5),
+        ] {
+            runner.run_phase(&phase);
+        }
+        runner.finish();
+        let outcome = (|| {
+            let (rounds, root) = check(&path, &dump, config.leaf_col)
+                .map_err(|err| format!("lineage of the run: {}", err))?;
+            if rounds != 6 || root != runner.root() {
+                return Err(format!(
+                    "{} rounds replayed to {:?}, the run had 6 ending with {:?}",
+                    rounds,
+                    root,
+                    runner.root()
+                ));
+            }
+            let text = std::fs::read_to_string(&path).map_err(|err| err.to_string())?;
+            let lines: Vec<String> = text.lines().map(str::to_string).collect();
+            let last = Entry::parse(&lines[lines.len() - 1])?;
+            if last.to_json() != lines[lines.len() - 1] {
+                return Err(format!(
+                    "{} parsed back as {:?}",
+                    lines[lines.len() - 1],
+                    last
+                ));
+            }
+            let mut changed = lines.clone();
+            let mut entry = Entry::parse(&lines[2])?;
+            entry.new_root = H256::zero();
+            changed[2] = entry.to_json();
+            let mut missing = lines.clone();
+            missing.remove(1);
+            // A round after the last one, of a transaction the dump does not have
+            let mut extra = lines.clone();
+            extra.push(
+                Entry {
+                    round: last.round + 1,
+                    parent_root: last.new_root,
+                    transactions: last.transactions + 1,
+                    ..last
+                }
+                .to_json(),
+            );
+            for (name, broken) in [
+                ("changed root", changed),
+                ("missing round", missing),
+                ("extra round", extra),
+            ] {
+                std::fs::write(&path, broken.join("\n")).map_err(|err| err.to_string())?;
+                if check(&path, &dump, config.leaf_col).is_ok() {
+                    return Err(format!("a lineage with a {} verified", name));
+                }
+            }
+            Ok(())
+        })();
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&dump);
+        outcome
+    }
+}
@@ -1,18 +1,89 @@
+mod analysis;
+mod batching;
+mod build_info;
+mod cache_advisor;
+mod churn;
+mod compaction;
+mod config;
+mod conflict;
+mod contract;
+mod corpus;
+mod determinism;
+mod differential;
+mod dot;
+mod encoding;
+mod drop_caches;
+mod durability;
+mod faulty;
+mod fingerprint;
+mod footprint;
+mod grid;
+mod growth;
+mod guard;
+mod handles;
+mod history;
+mod iops;
+mod leaves;
+mod lineage;
+mod markdown;
+mod markers;
+mod meta;
+mod merge_op;
+mod numa;
 mod old;
+mod pack_bench;
+mod path;
+mod phase;
+mod profile;
+mod proof_file;
+#[cfg(feature = "proxy")]
+mod proxy;
+mod readonly;
+#[cfg(feature = "remote")]
+mod remote;
+mod reorder;
+mod repair;
+mod replica;
+mod resume;
+mod results;
+mod rocksdb_options;
+mod rmw;
+mod runner;
+mod scan;
+mod schedule;
+mod seed;
+mod selftest;
+mod stages;
+mod suite;
+mod tiered;
 mod trie;
+#[cfg(feature = "tui")]
+mod tui;
 mod utils;
+mod verify;
+mod violations;
+mod writes;
 
 // extern crate cpuprofiler;
 
-use crate::{old::CountingStore, trie::TrieStore};
+use crate::{
+    config::{
+        Command, Config, DropCaches, Experiment, OutputFormat, PauseCompaction, SmtLifetime,
+        StoreKind,
+    },
+    old::CountingStore,
+    phase::{Phase, PhaseKind},
+    runner::{stop_requested, Runner},
+    seed::SeedTree,
+    stages::StageTimer,
+    trie::{TrieStore, LEAF_KEY_SIZE},
+};
 use gw_config::StoreConfig;
 use gw_db::RocksDB;
 use gw_store::Store as GwStore;
-use rand_chacha::{
-    rand_core::{RngCore, SeedableRng},
-    ChaCha20Rng,
-};
+use rand_chacha::rand_core::RngCore;
 use sparse_merkle_tree::{blake2b::Blake2bHasher, SparseMerkleTree, H256};
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 fn random_h256(rng: &mut impl RngCore) -> H256 {
@@ -21,68 +92,575 @@ fn random_h256(rng: &mut impl RngCore) -> H256 {
     buf.into()
 }
 
-type SMT<'a, DB> = SparseMerkleTree<Blake2bHasher, H256, CountingStore<'a, DB>>;
-type SMT2<'a, DB> = SparseMerkleTree<Blake2bHasher, H256, TrieStore<'a, DB>>;
+type CountingSmt<'a, DB> = SparseMerkleTree<Blake2bHasher, H256, CountingStore<'a, DB>>;
+type TrieSmt<'a, DB> = SparseMerkleTree<Blake2bHasher, H256, TrieStore<'a, DB>>;
 
 fn main() {
     // use cpuprofiler::PROFILER;
     // PROFILER.lock().unwrap().start("./my-prof.profile").unwrap();
 
-    let mut rng = ChaCha20Rng::seed_from_u64(0);
+    let mut stages = StageTimer::new();
+    let config = Config::from_args();
+    // Declared before every handle, so it is dropped after all of them
+    let _handles = handles::ExitCheck;
+    let _violations = violations::ExitCheck;
+    if config.debug_handles {
+        handles::enable();
+    }
+    if config.audit_seeds {
+        seed::enable_audit();
+    }
+    if let Err(err) = build_info::guard(config.command, config.allow_debug, cfg!(debug_assertions)) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+    if config.command == Command::VerifySizes {
+        verify::value_size_sweep(&config);
+        return;
+    }
+    if config.command == Command::GenCorpus {
+        if let Err(err) = corpus::generate(&config) {
+            eprintln!("Cannot write the corpus: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if config.command == Command::ShowResults {
+        let path = config
+            .input_file
+            .as_ref()
+            .expect("show-results requires a results file");
+        match results::render(path) {
+            Ok(lines) => lines.iter().for_each(|line| println!("{}", line)),
+            Err(err) => {
+                eprintln!("Cannot read the results: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if config.command == Command::VerifyProof {
+        proof_file::run(&config);
+        return;
+    }
+    if config.command == Command::PackBench {
+        pack_bench::compare_pack_key(&config);
+        return;
+    }
+    if config.command == Command::Bench && config.self_test {
+        stages.lap("startup");
+        match selftest::run(&config) {
+            Ok(elapsed) => println!("Self-test passed in {:?}", elapsed),
+            Err(err) => {
+                eprintln!("Self-test of {:?} TrieStore failed: {}", config.trie_layout, err);
+                eprintln!("The store or its serialization is broken, see `cargo test`");
+                std::process::exit(1);
+            }
+        }
+    }
+    stages.lap(if config.command == Command::Bench && config.self_test {
+        "self-test"
+    } else {
+        "startup"
+    });
+    // Pinned before RocksDB starts its threads and allocates its cache, both
+    // inherit the affinity and the memory policy
+    let pinning = config.pin_core.map(pin);
+    if let Some(pinning) = &pinning {
+        println!("Pinning: {}", pinning);
+    }
+    if config.command == Command::ReplayWrites {
+        writes::run(&config);
+        return;
+    }
+    if config.command == Command::VerifyLineage {
+        lineage::verify(&config);
+        return;
+    }
+    #[cfg(feature = "remote")]
+    if let Some(addr) = &config.serve {
+        remote::serve(&config, addr);
+        return;
+    }
+    if let Some(suite) = config.suite {
+        suite::run(&config, suite);
+        return;
+    }
+    if !config.grid.is_empty() {
+        grid::run(&config);
+        return;
+    }
+    if !config.profile_compare.is_empty() {
+        profile::compare(&config, &config.profile_compare);
+        return;
+    }
+    let seeds = SeedTree::new(config.seed, config.verbose);
+
+    // gw_db only opens a database for writing, which takes its lock, so
+    // --read-only opens it through RocksDB directly
+    if config.read_only {
+        readonly::run(&config, seeds);
+        return;
+    }
 
     // let store = GwStore::open_tmp().unwrap();
-    let config2 = StoreConfig{path: PathBuf::from("./store2.db".to_string()), ..Default::default()};
-    let db2 = RocksDB::open(&config2, 10);
-    let store2 = GwStore::new(db2);
+    let config2 = store_config(&config, config.db_path.clone());
+    let db2 = RocksDB::open(&config2, config.columns);
+    if let Some(path) = &config.rocksdb_options_file {
+        if let Err(err) = rocksdb_options::verify(path, &config.db_path) {
+            eprintln!(
+                "The database did not apply the options of {}: {}",
+                path.display(),
+                err
+            );
+            std::process::exit(1);
+        }
+    }
+    let store2 = handles::track(handles::STORE, GwStore::new(db2.clone()));
+    meta::check(&config);
+    stages.lap("database open");
+    if config.command == Command::Bench {
+        let fingerprint = fingerprint::fingerprint(&config);
+        println!("Fingerprint: {}", fingerprint);
+        if config.skip_if_run {
+            if let Some(prior) = meta::prior_run(&config, &fingerprint) {
+                println!("Skipping, this configuration already ran here: {}", prior);
+                return;
+            }
+        }
+    }
 
-    // Initializing
-    let root = {
-        // let tx = store.begin_transaction();
-        // let store = CountingStore::new(&tx);
-        // let mut smt = SMT::new(H256::default(), store);
+    if config.command == Command::Dot {
+        let tx2 = store2.begin_transaction();
+        let store2 = TrieStore::with_layout(&tx2, config.trie_layout)
+            .with_columns(config.branch_col, config.leaf_col);
+        dot::write_dot(&store2, config.max_nodes, &mut std::io::stdout()).unwrap();
+        return;
+    }
+    if config.command == Command::Path {
+        let key = config.key.expect("path requires --key");
+        let tx2 = store2.begin_transaction();
+        let out = &mut std::io::stdout();
+        match config.store {
+            StoreKind::Trie => {
+                let store2 = TrieStore::with_layout(&tx2, config.trie_layout)
+                    .with_columns(config.branch_col, config.leaf_col);
+                path::write_path(&store2, &key, true, config.json, out).unwrap();
+            }
+            StoreKind::Counting => {
+                let store2 =
+                    CountingStore::new(&tx2).with_columns(config.branch_col, config.leaf_col);
+                path::write_path(&store2, &key, false, config.json, out).unwrap();
+            }
+        }
+        return;
+    }
+
+    if config.command == Command::Repair {
+        repair::rebuild_branches(&config, &store2);
+        return;
+    }
 
+    if config.command == Command::EncodingSizes {
+        encoding::compare_sizes(&config, &store2);
+        return;
+    }
+
+    if config.command == Command::HistoryGet {
+        let key = config.key.expect("history-get requires --key");
+        let round = config.round.expect("history-get requires --round");
         let tx2 = store2.begin_transaction();
-        let store2 = TrieStore::new(&tx2);
-        let mut smt2 = SMT2::new(H256::default(), store2);
-
-        for _ in 0..200 {
-            let key = random_h256(&mut rng);
-            let value = random_h256(&mut rng);
-            // smt.update(key, value).unwrap();
-            smt2.update(key, value).unwrap();
-        }
-        // assert_eq!(smt.root(), smt2.root());
-        let root = smt2.root().clone();
-
-        // tx.commit().unwrap();
-        tx2.commit().unwrap();
-
-        root
-    };
-
-    // Testing
-    let mut pairs = vec![];
-    for _ in 0..10000 {
-        let key = random_h256(&mut rng);
-        let value = random_h256(&mut rng);
-        pairs.push((key, value));
-    }
-
-    // let tx = store.begin_transaction();
-    // let store = CountingStore::new(&tx);
-    // let mut smt = SMT::new(root, store);
-    // smt.update_all(pairs.clone()).unwrap();
-    // smt.store().stats();
-    // tx.commit().unwrap();
-
-    println!("Begin transaction");
-    let tx2 = store2.begin_transaction();
-    let store2 = TrieStore::new(&tx2);
-    let mut smt2 = SMT2::new(root, store2);
-    println!("Update all");
-    smt2.update_all(pairs).unwrap();
-    smt2.store().stats();
-    tx2.commit().unwrap();
-
-    // assert_eq!(smt.root(), smt2.root());
+        // The records telling the older rounds apart are gone
+        if let Some(horizon) = history::horizon(&tx2, config.history_col).filter(|h| round < *h) {
+            eprintln!(
+                "Round {} is before the trimmed history, which starts at round {}",
+                round, horizon
+            );
+            std::process::exit(1);
+        }
+        match history::history_get(&tx2, config.history_col, config.leaf_col, &key, round) {
+            Some(value) => println!("{}", dot::hex(&value)),
+            None => println!("none"),
+        }
+        return;
+    }
+
+    // Dot and path output go to stdout, so only the bench reports the build
+    // and the cache size
+    println!("Build: {}", build_info::label());
+    println!("Dependencies: {}", build_info::deps_label());
+    match config.block_cache_mb {
+        Some(mb) => println!("Block cache: {} MiB", mb),
+        None => println!("Block cache: gw_db default"),
+    }
+    if let Some(path) = &config.rocksdb_options_file {
+        println!(
+            "RocksDB options: {}, hash {}",
+            path.display(),
+            rocksdb_options::hash(path).unwrap()
+        );
+    }
+
+    if let Some(runs) = config.determinism_check {
+        determinism::run(&config, runs);
+        return;
+    }
+    if config.growth_profile {
+        growth::run(&config, &store2);
+        return;
+    }
+    if config.scan {
+        scan::run(&config, &store2);
+        return;
+    }
+
+    #[cfg(feature = "proxy")]
+    if let Some(socket) = &config.proxy_socket {
+        proxy::run(&config, socket, &store2);
+        stages.lap("proxy comparison");
+        if config.stage_times {
+            stages.print();
+        }
+        return;
+    }
+
+    if let Some(experiment) = config.experiment {
+        let label = match experiment {
+            Experiment::Churn => {
+                churn::run(&config, &store2, &db2);
+                "churn experiment"
+            }
+            Experiment::WriteConflict => {
+                conflict::run(&config, &store2);
+                "write-conflict experiment"
+            }
+            Experiment::Durability => {
+                durability::run(&config);
+                "durability experiment"
+            }
+            Experiment::UpdateVsBatch => {
+                batching::run(&config);
+                "update-vs-batch experiment"
+            }
+            Experiment::TrieRmw => {
+                rmw::run(&config);
+                "trie-rmw experiment"
+            }
+            Experiment::Reorder => {
+                reorder::run(&config);
+                "reorder experiment"
+            }
+            Experiment::Differential => {
+                differential::run(&config);
+                "differential experiment"
+            }
+            Experiment::Tiered => {
+                tiered::run(&config);
+                "tiered experiment"
+            }
+        };
+        stages.lap(label);
+        if config.stage_times {
+            stages.print();
+        }
+        return;
+    }
+
+    let mut phases = config.phases.clone();
+    if config.leaf_gets > 0 {
+        phases.push(Phase::new(PhaseKind::Read, 1, config.leaf_gets));
+    }
+
+    // A database holding a tree is only continued with --resume, the phases
+    // would build a second tree on top of it otherwise
+    let fingerprint = fingerprint::fingerprint(&config);
+    let checkpoint = resume::start(&config, &store2, &fingerprint);
+
+    let mut runner = Runner::new(&config, &store2, seeds);
+    let mut first_phase = 0;
+    runner.checkpoint(fingerprint);
+    if let Some(checkpoint) = &checkpoint {
+        let (phase, round) = resume::position(checkpoint, &phases);
+        if let Some(sampling) = resume::sampling_phase(&phases, phase) {
+            eprintln!(
+                "--resume: phase {} draws keys in the order the interrupted run wrote them, which is not kept, start the run over instead",
+                sampling
+            );
+            std::process::exit(1);
+        }
+        runner.resume(checkpoint, phase, round);
+        if phase < phases.len() {
+            println!(
+                "Resuming after round {} of the run, {} rounds into phase {} of {}, from root {}",
+                checkpoint.rounds_run,
+                round,
+                phase + 1,
+                phases.len(),
+                dot::hex(&checkpoint.root)
+            );
+        } else {
+            println!(
+                "Resuming after round {} of the run, every phase is done, root {}",
+                checkpoint.rounds_run,
+                dot::hex(&checkpoint.root)
+            );
+        }
+        first_phase = phase.min(phases.len());
+    }
+    if let Some(path) = &config.keys_file {
+        let corpus = corpus::Corpus::load(path).unwrap_or_else(|err| panic!("{}", err));
+        println!(
+            "Keys file {}: {} pairs generated with seed {}",
+            path.display(),
+            corpus.count,
+            corpus.seed
+        );
+        runner.set_corpus(corpus);
+    }
+    if config.pause_compaction == Some(PauseCompaction::DuringRounds) {
+        runner.pause_compaction(db2.clone());
+    }
+    runner.track_db_stats(db2.clone());
+    if let Some(path) = &config.violations_out {
+        let log = violations::ViolationLog::create(path)
+            .unwrap_or_else(|err| panic!("Cannot create {}: {}", path.display(), err));
+        runner.add_sink(Box::new(log));
+    }
+    #[cfg(feature = "tui")]
+    if config.tui {
+        runner.add_sink(Box::new(tui::Dashboard::new()));
+    }
+    runner::stop_on_ctrl_c();
+    let sampler = config.profile_phases.then(markers::Sampler::start);
+    stages.lap("initialization");
+    let mut reports: Vec<runner::PhaseReport> = vec![];
+    // What --compact-after-deletes measured, reported with the findings
+    let mut compactions = vec![];
+    for phase in &phases[first_phase..] {
+        let report = runner.run_phase(phase);
+        if !config.tui {
+            report.print();
+            if let Some(previous) = reports.last() {
+                report.print_transition(previous);
+            }
+        }
+        reports.push(report);
+        stages.lap(format!("phase {}", phase));
+        if config.drop_caches == Some(DropCaches::BeforeReads) && phase.kind == PhaseKind::Read {
+            match drop_caches::drop_system_cache() {
+                Ok(()) => {
+                    let cold = runner.rerun_phase(phase);
+                    let warm = reports.last().unwrap();
+                    if !config.tui {
+                        cold.print();
+                        println!(
+                            "  Page cache: warm mean round {:?}, cold mean round {:?} ({:.2}x)",
+                            warm.mean_elapsed(),
+                            cold.mean_elapsed(),
+                            cold.mean_elapsed().as_secs_f64()
+                                / warm.mean_elapsed().as_secs_f64().max(f64::EPSILON)
+                        );
+                    }
+                    reports.push(cold);
+                    stages.lap(format!("phase {} cold", phase));
+                }
+                Err(err) => println!(
+                    "Skipping the cold run of {}, cannot drop the page cache: {}",
+                    phase, err
+                ),
+            }
+        }
+        if config.compact_after_deletes && phase.kind == PhaseKind::Delete {
+            let deletes = reports.len() - 1;
+            let probe = compaction::tombstone_probe();
+            let read = runner.run_probe(&probe, "tombstone-probe");
+            let elapsed = footprint::compact(&db2, &config);
+            let compacted = runner.run_probe(&probe, "tombstone-probe");
+            if !config.tui {
+                read.print();
+                println!("  Compacted the tree's columns in {:?}", elapsed);
+                compacted.print();
+            }
+            compactions.push(analysis::compaction_finding(
+                &reports[deletes],
+                &read,
+                &compacted,
+            ));
+            reports.push(read);
+            reports.push(compacted);
+            stages.lap(format!("phase {} compaction", phase));
+        }
+        if stop_requested() {
+            println!("Stop requested, skipping remaining phases");
+            break;
+        }
+    }
+    runner.finish();
+    stages.lap("replica catch-up");
+    if let Some(lifetime) = config.smt_lifetime {
+        let lifetime = match lifetime {
+            SmtLifetime::PerChunk => "per-chunk",
+            SmtLifetime::PerRound => "per-round",
+        };
+        match footprint::peak_rss() {
+            Some(bytes) => println!(
+                "SMT lifetime {}: peak RSS {:.1} MiB",
+                lifetime,
+                bytes as f64 / (1 << 20) as f64
+            ),
+            None => println!("SMT lifetime {}: peak RSS unavailable", lifetime),
+        }
+    }
+    if let Some(transactions) = runner.dumped_transactions() {
+        println!(
+            "Dumped the writes of {} transactions to {}",
+            transactions,
+            config.dump_writes.as_ref().unwrap().display()
+        );
+    }
+    if let Some(sampler) = sampler {
+        sampler.finish();
+    }
+    if config.tui {
+        for (i, report) in reports.iter().enumerate() {
+            report.print();
+            if i > 0 {
+                report.print_transition(&reports[i - 1]);
+            }
+        }
+    }
+    let cache_curve = runner.access_trace().map_or(vec![], |trace| {
+        let trace = trace.borrow();
+        let curve = trace.curve();
+        if !config.json {
+            cache_advisor::print_curve(&trace, &curve);
+        }
+        curve
+    });
+    let mut findings = analysis::findings(&reports);
+    findings.extend(compactions);
+    analysis::print_findings(
+        &findings,
+        &analysis::steady_states(&reports, config.steady_after),
+        &cache_curve,
+        analysis::init_cost(&reports).as_ref(),
+        &config,
+    );
+
+    let last_round = reports.iter().rev().find_map(|r| r.rounds.last());
+    let leaves = last_round.map_or("0".to_string(), |r| r.leaves_label());
+    if let Err(err) = meta::write(&config, &runner.root(), &leaves) {
+        eprintln!("Warning: cannot write the run metadata: {}", err);
+    }
+    if config.output == OutputFormat::Binary {
+        match results::write(&config, &reports) {
+            Ok(path) => println!("Results written to {}", path.display()),
+            Err(err) => eprintln!("Warning: cannot write the results: {}", err),
+        }
+    }
+    stages.lap("analysis");
+
+    if config.final_compact {
+        let elapsed = footprint::compact(&db2, &config);
+        println!("Final compaction took {:?}", elapsed);
+        footprint::print_footprint(
+            &footprint::footprint(&db2, &config),
+            last_round.map_or(0, |r| r.leaves),
+            &leaves,
+        );
+        stages.lap("final compaction");
+    }
+
+    if config.disk_size {
+        // compact_range flushes the memtables before compacting, so the
+        // directory holds every write of the run as SST files
+        if !config.final_compact {
+            footprint::compact(&db2, &config);
+        }
+        let live = last_round.map_or(0, |r| r.leaves);
+        footprint::print_disk_size("store", &config.db_path, live);
+        if let Some((_, path)) = &config.replicate_to {
+            footprint::print_disk_size("replica", path, live);
+        }
+        stages.lap("disk size");
+    }
+
+    if let Some(bytes) = config.leaf_key_bytes {
+        let collisions: usize = reports
+            .iter()
+            .flat_map(|report| &report.rounds)
+            .map(|round| round.leaf_collisions)
+            .sum();
+        // Collided leaves keep their full key, so the saving is an upper bound
+        println!(
+            "Leaf keys truncated to {} bytes: up to {} key bytes fewer for {} live leaves, {} collisions",
+            bytes,
+            (LEAF_KEY_SIZE - bytes) * last_round.map_or(0, |r| r.leaves),
+            leaves,
+            collisions
+        );
+    }
+
+    if config.compaction_stats {
+        print_compaction_stats(&db2, &config);
+    }
+
+    if config.audit_seeds {
+        seed::print_audit();
+    }
+    if config.stage_times {
+        stages.print();
+    }
+}
+
+fn store_config(config: &Config, path: PathBuf) -> StoreConfig {
+    // gw_db applies these through set_options once the database is open
+    let mut options = HashMap::new();
+    if let Some(n) = config.max_background_compactions {
+        options.insert("max_background_compactions".to_string(), n.to_string());
+    }
+    if let Some(n) = config.max_background_flushes {
+        options.insert("max_background_flushes".to_string(), n.to_string());
+    }
+    StoreConfig {
+        path,
+        cache_size: config.block_cache_mb.map(|mb| mb << 20),
+        options,
+        options_file: config.rocksdb_options_file.clone(),
+    }
+}
+
+// Pins the bench to core and binds its memory to the core's NUMA node,
+// returning what was done for the report
+fn pin(core: usize) -> String {
+    if let Err(err) = numa::pin_to_core(core) {
+        panic!("Cannot pin to core {}: {}", core, err);
+    }
+    match numa::node_of_core(core) {
+        Some(node) => match numa::bind_memory(node) {
+            Ok(()) => format!("core {}, NUMA node {}, memory bound to it", core, node),
+            Err(err) => format!(
+                "core {}, NUMA node {}, memory not bound: {}",
+                core, node, err
+            ),
+        },
+        None => format!("core {}, no NUMA node found, memory not bound", core),
+    }
+}
+
+// Dumps RocksDB's statistics for the branch and leaf columns, which include
+// the bytes and time spent in compaction
+fn print_compaction_stats(db: &RocksDB, config: &Config) {
+    for (name, col) in [("branch", config.branch_col), ("leaf", config.leaf_col)] {
+        match db.property_value(col, "rocksdb.stats") {
+            Ok(Some(stats)) => println!("RocksDB stats of the {} column:\n{}", name, stats),
+            Ok(None) => println!("RocksDB stats of the {} column: unavailable", name),
+            Err(err) => println!("RocksDB stats of the {} column: {}", name, err),
+        }
+    }
+    if let Ok(Some(pending)) =
+        db.property_int_value(config.branch_col, "rocksdb.estimate-pending-compaction-bytes")
+    {
+        println!("Pending compaction bytes: {}", pending);
+    }
 }
@@ -0,0 +1,42 @@
+use crate::{build_info, config::Config};
+
+// The command line and the settings the numbers depend on, as a fenced
+// block printed above a table so a pasted comparison says what produced it
+pub fn print_preamble(config: &Config) {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let block_cache = match config.block_cache_mb {
+        Some(mb) => format!("{} MiB", mb),
+        None => "gw_db default".to_string(),
+    };
+    println!("```");
+    println!("smt-bench {} {}", env!("CARGO_PKG_VERSION"), args.join(" "));
+    println!("build: {}", build_info::label());
+    println!("dependencies: {}", build_info::deps_label());
+    println!(
+        "seed: {}, trie layout: {}, block cache: {}",
+        config.seed,
+        format!("{:?}", config.trie_layout).to_lowercase(),
+        block_cache
+    );
+    println!("```");
+    println!();
+}
+
+// Prints a GitHub-flavored Markdown table, every column but the first
+// holding numbers and aligned right
+pub fn print_table(header: &[String], rows: &[Vec<String>]) {
+    println!("{}", table_row(header));
+    let align: Vec<&str> = (0..header.len())
+        .map(|i| if i == 0 { "---" } else { "---:" })
+        .collect();
+    println!("|{}|", align.join("|"));
+    for row in rows {
+        println!("{}", table_row(row));
+    }
+}
+
+// A pipe inside a cell would end it early
+fn table_row(cells: &[String]) -> String {
+    let cells: Vec<String> = cells.iter().map(|c| c.replace('|', "\\|")).collect();
+    format!("| {} |", cells.join(" | "))
+}
@@ -0,0 +1,162 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Time between two --profile-phases samples
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Marker {
+    // Outside any marked code
+    Other = 0,
+    Init = 1,
+    Round = 2,
+    Commit = 3,
+    Verify = 4,
+}
+
+const MARKERS: [Marker; 5] = [
+    Marker::Other,
+    Marker::Init,
+    Marker::Round,
+    Marker::Commit,
+    Marker::Verify,
+];
+
+impl Marker {
+    fn label(self) -> &'static str {
+        match self {
+            Marker::Other => "other",
+            Marker::Init => "init",
+            Marker::Round => "round",
+            Marker::Commit => "commit",
+            Marker::Verify => "verify",
+        }
+    }
+}
+
+// Marker of the code the bench thread runs. Only the bench thread moves it,
+// the sampler thread reads it.
+static CURRENT: AtomicU8 = AtomicU8::new(Marker::Other as u8);
+
+pub fn current() -> Marker {
+    MARKERS[CURRENT.load(Ordering::Relaxed) as usize]
+}
+
+fn enter<T>(marker: Marker, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT.swap(marker as u8, Ordering::Relaxed);
+    let result = f();
+    CURRENT.store(previous, Ordering::Relaxed);
+    result
+}
+
+// Each phase runs inside a function of its own that is never inlined, so
+// profilers attribute the samples of a phase to a frame named after it.
+// Nested markers, a commit inside a round, take over until they return.
+#[inline(never)]
+pub fn phase_init<T>(f: impl FnOnce() -> T) -> T {
+    enter(Marker::Init, f)
+}
+
+#[inline(never)]
+pub fn phase_round<T>(f: impl FnOnce() -> T) -> T {
+    enter(Marker::Round, f)
+}
+
+#[inline(never)]
+pub fn phase_commit<T>(f: impl FnOnce() -> T) -> T {
+    enter(Marker::Commit, f)
+}
+
+#[inline(never)]
+pub fn phase_verify<T>(f: impl FnOnce() -> T) -> T {
+    enter(Marker::Verify, f)
+}
+
+// Counts, every SAMPLE_INTERVAL, which marker the bench thread is in
+pub struct Sampler {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<[usize; 5]>,
+}
+
+impl Sampler {
+    pub fn start() -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut counts = [0; 5];
+                while !stop.load(Ordering::Relaxed) {
+                    counts[current() as usize] += 1;
+                    thread::sleep(SAMPLE_INTERVAL);
+                }
+                counts
+            })
+        };
+        Self { stop, handle }
+    }
+
+    pub fn finish(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let counts = self.handle.join().unwrap();
+        let total: usize = counts.iter().sum();
+        println!("Phase samples, {} in total:", total);
+        for marker in MARKERS {
+            let count = counts[marker as usize];
+            println!(
+                "  {:<8} {:>10} {:>6.1}%",
+                marker.label(),
+                count,
+                count as f64 * 100.0 / total.max(1) as f64
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        phase::{Phase, PhaseKind},
+        runner::{tests::serial, Runner},
+        seed::SeedTree,
+    };
+    use gw_store::Store as GwStore;
+    use std::panic::{self, AssertUnwindSafe};
+
+    // Each marker must hold while its function runs and give way to the one
+    // around it on return. A run with commit verification also goes through
+    // the debug assertion that verify_commit runs under phase_verify.
+    #[test]
+    fn phase_markers() -> Result<(), String> {
+        let _serial = serial();
+        let seen = phase_round(|| {
+            let inner = phase_commit(current);
+            (current(), inner)
+        });
+        if seen != (Marker::Round, Marker::Commit) {
+            return Err(format!("a commit inside a round saw {:?}", seen));
+        }
+        if current() != Marker::Other {
+            return Err(format!("{:?} left marked after return", current()));
+        }
+        let config = Config {
+            report_every: 0,
+            verify_commit: 10,
+            ..Default::default()
+        };
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let mut runner = Runner::new(&config, &store, SeedTree::new(0, false));
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            runner.run_phase(&Phase::new(PhaseKind::Load, 1, 50));
+            runner.run_phase(&Phase::new(PhaseKind::Update, 1, 50));
+        }))
+        .map_err(|_| "a round ran outside its phase marker".to_string())?;
+        if current() != Marker::Other {
+            return Err(format!("{:?} left marked after the run", current()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{
+    batching::Measurement,
+    config::Config,
+    trie::{self, TrieStore},
+    TrieSmt,
+};
+use gw_db::{
+    error::Error,
+    internal::{
+        ops::{DeleteCF, GetColumnFamilys, GetPinnedCF, IterateCF, MergeCF, PutCF},
+        ColumnFamily, ColumnFamilyDescriptor, MergeOperands, OptimisticTransaction,
+        OptimisticTransactionDB, Options,
+    },
+    iter::{DBIter, IteratorMode},
+    schema::Col,
+};
+use gw_store::traits::KVStore;
+use sparse_merkle_tree::H256;
+use std::path::Path;
+use std::time::Instant;
+
+// Name RocksDB records the operator of the branch column under
+const OPERATOR_NAME: &str = "smt-bench-trie-slots";
+
+fn full_merge(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &mut MergeOperands,
+) -> Option<Vec<u8>> {
+    trie::materialize(existing, operands)
+}
+
+// A database of --trie-merge-op, opened through the rocksdb handle under
+// gw_db since gw_db's open cannot register a merge operator. It holds the
+// column families of --columns, the branch column with the operator that
+// materializes trie blobs from slot operands, all with RocksDB's default
+// options otherwise.
+pub struct MergeOpDb {
+    db: OptimisticTransactionDB,
+    branch_col: Col,
+}
+
+impl MergeOpDb {
+    pub fn open(config: &Config, path: &Path) -> Self {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        let descriptors = (0..config.columns).map(|col| {
+            let mut cf_options = Options::default();
+            if col == config.branch_col as u32 {
+                cf_options.set_merge_operator(OPERATOR_NAME, full_merge, None);
+            }
+            ColumnFamilyDescriptor::new(col.to_string(), cf_options)
+        });
+        let db = OptimisticTransactionDB::open_cf_descriptors(&options, path, descriptors)
+            .unwrap_or_else(|err| {
+                panic!(
+                    "Cannot open {} with the merge operator: {}",
+                    path.display(),
+                    err
+                )
+            });
+        Self {
+            db,
+            branch_col: config.branch_col,
+        }
+    }
+
+    pub fn begin_transaction(&self) -> MergeOpTransaction<'_> {
+        MergeOpTransaction {
+            db: self,
+            inner: self.db.transaction_default(),
+        }
+    }
+
+    fn cf(&self, col: Col) -> &ColumnFamily {
+        self.db
+            .cf_handle(&col.to_string())
+            .unwrap_or_else(|| panic!("Column {} does not exist", col))
+    }
+}
+
+// KVStore over a transaction of a MergeOpDb, whose inserts into the branch
+// column are merge operands rather than values. A get of a trie returns
+// the blob materialized from whatever operands are still pending.
+pub struct MergeOpTransaction<'a> {
+    db: &'a MergeOpDb,
+    inner: OptimisticTransaction,
+}
+
+impl MergeOpTransaction<'_> {
+    pub fn commit(&self) -> Result<(), String> {
+        self.inner.commit().map_err(|err| err.to_string())
+    }
+}
+
+impl KVStore for MergeOpTransaction<'_> {
+    fn get(&self, col: Col, key: &[u8]) -> Option<Box<[u8]>> {
+        self.inner
+            .get_pinned_cf(self.db.cf(col), key)
+            .unwrap_or_else(|err| panic!("Get from column {} failed: {}", col, err))
+            .map(|value| Box::from(value.as_ref()))
+    }
+
+    fn get_iter(&self, col: Col, mode: IteratorMode) -> DBIter<'_> {
+        self.inner
+            .iterator_cf(self.db.cf(col), mode)
+            .unwrap_or_else(|err| panic!("Iterator of column {} failed: {}", col, err))
+    }
+
+    fn insert_raw(&self, col: Col, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let cf = self.db.cf(col);
+        let result = if col == self.db.branch_col {
+            self.inner.merge_cf(cf, key, value)
+        } else {
+            self.inner.put_cf(cf, key, value)
+        };
+        result.unwrap_or_else(|err| panic!("Write to column {} failed: {}", col, err));
+        Ok(())
+    }
+
+    fn delete(&self, col: Col, key: &[u8]) -> Result<(), Error> {
+        self.inner
+            .delete_cf(self.db.cf(col), key)
+            .unwrap_or_else(|err| panic!("Delete from column {} failed: {}", col, err));
+        Ok(())
+    }
+}
+
+pub(crate) fn trie_store<'a, 'db>(
+    config: &Config,
+    tx: &'a MergeOpTransaction<'db>,
+) -> TrieStore<'a, MergeOpTransaction<'db>> {
+    TrieStore::with_layout(tx, config.trie_layout)
+        .with_columns(config.branch_col, config.leaf_col)
+        .merge_operands()
+}
+
+// Applies pairs to the tree of root in the database at path through a
+// TrieStore writing merge operands, in one transaction, and measures it
+// commit included, like batching::measure does for the other stores
+pub(crate) fn measure(
+    config: &Config,
+    path: &Path,
+    root: H256,
+    pairs: Vec<(H256, H256)>,
+) -> Measurement {
+    let db = MergeOpDb::open(config, path);
+    let tx = db.begin_transaction();
+    let start = Instant::now();
+    let mut smt = TrieSmt::new(root, trie_store(config, &tx));
+    smt.update_all(pairs).unwrap();
+    let root = *smt.root();
+    let trie = smt.store();
+    let (reads, writes, written_bytes) = (trie.reads(), trie.writes(), trie.written_bytes());
+    drop(smt);
+    tx.commit().unwrap();
+    Measurement {
+        root,
+        elapsed: start.elapsed(),
+        reads,
+        writes,
+        written_bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{random_h256, seed::SeedTree, trie::trie_branches};
+    use gw_store::Store as GwStore;
+    use sparse_merkle_tree::tree::BranchNode;
+    use std::collections::BTreeMap;
+
+    // Branches of every trie in the branch column
+    fn tries(store: &impl KVStore, config: &Config) -> BTreeMap<Box<[u8]>, Vec<BranchNode>> {
+        store
+            .get_iter(config.branch_col, IteratorMode::Start)
+            .map(|(key, value)| (key, trie_branches(&value)))
+            .collect()
+    }
+
+    // Rounds written as slot operands must end every round with the root and
+    // the tries of the same rounds rewriting whole blobs, deletes included,
+    // and write fewer bytes
+    #[test]
+    fn merged_tries() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("smt-bench-merge-op-{}", std::process::id()));
+        let result = (|| {
+            let config = Config::default();
+            let mut rng = SeedTree::new(5, false).child("check-merge-op").rng();
+            let db = MergeOpDb::open(&config, &dir);
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let (mut merged, mut rewritten) = (H256::zero(), H256::zero());
+            let (mut merged_bytes, mut rewritten_bytes) = (0, 0);
+            let mut keys = vec![];
+            for round in 0..4 {
+                let pairs: Vec<(H256, H256)> = if round < 3 {
+                    (0..20)
+                        .map(|_| (random_h256(&mut rng), random_h256(&mut rng)))
+                        .collect()
+                } else {
+                    keys.iter().map(|key| (*key, H256::zero())).collect()
+                };
+                keys.extend(pairs.iter().map(|(key, _)| *key));
+
+                let tx = db.begin_transaction();
+                let mut smt = TrieSmt::new(merged, trie_store(&config, &tx));
+                smt.update_all(pairs.clone())
+                    .map_err(|err| err.to_string())?;
+                merged = *smt.root();
+                merged_bytes += smt.store().written_bytes();
+                drop(smt);
+                tx.commit()?;
+
+                let tx = store.begin_transaction();
+                let mut smt = TrieSmt::new(rewritten, TrieStore::new(&tx));
+                smt.update_all(pairs).map_err(|err| err.to_string())?;
+                rewritten = *smt.root();
+                rewritten_bytes += smt.store().written_bytes();
+                drop(smt);
+                tx.commit().map_err(|err| err.to_string())?;
+
+                if merged != rewritten {
+                    return Err(format!(
+                        "round {} ended with root {:?} merged, {:?} rewritten",
+                        round + 1,
+                        merged,
+                        rewritten
+                    ));
+                }
+                if tries(&db.begin_transaction(), &config)
+                    != tries(&store.begin_transaction(), &config)
+                {
+                    return Err(format!(
+                        "round {} left other tries merged than rewritten",
+                        round + 1
+                    ));
+                }
+            }
+            if merged != H256::zero() || merged_bytes >= rewritten_bytes {
+                return Err(format!(
+                    "ended with root {:?} after writing {} bytes of operands, {} of blobs",
+                    merged, merged_bytes, rewritten_bytes
+                ));
+            }
+            Ok(())
+        })();
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{
+    build_info, config::Config, dot::hex, fingerprint::fingerprint, path::parse_h256,
+    rocksdb_options,
+};
+use sparse_merkle_tree::H256;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Written inside the database directory, RocksDB ignores files it does not
+// know
+const META_FILE: &str = "meta.json";
+
+// Fields that decide how the stored tree is laid out, a run disagreeing
+// with the stored value would read or write it wrongly
+const TREE_FIELDS: &[&str] = &["trie_layout", "branch_col", "leaf_col", "history_col"];
+
+// Version of how leaves are stored, 2 with --leaf-key-bytes, which no other
+// tool reads. Databases written before it was recorded are version 1.
+const SCHEMA_VERSION: u32 = 1;
+const TRUNCATED_LEAF_SCHEMA_VERSION: u32 = 2;
+// Fields no run may disagree with, as it could not find the stored leaves
+const SCHEMA_FIELDS: &[&str] = &["schema_version", "leaf_key_bytes"];
+
+// The configuration of a run as (field, JSON value) pairs
+fn config_fields(config: &Config) -> Vec<(&'static str, String)> {
+    let phases: Vec<String> = config.phases.iter().map(|p| p.to_string()).collect();
+    let mut fields = vec![
+        ("seed", config.seed.to_string()),
+        ("phases", format!("\"{}\"", phases.join(";"))),
+        (
+            "trie_layout",
+            format!("\"{}\"", format!("{:?}", config.trie_layout).to_lowercase()),
+        ),
+        ("branch_col", config.branch_col.to_string()),
+        ("leaf_col", config.leaf_col.to_string()),
+        ("columns", config.columns.to_string()),
+        ("key_preimage_size", config.key_preimage_size.to_string()),
+    ];
+    match config.leaf_key_bytes {
+        Some(bytes) => {
+            fields.push(("schema_version", TRUNCATED_LEAF_SCHEMA_VERSION.to_string()));
+            fields.push(("leaf_key_bytes", bytes.to_string()));
+        }
+        None => fields.push(("schema_version", SCHEMA_VERSION.to_string())),
+    }
+    if config.leaf_history {
+        fields.push(("history_col", config.history_col.to_string()));
+    }
+    if let Some(path) = &config.rocksdb_options_file {
+        if let Ok(hash) = rocksdb_options::hash(path) {
+            fields.push(("rocksdb_options_hash", format!("\"{}\"", hash)));
+        }
+    }
+    fields
+}
+
+// Records what produced the database: the run's configuration, the crate
+// version and build, and the root and leaf count it ended with.
+pub fn write(config: &Config, root: &H256, leaves: &str) -> io::Result<()> {
+    let mut fields = vec![
+        ("fingerprint", format!("\"{}\"", fingerprint(config))),
+        ("version", format!("\"{}\"", env!("CARGO_PKG_VERSION"))),
+        ("profile", format!("\"{}\"", build_info::PROFILE)),
+        ("opt_level", format!("\"{}\"", build_info::OPT_LEVEL)),
+        ("features", format!("\"{}\"", build_info::FEATURES)),
+    ];
+    fields.extend(
+        build_info::DEPS
+            .iter()
+            .map(|(name, version)| (*name, format!("\"{}\"", version))),
+    );
+    fields.extend(config_fields(config));
+    fields.push(("root", format!("\"{}\"", hex(root))));
+    fields.push(("leaves", format!("\"{}\"", leaves)));
+    let lines: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| format!("  \"{}\": {}", name, value))
+        .collect();
+    fs::write(
+        config.db_path.join(META_FILE),
+        format!("{{\n{}\n}}\n", lines.join(",\n")),
+    )
+}
+
+// Reads back a file written by write, one field per line
+fn read(db_path: &Path) -> Option<HashMap<String, String>> {
+    let content = fs::read_to_string(db_path.join(META_FILE)).ok()?;
+    let fields = content
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.trim().trim_end_matches(',').split_once(": ")?;
+            Some((name.trim_matches('"').to_string(), value.to_string()))
+        })
+        .collect();
+    Some(fields)
+}
+
+// Where the result of an earlier run with the fingerprint is, when it is the
+// run that last wrote the database
+pub fn prior_run(config: &Config, fingerprint: &str) -> Option<String> {
+    let stored = read(&config.db_path)?;
+    if stored.get("fingerprint")? != &format!("\"{}\"", fingerprint) {
+        return None;
+    }
+    Some(format!(
+        "{}, root {}, leaves {}",
+        config.db_path.join(META_FILE).display(),
+        stored
+            .get("root")
+            .map_or("unknown", |root| root.trim_matches('"')),
+        stored
+            .get("leaves")
+            .map_or("unknown", |leaves| leaves.trim_matches('"'))
+    ))
+}
+
+// Root the run that last wrote the database ended with
+pub fn recorded_root(db_path: &Path) -> Option<H256> {
+    let stored = read(db_path)?;
+    parse_h256(stored.get("root")?.trim_matches('"')).ok()
+}
+
+// Warns about every tree parameter of the run that differs from the one
+// recorded with the database, and exits when the leaves are stored in a
+// schema the run does not use.
+pub fn check(config: &Config) {
+    let stored = match read(&config.db_path) {
+        Some(stored) => stored,
+        None => return,
+    };
+    for (name, value) in config_fields(config) {
+        if !SCHEMA_FIELDS.contains(&name) {
+            continue;
+        }
+        let stored_value = match (name, stored.get(name)) {
+            (_, Some(stored_value)) => stored_value.clone(),
+            ("schema_version", None) => SCHEMA_VERSION.to_string(),
+            _ => continue,
+        };
+        if stored_value != value {
+            eprintln!(
+                "{} was written with {} {}, this run uses {}, pass the same --leaf-key-bytes or use another --db-path",
+                config.db_path.display(),
+                name,
+                stored_value,
+                value
+            );
+            std::process::exit(1);
+        }
+    }
+    let mut conflicts = 0;
+    for (name, value) in config_fields(config) {
+        if !TREE_FIELDS.contains(&name) {
+            continue;
+        }
+        if let Some(stored_value) = stored.get(name) {
+            if *stored_value != value {
+                eprintln!(
+                    "Warning: {} is {} but {} was written with {}",
+                    name,
+                    value,
+                    config.db_path.display(),
+                    stored_value
+                );
+                conflicts += 1;
+            }
+        }
+    }
+    // Other versions of these crates may encode or hash the tree differently
+    for (name, version) in build_info::DEPS {
+        let version = format!("\"{}\"", version);
+        if let Some(stored_version) = stored.get(*name) {
+            if *stored_version != version {
+                eprintln!(
+                    "Warning: {} was written with {} {}, this build has {}, results are not comparable",
+                    config.db_path.display(),
+                    name,
+                    stored_version,
+                    version
+                );
+            }
+        }
+    }
+    if conflicts > 0 {
+        eprintln!(
+            "Warning: use the same flags as the run that wrote the database, or another --db-path"
+        );
+    }
+}
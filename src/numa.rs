@@ -0,0 +1,78 @@
+use std::fs;
+use std::io;
+
+// Runs the calling thread, and every thread it starts afterwards, RocksDB's
+// background threads included, on core only
+#[cfg(target_os = "linux")]
+pub fn pin_to_core(core: usize) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_to_core(_core: usize) -> io::Result<()> {
+    Err(io::Error::other("sched_setaffinity only exists on linux"))
+}
+
+// NUMA node of core, from the nodeN link sysfs keeps in the core's
+// directory. None on machines without NUMA support.
+pub fn node_of_core(core: usize) -> Option<usize> {
+    fs::read_dir(format!("/sys/devices/system/cpu/cpu{}", core))
+        .ok()?
+        .flatten()
+        .find_map(|entry| {
+            entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("node")?
+                .parse()
+                .ok()
+        })
+}
+
+#[cfg(feature = "numa")]
+mod ffi {
+    use std::os::raw::{c_int, c_uint};
+
+    #[repr(C)]
+    pub struct Bitmask {
+        _private: [u8; 0],
+    }
+
+    #[link(name = "numa")]
+    extern "C" {
+        pub fn numa_available() -> c_int;
+        pub fn numa_allocate_nodemask() -> *mut Bitmask;
+        pub fn numa_bitmask_setbit(mask: *mut Bitmask, n: c_uint) -> *mut Bitmask;
+        pub fn numa_set_membind(mask: *mut Bitmask);
+        pub fn numa_bitmask_free(mask: *mut Bitmask);
+    }
+}
+
+// Allocates the memory of the calling thread and the threads it starts
+// afterwards on node only, so the trie blobs and the block cache stay local
+// to the pinned core
+#[cfg(feature = "numa")]
+pub fn bind_memory(node: usize) -> io::Result<()> {
+    unsafe {
+        if ffi::numa_available() < 0 {
+            return Err(io::Error::other("libnuma reports no NUMA support"));
+        }
+        let mask = ffi::numa_allocate_nodemask();
+        ffi::numa_bitmask_setbit(mask, node as u32);
+        ffi::numa_set_membind(mask);
+        ffi::numa_bitmask_free(mask);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "numa"))]
+pub fn bind_memory(_node: usize) -> io::Result<()> {
+    Err(io::Error::other("built without the numa feature"))
+}
@@ -1,4 +1,5 @@
-use crate::utils::*;
+use crate::{history::LeafHistory, utils::*};
+use gw_db::schema::Col;
 use gw_store::traits::KVStore;
 use gw_types::{packed, prelude::*};
 use sparse_merkle_tree::{
@@ -13,27 +14,54 @@ use std::cell::Cell;
 // only adding read/write stats.
 pub struct CountingStore<'a, DB: KVStore> {
     store: &'a DB,
+    branch_col: Col,
+    leaf_col: Col,
+    history: Option<LeafHistory>,
 
     reads: Cell<usize>,
     writes: usize,
+    // Bytes of keys and values passed to insert_raw
+    written_bytes: usize,
 }
 
 impl<'a, DB: KVStore> CountingStore<'a, DB> {
     pub fn new(store: &'a DB) -> Self {
         Self {
             store,
+            branch_col: 0,
+            leaf_col: 1,
+            history: None,
             reads: Cell::default(),
             writes: 0,
+            written_bytes: 0,
         }
     }
 
-    pub fn clear_stats(&mut self) {
-        self.reads.set(0);
-        self.writes = 0;
+    // Reads and writes branches and leaves in the given columns instead of
+    // the default 0 and 1, e.g. to point at a godwoken node's SMT columns.
+    pub fn with_columns(mut self, branch_col: Col, leaf_col: Col) -> Self {
+        self.branch_col = branch_col;
+        self.leaf_col = leaf_col;
+        self
     }
 
-    pub fn stats(&self) {
-        println!("Reads: {}, writes: {}", self.reads.get(), self.writes);
+    // Records the previous value of every changed leaf in history_col, see
+    // LeafHistory.
+    pub fn with_history(mut self, history_col: Col, round: u64) -> Self {
+        self.history = Some(LeafHistory::new(history_col, round));
+        self
+    }
+
+    pub fn reads(&self) -> usize {
+        self.reads.get()
+    }
+
+    pub fn writes(&self) -> usize {
+        self.writes
+    }
+
+    pub fn written_bytes(&self) -> usize {
+        self.written_bytes
     }
 }
 
@@ -41,7 +69,7 @@ impl<'a, DB: KVStore> Store<H256> for CountingStore<'a, DB> {
     fn get_branch(&self, branch_key: &BranchKey) -> Result<Option<BranchNode>, SMTError> {
         self.reads.set(self.reads.get() + 1);
         let branch_key: packed::SMTBranchKey = pack_key(branch_key);
-        match self.store.get(0, branch_key.as_slice()) {
+        match self.store.get(self.branch_col, branch_key.as_slice()) {
             Some(slice) => {
                 let branch = packed::SMTBranchNodeReader::from_slice_should_be_ok(slice.as_ref());
                 Ok(Some(unpack_branch(&branch)))
@@ -52,7 +80,7 @@ impl<'a, DB: KVStore> Store<H256> for CountingStore<'a, DB> {
 
     fn get_leaf(&self, leaf_key: &H256) -> Result<Option<H256>, SMTError> {
         self.reads.set(self.reads.get() + 1);
-        match self.store.get(1, leaf_key.as_slice()) {
+        match self.store.get(self.leaf_col, leaf_key.as_slice()) {
             Some(slice) if 32 == slice.len() => {
                 let mut leaf = [0u8; 32];
                 leaf.copy_from_slice(slice.as_ref());
@@ -68,17 +96,22 @@ impl<'a, DB: KVStore> Store<H256> for CountingStore<'a, DB> {
         let branch: packed::SMTBranchNode = pack_branch(&branch);
 
         self.writes += 1;
+        self.written_bytes += branch_key.as_slice().len() + branch.as_slice().len();
         self.store
-            .insert_raw(0, branch_key.as_slice(), branch.as_slice())
+            .insert_raw(self.branch_col, branch_key.as_slice(), branch.as_slice())
             .map_err(|err| SMTError::Store(format!("insert error {}", err)))?;
 
         Ok(())
     }
 
     fn insert_leaf(&mut self, leaf_key: H256, leaf: H256) -> Result<(), SMTError> {
+        if let Some(history) = &mut self.history {
+            history.record(self.store, self.leaf_col, &leaf_key)?;
+        }
         self.writes += 1;
+        self.written_bytes += 64;
         self.store
-            .insert_raw(1, leaf_key.as_slice(), leaf.as_slice())
+            .insert_raw(self.leaf_col, leaf_key.as_slice(), leaf.as_slice())
             .map_err(|err| SMTError::Store(format!("insert error {}", err)))?;
 
         Ok(())
@@ -89,17 +122,59 @@ impl<'a, DB: KVStore> Store<H256> for CountingStore<'a, DB> {
 
         self.writes += 1;
         self.store
-            .delete(0, branch_key.as_slice())
+            .delete(self.branch_col, branch_key.as_slice())
             .map_err(|err| SMTError::Store(format!("delete error {}", err)))?;
 
         Ok(())
     }
 
     fn remove_leaf(&mut self, leaf_key: &H256) -> Result<(), SMTError> {
+        if let Some(history) = &mut self.history {
+            history.record(self.store, self.leaf_col, leaf_key)?;
+        }
         self.store
-            .delete(1, leaf_key.as_slice())
+            .delete(self.leaf_col, leaf_key.as_slice())
             .map_err(|err| SMTError::Store(format!("delete error {}", err)))?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gw_store::Store as GwStore;
+    use sparse_merkle_tree::merge::MergeValue;
+
+    // CountingStore must persist branches exactly as the shared utils helpers
+    // encode them, so any fix to those helpers reaches the store.
+    #[test]
+    fn branch_encoding() -> Result<(), String> {
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let tx = store.begin_transaction();
+        let key = BranchKey::new(3, H256::from([7u8; 32]));
+        let branch = BranchNode {
+            left: MergeValue::Value(H256::from([1u8; 32])),
+            right: MergeValue::MergeWithZero {
+                base_node: H256::from([2u8; 32]),
+                zero_bits: H256::from([3u8; 32]),
+                zero_count: 4,
+            },
+        };
+        let mut counting = CountingStore::new(&tx);
+        counting
+            .insert_branch(key.clone(), branch.clone())
+            .map_err(|err| err.to_string())?;
+
+        let stored = tx
+            .get(0, pack_key(&key).as_slice())
+            .ok_or("branch was not stored")?;
+        if stored.as_ref() != pack_branch(&branch).as_slice() {
+            return Err("stored bytes differ from pack_branch".to_string());
+        }
+        match counting.get_branch(&key).map_err(|err| err.to_string())? {
+            Some(loaded) if loaded == branch => Ok(()),
+            loaded => Err(format!("branch read back as {:?}", loaded)),
+        }
+    }
+}
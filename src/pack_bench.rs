@@ -0,0 +1,72 @@
+use crate::{
+    config::Config,
+    random_h256,
+    seed::SeedTree,
+    utils::{conversion_stats, pack_key, pack_key_direct},
+};
+use gw_types::prelude::*;
+use rand_chacha::rand_core::RngCore;
+use sparse_merkle_tree::tree::BranchKey;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// Keys packed by each variant
+const PACK_KEYS: usize = 1_000_000;
+
+// Packs the same branch keys with pack_key, going through [u8; 32] and the
+// molecule builder, and with pack_key_direct writing the bytes straight
+// from the key, checks both give the same bytes and prints their time and,
+// with the conversion-stats feature, the conversions they did per key.
+pub fn compare_pack_key(config: &Config) {
+    let mut rng = SeedTree::new(config.seed, config.verbose)
+        .child("pack-bench")
+        .rng();
+    let keys: Vec<BranchKey> = (0..PACK_KEYS)
+        .map(|_| BranchKey::new(rng.next_u32() as u8, random_h256(&mut rng)))
+        .collect();
+    for key in &keys {
+        if pack_key(key).as_slice() != pack_key_direct(key) {
+            panic!("pack_key_direct differs from pack_key for {:?}", key);
+        }
+    }
+
+    let time = |pack: &dyn Fn(&BranchKey) -> usize| -> (Duration, (usize, usize)) {
+        let (calls, bytes) = conversion_stats();
+        let start = Instant::now();
+        for key in &keys {
+            black_box(pack(key));
+        }
+        let elapsed = start.elapsed();
+        let (end_calls, end_bytes) = conversion_stats();
+        (elapsed, (end_calls - calls, end_bytes - bytes))
+    };
+    let (builder, builder_conversions) = time(&|key| pack_key(key).as_slice().len());
+    let (direct, direct_conversions) = time(&|key| pack_key_direct(key).len());
+
+    println!("Packing {} branch keys", PACK_KEYS);
+    for (name, elapsed, (calls, bytes)) in [
+        ("pack_key", builder, builder_conversions),
+        ("pack_key_direct", direct, direct_conversions),
+    ] {
+        let conversions = if cfg!(feature = "conversion-stats") {
+            format!(
+                ", {:.1} conversions and {:.1} bytes copied per key",
+                calls as f64 / PACK_KEYS as f64,
+                bytes as f64 / PACK_KEYS as f64
+            )
+        } else {
+            String::new()
+        };
+        println!(
+            "  {:<16}{:?} ({:.1?} per key){}",
+            name,
+            elapsed,
+            elapsed / PACK_KEYS as u32,
+            conversions
+        );
+    }
+    println!(
+        "  pack_key_direct is {:.2}x pack_key",
+        builder.as_secs_f64() / direct.as_secs_f64().max(f64::EPSILON)
+    );
+}
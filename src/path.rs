@@ -0,0 +1,206 @@
+use crate::{dot::hex, trie::round_branch_key};
+use sparse_merkle_tree::{
+    merge::MergeValue,
+    traits::Store,
+    tree::{BranchKey, BranchNode},
+    H256,
+};
+use std::io::{self, Write};
+
+// Parses a 32-byte key written as 64 hex digits, with an optional 0x prefix
+pub fn parse_h256(s: &str) -> Result<H256, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    if digits.len() != 64 || !digits.is_ascii() {
+        return Err(format!("{} is not 32 hex-encoded bytes", s));
+    }
+    let mut buf = [0u8; 32];
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+            .map_err(|err| format!("{} is not hex: {}", s, err))?;
+    }
+    Ok(buf.into())
+}
+
+fn describe(value: &MergeValue) -> String {
+    match value {
+        MergeValue::Value(v) => format!("Value({})", hex(v)),
+        MergeValue::MergeWithZero {
+            base_node,
+            zero_bits,
+            zero_count,
+        } => format!(
+            "MergeWithZero(base_node: {}, zero_bits: {}, zero_count: {})",
+            hex(base_node),
+            hex(zero_bits),
+            zero_count
+        ),
+    }
+}
+
+fn describe_json(value: &MergeValue) -> String {
+    match value {
+        MergeValue::Value(v) => format!("{{\"type\": \"value\", \"value\": \"{}\"}}", hex(v)),
+        MergeValue::MergeWithZero {
+            base_node,
+            zero_bits,
+            zero_count,
+        } => format!(
+            "{{\"type\": \"merge_with_zero\", \"base_node\": \"{}\", \"zero_bits\": \"{}\", \"zero_count\": {}}}",
+            hex(base_node),
+            hex(zero_bits),
+            zero_count
+        ),
+    }
+}
+
+// Walks the branches along the path of one key from the root down, reading
+// the store directly rather than through the tree so the output is exactly
+// what the store returns. Stops at the first missing branch or at the leaf.
+// trie_keys adds the rounded key of the TrieStore blob holding each branch.
+pub fn write_path<S: Store<H256>>(
+    store: &S,
+    key: &H256,
+    trie_keys: bool,
+    json: bool,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    if json {
+        writeln!(out, "{{\"key\": \"{}\", \"branches\": [", hex(key))?;
+    } else {
+        writeln!(out, "Path of {}", hex(key))?;
+    }
+    let mut first = true;
+    let mut complete = true;
+    for height in (0..=u8::MAX).rev() {
+        let branch_key = BranchKey::new(height, key.parent_path(height));
+        let branch = match store.get_branch(&branch_key) {
+            Ok(Some(branch)) => branch,
+            Ok(None) => {
+                if !json {
+                    writeln!(out, "h={}: missing", height)?;
+                }
+                complete = false;
+                break;
+            }
+            Err(err) => {
+                if !json {
+                    writeln!(out, "h={}: error {}", height, err)?;
+                }
+                complete = false;
+                break;
+            }
+        };
+        let side = if key.get_bit(height) { "right" } else { "left" };
+        if json {
+            let trie_key = if trie_keys {
+                let rounded = round_branch_key(&branch_key);
+                format!(
+                    ", \"trie_key\": {{\"height\": {}, \"node_key\": \"{}\"}}",
+                    rounded.height,
+                    hex(&rounded.node_key)
+                )
+            } else {
+                String::new()
+            };
+            writeln!(
+                out,
+                "{}  {{\"height\": {}, \"node_key\": \"{}\"{}, \"side\": \"{}\", \"left\": {}, \"right\": {}}}",
+                if first { "" } else { "," },
+                height,
+                hex(&branch_key.node_key),
+                trie_key,
+                side,
+                describe_json(&branch.left),
+                describe_json(&branch.right)
+            )?;
+        } else {
+            writeln!(out, "h={}: node_key {}", height, hex(&branch_key.node_key))?;
+            if trie_keys {
+                let rounded = round_branch_key(&branch_key);
+                writeln!(
+                    out,
+                    "  trie h={} node_key {}",
+                    rounded.height,
+                    hex(&rounded.node_key)
+                )?;
+            }
+            writeln!(out, "  left:  {}", describe(&branch.left))?;
+            writeln!(out, "  right: {}", describe(&branch.right))?;
+            writeln!(out, "  key goes {}", side)?;
+        }
+        first = false;
+    }
+
+    let leaf = if complete {
+        store.get_leaf(key).ok().flatten()
+    } else {
+        None
+    };
+    if json {
+        let leaf = leaf
+            .map(|leaf| format!("\"{}\"", hex(&leaf)))
+            .unwrap_or_else(|| "null".to_string());
+        writeln!(out, "], \"complete\": {}, \"leaf\": {}}}", complete, leaf)
+    } else {
+        match leaf {
+            Some(leaf) => writeln!(out, "leaf: {}", hex(&leaf)),
+            None => writeln!(out, "leaf: none"),
+        }
+    }
+}
+
+fn describe_branch(branch: &Option<BranchNode>) -> String {
+    match branch {
+        Some(branch) => format!(
+            "left {}, right {}",
+            describe(&branch.left),
+            describe(&branch.right)
+        ),
+        None => "missing".to_string(),
+    }
+}
+
+// Compares the branches along the path of one key in two stores holding
+// the same tree, every height rather than stopping at a missing branch,
+// and writes the heights where they differ and then the leaf when that
+// differs. Returns the number of differences written.
+pub fn write_path_diff<A: Store<H256> + ?Sized, B: Store<H256> + ?Sized>(
+    (a_name, a): (&str, &A),
+    (b_name, b): (&str, &B),
+    key: &H256,
+    out: &mut impl Write,
+) -> io::Result<usize> {
+    let store_err = |err: sparse_merkle_tree::error::Error| io::Error::other(err.to_string());
+    let mut differences = 0;
+    writeln!(out, "Path of {}", hex(key))?;
+    for height in (0..=u8::MAX).rev() {
+        let branch_key = BranchKey::new(height, key.parent_path(height));
+        let a_branch = a.get_branch(&branch_key).map_err(store_err)?;
+        let b_branch = b.get_branch(&branch_key).map_err(store_err)?;
+        if a_branch == b_branch {
+            continue;
+        }
+        writeln!(out, "h={}: node_key {}", height, hex(&branch_key.node_key))?;
+        writeln!(out, "  {}: {}", a_name, describe_branch(&a_branch))?;
+        writeln!(out, "  {}: {}", b_name, describe_branch(&b_branch))?;
+        differences += 1;
+    }
+    let a_leaf = a.get_leaf(key).map_err(store_err)?;
+    let b_leaf = b.get_leaf(key).map_err(store_err)?;
+    if a_leaf != b_leaf {
+        let describe_leaf = |leaf: Option<H256>| leaf.map_or("none".to_string(), |leaf| hex(&leaf));
+        writeln!(
+            out,
+            "leaf: {} {}, {} {}",
+            a_name,
+            describe_leaf(a_leaf),
+            b_name,
+            describe_leaf(b_leaf)
+        )?;
+        differences += 1;
+    }
+    if differences == 0 {
+        writeln!(out, "  no branch differs")?;
+    }
+    Ok(differences)
+}
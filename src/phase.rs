@@ -0,0 +1,347 @@
+use crate::random_h256;
+use rand_chacha::rand_core::RngCore;
+use sparse_merkle_tree::{blake2b::Blake2bHasher, traits::Store, SparseMerkleTree, H256};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhaseKind {
+    // Inserts fresh keys one by one via update, in a single transaction
+    Load,
+    // Rounds of update_all over fresh random pairs
+    Update,
+    // Rounds of update_all setting existing keys to zero
+    Delete,
+    // Rounds of get_leaf over existing keys
+    Read,
+    // Rounds of merkle proofs for one key picked at the start of the phase,
+    // which exposes caching on repeated access
+    ProveSame,
+    // Rounds proving existing keys, updating a key next to each of them in
+    // one update_all, and proving the same keys again
+    ProveUpdate,
+    // Rounds building one compiled proof per --proof-mix ratio over existing
+    // and absent keys together, verified against the root
+    ProveMix,
+    // Rounds proving existing keys on the current root and on a root an
+    // earlier round committed, each proof verified against its root
+    ProveHistory,
+}
+
+impl PhaseKind {
+    // Whether rounds draw keys from the pool of keys the run wrote, in the
+    // order it wrote them
+    pub fn samples_keys(&self) -> bool {
+        !matches!(self, PhaseKind::Load | PhaseKind::Update)
+    }
+
+    // Whether the phase commits anything to the database
+    pub fn writes(&self) -> bool {
+        matches!(
+            self,
+            PhaseKind::Load | PhaseKind::Update | PhaseKind::Delete | PhaseKind::ProveUpdate
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Phase {
+    pub kind: PhaseKind,
+    pub rounds: usize,
+    pub batch_size: usize,
+}
+
+impl Phase {
+    pub fn new(kind: PhaseKind, rounds: usize, batch_size: usize) -> Self {
+        Self {
+            kind,
+            rounds,
+            batch_size,
+        }
+    }
+
+    // Whether the phase only sets and reads leaves, which is all a tree
+    // run outside the runner does
+    pub(crate) fn leaves_only(&self) -> bool {
+        matches!(
+            self.kind,
+            PhaseKind::Load | PhaseKind::Update | PhaseKind::Delete | PhaseKind::Read
+        )
+    }
+
+    // The pairs of one round of a leaves_only phase: fresh ones to load or
+    // update, which join keys, keys taken out of keys to delete, or keys to
+    // read back
+    pub(crate) fn round_pairs(
+        &self,
+        keys: &mut Vec<(H256, H256)>,
+        rng: &mut impl RngCore,
+    ) -> Vec<(H256, H256)> {
+        match self.kind {
+            PhaseKind::Load | PhaseKind::Update => {
+                let pairs: Vec<(H256, H256)> = (0..self.batch_size)
+                    .map(|_| (random_h256(rng), random_h256(rng)))
+                    .collect();
+                keys.extend(pairs.iter().cloned());
+                pairs
+            }
+            PhaseKind::Delete => (0..self.batch_size.min(keys.len()))
+                .map(|_| {
+                    let i = rng.next_u64() as usize % keys.len();
+                    (keys.swap_remove(i).0, H256::zero())
+                })
+                .collect(),
+            _ if keys.is_empty() => vec![],
+            _ => (0..self.batch_size)
+                .map(|_| keys[rng.next_u64() as usize % keys.len()])
+                .collect(),
+        }
+    }
+
+    // Applies one round of pairs from round_pairs to the tree of root
+    // through store, returning the new root
+    pub(crate) fn apply<S: Store<H256>>(
+        &self,
+        store: S,
+        root: H256,
+        pairs: &[(H256, H256)],
+    ) -> H256 {
+        let mut smt: SparseMerkleTree<Blake2bHasher, H256, S> = SparseMerkleTree::new(root, store);
+        match self.kind {
+            PhaseKind::Load => {
+                for (key, value) in pairs {
+                    smt.update(*key, *value).unwrap();
+                }
+            }
+            PhaseKind::Read => {
+                for (key, value) in pairs {
+                    assert_eq!(smt.get(key).unwrap(), *value, "leaf {:?} read back", key);
+                }
+            }
+            _ => {
+                smt.update_all(pairs.to_vec()).unwrap();
+            }
+        }
+        *smt.root()
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.kind {
+            PhaseKind::Load => "load",
+            PhaseKind::Update => "update",
+            PhaseKind::Delete => "delete",
+            PhaseKind::Read => "read",
+            PhaseKind::ProveSame => "prove-same",
+            PhaseKind::ProveUpdate => "prove-update",
+            PhaseKind::ProveMix => "prove-mix",
+            PhaseKind::ProveHistory => "prove-history",
+        };
+        if self.kind == PhaseKind::Load {
+            write!(f, "{}:{}", name, self.batch_size)
+        } else {
+            write!(f, "{}:{}x{}", name, self.rounds, self.batch_size)
+        }
+    }
+}
+
+// Parses "load:N" or "<kind>:RxB", where R is the number of rounds and B the
+// batch size of each round. A bare "<kind>:B" means a single round.
+impl FromStr for Phase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, size) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Phase {} is missing a size", s))?;
+        let kind = match name {
+            "load" => PhaseKind::Load,
+            "update" => PhaseKind::Update,
+            "delete" => PhaseKind::Delete,
+            "read" => PhaseKind::Read,
+            "prove-same" => PhaseKind::ProveSame,
+            "prove-update" => PhaseKind::ProveUpdate,
+            "prove-mix" => PhaseKind::ProveMix,
+            "prove-history" => PhaseKind::ProveHistory,
+            _ => return Err(format!("Unknown phase kind: {}", name)),
+        };
+        let parse = |v: &str| {
+            v.parse::<usize>()
+                .map_err(|err| format!("Invalid phase size {}: {}", s, err))
+        };
+        let (rounds, batch_size) = match size.split_once('x') {
+            Some(_) if kind == PhaseKind::Load => {
+                return Err(format!("Load phase {} only takes a key count", s))
+            }
+            Some((rounds, batch_size)) => (parse(rounds)?, parse(batch_size)?),
+            None => (1, parse(size)?),
+        };
+        Ok(Phase::new(kind, rounds, batch_size))
+    }
+}
+
+pub fn parse_phases(s: &str) -> Result<Vec<Phase>, String> {
+    s.split(';')
+        .filter(|p| !p.trim().is_empty())
+        .map(|p| p.trim().parse())
+        .collect()
+}
+
+// Distribution the key count of every update and delete round is drawn
+// from with --round-size-distribution, in place of the phase's batch size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoundSizes {
+    // Every size from min to max inclusive equally likely
+    Uniform(usize, usize),
+    // Poisson with the given mean
+    Poisson(f64),
+}
+
+// Poisson means above this are drawn from the normal approximation, the
+// product of uniforms underflowing for large means
+const POISSON_EXACT: f64 = 30.0;
+
+impl RoundSizes {
+    // The largest size a draw can give, none for Poisson
+    pub fn max(&self) -> Option<usize> {
+        match *self {
+            RoundSizes::Uniform(_, max) => Some(max),
+            RoundSizes::Poisson(_) => None,
+        }
+    }
+
+    // A size of at least 1
+    pub fn sample(&self, rng: &mut impl RngCore) -> usize {
+        let size = match *self {
+            RoundSizes::Uniform(min, max) => {
+                min + (rng.next_u64() % (max - min + 1) as u64) as usize
+            }
+            RoundSizes::Poisson(mean) if mean <= POISSON_EXACT => {
+                let limit = (-mean).exp();
+                let mut product = unit(rng);
+                let mut count = 0;
+                while product > limit {
+                    product *= unit(rng);
+                    count += 1;
+                }
+                count
+            }
+            RoundSizes::Poisson(mean) => {
+                // Box-Muller
+                let normal = (-2.0 * (1.0 - unit(rng)).ln()).sqrt()
+                    * (2.0 * std::f64::consts::PI * unit(rng)).cos();
+                (mean + normal * mean.sqrt()).round().max(0.0) as usize
+            }
+        };
+        size.max(1)
+    }
+}
+
+// Uniform in [0, 1)
+fn unit(rng: &mut impl RngCore) -> f64 {
+    (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Parses "uniform:MIN-MAX" or "poisson:MEAN"
+impl FromStr for RoundSizes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, params) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Round size distribution {} is missing parameters", s))?;
+        match name {
+            "uniform" => {
+                let (min, max) = params
+                    .split_once('-')
+                    .ok_or_else(|| format!("Uniform round sizes {} must be MIN-MAX", s))?;
+                let parse = |v: &str| {
+                    v.parse::<usize>()
+                        .map_err(|err| format!("Invalid round size {}: {}", s, err))
+                };
+                let (min, max) = (parse(min)?, parse(max)?);
+                if min == 0 || min > max {
+                    return Err(format!("Uniform round sizes {} need 0 < MIN <= MAX", s));
+                }
+                Ok(RoundSizes::Uniform(min, max))
+            }
+            "poisson" => {
+                let mean: f64 = params
+                    .parse()
+                    .map_err(|err| format!("Invalid Poisson mean {}: {}", s, err))?;
+                if !mean.is_finite() || mean <= 0.0 {
+                    return Err(format!("Poisson mean {} must be above 0", s));
+                }
+                Ok(RoundSizes::Poisson(mean))
+            }
+            _ => Err(format!("Unknown round size distribution: {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        runner::{tests::serial, Runner},
+        seed::SeedTree,
+    };
+    use gw_store::Store as GwStore;
+
+    // Round sizes must parse, stay within a uniform range, average close to a
+    // Poisson mean on either side of the exact sampling limit, and vary the
+    // key count of update rounds only
+    #[test]
+    fn round_size_distributions() -> Result<(), String> {
+        let _serial = serial();
+        for bad in [
+            "uniform:0-10",
+            "uniform:20-10",
+            "uniform:10",
+            "poisson:0",
+            "normal:5",
+        ] {
+            if bad.parse::<RoundSizes>().is_ok() {
+                return Err(format!("{} parsed", bad));
+            }
+        }
+        let mut rng = SeedTree::new(0, false).child("round-sizes").rng();
+        let uniform: RoundSizes = "uniform:100-200".parse()?;
+        let sizes: Vec<usize> = (0..2000).map(|_| uniform.sample(&mut rng)).collect();
+        if sizes.iter().any(|size| !(100..=200).contains(size)) {
+            return Err("a uniform size fell outside 100-200".to_string());
+        }
+        if !sizes.contains(&100) || !sizes.contains(&200) {
+            return Err("uniform sizes never reached 100 or 200".to_string());
+        }
+        for (mean, tolerance) in [(5.0, 0.3), (500.0, 5.0)] {
+            let poisson = RoundSizes::Poisson(mean);
+            let drawn = (0..2000).map(|_| poisson.sample(&mut rng)).sum::<usize>() as f64 / 2000.0;
+            if (drawn - mean).abs() > tolerance {
+                return Err(format!("Poisson sizes of mean {} averaged {}", mean, drawn));
+            }
+        }
+
+        let config = Config {
+            report_every: 0,
+            round_sizes: Some(RoundSizes::Uniform(10, 60)),
+            ..Default::default()
+        };
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let mut runner = Runner::new(&config, &store, SeedTree::new(0, false));
+        let load = runner.run_phase(&Phase::new(PhaseKind::Load, 1, 100));
+        if load.rounds[0].ops != 100 {
+            return Err(format!("load round of 100 keys did {}", load.rounds[0].ops));
+        }
+        let update = runner.run_phase(&Phase::new(PhaseKind::Update, 8, 1000));
+        let sizes: Vec<usize> = update.rounds.iter().map(|r| r.ops).collect();
+        if sizes.iter().any(|size| !(10..=60).contains(size))
+            || sizes.iter().all(|s| *s == sizes[0])
+        {
+            return Err(format!("update rounds did {:?} keys", sizes));
+        }
+        Ok(())
+    }
+}
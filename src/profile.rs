@@ -0,0 +1,348 @@
+use crate::{
+    config::{Config, OutputFormat},
+    footprint, markdown,
+    phase::{Phase, PhaseKind},
+    runner::{PhaseReport, Runner},
+    seed::SeedTree,
+    store_config,
+};
+use gw_db::RocksDB;
+use gw_store::Store as GwStore;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+// Workloads run by --profile-compare. Both load LOAD leaves and then run
+// ROUNDS write rounds of BATCH keys: append-only inserts new keys in every
+// round, delete-heavy deletes in every other round as many keys as the round
+// before inserted.
+const LOAD: usize = 10_000;
+const ROUNDS: usize = 20;
+const BATCH: usize = 1_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    AppendOnly,
+    DeleteHeavy,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "append-only" => Ok(Profile::AppendOnly),
+            "delete-heavy" => Ok(Profile::DeleteHeavy),
+            _ => Err(format!("Unknown profile: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::AppendOnly => write!(f, "append-only"),
+            Profile::DeleteHeavy => write!(f, "delete-heavy"),
+        }
+    }
+}
+
+impl Profile {
+    fn phases(self) -> Vec<Phase> {
+        let mut phases = vec![Phase::new(PhaseKind::Load, 1, LOAD)];
+        match self {
+            Profile::AppendOnly => phases.push(Phase::new(PhaseKind::Update, ROUNDS, BATCH)),
+            Profile::DeleteHeavy => {
+                for _ in 0..ROUNDS / 2 {
+                    phases.push(Phase::new(PhaseKind::Update, 1, BATCH));
+                    phases.push(Phase::new(PhaseKind::Delete, 1, BATCH));
+                }
+            }
+        }
+        phases
+    }
+}
+
+// What one profile cost, measured on its own database
+struct ProfileResult {
+    ops: usize,
+    rounds: usize,
+    elapsed: Duration,
+    reads: usize,
+    writes: usize,
+    written_bytes: usize,
+    leaves: String,
+    live_leaves: usize,
+    branch_keys: Option<u64>,
+    leaf_keys: Option<u64>,
+    disk_bytes: u64,
+    // SST bytes of the tree's columns, measured after --final-compact
+    compacted_bytes: Option<u64>,
+    // Spread of the rounds after the load phase, which is a single round of
+    // another size
+    round_stddev: Duration,
+    round_max: Duration,
+    // Most pending compaction bytes when a paused round re-enabled them
+    compaction_debt: Option<u64>,
+}
+
+impl ProfileResult {
+    fn new(reports: &[PhaseReport], db: &RocksDB, config: &Config, path: &Path) -> Self {
+        let rounds: Vec<_> = reports.iter().flat_map(|r| &r.rounds).collect();
+        let timed: Vec<f64> = reports
+            .iter()
+            .skip(1)
+            .flat_map(|r| &r.rounds)
+            .map(|r| r.elapsed.as_secs_f64())
+            .collect();
+        let mean = timed.iter().sum::<f64>() / timed.len().max(1) as f64;
+        let variance =
+            timed.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / timed.len().max(1) as f64;
+        let keys = |col| {
+            db.property_int_value(col, "rocksdb.estimate-num-keys")
+                .ok()
+                .flatten()
+        };
+        Self {
+            ops: rounds.iter().map(|r| r.ops).sum(),
+            rounds: rounds.len(),
+            elapsed: rounds.iter().map(|r| r.elapsed).sum(),
+            reads: rounds.iter().map(|r| r.reads).sum(),
+            writes: rounds.iter().map(|r| r.writes).sum(),
+            written_bytes: rounds.iter().map(|r| r.written_bytes).sum(),
+            leaves: rounds.last().map(|r| r.leaves_label()).unwrap_or_default(),
+            live_leaves: rounds.last().map_or(0, |r| r.leaves),
+            branch_keys: keys(config.branch_col),
+            leaf_keys: keys(config.leaf_col),
+            disk_bytes: footprint::dir_size(path),
+            compacted_bytes: None,
+            round_stddev: Duration::from_secs_f64(variance.sqrt()),
+            round_max: Duration::from_secs_f64(timed.iter().cloned().fold(0.0, f64::max)),
+            compaction_debt: rounds.iter().filter_map(|r| r.compaction_debt).max(),
+        }
+    }
+}
+
+// Runs every profile against a fresh database next to --db-path and prints
+// their footprints and timings side by side, with the ratio to the first
+// profile when there are two. With --pause-compaction every profile runs
+// once more with compactions paused during its rounds, so the round spread
+// of both shows how much of it compactions cause.
+pub fn compare(config: &Config, profiles: &[Profile]) {
+    let pauses: &[bool] = if config.pause_compaction.is_some() {
+        &[false, true]
+    } else {
+        &[false]
+    };
+    let runs: Vec<(Profile, bool)> = profiles
+        .iter()
+        .flat_map(|profile| pauses.iter().map(move |paused| (*profile, *paused)))
+        .collect();
+    let labels: Vec<String> = runs
+        .iter()
+        .map(|(profile, paused)| {
+            if *paused {
+                format!("{}+paused", profile)
+            } else {
+                profile.to_string()
+            }
+        })
+        .collect();
+    let mut results = vec![];
+    for ((profile, paused), label) in runs.iter().zip(&labels) {
+        let path = profile_path(&config.db_path, label);
+        if path.exists() {
+            panic!(
+                "{} already exists, --profile-compare needs a fresh database",
+                path.display()
+            );
+        }
+        println!("Profile {} in {}", label, path.display());
+        let db = RocksDB::open(&store_config(config, path.clone()), config.columns);
+        let store = GwStore::new(db.clone());
+        let seeds = SeedTree::new(config.seed, config.verbose).child(&profile.to_string());
+        let mut runner = Runner::new(config, &store, seeds);
+        if *paused {
+            runner.pause_compaction(db.clone());
+        }
+        let reports: Vec<PhaseReport> = profile
+            .phases()
+            .iter()
+            .map(|phase| runner.run_phase(phase))
+            .collect();
+        let mut result = ProfileResult::new(&reports, &db, config, &path);
+        if config.final_compact {
+            footprint::compact(&db, config);
+            result.compacted_bytes = footprint::total_bytes(&footprint::footprint(&db, config));
+        }
+        results.push(result);
+    }
+
+    let opt = |value: Option<u64>| value.map_or("n/a".to_string(), |v| v.to_string());
+    let per_leaf = |r: &ProfileResult| {
+        r.compacted_bytes
+            .filter(|_| r.live_leaves > 0)
+            .map_or(f64::NAN, |bytes| bytes as f64 / r.live_leaves as f64)
+    };
+    let mut rows: Vec<(&str, Vec<String>, Vec<f64>)> = vec![
+        row("ops", &results, |r| r.ops.to_string(), |r| r.ops as f64),
+        row(
+            "time",
+            &results,
+            |r| format!("{:.3?}", r.elapsed),
+            |r| r.elapsed.as_secs_f64(),
+        ),
+        row(
+            "mean round",
+            &results,
+            |r| format!("{:.3?}", r.elapsed / r.rounds.max(1) as u32),
+            |r| r.elapsed.as_secs_f64() / r.rounds.max(1) as f64,
+        ),
+        row(
+            "round stddev",
+            &results,
+            |r| format!("{:.3?}", r.round_stddev),
+            |r| r.round_stddev.as_secs_f64(),
+        ),
+        row(
+            "round max",
+            &results,
+            |r| format!("{:.3?}", r.round_max),
+            |r| r.round_max.as_secs_f64(),
+        ),
+        row(
+            "reads",
+            &results,
+            |r| r.reads.to_string(),
+            |r| r.reads as f64,
+        ),
+        row(
+            "writes",
+            &results,
+            |r| r.writes.to_string(),
+            |r| r.writes as f64,
+        ),
+        row(
+            "written bytes",
+            &results,
+            |r| r.written_bytes.to_string(),
+            |r| r.written_bytes as f64,
+        ),
+        row("leaves", &results, |r| r.leaves.clone(), |_| f64::NAN),
+        row(
+            "branch keys (est.)",
+            &results,
+            |r| opt(r.branch_keys),
+            |r| r.branch_keys.map_or(f64::NAN, |v| v as f64),
+        ),
+        row(
+            "leaf keys (est.)",
+            &results,
+            |r| opt(r.leaf_keys),
+            |r| r.leaf_keys.map_or(f64::NAN, |v| v as f64),
+        ),
+        row(
+            "disk bytes",
+            &results,
+            |r| r.disk_bytes.to_string(),
+            |r| r.disk_bytes as f64,
+        ),
+    ];
+    if config.pause_compaction.is_some() {
+        rows.push(row(
+            "max debt bytes",
+            &results,
+            |r| opt(r.compaction_debt),
+            |r| r.compaction_debt.map_or(f64::NAN, |v| v as f64),
+        ));
+    }
+    if config.final_compact {
+        rows.push(row(
+            "compacted bytes",
+            &results,
+            |r| opt(r.compacted_bytes),
+            |r| r.compacted_bytes.map_or(f64::NAN, |v| v as f64),
+        ));
+        rows.push(row(
+            "bytes per leaf",
+            &results,
+            |r| {
+                let bytes = per_leaf(r);
+                if bytes.is_finite() {
+                    format!("{:.1}", bytes)
+                } else {
+                    "n/a".to_string()
+                }
+            },
+            per_leaf,
+        ));
+    }
+
+    let ratio = |values: &[f64]| {
+        let ratio = values[1] / values[0];
+        if ratio.is_finite() {
+            format!("{:.2}x", ratio)
+        } else {
+            String::new()
+        }
+    };
+    if config.output == OutputFormat::Markdown {
+        let mut header = vec!["Profile comparison".to_string()];
+        header.extend(labels.iter().cloned());
+        if labels.len() == 2 {
+            header.push("ratio".to_string());
+        }
+        let rows: Vec<Vec<String>> = rows
+            .into_iter()
+            .map(|(name, mut cells, values)| {
+                cells.insert(0, name.to_string());
+                if values.len() == 2 {
+                    cells.push(ratio(&values));
+                }
+                cells
+            })
+            .collect();
+        markdown::print_preamble(config);
+        markdown::print_table(&header, &rows);
+        return;
+    }
+
+    print!("{:<20}", "Profile comparison");
+    for label in &labels {
+        print!("{:>16}", label);
+    }
+    if labels.len() == 2 {
+        print!("{:>10}", "ratio");
+    }
+    println!();
+    for (name, cells, values) in rows {
+        print!("  {:<18}", name);
+        for cell in &cells {
+            print!("{:>16}", cell);
+        }
+        if values.len() == 2 {
+            print!("{:>10}", ratio(&values));
+        }
+        println!();
+    }
+}
+
+fn row<'a>(
+    name: &'a str,
+    results: &[ProfileResult],
+    cell: impl Fn(&ProfileResult) -> String,
+    value: impl Fn(&ProfileResult) -> f64,
+) -> (&'a str, Vec<String>, Vec<f64>) {
+    (
+        name,
+        results.iter().map(&cell).collect(),
+        results.iter().map(&value).collect(),
+    )
+}
+
+fn profile_path(db_path: &Path, label: &str) -> PathBuf {
+    let mut name = db_path.as_os_str().to_owned();
+    name.push(format!("-{}", label));
+    PathBuf::from(name)
+}
@@ -0,0 +1,152 @@
+use crate::{config::Config, dot::hex};
+use sparse_merkle_tree::{blake2b::Blake2bHasher, CompiledMerkleProof, H256};
+use std::fs;
+use std::path::Path;
+
+// Decodes a proof file: the bytes of a CompiledMerkleProof, or the same
+// bytes hex encoded, with an optional 0x and surrounding whitespace. A
+// compiled proof starts with an opcode that is neither a hex digit nor
+// whitespace, so a file starting with one of those is read as hex.
+pub fn read(path: &Path) -> Result<CompiledMerkleProof, String> {
+    let data = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let bytes = match data.first() {
+        None => return Err(format!("{} is empty", path.display())),
+        Some(b) if b.is_ascii_hexdigit() || b.is_ascii_whitespace() => decode_hex(&data)
+            .map_err(|err| format!("{} is not a hex proof: {}", path.display(), err))?,
+        Some(_) => data,
+    };
+    Ok(CompiledMerkleProof(bytes))
+}
+
+fn decode_hex(data: &[u8]) -> Result<Vec<u8>, String> {
+    let text = std::str::from_utf8(data)
+        .map_err(|err| err.to_string())?
+        .trim();
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    if digits.is_empty() {
+        return Err("no digits".to_string());
+    }
+    if !digits.is_ascii() {
+        return Err("non-ASCII text".to_string());
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("odd number of digits, {}", digits.len()));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("{:?} at {} is not a hex byte", &digits[i..i + 2], i))
+        })
+        .collect()
+}
+
+// Whether proof shows that key holds value under root, a zero value
+// meaning the key is absent. Uses nothing but the proof, with the hasher
+// every store of the bench uses.
+pub fn verify(
+    proof: &CompiledMerkleProof,
+    root: &H256,
+    key: H256,
+    value: H256,
+) -> Result<bool, String> {
+    proof
+        .verify::<Blake2bHasher>(root, vec![(key, value)])
+        .map_err(|err| err.to_string())
+}
+
+// Verifies the proof file given to verify-proof against --root, --key and
+// --value, without opening a database. Exits with 1 for an invalid proof,
+// 2 when the input cannot be read.
+pub fn run(config: &Config) {
+    let path = config
+        .input_file
+        .as_ref()
+        .expect("verify-proof requires a proof file");
+    let root = config.proof_root.expect("verify-proof requires --root");
+    let key = config.key.expect("verify-proof requires --key");
+    let value = config.proof_value.expect("verify-proof requires --value");
+    let proof = read(path).unwrap_or_else(|err| {
+        eprintln!("Cannot read the proof: {}", err);
+        std::process::exit(2);
+    });
+    let claim = if value.is_zero() {
+        format!("key {} is absent", hex(&key))
+    } else {
+        format!("key {} holds {}", hex(&key), hex(&value))
+    };
+    match verify(&proof, &root, key, value) {
+        Ok(true) => println!("valid: {} under root {}", claim, hex(&root)),
+        Ok(false) => {
+            println!(
+                "invalid: the proof does not show that {} under root {}",
+                claim,
+                hex(&root)
+            );
+            std::process::exit(1);
+        }
+        Err(err) => {
+            println!(
+                "invalid: {} bytes of proof do not verify: {}",
+                proof.0.len(),
+                err
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{trie::TrieStore, TrieSmt};
+    use gw_store::Store as GwStore;
+
+    // A compiled proof of a present and of an absent key, written raw and as
+    // hex, must read back and verify with nothing but the root. Text that is
+    // neither must be rejected.
+    #[test]
+    fn proof_file() -> Result<(), String> {
+        let map_err = |err: sparse_merkle_tree::error::Error| err.to_string();
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let tx = store.begin_transaction();
+        let mut smt = TrieSmt::new(H256::zero(), TrieStore::new(&tx));
+        let pairs: Vec<(H256, H256)> = (1..=8u8)
+            .map(|i| (H256::from([i; 32]), H256::from([i.wrapping_mul(31); 32])))
+            .collect();
+        smt.update_all(pairs.clone()).map_err(map_err)?;
+        let root = *smt.root();
+        let absent = H256::from([0x77; 32]);
+
+        let path =
+            std::env::temp_dir().join(format!("smt-bench-check-{}.proof", std::process::id()));
+        let outcome = (|| {
+            for (key, value) in [pairs[3], (absent, H256::zero())] {
+                let proof = smt
+                    .merkle_proof(vec![key])
+                    .and_then(|proof| proof.compile(vec![key]))
+                    .map_err(map_err)?;
+                let hex: String = proof.0.iter().map(|b| format!("{:02x}", b)).collect();
+                for data in [proof.0.clone(), format!("0x{}\n", hex).into_bytes()] {
+                    std::fs::write(&path, &data).map_err(|err| err.to_string())?;
+                    let read = read(&path)?;
+                    if read.0 != proof.0 {
+                        return Err(format!("proof of {:?} read back differently", key));
+                    }
+                    if !verify(&read, &root, key, value)? {
+                        return Err(format!("proof of {:?} did not verify", key));
+                    }
+                }
+            }
+            for bad in ["", "0x", " \n", "0xabc", "0xzz"] {
+                std::fs::write(&path, bad).map_err(|err| err.to_string())?;
+                if read(&path).is_ok() {
+                    return Err(format!("{:?} was read as a proof", bad));
+                }
+            }
+            Ok(())
+        })();
+        let _ = std::fs::remove_file(&path);
+        outcome
+    }
+}
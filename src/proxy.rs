@@ -0,0 +1,547 @@
+use crate::{config::Config, phase::Phase, seed::SeedTree, store_config, trie::TrieStore};
+use gw_db::{
+    error::Error,
+    iter::{DBIter, IteratorMode},
+    schema::Col,
+    RocksDB,
+};
+use gw_store::{traits::KVStore, Store as GwStore};
+use sparse_merkle_tree::H256;
+use std::cell::{Cell, RefCell};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Largest frame either side accepts, a wrapped trie blob is about 33 KB
+const MAX_FRAME: usize = 1 << 20;
+
+const OP_GET: u8 = 0;
+const OP_PUT: u8 = 1;
+const OP_DELETE: u8 = 2;
+const OP_COMMIT: u8 = 3;
+
+const REPLY_MISSING: u8 = 0;
+const REPLY_VALUE: u8 = 1;
+const REPLY_DONE: u8 = 2;
+const REPLY_FAILED: u8 = 3;
+
+// What the client asks of the server. Puts and deletes go into the
+// server's open transaction, which commit commits and replaces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Request {
+    Get(Col, Vec<u8>),
+    Put(Col, Vec<u8>, Vec<u8>),
+    Delete(Col, Vec<u8>),
+    Commit,
+}
+
+// The server's answer to each request, in the order of the requests
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Reply {
+    // A get of an absent key, or the value found
+    Value(Option<Vec<u8>>),
+    Done,
+    Failed(String),
+}
+
+// An op byte, then the column and key of every op but commit. A put's key
+// is preceded by its length as a 4 byte little-endian number, its value
+// takes the rest of the frame.
+impl Request {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Request::Get(col, key) => [&[OP_GET, *col][..], key].concat(),
+            Request::Put(col, key, value) => [
+                &[OP_PUT, *col][..],
+                &(key.len() as u32).to_le_bytes(),
+                key,
+                value,
+            ]
+            .concat(),
+            Request::Delete(col, key) => [&[OP_DELETE, *col][..], key].concat(),
+            Request::Commit => vec![OP_COMMIT],
+        }
+    }
+
+    fn decode(frame: &[u8]) -> Result<Self, String> {
+        match frame {
+            [OP_COMMIT] => Ok(Request::Commit),
+            [OP_GET, col, key @ ..] => Ok(Request::Get(*col, key.to_vec())),
+            [OP_DELETE, col, key @ ..] => Ok(Request::Delete(*col, key.to_vec())),
+            [OP_PUT, col, rest @ ..] if rest.len() >= 4 => {
+                let mut len = [0u8; 4];
+                len.copy_from_slice(&rest[..4]);
+                let len = u32::from_le_bytes(len) as usize;
+                if rest.len() - 4 < len {
+                    return Err(format!(
+                        "put of a {} byte key in a {} byte frame",
+                        len,
+                        frame.len()
+                    ));
+                }
+                let (key, value) = rest[4..].split_at(len);
+                Ok(Request::Put(*col, key.to_vec(), value.to_vec()))
+            }
+            _ => Err(format!("malformed request of {} bytes", frame.len())),
+        }
+    }
+}
+
+impl Reply {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Reply::Value(None) => vec![REPLY_MISSING],
+            Reply::Value(Some(value)) => [&[REPLY_VALUE][..], value].concat(),
+            Reply::Done => vec![REPLY_DONE],
+            Reply::Failed(message) => [&[REPLY_FAILED][..], message.as_bytes()].concat(),
+        }
+    }
+
+    fn decode(frame: &[u8]) -> Result<Self, String> {
+        match frame {
+            [REPLY_MISSING] => Ok(Reply::Value(None)),
+            [REPLY_VALUE, value @ ..] => Ok(Reply::Value(Some(value.to_vec()))),
+            [REPLY_DONE] => Ok(Reply::Done),
+            [REPLY_FAILED, message @ ..] => {
+                Ok(Reply::Failed(String::from_utf8_lossy(message).into_owned()))
+            }
+            _ => Err(format!("malformed reply of {} bytes", frame.len())),
+        }
+    }
+}
+
+// A frame is its length as a 4 byte little-endian number and the payload
+fn write_frame(writer: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} byte frame, at most {} allowed",
+                payload.len(),
+                MAX_FRAME
+            ),
+        ));
+    }
+    writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+    writer.write_all(payload)
+}
+
+// The next frame, None when the stream ends between frames
+fn read_frame(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    let mut filled = 0;
+    while filled < len.len() {
+        match reader.read(&mut len[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => filled += n,
+        }
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} byte frame, at most {} allowed", len, MAX_FRAME),
+        ));
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+// Serves the store to the clients connecting to listener, one at a time,
+// each with a transaction of its own. Replies are flushed once no request
+// is left buffered, so pipelined requests get their replies together.
+pub fn serve(store: GwStore, listener: UnixListener) {
+    for stream in listener.incoming() {
+        let stream = stream.unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = BufWriter::new(stream);
+        let mut tx = store.begin_transaction();
+        while let Some(frame) = read_frame(&mut reader).unwrap() {
+            let reply = match Request::decode(&frame) {
+                Ok(Request::Get(col, key)) => Reply::Value(tx.get(col, &key).map(Vec::from)),
+                Ok(Request::Put(col, key, value)) => match tx.insert_raw(col, &key, &value) {
+                    Ok(()) => Reply::Done,
+                    Err(err) => Reply::Failed(err.to_string()),
+                },
+                Ok(Request::Delete(col, key)) => match tx.delete(col, &key) {
+                    Ok(()) => Reply::Done,
+                    Err(err) => Reply::Failed(err.to_string()),
+                },
+                Ok(Request::Commit) => {
+                    let committed = tx.commit();
+                    tx = store.begin_transaction();
+                    match committed {
+                        Ok(()) => Reply::Done,
+                        Err(err) => Reply::Failed(err.to_string()),
+                    }
+                }
+                Err(err) => Reply::Failed(err),
+            };
+            write_frame(&mut writer, &reply.encode()).unwrap();
+            if reader.buffer().is_empty() {
+                writer.flush().unwrap();
+            }
+        }
+    }
+}
+
+// KVStore talking to a server started by serve. Puts and deletes are sent
+// without waiting for their replies until pipeline of them are
+// outstanding; a get or commit waits for every outstanding reply before
+// its own. Each wait is one round trip. Iterators are not served.
+pub struct ProxyClient {
+    reader: RefCell<BufReader<UnixStream>>,
+    writer: RefCell<BufWriter<UnixStream>>,
+    pipeline: usize,
+    outstanding: Cell<usize>,
+    requests: Cell<usize>,
+    round_trips: Cell<usize>,
+}
+
+// The tree through the proxy: a TrieStore over the client
+pub type ProxyStore<'a> = TrieStore<'a, ProxyClient>;
+
+impl ProxyClient {
+    pub fn connect(socket: &Path, pipeline: usize) -> io::Result<Self> {
+        let stream = UnixStream::connect(socket)?;
+        Ok(Self {
+            reader: RefCell::new(BufReader::new(stream.try_clone()?)),
+            writer: RefCell::new(BufWriter::new(stream)),
+            pipeline: pipeline.max(1),
+            outstanding: Cell::new(0),
+            requests: Cell::new(0),
+            round_trips: Cell::new(0),
+        })
+    }
+
+    pub fn requests(&self) -> usize {
+        self.requests.get()
+    }
+
+    pub fn round_trips(&self) -> usize {
+        self.round_trips.get()
+    }
+
+    fn send(&self, request: &Request) {
+        write_frame(&mut *self.writer.borrow_mut(), &request.encode()).unwrap();
+        self.requests.set(self.requests.get() + 1);
+    }
+
+    // Reads the replies of every request sent, returning the last one
+    fn wait(&self) -> Reply {
+        self.writer.borrow_mut().flush().unwrap();
+        self.round_trips.set(self.round_trips.get() + 1);
+        let mut reader = self.reader.borrow_mut();
+        let mut last = Reply::Done;
+        for _ in 0..self.outstanding.replace(0) {
+            let frame = read_frame(&mut *reader)
+                .unwrap()
+                .expect("proxy server closed the connection");
+            last = Reply::decode(&frame).unwrap();
+            if let Reply::Failed(err) = &last {
+                panic!("proxy server failed a request: {}", err);
+            }
+        }
+        last
+    }
+
+    fn send_write(&self, request: &Request) {
+        self.send(request);
+        self.outstanding.set(self.outstanding.get() + 1);
+        if self.outstanding.get() >= self.pipeline {
+            self.wait();
+        }
+    }
+
+    // Commits the server's transaction once every write is applied
+    pub fn commit(&self) {
+        self.send(&Request::Commit);
+        self.outstanding.set(self.outstanding.get() + 1);
+        self.wait();
+    }
+}
+
+impl KVStore for ProxyClient {
+    fn get(&self, col: Col, key: &[u8]) -> Option<Box<[u8]>> {
+        self.send(&Request::Get(col, key.to_vec()));
+        self.outstanding.set(self.outstanding.get() + 1);
+        match self.wait() {
+            Reply::Value(value) => value.map(Vec::into_boxed_slice),
+            reply => panic!("proxy server answered a get with {:?}", reply),
+        }
+    }
+
+    fn get_iter(&self, _col: Col, _mode: IteratorMode) -> DBIter<'_> {
+        panic!("the proxy serves no iterators");
+    }
+
+    fn insert_raw(&self, col: Col, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.send_write(&Request::Put(col, key.to_vec(), value.to_vec()));
+        Ok(())
+    }
+
+    fn delete(&self, col: Col, key: &[u8]) -> Result<(), Error> {
+        self.send_write(&Request::Delete(col, key.to_vec()));
+        Ok(())
+    }
+}
+
+fn trie_store<'a, DB: KVStore>(config: &Config, db: &'a DB) -> TrieStore<'a, DB> {
+    TrieStore::with_layout(db, config.trie_layout)
+        .with_columns(config.branch_col, config.leaf_col)
+        .molecule_wrap(config.trie_molecule_wrap)
+        .truncate_leaf_keys(config.leaf_key_bytes)
+}
+
+// The rounds of a phase applied both ways
+#[derive(Default)]
+struct Comparison {
+    rounds: usize,
+    ops: usize,
+    in_process: Duration,
+    proxied: Duration,
+    requests: usize,
+    round_trips: usize,
+}
+
+// Runs the load, update, delete and read phases of --phases twice, round
+// by round: in process on the database of --db-path, and through a
+// ProxyClient on a server owning a fresh database next to it, listening
+// on socket. Both must end every round with the same root. Reports what
+// the proxy adds per round and per op.
+pub fn run(config: &Config, socket: &Path, store: &GwStore) {
+    let mut dir = config.db_path.as_os_str().to_owned();
+    dir.push("-proxy");
+    let dir = PathBuf::from(dir);
+    if dir.exists() {
+        panic!(
+            "{} already exists, --store proxy needs a fresh directory",
+            dir.display()
+        );
+    }
+    let listener = UnixListener::bind(socket)
+        .unwrap_or_else(|err| panic!("Cannot listen on {}: {}", socket.display(), err));
+    let server_config = store_config(config, dir.clone());
+    let columns = config.columns;
+    thread::spawn(move || {
+        serve(
+            GwStore::new(RocksDB::open(&server_config, columns)),
+            listener,
+        )
+    });
+    let client = ProxyClient::connect(socket, config.proxy_pipeline)
+        .unwrap_or_else(|err| panic!("Cannot connect to {}: {}", socket.display(), err));
+    println!(
+        "Proxy: {} in process against a server on {} owning {}, pipeline {}",
+        config.db_path.display(),
+        socket.display(),
+        dir.display(),
+        config.proxy_pipeline
+    );
+
+    let mut rng = SeedTree::new(config.seed, config.verbose)
+        .child("proxy")
+        .rng();
+    let mut keys: Vec<(H256, H256)> = vec![];
+    let mut root = H256::zero();
+    for phase in &config.phases {
+        if !phase.leaves_only() {
+            println!(
+                "  {}: skipped, only load, update, delete and read go through the proxy",
+                phase
+            );
+            continue;
+        }
+        let mut comparison = Comparison::default();
+        for _ in 0..phase.rounds {
+            let pairs = phase.round_pairs(&mut keys, &mut rng);
+
+            let start = Instant::now();
+            let tx = store.begin_transaction();
+            let in_process = phase.apply(trie_store(config, &tx), root, &pairs);
+            tx.commit().unwrap();
+            comparison.in_process += start.elapsed();
+
+            let (requests, round_trips) = (client.requests(), client.round_trips());
+            let start = Instant::now();
+            let proxy_store: ProxyStore = trie_store(config, &client);
+            let proxied = phase.apply(proxy_store, root, &pairs);
+            client.commit();
+            comparison.proxied += start.elapsed();
+            comparison.requests += client.requests() - requests;
+            comparison.round_trips += client.round_trips() - round_trips;
+
+            if proxied != in_process {
+                eprintln!(
+                    "{} round {}: root {:?} through the proxy, {:?} in process",
+                    phase,
+                    comparison.rounds + 1,
+                    proxied,
+                    in_process
+                );
+                std::process::exit(1);
+            }
+            root = in_process;
+            comparison.rounds += 1;
+            comparison.ops += pairs.len();
+        }
+        print_comparison(phase, &comparison);
+    }
+    drop(client);
+    let _ = std::fs::remove_file(socket);
+}
+
+fn print_comparison(phase: &Phase, comparison: &Comparison) {
+    let rounds = comparison.rounds.max(1) as u32;
+    let ops = comparison.ops.max(1) as u32;
+    let overhead = comparison.proxied.saturating_sub(comparison.in_process);
+    println!(
+        "  {}: in process {:?} per round, proxy {:?} per round ({:.2}x)",
+        phase,
+        comparison.in_process / rounds,
+        comparison.proxied / rounds,
+        comparison.proxied.as_secs_f64() / comparison.in_process.as_secs_f64().max(f64::EPSILON)
+    );
+    println!(
+        "    overhead {:?} per round, {:?} per op; {:.1} requests and {:.1} round trips per op",
+        overhead / rounds,
+        overhead / ops,
+        comparison.requests as f64 / ops as f64,
+        comparison.round_trips as f64 / ops as f64
+    );
+}
+
+#[cfg(all(test, feature = "proxy"))]
+mod tests {
+    use super::*;
+    use crate::{random_h256, TrieSmt};
+
+    // Hands out one byte per read, as a socket may
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((byte, rest)), Some(slot)) => {
+                    *slot = *byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn requests() -> Vec<Request> {
+        vec![
+            Request::Get(1, vec![7; 32]),
+            Request::Get(0, vec![]),
+            Request::Put(0, vec![1, 2, 3], vec![4; 100]),
+            Request::Put(1, vec![5; 32], vec![]),
+            Request::Delete(2, vec![9; 32]),
+            Request::Commit,
+        ]
+    }
+
+    fn stream() -> Vec<u8> {
+        let mut stream = vec![];
+        for request in requests() {
+            write_frame(&mut stream, &request.encode()).unwrap();
+        }
+        stream
+    }
+
+    #[test]
+    fn frames_read_a_byte_at_a_time() {
+        let stream = stream();
+        let mut reader = Trickle(&stream);
+        for request in requests() {
+            let frame = read_frame(&mut reader)
+                .unwrap()
+                .expect("stream ended early");
+            assert_eq!(Request::decode(&frame), Ok(request));
+        }
+        assert!(matches!(read_frame(&mut reader), Ok(None)));
+        let replies = vec![
+            Reply::Value(None),
+            Reply::Value(Some(vec![])),
+            Reply::Value(Some(vec![3; 1000])),
+            Reply::Done,
+            Reply::Failed("no space left".to_string()),
+        ];
+        for reply in replies {
+            assert_eq!(Reply::decode(&reply.encode()), Ok(reply));
+        }
+    }
+
+    #[test]
+    fn truncated_frames_are_refused() {
+        let stream = stream();
+        assert!(read_frame(&mut Trickle(&stream[..2])).is_err());
+        let mut partial = Trickle(&stream[..stream.len() - 1]);
+        let mut result = read_frame(&mut partial);
+        while let Ok(Some(_)) = result {
+            result = read_frame(&mut partial);
+        }
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn oversized_frames_are_refused() {
+        let oversized = ((MAX_FRAME + 1) as u32).to_le_bytes();
+        assert!(read_frame(&mut Trickle(&oversized)).is_err());
+        assert!(write_frame(&mut vec![], &vec![0; MAX_FRAME + 1]).is_err());
+    }
+
+    #[test]
+    fn malformed_payloads_are_refused() {
+        let short_put = [&[OP_PUT, 0][..], &10u32.to_le_bytes(), &[1, 2, 3]].concat();
+        assert!(Request::decode(&short_put).is_err());
+        assert!(Request::decode(&[]).is_err());
+        assert!(Reply::decode(&[9]).is_err());
+        assert!(Reply::decode(&[REPLY_DONE, 0]).is_err());
+    }
+
+    // A tree updated through a pipelining client must get the root and
+    // leaves it gets in process, in fewer round trips than requests
+    #[test]
+    fn pipelined_client_matches_in_process() {
+        let socket =
+            std::env::temp_dir().join(format!("smt-bench-proxy-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        let served = GwStore::open_tmp().unwrap();
+        thread::spawn(move || serve(served, listener));
+        let client = ProxyClient::connect(&socket, 4).unwrap();
+        let _ = std::fs::remove_file(&socket);
+
+        let mut rng = SeedTree::new(0, false).child("proxy-test").rng();
+        let pairs: Vec<(H256, H256)> = (0..20)
+            .map(|_| (random_h256(&mut rng), random_h256(&mut rng)))
+            .collect();
+        let local = GwStore::open_tmp().unwrap();
+        let tx = local.begin_transaction();
+        let mut smt = TrieSmt::new(H256::zero(), TrieStore::new(&tx));
+        smt.update_all(pairs.clone()).unwrap();
+        let expected = *smt.root();
+
+        let mut smt = TrieSmt::new(H256::zero(), TrieStore::new(&client));
+        smt.update_all(pairs.clone()).unwrap();
+        client.commit();
+        assert_eq!(*smt.root(), expected);
+        let smt = TrieSmt::new(expected, TrieStore::new(&client));
+        for (key, value) in &pairs {
+            assert_eq!(smt.get(key).unwrap(), *value);
+        }
+        assert!(
+            client.round_trips() < client.requests(),
+            "{} round trips for {} requests with a pipeline of 4",
+            client.round_trips(),
+            client.requests()
+        );
+    }
+}
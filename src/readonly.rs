@@ -0,0 +1,275 @@
+use crate::{
+    analysis,
+    config::{Command, Config, OutputFormat, StoreKind},
+    dot,
+    old::CountingStore,
+    path,
+    phase::{Phase, PhaseKind},
+    results,
+    runner::{PhaseReport, RoundReport},
+    seed::SeedTree,
+    trie::TrieStore,
+};
+use gw_db::{
+    error::Error,
+    internal::{
+        ops::{GetColumnFamilys, GetPinnedCF, IterateCF},
+        ColumnFamily, Options, ReadOnlyDB,
+    },
+    iter::{DBIter, Direction, IteratorMode},
+    schema::Col,
+};
+use gw_store::traits::KVStore;
+use rand_chacha::{rand_core::RngCore, ChaCha20Rng};
+use sparse_merkle_tree::{
+    blake2b::Blake2bHasher, merge::merge, traits::Store, tree::BranchKey, SparseMerkleTree, H256,
+};
+use std::time::Instant;
+
+// The tree's columns of a database opened with RocksDB's read-only open,
+// which takes no lock, so a running godwoken node can keep the database.
+// Only the column families of --branch-col and --leaf-col are opened,
+// RocksDB allows a subset of them read-only. Reads see the database as it
+// was when it was opened.
+pub struct ReadOnlyStore {
+    db: ReadOnlyDB,
+}
+
+impl ReadOnlyStore {
+    pub fn open(config: &Config) -> Result<Self, String> {
+        let mut names = vec![config.branch_col.to_string()];
+        if config.leaf_col != config.branch_col {
+            names.push(config.leaf_col.to_string());
+        }
+        ReadOnlyDB::open_cf(&Options::default(), &config.db_path, &names)
+            .map(|db| Self { db })
+            .map_err(|err| err.to_string())
+    }
+
+    fn cf(&self, col: Col) -> &ColumnFamily {
+        self.db
+            .cf_handle(&col.to_string())
+            .unwrap_or_else(|| panic!("Column {} is not opened read-only", col))
+    }
+}
+
+impl KVStore for ReadOnlyStore {
+    fn get(&self, col: Col, key: &[u8]) -> Option<Box<[u8]>> {
+        self.db
+            .get_pinned_cf(self.cf(col), key)
+            .unwrap_or_else(|err| panic!("Read-only get failed: {}", err))
+            .map(|value| Box::from(value.as_ref()))
+    }
+
+    fn get_iter(&self, col: Col, mode: IteratorMode) -> DBIter<'_> {
+        self.db
+            .iterator_cf(self.cf(col), mode)
+            .unwrap_or_else(|err| panic!("Read-only iterator failed: {}", err))
+    }
+
+    fn insert_raw(&self, col: Col, _key: &[u8], _value: &[u8]) -> Result<(), Error> {
+        panic!("--read-only opened column {} without write access", col);
+    }
+
+    fn delete(&self, col: Col, _key: &[u8]) -> Result<(), Error> {
+        panic!("--read-only opened column {} without write access", col);
+    }
+}
+
+// Runs the command of a --read-only run on the read-only store: the dot and
+// path output, or the read and prove-same phases of bench
+pub fn run(config: &Config, seeds: SeedTree) {
+    let store = ReadOnlyStore::open(config).unwrap_or_else(|err| {
+        panic!(
+            "Cannot open {} read-only: {}",
+            config.db_path.display(),
+            err
+        )
+    });
+    let out = &mut std::io::stdout();
+    match config.command {
+        Command::Dot => {
+            dot::write_dot(&trie_store(config, &store), config.max_nodes, out).unwrap();
+        }
+        Command::Path => {
+            let key = config.key.expect("path requires --key");
+            match config.store {
+                StoreKind::Trie => {
+                    path::write_path(&trie_store(config, &store), &key, true, config.json, out)
+                        .unwrap()
+                }
+                StoreKind::Counting => {
+                    let counting =
+                        CountingStore::new(&store).with_columns(config.branch_col, config.leaf_col);
+                    path::write_path(&counting, &key, false, config.json, out).unwrap();
+                }
+            }
+        }
+        _ => bench(config, &store, seeds),
+    }
+}
+
+fn trie_store<'a>(config: &Config, store: &'a ReadOnlyStore) -> TrieStore<'a, ReadOnlyStore> {
+    TrieStore::with_layout(store, config.trie_layout)
+        .with_columns(config.branch_col, config.leaf_col)
+        .molecule_wrap(config.trie_molecule_wrap)
+}
+
+fn bench(config: &Config, store: &ReadOnlyStore, seeds: SeedTree) {
+    println!(
+        "Read-only: {} is opened without its lock, reads see it as of now",
+        config.db_path.display()
+    );
+    let root = match config.store {
+        StoreKind::Trie => stored_root(&trie_store(config, store)),
+        StoreKind::Counting => {
+            stored_root(&CountingStore::new(store).with_columns(config.branch_col, config.leaf_col))
+        }
+    };
+    println!("Root: {}", dot::hex(&root));
+    let mut phases = config.phases.clone();
+    if config.leaf_gets > 0 {
+        phases.push(Phase::new(PhaseKind::Read, 1, config.leaf_gets));
+    }
+    let mut reports: Vec<PhaseReport> = vec![];
+    for (i, phase) in phases.iter().enumerate() {
+        let phase_seeds = seeds.child(&format!("phase-{}", i));
+        // Every round of a prove-same phase proves the same key
+        let proof_key = (phase.kind == PhaseKind::ProveSame).then(|| {
+            let mut rng = phase_seeds.child("proof-key").rng();
+            sample_leaves(config, store, &mut rng, 1)[0]
+        });
+        let rounds = (0..phase.rounds)
+            .map(|round| {
+                let keys = match proof_key {
+                    Some(key) => vec![key; phase.batch_size],
+                    None => {
+                        let mut rng = phase_seeds.child(&format!("round-{}", round)).rng();
+                        sample_leaves(config, store, &mut rng, phase.batch_size)
+                    }
+                };
+                match config.store {
+                    StoreKind::Trie => run_round(
+                        phase.kind,
+                        root,
+                        trie_store(config, store),
+                        &keys,
+                        TrieStore::reads,
+                    ),
+                    StoreKind::Counting => run_round(
+                        phase.kind,
+                        root,
+                        CountingStore::new(store).with_columns(config.branch_col, config.leaf_col),
+                        &keys,
+                        CountingStore::reads,
+                    ),
+                }
+            })
+            .collect();
+        let report = PhaseReport {
+            phase: phase.clone(),
+            rounds,
+        };
+        report.print();
+        if let Some(previous) = reports.last() {
+            report.print_transition(previous);
+        }
+        reports.push(report);
+    }
+    analysis::print_findings(
+        &analysis::findings(&reports),
+        &analysis::steady_states(&reports, config.steady_after),
+        &[],
+        None,
+        config,
+    );
+    if config.output == OutputFormat::Binary {
+        match results::write(config, &reports) {
+            Ok(path) => println!("Results written to {}", path.display()),
+            Err(err) => eprintln!("Warning: cannot write the results: {}", err),
+        }
+    }
+}
+
+// The root the stored top branch hashes to, zero without one
+fn stored_root<S: Store<H256>>(store: &S) -> H256 {
+    let top = store
+        .get_branch(&BranchKey::new(u8::MAX, H256::zero()))
+        .unwrap_or_else(|err| panic!("Cannot read the top branch: {}", err));
+    top.map_or(H256::zero(), |branch| {
+        merge::<Blake2bHasher>(u8::MAX, &H256::zero(), &branch.left, &branch.right)
+            .hash::<Blake2bHasher>()
+    })
+}
+
+// Count leaves of the tree, each the first at or after a random key, so
+// the rounds read keys that exist. Done before the round is timed.
+fn sample_leaves(
+    config: &Config,
+    store: &ReadOnlyStore,
+    rng: &mut ChaCha20Rng,
+    count: usize,
+) -> Vec<H256> {
+    let mut keys = vec![];
+    for _ in 0..count {
+        let mut start = [0u8; 32];
+        rng.fill_bytes(&mut start);
+        let leaf = store
+            .get_iter(
+                config.leaf_col,
+                IteratorMode::From(&start, Direction::Forward),
+            )
+            .next()
+            .or_else(|| store.get_iter(config.leaf_col, IteratorMode::Start).next());
+        match leaf {
+            Some((key, _)) if key.len() == 32 => {
+                let mut buf = [0u8; 32];
+                buf.copy_from_slice(&key);
+                keys.push(buf.into());
+            }
+            Some((key, _)) => panic!(
+                "Column {} holds a {} byte key, is --leaf-col right?",
+                config.leaf_col,
+                key.len()
+            ),
+            None => panic!("Column {} holds no leaves", config.leaf_col),
+        }
+    }
+    keys
+}
+
+// Reads every key, or generates a proof of every key with prove-same,
+// timed. reads counts the store reads of the round.
+fn run_round<S: Store<H256>>(
+    kind: PhaseKind,
+    root: H256,
+    store: S,
+    keys: &[H256],
+    reads: fn(&S) -> usize,
+) -> RoundReport {
+    let mut failed_ops = 0;
+    let start = Instant::now();
+    let store = if kind == PhaseKind::Read {
+        for key in keys {
+            if store.get_leaf(key).is_err() {
+                failed_ops += 1;
+            }
+        }
+        store
+    } else {
+        let tree = SparseMerkleTree::<Blake2bHasher, H256, S>::new(root, store);
+        for key in keys {
+            if tree.merkle_proof(vec![*key]).is_err() {
+                failed_ops += 1;
+            }
+        }
+        tree.take_store()
+    };
+    RoundReport {
+        ops: keys.len(),
+        reads: reads(&store),
+        elapsed: start.elapsed(),
+        failed_ops,
+        ..Default::default()
+    }
+}
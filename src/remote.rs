@@ -0,0 +1,528 @@
+use crate::{
+    config::{Command, Config},
+    grid::{base_args, cell_metrics, panic_message},
+    phase::{Phase, PhaseKind},
+    results::round_fields,
+    runner::{clear_stop, request_stop, stop_requested, ReportSink, RoundReport, Runner},
+    seed::SeedTree,
+    store_config,
+};
+use gw_db::RocksDB;
+use gw_store::Store as GwStore;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Largest request body accepted, a configuration is a few hundred bytes
+const MAX_BODY: usize = 64 << 10;
+// The only options a request may give: the workload and the store and
+// layout it runs on. The API has no authentication, so nothing naming a
+// file or touching the host beyond the run's own database is allowed.
+const WORKLOAD_OPTIONS: &[&str] = &[
+    "phases",
+    "seed",
+    "leaf-gets",
+    "at",
+    "round-size-distribution",
+    "key-preimage-size",
+    "app-key-size",
+    "value-sizes",
+    "proof-mix",
+    "sorted-keys",
+    "rewrite-storm",
+    "smt-lifetime",
+    "stats-mode",
+    "sample",
+    "verify-commit",
+    "verify-leaves",
+    "time-root",
+    "assert-root-changes",
+    "max-writes-per-update",
+    "check-contract",
+    "strict-leaf",
+    "guard-columns",
+    "tolerate",
+    "on-violation",
+    "fault-rate",
+    "store",
+    "trie-layout",
+    "trie-molecule-wrap",
+    "branch-col",
+    "leaf-col",
+    "columns",
+    "block-cache-mb",
+    "init-threads",
+    "init-chunk",
+    "max-staged-bytes",
+    "split-commits",
+    "iops-limit",
+    "pause-compaction",
+    "max-background-compactions",
+    "max-background-flushes",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Running,
+    // Asked to stop, finishing the round in progress
+    Stopping,
+    Finished,
+    Stopped,
+    Failed,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Running => "running",
+            Status::Stopping => "stopping",
+            Status::Finished => "finished",
+            Status::Stopped => "stopped",
+            Status::Failed => "failed",
+        }
+    }
+
+    fn active(self) -> bool {
+        matches!(self, Status::Running | Status::Stopping)
+    }
+}
+
+struct Run {
+    status: Status,
+    // Every round reported so far, each a JSON object
+    rounds: Vec<String>,
+    // Metrics once the run ended, or the message it failed with
+    result: Option<Result<Vec<(&'static str, String)>, String>>,
+}
+
+// Runs by id, the id of a run being its index plus one
+type Runs = Arc<Mutex<Vec<Run>>>;
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Response {
+    fn new(status: u16, body: String) -> Self {
+        Self { status, body }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::new(status, format!("{{\"error\": {}}}", json_str(message)))
+    }
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// A metric as a JSON number when it is one, a string otherwise
+fn json_metric(value: &str) -> String {
+    match value.parse::<f64>() {
+        Ok(number) if number.is_finite() => value.to_string(),
+        _ => json_str(value),
+    }
+}
+
+// Reads a string of a JSON document, the opening quote already consumed
+fn parse_json_str(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+    let mut out = String::new();
+    loop {
+        match chars.next().ok_or("unterminated string")? {
+            '"' => return Ok(out),
+            '\\' => match chars.next().ok_or("unterminated string")? {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                'r' => out.push('\r'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("bad escape \\u{}", code))?;
+                    out.push(code);
+                }
+                c => out.push(c),
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+// The options of a configuration given as a flat JSON object, each member
+// an option named without its dashes: a string or number is its value,
+// true gives an option taking no value and false or null leaves it out, as
+// the on and off of --grid do
+fn parse_options(body: &str) -> Result<Vec<String>, String> {
+    let mut chars = body.trim().chars().peekable();
+    let mut args = vec![];
+    let skip_ws = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    };
+    if chars.next() != Some('{') {
+        return Err("the configuration must be a JSON object".to_string());
+    }
+    skip_ws(&mut chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return Ok(args);
+    }
+    loop {
+        skip_ws(&mut chars);
+        if chars.next() != Some('"') {
+            return Err("expected an option name".to_string());
+        }
+        let name = parse_json_str(&mut chars)?;
+        if !WORKLOAD_OPTIONS.contains(&name.as_str()) {
+            return Err(format!(
+                "--{} cannot be set for a remote run, only workload, store and layout options can",
+                name
+            ));
+        }
+        skip_ws(&mut chars);
+        if chars.next() != Some(':') {
+            return Err(format!("expected a value for {}", name));
+        }
+        skip_ws(&mut chars);
+        let option = format!("--{}", name);
+        match chars.peek() {
+            Some('"') => {
+                chars.next();
+                let value = parse_json_str(&mut chars)?;
+                // A proxy store names the socket of the proxy
+                if name == "store" && value.starts_with("proxy:") {
+                    return Err("a remote run cannot use a proxy store".to_string());
+                }
+                args.extend([option, value]);
+            }
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || "+-.".contains(*c)) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "true" => args.push(option),
+                    "false" | "null" => {}
+                    _ if word.parse::<f64>().is_ok() => args.extend([option, word]),
+                    _ => return Err(format!("bad value for {}", name)),
+                }
+            }
+            None => return Err(format!("expected a value for {}", name)),
+        }
+        skip_ws(&mut chars);
+        match chars.next() {
+            Some(',') => {}
+            Some('}') if chars.all(char::is_whitespace) => return Ok(args),
+            _ => return Err("expected , or } after a value".to_string()),
+        }
+    }
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let method = words.next().unwrap_or_default().to_string();
+    let path = words.next().unwrap_or_default().to_string();
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(usize::MAX);
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(io::Error::other(format!(
+            "a body of more than {} bytes",
+            MAX_BODY
+        )));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Request { method, path, body })
+}
+
+fn write_response(mut stream: &TcpStream, response: &Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason,
+        response.body.len() + 1,
+        response.body
+    )?;
+    writeln!(stream)?;
+    stream.flush()
+}
+
+// Records the rounds of a run as they are reported
+struct Recorder {
+    runs: Runs,
+    id: usize,
+}
+
+impl ReportSink for Recorder {
+    fn round(&mut self, phase: &Phase, index: usize, round: &RoundReport) {
+        let fields: Vec<String> = round_fields(round)
+            .iter()
+            .map(|(name, value)| format!("{}: {}", json_str(name), value))
+            .collect();
+        let line = format!(
+            "{{\"phase\": {}, \"round\": {}, {}}}",
+            json_str(&phase.to_string()),
+            index + 1,
+            fields.join(", ")
+        );
+        self.runs.lock().unwrap()[self.id - 1].rounds.push(line);
+    }
+}
+
+// The option of a mode config runs in instead of the phases, which run
+// does not know
+fn other_mode(config: &Config) -> Option<&'static str> {
+    if config.experiment.is_some() {
+        Some("--experiment")
+    } else if config.growth_profile {
+        Some("--growth-profile")
+    } else if config.scan {
+        Some("--workload scan")
+    } else if config.determinism_check.is_some() {
+        Some("--determinism-check")
+    } else if !config.profile_compare.is_empty() {
+        Some("--profile-compare")
+    } else if config.resume {
+        Some("--resume")
+    } else {
+        None
+    }
+}
+
+// Runs the phases of config on a fresh database in path, like a cell of a
+// grid, until they are done or a stop is requested. A stopped run still
+// finishes the round in progress and the runner, so its database and
+// report are complete up to that round.
+fn run(runs: Runs, id: usize, config: Config, path: PathBuf) {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let db = RocksDB::open(&store_config(&config, path.clone()), config.columns);
+        let store = GwStore::new(db);
+        let mut runner = Runner::new(&config, &store, SeedTree::new(config.seed, false));
+        runner.add_sink(Box::new(Recorder {
+            runs: runs.clone(),
+            id,
+        }));
+        let mut phases = config.phases.clone();
+        if config.leaf_gets > 0 {
+            phases.push(Phase::new(PhaseKind::Read, 1, config.leaf_gets));
+        }
+        let mut rounds = vec![];
+        for phase in &phases {
+            rounds.extend(runner.run_phase(phase).rounds);
+            if stop_requested() {
+                break;
+            }
+        }
+        runner.finish();
+        cell_metrics(&rounds, &path, runner.root())
+    }))
+    .map_err(|payload| panic_message(&*payload));
+
+    let mut runs = runs.lock().unwrap();
+    let entry = &mut runs[id - 1];
+    entry.status = match (&result, entry.status) {
+        (Err(_), _) => Status::Failed,
+        (Ok(_), Status::Stopping) => Status::Stopped,
+        (Ok(_), _) => Status::Finished,
+    };
+    println!("Run {} {}", id, entry.status.name());
+    entry.result = Some(result);
+    // Under the lock, so a stop asked for this run cannot outlive it
+    clear_stop();
+}
+
+// Starts a run of the configuration in body unless one is in progress
+fn start(runs: &Runs, base: &[String], dir: &Path, body: &[u8]) -> Response {
+    let body = match std::str::from_utf8(body) {
+        Ok(body) => body,
+        Err(_) => return Response::error(400, "the configuration is not UTF-8"),
+    };
+    let options = match parse_options(body) {
+        Ok(options) => options,
+        Err(err) => return Response::error(400, &err),
+    };
+    let mut list = runs.lock().unwrap();
+    if let Some(active) = list.iter().position(|run| run.status.active()) {
+        return Response::error(409, &format!("run {} is still in progress", active + 1));
+    }
+    let id = list.len() + 1;
+    let path = dir.join(format!("run-{}", id));
+    let mut args = base.to_vec();
+    args.extend(options);
+    args.extend(["--db-path".to_string(), path.display().to_string()]);
+    let config = match panic::catch_unwind(|| Config::parse(args)) {
+        Ok(config) if config.command == Command::Bench => config,
+        Ok(_) => return Response::error(400, "a remote run can only be a bench"),
+        Err(payload) => return Response::error(400, &panic_message(&*payload)),
+    };
+    if let Some(mode) = other_mode(&config) {
+        return Response::error(400, &format!("a remote run cannot run {}", mode));
+    }
+    let config = Config {
+        report_every: 0,
+        tui: false,
+        ..config
+    };
+    list.push(Run {
+        status: Status::Running,
+        rounds: vec![],
+        result: None,
+    });
+    println!("Run {} started in {}", id, path.display());
+    let runs = runs.clone();
+    thread::spawn(move || run(runs, id, config, path));
+    Response::new(201, format!("{{\"id\": {}}}", id))
+}
+
+fn handle(request: &Request, runs: &Runs, base: &[String], dir: &Path) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    if segments.first() != Some(&"runs") {
+        return Response::error(404, "no such resource");
+    }
+    if segments.len() == 1 {
+        return match request.method.as_str() {
+            "POST" => start(runs, base, dir, &request.body),
+            _ => Response::error(405, "only POST is allowed on /runs"),
+        };
+    }
+    let id = match segments[1].parse::<usize>() {
+        Ok(id) if id >= 1 && id <= runs.lock().unwrap().len() => id,
+        _ => return Response::error(404, "no such run"),
+    };
+    let mut list = runs.lock().unwrap();
+    let run = &mut list[id - 1];
+    match (request.method.as_str(), segments.get(2).copied()) {
+        ("GET", None) => Response::new(
+            200,
+            format!(
+                "{{\"id\": {}, \"status\": \"{}\", \"rounds\": [{}]}}",
+                id,
+                run.status.name(),
+                run.rounds.join(", ")
+            ),
+        ),
+        ("DELETE", None) => {
+            if run.status != Status::Running {
+                return Response::error(409, &format!("run {} is {}", id, run.status.name()));
+            }
+            request_stop();
+            run.status = Status::Stopping;
+            println!("Run {} stopping", id);
+            Response::new(202, format!("{{\"id\": {}, \"status\": \"stopping\"}}", id))
+        }
+        ("GET", Some("report")) => match &run.result {
+            None => Response::error(409, &format!("run {} is {}", id, run.status.name())),
+            Some(Err(err)) => Response::error(500, err),
+            Some(Ok(metrics)) => {
+                let metrics: Vec<String> = metrics
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", json_str(name), json_metric(value)))
+                    .collect();
+                Response::new(
+                    200,
+                    format!(
+                        "{{\"id\": {}, \"status\": \"{}\", \"rounds\": {}, \"metrics\": {{{}}}}}",
+                        id,
+                        run.status.name(),
+                        run.rounds.len(),
+                        metrics.join(", ")
+                    ),
+                )
+            }
+        },
+        (_, None) => Response::error(405, "only GET and DELETE are allowed on a run"),
+        (_, Some("report")) => Response::error(405, "only GET is allowed on a report"),
+        _ => Response::error(404, "no such resource"),
+    }
+}
+
+// Serves the HTTP API of --serve: POST /runs starts a run of the JSON
+// configuration in the body, on top of the options of the command line,
+// GET /runs/{id} returns its status and rounds so far, DELETE /runs/{id}
+// stops it after the round in progress and GET /runs/{id}/report returns
+// its metrics once it ended. One run at a time, each on a fresh database
+// in a directory next to --db-path.
+pub fn serve(config: &Config, addr: &str) {
+    let mut dir = config.db_path.as_os_str().to_owned();
+    dir.push("-runs");
+    let dir = PathBuf::from(dir);
+    if dir.exists() {
+        panic!(
+            "{} already exists, --serve needs a fresh directory",
+            dir.display()
+        );
+    }
+    fs::create_dir_all(&dir).unwrap();
+    let listener =
+        TcpListener::bind(addr).unwrap_or_else(|err| panic!("Cannot listen on {}: {}", addr, err));
+    println!(
+        "Serving runs on {}, databases in {}",
+        listener.local_addr().unwrap(),
+        dir.display()
+    );
+    let runs: Runs = Arc::new(Mutex::new(vec![]));
+    let base = Arc::new(base_args());
+    let dir = Arc::new(dir);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Accepting a connection failed: {}", err);
+                continue;
+            }
+        };
+        let (runs, base, dir) = (runs.clone(), base.clone(), dir.clone());
+        thread::spawn(move || {
+            let response = match read_request(&stream) {
+                Ok(request) => handle(&request, &runs, &base, &dir),
+                Err(err) => Response::error(400, &err.to_string()),
+            };
+            let _ = write_response(&stream, &response);
+        });
+    }
+}
@@ -0,0 +1,296 @@
+use crate::{
+    batching::{copy_dir, open},
+    config::Config,
+    dot::hex,
+    random_h256,
+    seed::SeedTree,
+    trie::TrieStore,
+    TrieSmt,
+};
+use gw_store::{traits::KVStore, Store as GwStore};
+use rand_chacha::rand_core::RngCore;
+use sparse_merkle_tree::H256;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+fn trie_store<'a, DB: KVStore>(config: &Config, db: &'a DB) -> TrieStore<'a, DB> {
+    TrieStore::with_layout(db, config.trie_layout)
+        .with_columns(config.branch_col, config.leaf_col)
+        .molecule_wrap(config.trie_molecule_wrap)
+        .truncate_leaf_keys(config.leaf_key_bytes)
+}
+
+// Rounds applied before the snapshot, the rest are applied again after
+// reverting to it
+pub(crate) fn snapshot_round(config: &Config) -> usize {
+    config.reorder_rounds / 2
+}
+
+// The --reorder-rounds rounds of --reorder-batch-size pairs. Rounds before
+// the snapshot insert fresh keys. The rounds after it each insert fresh
+// keys, update keys from before the snapshot and delete others, a third
+// each, and no key is written by two of them, so applying them in any
+// order leaves the same leaves.
+pub(crate) fn workload(config: &Config, seeds: &SeedTree) -> Vec<Vec<(H256, H256)>> {
+    let mut rng = seeds.child("workload").rng();
+    let mut written = vec![];
+    (0..config.reorder_rounds)
+        .map(|round| {
+            let pairs: Vec<(H256, H256)> = (0..config.reorder_batch_size)
+                .map(|_| {
+                    let pick = rng.next_u64() % 3;
+                    if round < snapshot_round(config) || pick == 0 || written.is_empty() {
+                        return (random_h256(&mut rng), random_h256(&mut rng));
+                    }
+                    let i = rng.next_u64() as usize % written.len();
+                    let key = written.swap_remove(i);
+                    if pick == 1 {
+                        (key, random_h256(&mut rng))
+                    } else {
+                        (key, H256::zero())
+                    }
+                })
+                .collect();
+            if round < snapshot_round(config) {
+                written.extend(pairs.iter().map(|(key, _)| *key));
+            }
+            pairs
+        })
+        .collect()
+}
+
+// The order the n rounds after the snapshot are applied again in, 0 being
+// the first of them: a shuffle drawn from a seed of its own, so the same
+// --seed always gives the same order whatever the workload, and never the
+// original order when there are two rounds or more
+pub(crate) fn permutation(seeds: &SeedTree, n: usize) -> Vec<usize> {
+    let mut rng = seeds.child("permutation").rng();
+    let mut order: Vec<usize> = (0..n).collect();
+    for i in (1..n).rev() {
+        let j = rng.next_u64() as usize % (i + 1);
+        order.swap(i, j);
+    }
+    if order.iter().enumerate().all(|(i, round)| i == *round) {
+        order.rotate_left(1);
+    }
+    order
+}
+
+// A copy of a closed database, which restore puts back in place of what
+// the database became since. Taking one replaces an older copy at path.
+pub(crate) struct Snapshot {
+    path: PathBuf,
+}
+
+impl Snapshot {
+    pub(crate) fn take(from: &Path, path: PathBuf) -> Self {
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        copy_dir(from, &path).unwrap();
+        Self { path }
+    }
+
+    pub(crate) fn restore(&self, to: &Path) {
+        fs::remove_dir_all(to).unwrap();
+        copy_dir(&self.path, to).unwrap();
+    }
+}
+
+// What applying rounds one after the other cost, commits included
+#[derive(Default)]
+struct Pass {
+    elapsed: Duration,
+    reads: usize,
+    writes: usize,
+    written_bytes: usize,
+}
+
+// Applies rounds to the tree of root in store, a transaction each, and
+// returns the new root
+fn apply<'a>(
+    config: &Config,
+    store: &GwStore,
+    mut root: H256,
+    rounds: impl Iterator<Item = &'a Vec<(H256, H256)>>,
+    pass: &mut Pass,
+) -> H256 {
+    for pairs in rounds {
+        let start = Instant::now();
+        let tx = store.begin_transaction();
+        let mut smt = TrieSmt::new(root, trie_store(config, &tx));
+        smt.update_all(pairs.clone()).unwrap();
+        root = *smt.root();
+        let trie = smt.store();
+        pass.reads += trie.reads();
+        pass.writes += trie.writes();
+        pass.written_bytes += trie.written_bytes();
+        tx.commit().unwrap();
+        pass.elapsed += start.elapsed();
+    }
+    root
+}
+
+// Applies the rounds of the workload, snapshots the database midway and
+// applies the rounds after the snapshot in their order, then reverts to
+// the snapshot and applies them again in a permuted order. Both orders
+// leave the same leaves, so they must end with the same root. Reports the
+// branch I/O the permuted order costs over the original one.
+pub fn run(config: &Config) {
+    let mut dir = config.db_path.as_os_str().to_owned();
+    dir.push("-reorder");
+    let dir = PathBuf::from(dir);
+    if dir.exists() {
+        panic!(
+            "{} already exists, --experiment reorder needs a fresh directory",
+            dir.display()
+        );
+    }
+    let seeds = SeedTree::new(config.seed, config.verbose).child("reorder");
+    let rounds = workload(config, &seeds);
+    let (before, after) = rounds.split_at(snapshot_round(config));
+    let order = permutation(&seeds, after.len());
+    println!(
+        "Reorder: {} rounds of {} keys, snapshot after round {}, in {}",
+        config.reorder_rounds,
+        config.reorder_batch_size,
+        before.len(),
+        dir.display()
+    );
+    println!(
+        "  Rounds {}-{} applied again in the order {:?}",
+        before.len() + 1,
+        rounds.len(),
+        order
+            .iter()
+            .map(|i| before.len() + i + 1)
+            .collect::<Vec<_>>()
+    );
+
+    let path = dir.join("db");
+    let store = open(config, &path);
+    let snapshot_root = apply(
+        config,
+        &store,
+        H256::zero(),
+        before.iter(),
+        &mut Pass::default(),
+    );
+    drop(store);
+    let snapshot = Snapshot::take(&path, dir.join("snapshot"));
+
+    let mut original = Pass::default();
+    let store = open(config, &path);
+    let original_root = apply(config, &store, snapshot_root, after.iter(), &mut original);
+    drop(store);
+
+    snapshot.restore(&path);
+    let mut permuted = Pass::default();
+    let store = open(config, &path);
+    let permuted_root = apply(
+        config,
+        &store,
+        snapshot_root,
+        order.iter().map(|i| &after[*i]),
+        &mut permuted,
+    );
+    drop(store);
+    fs::remove_dir_all(&dir).unwrap();
+
+    if permuted_root != original_root {
+        eprintln!(
+            "The permuted rounds ended with root {}, the original order with {}",
+            hex(&permuted_root),
+            hex(&original_root)
+        );
+        std::process::exit(1);
+    }
+    for (name, pass) in [("original", &original), ("permuted", &permuted)] {
+        println!(
+            "  {:<9} {:?}, {} reads, {} writes, {:.1} MiB written",
+            name,
+            pass.elapsed,
+            pass.reads,
+            pass.writes,
+            pass.written_bytes as f64 / (1 << 20) as f64
+        );
+    }
+    let extra = |permuted: usize, original: usize| {
+        (permuted as f64 - original as f64) * 100.0 / original.max(1) as f64
+    };
+    println!(
+        "  Permuted order: {:+.1}% reads, {:+.1}% writes, {:+.1}% bytes written, {:+.1}% time; both end with root {}",
+        extra(permuted.reads, original.reads),
+        extra(permuted.writes, original.writes),
+        extra(permuted.written_bytes, original.written_bytes),
+        (permuted.elapsed.as_secs_f64() - original.elapsed.as_secs_f64()) * 100.0
+            / original.elapsed.as_secs_f64().max(f64::EPSILON),
+        hex(&original_root)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    // The same seed must give the same order of the rounds after the snapshot,
+    // a permutation other than the original order. Those rounds must write
+    // distinct keys and end with the same root applied in either order.
+    #[test]
+    fn reordered_rounds() -> Result<(), String> {
+        let seeds = SeedTree::new(7, false).child("check-reorder");
+        for n in 2..8 {
+            let order = permutation(&seeds, n);
+            let mut sorted = order.clone();
+            sorted.sort_unstable();
+            if sorted != (0..n).collect::<Vec<_>>() || order == sorted {
+                return Err(format!("{:?} is no reordering of {} rounds", order, n));
+            }
+            if permutation(&seeds, n) != order {
+                return Err(format!(
+                    "the order of {} rounds changed with the same seed",
+                    n
+                ));
+            }
+        }
+
+        let config = Config {
+            reorder_rounds: 6,
+            reorder_batch_size: 12,
+            ..Default::default()
+        };
+        let rounds = workload(&config, &seeds);
+        let (before, after) = rounds.split_at(snapshot_round(&config));
+        let keys: BTreeSet<H256> = after.iter().flatten().map(|(key, _)| *key).collect();
+        if keys.len() != after.iter().map(Vec::len).sum::<usize>() {
+            return Err("a key is written by two rounds after the snapshot".to_string());
+        }
+        let order = permutation(&seeds, after.len());
+        let mut roots = vec![];
+        for rounds in [
+            before.iter().chain(after).collect::<Vec<_>>(),
+            before
+                .iter()
+                .chain(order.iter().map(|i| &after[*i]))
+                .collect(),
+        ] {
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let mut root = H256::zero();
+            for pairs in rounds {
+                let tx = store.begin_transaction();
+                let mut smt = TrieSmt::new(root, TrieStore::new(&tx));
+                smt.update_all(pairs.clone())
+                    .map_err(|err| err.to_string())?;
+                root = *smt.root();
+                tx.commit().map_err(|err| err.to_string())?;
+            }
+            roots.push(root);
+        }
+        if roots[0] != roots[1] {
+            return Err(format!("the orders end with roots {:?}", roots));
+        }
+        Ok(())
+    }
+}
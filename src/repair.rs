@@ -0,0 +1,331 @@
+use crate::{
+    config::{Config, StoreKind},
+    dot::hex,
+    meta,
+    old::CountingStore,
+    trie::{validate_trie, TrieStore},
+    CountingSmt, TrieSmt,
+};
+use gw_db::{
+    iter::{Direction, IteratorMode},
+    schema::Col,
+};
+use gw_store::{traits::KVStore, Store as GwStore};
+use sparse_merkle_tree::H256;
+use std::time::Instant;
+
+// Keys and values read from a column
+type Entries = Vec<(Box<[u8]>, Box<[u8]>)>;
+
+// Up to chunk entries of col, from the first key after `after` or from the
+// start, read in a transaction of their own
+fn next_chunk(store: &GwStore, col: Col, after: Option<&[u8]>, chunk: usize) -> Entries {
+    let tx = store.begin_transaction();
+    let mode = match after {
+        Some(key) => IteratorMode::From(key, Direction::Forward),
+        None => IteratorMode::Start,
+    };
+    tx.get_iter(col, mode)
+        .filter(|(key, _)| Some(key.as_ref()) != after)
+        .take(chunk)
+        .collect()
+}
+
+// Every leaf must be a 32 byte key holding a non-zero 32 byte value, the
+// tree never stores zero. Returns the leaf count.
+pub(crate) fn validate_leaves(config: &Config, store: &GwStore) -> Result<usize, String> {
+    let tx = store.begin_transaction();
+    let mut leaves = 0;
+    for (key, value) in tx.get_iter(config.leaf_col, IteratorMode::Start) {
+        if key.len() != 32 || value.len() != 32 {
+            return Err(format!(
+                "leaf {} has a {} byte key and a {} byte value",
+                leaves,
+                key.len(),
+                value.len()
+            ));
+        }
+        if value.iter().all(|b| *b == 0) {
+            return Err(format!("leaf {} holds zero", leaves));
+        }
+        leaves += 1;
+    }
+    Ok(leaves)
+}
+
+// Keys of the branch blobs of --store trie that fail validate_trie, with
+// the reason. The counting store keeps single branches, there is nothing
+// to check.
+pub(crate) fn corrupted_tries(config: &Config, store: &GwStore) -> Vec<(Box<[u8]>, String)> {
+    if config.store != StoreKind::Trie {
+        return vec![];
+    }
+    let tx = store.begin_transaction();
+    tx.get_iter(config.branch_col, IteratorMode::Start)
+        .filter_map(|(key, blob)| {
+            validate_trie(&blob, config.trie_layout)
+                .err()
+                .map(|err| (key, err.to_string()))
+        })
+        .collect()
+}
+
+// Deletes every branch, a chunk per transaction. Returns the count.
+pub(crate) fn drop_branches(config: &Config, store: &GwStore) -> usize {
+    let mut dropped = 0;
+    let mut last: Option<Box<[u8]>> = None;
+    loop {
+        let chunk = next_chunk(
+            store,
+            config.branch_col,
+            last.as_deref(),
+            config.rebuild_chunk,
+        );
+        if chunk.is_empty() {
+            return dropped;
+        }
+        let tx = store.begin_transaction();
+        for (key, _) in &chunk {
+            tx.delete(config.branch_col, key).unwrap();
+        }
+        tx.commit().unwrap();
+        dropped += chunk.len();
+        last = chunk.into_iter().last().map(|(key, _)| key);
+    }
+}
+
+// Inserts a chunk of leaves into the tree of root through the store of
+// --store, committing them. Returns the new root and the bytes written.
+fn insert_chunk(
+    config: &Config,
+    store: &GwStore,
+    root: H256,
+    pairs: Vec<(H256, H256)>,
+) -> (H256, usize) {
+    let tx = store.begin_transaction();
+    let (root, written) = match config.store {
+        StoreKind::Trie => {
+            let trie = TrieStore::with_layout(&tx, config.trie_layout)
+                .with_columns(config.branch_col, config.leaf_col)
+                .molecule_wrap(config.trie_molecule_wrap);
+            let mut smt = TrieSmt::new(root, trie);
+            let root = *smt.update_all(pairs).unwrap();
+            (root, smt.store().written_bytes())
+        }
+        StoreKind::Counting => {
+            let counting = CountingStore::new(&tx).with_columns(config.branch_col, config.leaf_col);
+            let mut smt = CountingSmt::new(root, counting);
+            let root = *smt.update_all(pairs).unwrap();
+            (root, smt.store().written_bytes())
+        }
+    };
+    tx.commit().unwrap();
+    (root, written)
+}
+
+// Inserts every leaf again into an empty tree, a chunk at a time, calling
+// progress with the leaves inserted after every chunk. Returns the root and
+// the bytes written.
+pub(crate) fn rebuild(
+    config: &Config,
+    store: &GwStore,
+    mut progress: impl FnMut(usize),
+) -> (H256, usize) {
+    let mut root = H256::zero();
+    let mut inserted = 0;
+    let mut written_bytes = 0;
+    let mut last: Option<Box<[u8]>> = None;
+    loop {
+        let chunk = next_chunk(
+            store,
+            config.leaf_col,
+            last.as_deref(),
+            config.rebuild_chunk,
+        );
+        if chunk.is_empty() {
+            return (root, written_bytes);
+        }
+        let pairs: Vec<(H256, H256)> = chunk
+            .iter()
+            .map(|(key, value)| {
+                let mut k = [0u8; 32];
+                let mut v = [0u8; 32];
+                k.copy_from_slice(key);
+                v.copy_from_slice(value);
+                (H256::from(k), H256::from(v))
+            })
+            .collect();
+        inserted += pairs.len();
+        let (new_root, written) = insert_chunk(config, store, root, pairs);
+        root = new_root;
+        written_bytes += written;
+        last = chunk.into_iter().last().map(|(key, _)| key);
+        progress(inserted);
+    }
+}
+
+// Rebuilds every branch of the database from its leaves: checks the leaf
+// column, reports the corrupted trie blobs, drops the branch column, inserts the leaves again a chunk at a
+// time through the store of --store, and checks the rebuilt root against
+// --expect-root or the recorded root. Exits with an error when the leaves
+// are damaged or the root differs.
+pub fn rebuild_branches(config: &Config, store: &GwStore) {
+    let leaves = match validate_leaves(config, store) {
+        Ok(leaves) => leaves,
+        Err(err) => {
+            eprintln!(
+                "Refusing to rebuild, column {} is damaged as well: {}",
+                config.leaf_col, err
+            );
+            std::process::exit(1);
+        }
+    };
+    let expected = config
+        .expect_root
+        .or_else(|| meta::recorded_root(&config.db_path));
+    match expected {
+        Some(root) => println!(
+            "Rebuilding the branches of {} leaves, expecting root {}",
+            leaves,
+            hex(&root)
+        ),
+        None => println!(
+            "Rebuilding the branches of {} leaves, no --expect-root or recorded root to check against",
+            leaves
+        ),
+    }
+
+    let corrupted = corrupted_tries(config, store);
+    if !corrupted.is_empty() {
+        println!(
+            "  {} trie blobs of column {} are corrupted:",
+            corrupted.len(),
+            config.branch_col
+        );
+        for (key, err) in &corrupted {
+            let key: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+            println!("    {}: {}", key, err);
+        }
+    }
+
+    let start = Instant::now();
+    let dropped = drop_branches(config, store);
+    println!(
+        "  Dropped {} entries of column {} in {:?}",
+        dropped,
+        config.branch_col,
+        start.elapsed()
+    );
+
+    let start = Instant::now();
+    let (root, written_bytes) = rebuild(config, store, |inserted| {
+        let secs = start.elapsed().as_secs_f64().max(f64::EPSILON);
+        println!(
+            "  Rebuilt {}/{} leaves, {:.0} leaves/s",
+            inserted,
+            leaves,
+            inserted as f64 / secs
+        );
+    });
+    let elapsed = start.elapsed();
+    let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+    println!(
+        "Rebuilt {} leaves in {:?}: {:.0} leaves/s, {:.1} MiB written, {:.1} MiB/s, root {}",
+        leaves,
+        elapsed,
+        leaves as f64 / secs,
+        written_bytes as f64 / (1 << 20) as f64,
+        written_bytes as f64 / (1 << 20) as f64 / secs,
+        hex(&root)
+    );
+    if let Some(expected) = expected {
+        if root != expected {
+            eprintln!(
+                "Rebuilt root {} differs from the expected {}, the leaves do not hold the tree that was recorded",
+                hex(&root),
+                hex(&expected)
+            );
+            std::process::exit(1);
+        }
+        println!("  The rebuilt root matches");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        phase::{Phase, PhaseKind},
+        runner::{tests::serial, Runner},
+        seed::SeedTree,
+    };
+
+    // A branch blob overwritten with garbage must be reported as corrupted,
+    // rebuilding the branches from the leaves must then give back the root of the run, and a
+    // damaged leaf must fail the leaf validation
+    #[test]
+    fn branch_rebuild() -> Result<(), String> {
+        let _serial = serial();
+        let config = Config {
+            report_every: 0,
+            rebuild_chunk: 16,
+            ..Default::default()
+        };
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let mut runner = Runner::new(&config, &store, SeedTree::new(0, false));
+        for phase in [
+            Phase::new(PhaseKind::Load, 1, 40),
+            Phase::new(PhaseKind::Update, 2, 20),
+            Phase::new(PhaseKind::Delete, 1, 10),
+        ] {
+            runner.run_phase(&phase);
+        }
+        let expected = runner.root();
+
+        let tx = store.begin_transaction();
+        let (blob_key, _) = tx
+            .get_iter(config.branch_col, IteratorMode::Start)
+            .next()
+            .ok_or("no branch was written")?;
+        tx.insert_raw(config.branch_col, &blob_key, &[0xab; 7])
+            .map_err(|err| err.to_string())?;
+        tx.commit().map_err(|err| err.to_string())?;
+        let corrupted = corrupted_tries(&config, &store);
+        if corrupted.len() != 1 || corrupted[0].0 != blob_key {
+            return Err(format!(
+                "{} corrupted tries reported, expected only the overwritten one",
+                corrupted.len()
+            ));
+        }
+
+        let leaves = validate_leaves(&config, &store)?;
+        drop_branches(&config, &store);
+        let mut progress = vec![];
+        let (root, _) = rebuild(&config, &store, |inserted| progress.push(inserted));
+        if root != expected {
+            return Err(format!(
+                "rebuilt root {:?}, the run ended with {:?}",
+                root, expected
+            ));
+        }
+        if progress.last() != Some(&leaves) || progress.len() != leaves.div_ceil(16) {
+            return Err(format!(
+                "{} leaves rebuilt in chunks reaching {:?}",
+                leaves, progress
+            ));
+        }
+
+        let tx = store.begin_transaction();
+        let (leaf_key, _) = tx
+            .get_iter(config.leaf_col, IteratorMode::Start)
+            .next()
+            .ok_or("no leaf was written")?;
+        tx.insert_raw(config.leaf_col, &leaf_key, &[1; 31])
+            .map_err(|err| err.to_string())?;
+        tx.commit().map_err(|err| err.to_string())?;
+        if validate_leaves(&config, &store).is_ok() {
+            return Err("a 31 byte leaf passed the validation".to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    config::{Config, StoreKind},
+    history,
+    old::CountingStore,
+    store_config,
+    trie::TrieStore,
+    CountingSmt, TrieSmt,
+};
+use gw_db::{schema::Col, RocksDB};
+use gw_store::Store as GwStore;
+use sparse_merkle_tree::H256;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Batches waiting for the replica before the runner blocks on it
+const QUEUE_CAPACITY: usize = 16;
+
+// Round of the primary and the pairs of a committed transaction
+type Batch = (u64, Vec<(H256, H256)>);
+
+// Parses --replicate-to, "<store>:<path>"
+pub fn parse_target(s: &str) -> Result<(StoreKind, PathBuf), String> {
+    let (kind, path) = s
+        .split_once(':')
+        .ok_or_else(|| format!("Replica {} is not <store>:<path>", s))?;
+    Ok((kind.parse()?, PathBuf::from(path)))
+}
+
+// A second database receiving the pairs of every committed transaction of
+// the primary on a background thread, each batch committed in its own
+// transaction. With --leaf-history the replica keeps the history of the
+// primary's rounds as well, trimmed the same way. The queue is bounded, so a replica that falls behind slows
+// the runner down instead of buffering without limit.
+pub struct Replica {
+    kind: StoreKind,
+    path: PathBuf,
+    history_col: Option<Col>,
+    sender: Option<SyncSender<Batch>>,
+    // Apply time of every batch, in order
+    applied: Receiver<Duration>,
+    handle: Option<JoinHandle<(H256, GwStore)>>,
+    queued: Arc<AtomicUsize>,
+    batches: usize,
+    pairs: usize,
+    apply_elapsed: Duration,
+    wait_elapsed: Duration,
+    max_queued: usize,
+}
+
+impl Replica {
+    pub fn start(config: &Config, kind: StoreKind, path: PathBuf) -> Self {
+        if path.exists() {
+            panic!(
+                "{} already exists, --replicate-to needs a fresh database",
+                path.display()
+            );
+        }
+        let store_config = store_config(config, path.clone());
+        let columns = config.columns;
+        let layout = config.trie_layout;
+        let wrap = config.trie_molecule_wrap;
+        let history_col = Some(config.history_col).filter(|_| config.leaf_history);
+        let (sender, receiver) = mpsc::sync_channel::<Batch>(QUEUE_CAPACITY);
+        let (applied_sender, applied) = mpsc::channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let thread_queued = queued.clone();
+        let handle = thread::spawn(move || {
+            let store = GwStore::new(RocksDB::open(&store_config, columns));
+            let mut root = H256::zero();
+            for (round, pairs) in receiver {
+                let start = Instant::now();
+                let tx = store.begin_transaction();
+                root = match kind {
+                    StoreKind::Trie => {
+                        let trie = TrieStore::with_layout(&tx, layout).molecule_wrap(wrap);
+                        let trie = match history_col {
+                            Some(col) => trie.with_history(col, round),
+                            None => trie,
+                        };
+                        let mut smt = TrieSmt::new(root, trie);
+                        *smt.update_all(pairs).unwrap()
+                    }
+                    StoreKind::Counting => {
+                        let counting = CountingStore::new(&tx);
+                        let counting = match history_col {
+                            Some(col) => counting.with_history(col, round),
+                            None => counting,
+                        };
+                        let mut smt = CountingSmt::new(root, counting);
+                        *smt.update_all(pairs).unwrap()
+                    }
+                };
+
+                tx.commit().unwrap();
+                thread_queued.fetch_sub(1, Ordering::SeqCst);
+                // The runner may be gone already when shutting down
+                let _ = applied_sender.send(start.elapsed());
+            }
+            (root, store)
+        });
+        Self {
+            kind,
+            path,
+            history_col,
+            sender: Some(sender),
+            applied,
+            handle: Some(handle),
+            queued,
+            batches: 0,
+            pairs: 0,
+            apply_elapsed: Duration::default(),
+            wait_elapsed: Duration::default(),
+            max_queued: 0,
+        }
+    }
+
+    // Queues the pairs of a transaction the primary committed in round.
+    // Returns the time spent blocked on a full queue and the number of
+    // batches queued after it.
+    pub fn send(&mut self, round: u64, pairs: Vec<(H256, H256)>) -> (Duration, usize) {
+        let count = pairs.len();
+        let queued = self.queued.fetch_add(1, Ordering::SeqCst) + 1;
+        let start = Instant::now();
+        let sender = self.sender.as_ref().unwrap();
+        if sender.send((round, pairs)).is_err() {
+            // The thread only stops early by panicking, join shows why
+            self.sender = None;
+            let _ = self.handle.take().unwrap().join();
+            panic!("Replica thread for {} stopped", self.path.display());
+        }
+        let wait = start.elapsed();
+        self.batches += 1;
+        self.pairs += count;
+        self.wait_elapsed += wait;
+        self.max_queued = self.max_queued.max(queued);
+        (wait, queued)
+    }
+
+    // Number of batches applied since the last call and their apply time
+    pub fn drain(&mut self) -> (usize, Duration) {
+        let mut count = 0;
+        let mut elapsed = Duration::default();
+        for apply in self.applied.try_iter() {
+            count += 1;
+            elapsed += apply;
+        }
+        self.apply_elapsed += elapsed;
+        (count, elapsed)
+    }
+
+    // Waits for the replica to apply everything queued, prints its summary
+    // and panics when it did not end at the primary's root. The leaf history
+    // is trimmed to the horizon the primary's was.
+    pub fn finish(mut self, primary_root: H256, history_horizon: Option<u64>) {
+        let start = Instant::now();
+        let replica = self.shutdown();
+        let catch_up = start.elapsed();
+        self.drain();
+        println!(
+            "Replica {:?} at {}: {} batches, {} pairs, apply {:?} ({:.0} pairs/s), blocked {:?} on the queue, max queue {}, caught up in {:?}",
+            self.kind,
+            self.path.display(),
+            self.batches,
+            self.pairs,
+            self.apply_elapsed,
+            self.pairs as f64 / self.apply_elapsed.as_secs_f64().max(f64::EPSILON),
+            self.wait_elapsed,
+            self.max_queued,
+            catch_up
+        );
+        let root = replica.as_ref().map(|(root, _)| *root);
+        if root != Some(primary_root) {
+            panic!(
+                "Replica root {:?} differs from primary root {:?}",
+                root, primary_root
+            );
+        }
+        if let (Some((_, store)), Some(col), Some(horizon)) =
+            (&replica, self.history_col, history_horizon)
+        {
+            let tx = store.begin_transaction();
+            history::trim(&tx, col, horizon)
+                .and_then(|_| tx.commit().map_err(|err| err.to_string()))
+                .unwrap_or_else(|err| panic!("Cannot trim the replica's leaf history: {}", err));
+        }
+    }
+
+    // Closes the queue and joins the thread once it applied what is left
+    fn shutdown(&mut self) -> Option<(H256, GwStore)> {
+        self.sender = None;
+        self.handle.take().and_then(|handle| handle.join().ok())
+    }
+}
+
+impl Drop for Replica {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{
+    build_info,
+    config::Config,
+    fingerprint::fingerprint,
+    runner::{PhaseReport, RoundReport},
+};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// A results file is MAGIC and a format version byte, then in version 1:
+// the fingerprint, the build label and the Debug form of the configuration
+// as strings, the names of the round fields as a count and strings, and
+// the phases as a count and, for each, its label, its round count and
+// every round as one varint per field. Strings are a varint length and
+// UTF-8 bytes, varints unsigned LEB128. Naming the fields in the file keeps
+// files readable when fields are added, a new version is only needed when
+// the layout itself changes.
+const MAGIC: &[u8; 7] = b"SMTRES\0";
+const VERSION: u8 = 1;
+// Written next to meta.json unless --out gives a path
+const RESULTS_FILE: &str = "results.bin";
+
+// Every field of a round as an integer: durations in nanoseconds, flags as
+// 0 or 1, options as the value plus one with 0 for none
+pub(crate) fn round_fields(round: &RoundReport) -> Vec<(&'static str, u64)> {
+    let nanos = |d: std::time::Duration| d.as_nanos() as u64;
+    let opt_nanos = |d: Option<std::time::Duration>| d.map_or(0, |d| nanos(d) + 1);
+    vec![
+        ("ops", round.ops as u64),
+        ("reads", round.reads as u64),
+        ("writes", round.writes as u64),
+        ("written_bytes", round.written_bytes as u64),
+        ("elapsed_ns", nanos(round.elapsed)),
+        ("verify_ns", nanos(round.verify_elapsed)),
+        ("hash_ns", nanos(round.hash_elapsed)),
+        ("gen_ns", nanos(round.gen_elapsed)),
+        ("serial_gen_ns", nanos(round.serial_gen_elapsed)),
+        ("update_ns+1", opt_nanos(round.update_elapsed)),
+        ("root_ns+1", opt_nanos(round.root_elapsed)),
+        ("history_reads", round.history_reads as u64),
+        ("history_writes", round.history_writes as u64),
+        ("history_bytes", round.history_bytes as u64),
+        ("faults", round.faults as u64),
+        ("rolled_back", round.rolled_back as u64),
+        ("failed_ops", round.failed_ops as u64),
+        ("leaves", round.leaves as u64),
+        ("leaves_estimated", round.leaves_estimated as u64),
+        ("staged_bytes", round.staged_bytes as u64),
+        ("commits", round.commits as u64),
+        (
+            "replica_queue+1",
+            round.replica_queue.map_or(0, |q| q as u64 + 1),
+        ),
+        ("replica_wait_ns", nanos(round.replica_wait)),
+        ("replica_applied", round.replica_applied as u64),
+        ("replica_apply_ns", nanos(round.replica_apply)),
+        ("storm_writes", round.storm_writes as u64),
+        ("storm_bytes", round.storm_bytes as u64),
+        ("strict_violations", round.strict_violations as u64),
+        ("column_violations", round.column_violations as u64),
+        ("leaf_collisions", round.leaf_collisions as u64),
+        ("conversions", round.conversions as u64),
+        ("converted_bytes", round.converted_bytes as u64),
+        ("serde_ns", nanos(round.serde_elapsed)),
+        ("smt_rebuilds", round.smt_rebuilds as u64),
+        ("rebuild_ns", nanos(round.rebuild_elapsed)),
+        ("proof_reads", round.proof_reads as u64),
+        ("reproof_reads", round.reproof_reads as u64),
+        ("stale_proofs", round.stale_proofs as u64),
+        ("physical_ops", round.physical_ops as u64),
+        ("throttled_ns", nanos(round.throttled)),
+        (
+            "compaction_debt+1",
+            round.compaction_debt.map_or(0, |debt| debt + 1),
+        ),
+        ("tombstones+1", round.tombstones.map_or(0, |n| n + 1)),
+        ("init", round.init as u64),
+        ("violations", round.violations.len() as u64),
+    ]
+}
+
+pub(crate) fn write_varint(out: &mut impl Write, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return out.write_all(&[byte]);
+        }
+        out.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn write_str(out: &mut impl Write, s: &str) -> io::Result<()> {
+    write_varint(out, s.len() as u64)?;
+    out.write_all(s.as_bytes())
+}
+
+fn results_path(config: &Config) -> PathBuf {
+    config
+        .results_out
+        .clone()
+        .unwrap_or_else(|| config.db_path.join(RESULTS_FILE))
+}
+
+// Writes the configuration and every round of reports in the binary
+// format, for --output binary
+pub fn write(config: &Config, reports: &[PhaseReport]) -> io::Result<PathBuf> {
+    let path = results_path(config);
+    let mut out = BufWriter::new(File::create(&path)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    write_str(&mut out, &fingerprint(config))?;
+    write_str(&mut out, &build_info::label())?;
+    write_str(&mut out, &format!("{:?}", config))?;
+    let names = round_fields(&RoundReport::default());
+    write_varint(&mut out, names.len() as u64)?;
+    for (name, _) in &names {
+        write_str(&mut out, name)?;
+    }
+    write_varint(&mut out, reports.len() as u64)?;
+    for report in reports {
+        write_str(&mut out, &report.phase.to_string())?;
+        write_varint(&mut out, report.rounds.len() as u64)?;
+        for round in &report.rounds {
+            for (_, value) in round_fields(round) {
+                write_varint(&mut out, value)?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(path)
+}
+
+// Reads a results file or a write dump from the front, failing on
+// truncation
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    pub(crate) fn at_end(&self) -> bool {
+        self.offset == self.data.len()
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| format!("truncated at byte {}", self.offset))?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.bytes(1)?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(format!("varint too long at byte {}", self.offset))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.varint()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|err| err.to_string())
+    }
+}
+
+// Renders a field value back in the unit its name gives
+fn format_field(name: &str, value: u64) -> String {
+    let (name, optional) = match name.strip_suffix("+1") {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    let value = match (optional, value) {
+        (true, 0) => return "none".to_string(),
+        (true, value) => value - 1,
+        (false, value) => value,
+    };
+    if name.ends_with("_ns") {
+        format!("{:?}", std::time::Duration::from_nanos(value))
+    } else {
+        value.to_string()
+    }
+}
+
+fn render_v1(reader: &mut Reader) -> Result<Vec<String>, String> {
+    let mut lines = vec![
+        format!("Fingerprint: {}", reader.string()?),
+        format!("Build: {}", reader.string()?),
+        format!("Config: {}", reader.string()?),
+    ];
+    let names = (0..reader.varint()?)
+        .map(|_| reader.string())
+        .collect::<Result<Vec<_>, _>>()?;
+    for _ in 0..reader.varint()? {
+        let phase = reader.string()?;
+        let rounds = reader.varint()?;
+        lines.push(format!("Phase {}, {} rounds:", phase, rounds));
+        for round in 1..=rounds {
+            let fields = names
+                .iter()
+                .map(|name| Ok((name, reader.varint()?)))
+                .collect::<Result<Vec<_>, String>>()?;
+            // Zero counters say nothing in a listing of every field
+            let shown: Vec<String> = fields
+                .iter()
+                .filter(|(_, value)| *value != 0)
+                .map(|(name, value)| {
+                    format!(
+                        "{}={}",
+                        name.trim_end_matches("+1").trim_end_matches("_ns"),
+                        format_field(name, *value)
+                    )
+                })
+                .collect();
+            lines.push(format!("  Round {}: {}", round, shown.join(" ")));
+        }
+    }
+    if !reader.at_end() {
+        return Err(format!(
+            "{} bytes after the last phase",
+            reader.data.len() - reader.offset
+        ));
+    }
+    Ok(lines)
+}
+
+// Decodes a results file of any version into the lines show-results prints
+pub fn render(path: &Path) -> Result<Vec<String>, String> {
+    let data = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut reader = Reader::new(&data);
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        return Err(format!("{} is not a results file", path.display()));
+    }
+    match reader.bytes(1)?[0] {
+        1 => render_v1(&mut reader),
+        version => Err(format!(
+            "{} has format version {}, this build reads up to {}",
+            path.display(),
+            version,
+            VERSION
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        phase::{Phase, PhaseKind},
+        runner::{tests::serial, Runner},
+        seed::SeedTree,
+    };
+    use gw_store::Store as GwStore;
+
+    // A results file of a short run must read back whole, and a truncated one
+    // or one of a later format version must be refused
+    #[test]
+    fn results_file() -> Result<(), String> {
+        let _serial = serial();
+        let path = std::env::temp_dir().join(format!("smt-bench-check-{}.bin", std::process::id()));
+        let config = Config {
+            report_every: 0,
+            results_out: Some(path.clone()),
+            ..Default::default()
+        };
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let mut runner = Runner::new(&config, &store, SeedTree::new(0, false));
+        let reports = vec![
+            runner.run_phase(&Phase::new(PhaseKind::Load, 1, 20)),
+            runner.run_phase(&Phase::new(PhaseKind::Update, 2, 10)),
+        ];
+        write(&config, &reports).map_err(|err| err.to_string())?;
+        let data = std::fs::read(&path).map_err(|err| err.to_string())?;
+        let outcome = (|| {
+            let lines = render(&path).map_err(|err| format!("written file: {}", err))?;
+            let rounds = lines
+                .iter()
+                .filter(|line| line.starts_with("  Round"))
+                .count();
+            if rounds != 3 {
+                return Err(format!("{} of 3 rounds read back", rounds));
+            }
+            std::fs::write(&path, &data[..data.len() - 1]).map_err(|err| err.to_string())?;
+            if render(&path).is_ok() {
+                return Err("a truncated file was read".to_string());
+            }
+            let mut later = data.clone();
+            later[7] += 1;
+            std::fs::write(&path, &later).map_err(|err| err.to_string())?;
+            if render(&path).is_ok() {
+                return Err("a file of a later version was read".to_string());
+            }
+            Ok(())
+        })();
+        let _ = std::fs::remove_file(&path);
+        outcome
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{churn, config::Config, dot::hex, path::parse_h256, phase::Phase};
+use gw_store::Store as GwStore;
+use sparse_merkle_tree::H256;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Written inside the database directory next to meta.json, replaced after
+// every round
+const CHECKPOINT_FILE: &str = "checkpoint.json";
+
+// Where a run stood after its last round: the phase it was in, counted as
+// the runner counts them to derive the phase seeds, the rounds of it done,
+// the rounds of the whole run, the root and the next --sorted-keys counter.
+// Every round draws its keys from a seed derived from the phase and round
+// numbers, so these are all a run needs to go on from the tree on disk as
+// long as the phases left only write fresh keys, see sampling_phase.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub fingerprint: String,
+    pub phases_run: usize,
+    pub round: usize,
+    pub rounds_run: u64,
+    pub root: H256,
+    pub next_sorted: u64,
+}
+
+impl Checkpoint {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"fingerprint\": \"{}\", \"phases_run\": {}, \"round\": {}, \"rounds_run\": {}, \"root\": \"{}\", \"next_sorted\": {}}}",
+            self.fingerprint,
+            self.phases_run,
+            self.round,
+            self.rounds_run,
+            hex(&self.root),
+            self.next_sorted
+        )
+    }
+
+    // Reads back a line written by to_json
+    fn parse(line: &str) -> Result<Self, String> {
+        let fields: BTreeMap<&str, &str> = line
+            .trim()
+            .trim_start_matches('{')
+            .trim_end_matches('}')
+            .split(", ")
+            .filter_map(|field| {
+                let (name, value) = field.split_once(": ")?;
+                Some((name.trim_matches('"'), value.trim_matches('"')))
+            })
+            .collect();
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .copied()
+                .ok_or_else(|| format!("no {} in {}", name, line))
+        };
+        let number = |name: &str| {
+            field(name)?
+                .parse::<u64>()
+                .map_err(|err| format!("{}: {}", name, err))
+        };
+        Ok(Self {
+            fingerprint: field("fingerprint")?.to_string(),
+            phases_run: number("phases_run")? as usize,
+            round: number("round")? as usize,
+            rounds_run: number("rounds_run")?,
+            root: parse_h256(field("root")?)?,
+            next_sorted: number("next_sorted")?,
+        })
+    }
+}
+
+// Replaces the checkpoint of db_path through a rename, so a run killed
+// while writing it leaves the previous one
+pub fn write(db_path: &Path, checkpoint: &Checkpoint) -> io::Result<()> {
+    let path = db_path.join(CHECKPOINT_FILE);
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, format!("{}\n", checkpoint.to_json()))?;
+    fs::rename(tmp, path)
+}
+
+pub fn read(db_path: &Path) -> Result<Option<Checkpoint>, String> {
+    let path = db_path.join(CHECKPOINT_FILE);
+    match fs::read_to_string(&path) {
+        Ok(content) => Checkpoint::parse(&content)
+            .map(Some)
+            .map_err(|err| format!("{}: {}", path.display(), err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("{}: {}", path.display(), err)),
+    }
+}
+
+// The index in phases of the phase to go on with and the rounds of it
+// already done, the next phase from its start when the checkpoint's was
+// complete
+pub(crate) fn position(checkpoint: &Checkpoint, phases: &[Phase]) -> (usize, usize) {
+    let phase = checkpoint.phases_run.saturating_sub(1);
+    match phases.get(phase) {
+        Some(current) if checkpoint.round < current.rounds => (phase, checkpoint.round),
+        _ => (checkpoint.phases_run, 0),
+    }
+}
+
+// The first of the phases left to run that draws keys from the run's pool.
+// A resumed run rebuilds the pool from the leaf column, in key order rather
+// than the order the keys were written in, so such a phase would pick other
+// keys than the run in one go did.
+pub(crate) fn sampling_phase(phases: &[Phase], phase: usize) -> Option<&Phase> {
+    phases
+        .get(phase..)
+        .unwrap_or_default()
+        .iter()
+        .find(|phase| phase.kind.samples_keys())
+}
+
+// Exits unless the bench may run on the database: with --resume it returns
+// the checkpoint of the run that wrote it, which must have run with the
+// same fingerprint, otherwise the tree's columns must be empty
+pub fn start(config: &Config, store: &GwStore, fingerprint: &str) -> Option<Checkpoint> {
+    if !config.resume {
+        if !churn::tree_empty(config, store) {
+            eprintln!(
+                "{} holds a tree, pass --resume to go on with the run that wrote it or use another --db-path",
+                config.db_path.display()
+            );
+            std::process::exit(1);
+        }
+        return None;
+    }
+    let checkpoint = match read(&config.db_path) {
+        Ok(Some(checkpoint)) => checkpoint,
+        Ok(None) => {
+            eprintln!(
+                "--resume: {} has no checkpoint to resume from",
+                config.db_path.display()
+            );
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("--resume: cannot read the checkpoint, {}", err);
+            std::process::exit(1);
+        }
+    };
+    if checkpoint.fingerprint != fingerprint {
+        eprintln!(
+            "--resume: {} was written by a run with fingerprint {}, this one is {}, pass the same options to the same build",
+            config.db_path.display(),
+            checkpoint.fingerprint,
+            fingerprint
+        );
+        std::process::exit(1);
+    }
+    Some(checkpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        phase::PhaseKind,
+        runner::{tests::serial, Runner},
+        seed::SeedTree,
+    };
+
+    // A run of 10 rounds interrupted after 5 and resumed from its checkpoint
+    // must end with the root of the same run done in one go, its checkpoints
+    // placing the resumed run 5 rounds into the phase and after the phase
+    // once it is done.
+    #[test]
+    fn resumed_run() -> Result<(), String> {
+        let _serial = serial();
+        let dir = std::env::temp_dir().join(format!("smt-bench-resume-{}", std::process::id()));
+        let result = (|| {
+            std::fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+            let config = Config {
+                report_every: 0,
+                db_path: dir.clone(),
+                ..Default::default()
+            };
+            let phases = [Phase::new(PhaseKind::Update, 10, 20)];
+            let seeds = || SeedTree::new(0, false).child("check-resume");
+
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let mut runner = Runner::new(&config, &store, seeds());
+            runner.run_phase(&phases[0]);
+            let expected = runner.root();
+
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let mut runner = Runner::new(&config, &store, seeds());
+            runner.checkpoint("check".to_string());
+            // The first 5 rounds of the phase, on the seeds of the 10 round one
+            runner.run_phase(&Phase::new(PhaseKind::Update, 5, 20));
+            let interrupted = runner.root();
+            drop(runner);
+            let checkpoint = read(&dir)?.ok_or("no checkpoint after 5 rounds")?;
+            if checkpoint.root != interrupted || checkpoint.rounds_run != 5 {
+                return Err(format!("checkpoint after 5 rounds is {:?}", checkpoint));
+            }
+            let (phase, round) = position(&checkpoint, &phases);
+            if (phase, round) != (0, 5) {
+                return Err(format!(
+                    "resuming after 5 rounds starts at phase {} round {}",
+                    phase, round
+                ));
+            }
+
+            let mut runner = Runner::new(&config, &store, seeds());
+            runner.checkpoint("check".to_string());
+            runner.resume(&checkpoint, phase, round);
+            let report = runner.run_phase(&phases[0]);
+            if report.rounds.len() != 5 || runner.root() != expected {
+                return Err(format!(
+                    "resumed run did {} rounds and ended with root {:?}, the run in one go with {:?}",
+                    report.rounds.len(),
+                    runner.root(),
+                    expected
+                ));
+            }
+            let done = read(&dir)?.ok_or("no checkpoint after 10 rounds")?;
+            if done.rounds_run != 10 || position(&done, &phases) != (1, 0) {
+                return Err(format!("checkpoint after the phase is {:?}", done));
+            }
+
+            // Deletes draw from the key pool, whose order a resume cannot rebuild
+            let phases = [
+                Phase::new(PhaseKind::Load, 1, 20),
+                Phase::new(PhaseKind::Delete, 2, 5),
+            ];
+            let dir = dir.join("load-delete");
+            std::fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+            let config = Config {
+                report_every: 0,
+                db_path: dir.clone(),
+                ..Default::default()
+            };
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let mut runner = Runner::new(&config, &store, seeds());
+            runner.checkpoint("check".to_string());
+            runner.run_phase(&phases[0]);
+            drop(runner);
+            let checkpoint = read(&dir)?.ok_or("no checkpoint after the load")?;
+            let (phase, _) = position(&checkpoint, &phases);
+            if phase != 1 || sampling_phase(&phases, phase).is_none() {
+                return Err(format!(
+                    "resuming before the delete phase at phase {} is allowed",
+                    phase
+                ));
+            }
+            if sampling_phase(&phases[..1], 0).is_some() {
+                return Err("resuming a load is refused".to_string());
+            }
+            Ok(())
+        })();
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{
+    config::Config,
+    dot::hex,
+    random_h256,
+    seed::SeedTree,
+    store_config,
+    trie::{round_branch_key, BranchTrie, TrieStore, TRIE_SIZE},
+};
+use gw_db::RocksDB;
+use gw_store::Store as GwStore;
+use sparse_merkle_tree::{
+    merge::MergeValue,
+    traits::Store,
+    tree::{BranchKey, BranchNode},
+    H256,
+};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// Cycles per transaction, so the write batch does not hold every blob
+// written
+const RMW_CHUNK: usize = 100;
+
+// The branch a cycle writes back: the one read with its right child
+// replaced
+fn modify(branch: Option<BranchNode>, value: H256) -> BranchNode {
+    let left = branch.map_or(MergeValue::zero(), |branch| branch.left);
+    BranchNode {
+        left,
+        right: MergeValue::Value(value),
+    }
+}
+
+// Reads a branch of one trie, changes it and writes it back, cycling over
+// the branches of the trie's 8 heights, --rmw-iterations times: through
+// TrieStore, which reads and writes the whole blob every time, and on the
+// trie kept decoded in memory, which is what caching the trie would leave
+// of the cycle. Both must end with the same trie.
+pub fn run(config: &Config) {
+    let mut dir = config.db_path.as_os_str().to_owned();
+    dir.push("-trie-rmw");
+    let dir = PathBuf::from(dir);
+    if dir.exists() {
+        panic!(
+            "{} already exists, --experiment trie-rmw needs a fresh directory",
+            dir.display()
+        );
+    }
+    let mut rng = SeedTree::new(config.seed, config.verbose)
+        .child("trie-rmw")
+        .rng();
+    let path = random_h256(&mut rng);
+    let keys: Vec<BranchKey> = (248..=255u8)
+        .map(|height| BranchKey::new(height, path.parent_path(height)))
+        .collect();
+    let values: Vec<H256> = (0..config.rmw_iterations)
+        .map(|_| random_h256(&mut rng))
+        .collect();
+    let rounded_key = round_branch_key(&keys[0]);
+    println!(
+        "Trie read-modify-write: {} cycles on the top trie along path {}, {:?} layout, in {}",
+        config.rmw_iterations,
+        hex(&path),
+        config.trie_layout,
+        dir.display()
+    );
+
+    let store = GwStore::new(RocksDB::open(
+        &store_config(config, dir.clone()),
+        config.columns,
+    ));
+    let mut stored = Duration::default();
+    let (mut reads, mut writes, mut written_bytes) = (0, 0, 0);
+    for (chunk, values) in values.chunks(RMW_CHUNK).enumerate() {
+        let tx = store.begin_transaction();
+        let mut trie = TrieStore::with_layout(&tx, config.trie_layout)
+            .with_columns(config.branch_col, config.leaf_col)
+            .molecule_wrap(config.trie_molecule_wrap);
+        let start = Instant::now();
+        for (i, value) in values.iter().enumerate() {
+            let key = &keys[(chunk * RMW_CHUNK + i) % keys.len()];
+            let branch = trie.get_branch(key).unwrap();
+            trie.insert_branch(key.clone(), modify(branch, *value))
+                .unwrap();
+        }
+        stored += start.elapsed();
+        reads += trie.reads();
+        writes += trie.writes();
+        written_bytes += trie.written_bytes();
+        tx.commit().unwrap();
+    }
+
+    let mut cached = BranchTrie::empty(rounded_key, config.trie_layout);
+    let start = Instant::now();
+    for (i, value) in values.iter().enumerate() {
+        let key = &keys[i % keys.len()];
+        let branch = cached.get_branch(key).unwrap();
+        cached.insert_branch(key, &modify(branch, *value)).unwrap();
+    }
+    let in_memory = start.elapsed();
+
+    let tx = store.begin_transaction();
+    let trie = TrieStore::with_layout(&tx, config.trie_layout)
+        .with_columns(config.branch_col, config.leaf_col);
+    for key in &keys {
+        let expected = cached.get_branch(key).unwrap();
+        let actual = trie.get_branch(key).unwrap();
+        if actual != expected {
+            eprintln!(
+                "Branch {:?} is {:?} through TrieStore, {:?} in memory",
+                key, actual, expected
+            );
+            std::process::exit(1);
+        }
+    }
+    drop(tx);
+    drop(store);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let cycles = config.rmw_iterations as f64;
+    for (name, elapsed) in [("TrieStore", stored), ("in memory", in_memory)] {
+        println!(
+            "  {:<10} {:?} ({:.1?} per cycle), {:.0} cycles/s",
+            name,
+            elapsed,
+            elapsed / config.rmw_iterations as u32,
+            cycles / elapsed.as_secs_f64().max(f64::EPSILON)
+        );
+    }
+    println!(
+        "  TrieStore per cycle: {:.1} blob reads of {} bytes, {:.1} writes of {:.0} bytes",
+        reads as f64 / cycles,
+        TRIE_SIZE,
+        writes as f64 / cycles,
+        written_bytes as f64 / writes.max(1) as f64
+    );
+    println!(
+        "  In memory is {:.1}x TrieStore",
+        stored.as_secs_f64() / in_memory.as_secs_f64().max(f64::EPSILON)
+    );
+}
@@ -0,0 +1,151 @@
+use crate::dot::hex;
+use gw_common::blake2b::new_blake2b;
+use sparse_merkle_tree::H256;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// Prefix of the options files RocksDB writes into the database directory
+// at every open, holding the options it actually applied
+const PERSISTED_PREFIX: &str = "OPTIONS-";
+
+// Hash of the options file's bytes, identifying the options a run used
+pub fn hash(path: &Path) -> Result<String, String> {
+    let data = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut hasher = new_blake2b();
+    hasher.update(&data);
+    let mut hash = [0u8; 32];
+    hasher.finalize(&mut hash);
+    Ok(hex(&H256::from(hash)))
+}
+
+// Options of an options file by section and name. The Version section
+// only tells which RocksDB wrote the file and is left out.
+pub(crate) fn parse(text: &str) -> BTreeMap<(String, String), String> {
+    let mut options = BTreeMap::new();
+    let mut section = String::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            continue;
+        }
+        if section == "Version" {
+            continue;
+        }
+        if let Some((name, value)) = line.split_once('=') {
+            options.insert(
+                (section.clone(), name.trim().to_string()),
+                value.trim().to_string(),
+            );
+        }
+    }
+    options
+}
+
+// The options file RocksDB wrote last in db_path
+fn persisted(db_path: &Path) -> Result<String, String> {
+    let entries = fs::read_dir(db_path).map_err(|err| format!("{}: {}", db_path.display(), err))?;
+    let latest = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let number: u64 = name.strip_prefix(PERSISTED_PREFIX)?.parse().ok()?;
+            Some((number, entry.path()))
+        })
+        .max()
+        .ok_or_else(|| format!("{} holds no {}* file", db_path.display(), PERSISTED_PREFIX))?;
+    fs::read_to_string(&latest.1).map_err(|err| format!("{}: {}", latest.1.display(), err))
+}
+
+// Checks that every option requested reads back with the same value from
+// persisted, the options the database reports
+pub(crate) fn compare(requested: &str, persisted: &str) -> Result<(), String> {
+    let applied = parse(persisted);
+    let mismatches: Vec<String> = parse(requested)
+        .into_iter()
+        .filter(|(option, value)| applied.get(option) != Some(value))
+        .map(|((section, name), value)| {
+            format!(
+                "[{}] {} requested {}, applied {}",
+                section,
+                name,
+                value,
+                applied
+                    .get(&(section.clone(), name.clone()))
+                    .map_or("nothing", String::as_str)
+            )
+        })
+        .collect();
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(mismatches.join("; "))
+    }
+}
+
+// Checks that the database opened at db_path applied the options file at
+// path, from the options file RocksDB wrote when opening it
+pub fn verify(path: &Path, db_path: &Path) -> Result<(), String> {
+    let requested =
+        fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    compare(&requested, &persisted(db_path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An options file must count as applied when the latest options RocksDB
+    // wrote hold every option it sets, whatever else they hold, and not when
+    // one differs or is missing. Its hash follows its content.
+    #[test]
+    fn options_file() -> Result<(), String> {
+        let requested = "# Tuned\n[Version]\n  rocksdb_version=6.20.3\n[DBOptions]\n  max_open_files=512\n[CFOptions \"0\"]\n  write_buffer_size=67108864\n";
+        let applied = "[Version]\n  rocksdb_version=6.29.5\n[DBOptions]\n  max_open_files=512\n  max_background_jobs=2\n[CFOptions \"0\"]\n  write_buffer_size=67108864\n[CFOptions \"1\"]\n  write_buffer_size=4194304\n";
+        compare(requested, applied)?;
+        for (case, persisted) in [
+            (
+                "a changed value",
+                applied.replace("max_open_files=512", "max_open_files=-1"),
+            ),
+            (
+                "a missing column family",
+                applied.replace("[CFOptions \"0\"]", "[CFOptions \"2\"]"),
+            ),
+        ] {
+            if compare(requested, &persisted).is_ok() {
+                return Err(format!("{} counted as applied", case));
+            }
+        }
+
+        let dir = std::env::temp_dir().join(format!("smt-bench-options-{}", std::process::id()));
+        let result = (|| {
+            std::fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+            let path = dir.join("tuned.ini");
+            std::fs::write(&path, requested).map_err(|err| err.to_string())?;
+            std::fs::write(dir.join("OPTIONS-000005"), applied).map_err(|err| err.to_string())?;
+            std::fs::write(dir.join("OPTIONS-000012"), applied.replace("=512", "=64"))
+                .map_err(|err| err.to_string())?;
+            if verify(&path, &dir).is_ok() {
+                return Err("an older options file was checked instead of the latest".to_string());
+            }
+            std::fs::write(dir.join("OPTIONS-000012"), applied).map_err(|err| err.to_string())?;
+            verify(&path, &dir)?;
+            let digest = hash(&path)?;
+            if hash(&path)? != digest {
+                return Err("the hash of an unchanged file changed".to_string());
+            }
+            std::fs::write(&path, requested.replace("=512", "=1024"))
+                .map_err(|err| err.to_string())?;
+            if hash(&path)? == digest {
+                return Err("the hash did not change with the content".to_string());
+            }
+            Ok(())
+        })();
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+}
@@ -0,0 +1,2192 @@
+use crate::{
+    cache_advisor::{AccessTrace, TraceStore},
+    compaction,
+    config::{Config, DropCaches, SmtLifetime, StatsMode},
+    contract::{ContractCheckStore, ContractModel},
+    corpus::Corpus,
+    dot::hex,
+    drop_caches::drop_os_cache,
+    faulty::FaultyStore,
+    footprint,
+    guard::{self, ColumnGuardStore},
+    handles::{self, Tracked},
+    history,
+    iops::{IopsLimitedStore, TokenBucket},
+    leaves::LeafCounter,
+    lineage::{Entry, LineageFile},
+    markers::{self, Marker},
+    phase::{Phase, PhaseKind},
+    random_h256,
+    replica::Replica,
+    resume::{self, Checkpoint},
+    schedule::Setting,
+    seed::SeedTree,
+    trie::TrieStore,
+    utils::{conversion_stats, rewind_serde_nanos, serde_nanos},
+    violations::{self, Monitor, Violation},
+    writes::{DumpStore, WriteDump},
+};
+use gw_common::blake2b::new_blake2b;
+use gw_db::{iter::IteratorMode, RocksDB};
+use gw_store::{traits::KVStore, transaction::StoreTransaction, Store as GwStore};
+use rand_chacha::{rand_core::RngCore, ChaCha20Rng};
+use sparse_merkle_tree::{
+    blake2b::Blake2bHasher,
+    traits::Store,
+    tree::{BranchKey, BranchNode},
+    SparseMerkleTree, H256,
+};
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+type RoundTx<'a> = DumpStore<TraceStore<IopsLimitedStore<'a, StoreTransaction>>>;
+type LimitedTx<'a> = ColumnGuardStore<RoundTx<'a>>;
+type BenchStore<'a> = FaultyStore<ContractCheckStore<TrieStore<'a, LimitedTx<'a>>>>;
+type BenchSMT<'a> = SparseMerkleTree<Blake2bHasher, H256, BenchStore<'a>>;
+
+// Keys per update_all call when --split-commits may commit between calls
+const SPLIT_CHUNK: usize = 64;
+
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Asks the runner to stop gracefully once the current round is done
+pub fn request_stop() {
+    STOP_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+// The first Ctrl-C asks for a graceful stop, and puts the default action
+// back so that a second one kills the process. Both calls are
+// async-signal-safe.
+extern "C" fn on_sigint(_: libc::c_int) {
+    request_stop();
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+// Makes Ctrl-C stop the run once the current round is done
+pub fn stop_on_ctrl_c() {
+    let handler: extern "C" fn(libc::c_int) = on_sigint;
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+// Lets the next run start after a stopped one, for a process that runs the
+// bench more than once
+#[cfg(feature = "remote")]
+pub fn clear_stop() {
+    STOP_REQUESTED.store(false, Ordering::SeqCst);
+}
+
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::SeqCst)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RoundReport {
+    // Keys touched in this round
+    pub ops: usize,
+    pub reads: usize,
+    pub writes: usize,
+    // Bytes of keys and values written to the store
+    pub written_bytes: usize,
+    pub elapsed: Duration,
+    // Time spent re-reading committed data, not included in elapsed
+    pub verify_elapsed: Duration,
+    // Time spent deriving keys from preimages, not included in elapsed
+    pub hash_elapsed: Duration,
+    // Time spent generating the keys and values of a load round, not
+    // included in elapsed
+    pub gen_elapsed: Duration,
+    // Time the same generation took serially, only measured with
+    // --init-threads
+    pub serial_gen_elapsed: Duration,
+    // Time spent in update or update_all and in the root() call after it,
+    // both part of elapsed and only measured with --time-root
+    pub update_elapsed: Option<Duration>,
+    pub root_elapsed: Option<Duration>,
+    // Extra reads and writes of --leaf-history, not included in reads and
+    // writes
+    pub history_reads: usize,
+    pub history_writes: usize,
+    pub history_bytes: usize,
+    // Errors injected by --fault-rate, whether they rolled the round back,
+    // and reads or proofs that failed and were skipped
+    pub faults: usize,
+    pub rolled_back: bool,
+    pub failed_ops: usize,
+    // Distinct non-zero leaves written by the run so far
+    pub leaves: usize,
+    pub leaves_estimated: bool,
+    // Largest key and value bytes buffered by one of the round's
+    // transactions, and how many transactions the round committed
+    pub staged_bytes: usize,
+    pub commits: usize,
+    // With --replicate-to: batches queued after the round, time blocked on a
+    // full queue, and the batches the replica finished during the round with
+    // their apply time
+    pub replica_queue: Option<usize>,
+    pub replica_wait: Duration,
+    pub replica_applied: usize,
+    pub replica_apply: Duration,
+    // With --rewrite-storm: writes to the bottom trie shared by every key
+    // of the round and the bytes they wrote
+    pub storm_writes: usize,
+    pub storm_bytes: usize,
+    // With --strict-leaf: inserts replacing a leaf by a different value
+    pub strict_violations: usize,
+    // With --leaf-key-bytes: inserts whose truncated key held another leaf,
+    // stored under their full key instead
+    pub leaf_collisions: usize,
+    // With --guard-columns --tolerate: store accesses of the round outside
+    // the columns of the tree, the writes among them skipped
+    pub column_violations: usize,
+    // H256 and molecule conversions of the round and the bytes they copied,
+    // only counted with the conversion-stats feature
+    pub conversions: usize,
+    // With --smt-lifetime per-chunk: trees built again from the current
+    // root within the round and the time it took, part of elapsed
+    pub smt_rebuilds: usize,
+    pub rebuild_elapsed: Duration,
+    // Time spent encoding and decoding branches and keys, part of elapsed,
+    // only measured with the serde-timing feature
+    pub serde_elapsed: Duration,
+    pub converted_bytes: usize,
+    // Settings --at changed before the round
+    pub changes: Vec<Setting>,
+    // A load round, initializing the tree the other phases measure
+    pub init: bool,
+    // Reads of the proofs a prove-update round generated before and after
+    // updating the keys next to the proven ones, not included in reads, or
+    // a prove-history round generated on the current and on a past root
+    pub proof_reads: usize,
+    pub reproof_reads: usize,
+    // Proofs a prove-history round generated on a past root that did not
+    // verify against it
+    pub stale_proofs: usize,
+    // With --iops-limit: physical store operations of the round and the
+    // time spent waiting for the limit, part of elapsed
+    pub physical_ops: usize,
+    pub throttled: Duration,
+    // Pending compaction bytes when --pause-compaction turned compactions
+    // back on after the round
+    pub compaction_debt: Option<u64>,
+    // Delete tombstones in the tree's columns after the round, when the
+    // runner tracks them and RocksDB reports them
+    pub tombstones: Option<u64>,
+    // Files in level 0 and bytes pending compaction of the tree's columns
+    // after the round, sampled the same way
+    pub l0_files: Option<u64>,
+    pub pending_compaction_bytes: Option<u64>,
+    // Block cache hits and misses of the round, when RocksDB statistics are
+    // on, and the size of the database directory after the round
+    pub block_cache_hits: Option<u64>,
+    pub block_cache_misses: Option<u64>,
+    pub db_bytes: Option<u64>,
+    // Compiled proof bytes of a prove-mix round for every --proof-mix share
+    pub proof_sizes: Vec<(f64, usize)>,
+    // With --on-violation tally: the correctness monitors that fired in the
+    // round
+    pub violations: Vec<Violation>,
+}
+
+impl RoundReport {
+    pub(crate) fn leaves_label(&self) -> String {
+        if self.leaves_estimated {
+            format!("~{}", self.leaves)
+        } else {
+            self.leaves.to_string()
+        }
+    }
+}
+
+pub struct PhaseReport {
+    pub phase: Phase,
+    pub rounds: Vec<RoundReport>,
+}
+
+impl PhaseReport {
+    pub fn elapsed(&self) -> Duration {
+        self.rounds.iter().map(|r| r.elapsed).sum()
+    }
+
+    pub fn mean_elapsed(&self) -> Duration {
+        if self.rounds.is_empty() {
+            return Duration::default();
+        }
+        self.elapsed() / self.rounds.len() as u32
+    }
+
+    // Nearest-rank percentile of round durations, p in 0..=100
+    pub fn percentile(&self, p: usize) -> Duration {
+        let mut elapsed: Vec<Duration> = self.rounds.iter().map(|r| r.elapsed).collect();
+        if elapsed.is_empty() {
+            return Duration::default();
+        }
+        elapsed.sort();
+        let rank = (p * elapsed.len()).div_ceil(100);
+        elapsed[rank.max(1) - 1]
+    }
+
+    pub fn print(&self) {
+        let ops: usize = self.rounds.iter().map(|r| r.ops).sum();
+        let reads: usize = self.rounds.iter().map(|r| r.reads).sum();
+        let writes: usize = self.rounds.iter().map(|r| r.writes).sum();
+        println!(
+            "Phase {}: {} rounds ({} ops) took {:?}, mean round {:?}, reads: {}, writes: {}, leaves: {}",
+            self.phase,
+            self.rounds.len(),
+            ops,
+            self.elapsed(),
+            self.mean_elapsed(),
+            reads,
+            writes,
+            self.rounds.last().map(|r| r.leaves_label()).unwrap_or_default()
+        );
+        let verify_elapsed: Duration = self.rounds.iter().map(|r| r.verify_elapsed).sum();
+        if verify_elapsed > Duration::default() {
+            println!(
+                "  Commit verification took {:?} ({:.1}% of round time)",
+                verify_elapsed,
+                verify_elapsed.as_secs_f64() * 100.0
+                    / self.elapsed().as_secs_f64().max(f64::EPSILON)
+            );
+        }
+        let hash_elapsed: Duration = self.rounds.iter().map(|r| r.hash_elapsed).sum();
+        if hash_elapsed > Duration::default() {
+            println!(
+                "  Key hashing took {:?}, {:?} per key",
+                hash_elapsed,
+                hash_elapsed / ops.max(1) as u32
+            );
+            // What users pay per key: hashing the application key, then the
+            // update itself
+            let end_to_end = self.elapsed() + hash_elapsed;
+            println!(
+                "  End to end: {:.0} ops/s with hashing, {:.0} without, hashing is {:.1}% of the cost",
+                ops as f64 / end_to_end.as_secs_f64().max(f64::EPSILON),
+                ops as f64 / self.elapsed().as_secs_f64().max(f64::EPSILON),
+                hash_elapsed.as_secs_f64() * 100.0 / end_to_end.as_secs_f64().max(f64::EPSILON)
+            );
+        }
+        let serial_gen_elapsed: Duration = self.rounds.iter().map(|r| r.serial_gen_elapsed).sum();
+        if serial_gen_elapsed > Duration::default() {
+            let gen_elapsed: Duration = self.rounds.iter().map(|r| r.gen_elapsed).sum();
+            println!(
+                "  Key generation took {:?} in parallel, {:?} serially ({:.2}x speedup)",
+                gen_elapsed,
+                serial_gen_elapsed,
+                serial_gen_elapsed.as_secs_f64() / gen_elapsed.as_secs_f64().max(f64::EPSILON)
+            );
+        }
+        if self.rounds.first().is_some_and(|r| r.init) {
+            let written_bytes: usize = self.rounds.iter().map(|r| r.written_bytes).sum();
+            println!(
+                "  Initialization: {} keys at {:.0} keys/s, {:.1} reads, {:.1} writes and {:.0} bytes per key",
+                ops,
+                ops as f64 / self.elapsed().as_secs_f64().max(f64::EPSILON),
+                reads as f64 / ops.max(1) as f64,
+                writes as f64 / ops.max(1) as f64,
+                written_bytes as f64 / ops.max(1) as f64
+            );
+        }
+        if let Some((update, root)) = root_timing(&self.rounds) {
+            println!(
+                "  Updates took {:?}, root() {:?} ({:.2}% of update time)",
+                update,
+                root,
+                root.as_secs_f64() * 100.0 / update.as_secs_f64().max(f64::EPSILON)
+            );
+        }
+        let history_writes: usize = self.rounds.iter().map(|r| r.history_writes).sum();
+        if history_writes > 0 {
+            println!(
+                "  Leaf history: {} writes ({} bytes), {} reads",
+                history_writes,
+                self.rounds.iter().map(|r| r.history_bytes).sum::<usize>(),
+                self.rounds.iter().map(|r| r.history_reads).sum::<usize>()
+            );
+        }
+        let faults: usize = self.rounds.iter().map(|r| r.faults).sum();
+        if faults > 0 {
+            println!(
+                "  Faults injected: {}, rounds rolled back: {}, failed ops skipped: {}",
+                faults,
+                self.rounds.iter().filter(|r| r.rolled_back).count(),
+                self.rounds.iter().map(|r| r.failed_ops).sum::<usize>()
+            );
+        }
+        let storm_bytes: usize = self.rounds.iter().map(|r| r.storm_bytes).sum();
+        if storm_bytes > 0 {
+            let written_bytes: usize = self.rounds.iter().map(|r| r.written_bytes).sum();
+            println!(
+                "  Rewrite storm: shared trie written {} times, {} bytes ({:.1}% of written bytes)",
+                self.rounds.iter().map(|r| r.storm_writes).sum::<usize>(),
+                storm_bytes,
+                storm_bytes as f64 * 100.0 / written_bytes.max(1) as f64
+            );
+        }
+        let strict_violations: usize = self.rounds.iter().map(|r| r.strict_violations).sum();
+        if strict_violations > 0 {
+            println!(
+                "  Strict leaf violations: {} inserts replaced a leaf by a different value",
+                strict_violations
+            );
+        }
+        let leaf_collisions: usize = self.rounds.iter().map(|r| r.leaf_collisions).sum();
+        if leaf_collisions > 0 {
+            println!(
+                "  Leaf key collisions: {} inserts stored under the full key",
+                leaf_collisions
+            );
+        }
+        let column_violations: usize = self.rounds.iter().map(|r| r.column_violations).sum();
+        if column_violations > 0 {
+            println!(
+                "  Column violations: {} accesses outside the columns of the tree",
+                column_violations
+            );
+        }
+        let violations: usize = self.rounds.iter().map(|r| r.violations.len()).sum();
+        if violations > 0 {
+            println!(
+                "  Violations tallied: {} in {} rounds",
+                violations,
+                self.rounds
+                    .iter()
+                    .filter(|r| !r.violations.is_empty())
+                    .count()
+            );
+        }
+        let tombstones: Vec<u64> = self.rounds.iter().filter_map(|r| r.tombstones).collect();
+        if tombstones.iter().any(|n| *n > 0) {
+            println!(
+                "  Tombstones: {} after the first round, {} after the last",
+                tombstones[0],
+                tombstones[tombstones.len() - 1]
+            );
+        }
+        let smt_rebuilds: usize = self.rounds.iter().map(|r| r.smt_rebuilds).sum();
+        if smt_rebuilds > 0 {
+            let rebuild_elapsed: Duration = self.rounds.iter().map(|r| r.rebuild_elapsed).sum();
+            println!(
+                "  SMT rebuilds: {:.1} per round, {:?} ({:.3}% of round time)",
+                smt_rebuilds as f64 / self.rounds.len() as f64,
+                rebuild_elapsed,
+                rebuild_elapsed.as_secs_f64() * 100.0
+                    / self.elapsed().as_secs_f64().max(f64::EPSILON)
+            );
+        }
+        let serde_elapsed: Duration = self.rounds.iter().map(|r| r.serde_elapsed).sum();
+        if serde_elapsed > Duration::default() {
+            println!(
+                "  Serialization: {:?} ({:.1}% of round time)",
+                serde_elapsed,
+                serde_elapsed.as_secs_f64() * 100.0
+                    / self.elapsed().as_secs_f64().max(f64::EPSILON)
+            );
+        }
+        let conversions: usize = self.rounds.iter().map(|r| r.conversions).sum();
+        if conversions > 0 {
+            let converted_bytes: usize = self.rounds.iter().map(|r| r.converted_bytes).sum();
+            println!(
+                "  Conversions: {} ({:.1} per op), {} bytes copied ({:.1} per op)",
+                conversions,
+                conversions as f64 / ops.max(1) as f64,
+                converted_bytes,
+                converted_bytes as f64 / ops.max(1) as f64
+            );
+        }
+        if self.phase.kind == PhaseKind::ProveUpdate && ops > 0 {
+            let proof_reads: usize = self.rounds.iter().map(|r| r.proof_reads).sum();
+            let reproof_reads: usize = self.rounds.iter().map(|r| r.reproof_reads).sum();
+            println!(
+                "  Proof reads: {:.1} per proof before the update, {:.1} after, ratio: {:.2}",
+                proof_reads as f64 / ops as f64,
+                reproof_reads as f64 / ops as f64,
+                reproof_reads as f64 / proof_reads.max(1) as f64
+            );
+        }
+        if self.phase.kind == PhaseKind::ProveHistory && ops > 0 {
+            let proof_reads: usize = self.rounds.iter().map(|r| r.proof_reads).sum();
+            let reproof_reads: usize = self.rounds.iter().map(|r| r.reproof_reads).sum();
+            let stale_proofs: usize = self.rounds.iter().map(|r| r.stale_proofs).sum();
+            println!(
+                "  Proof reads: {:.1} per proof on the current root, {:.1} on a past root, ratio: {:.2}",
+                proof_reads as f64 / ops as f64,
+                reproof_reads as f64 / ops as f64,
+                reproof_reads as f64 / proof_reads.max(1) as f64
+            );
+            println!(
+                "  Past roots: {} of {} proofs did not verify against the root they were generated on",
+                stale_proofs, ops
+            );
+        }
+        if self.phase.kind == PhaseKind::ProveMix && !self.rounds.is_empty() {
+            for (i, mix) in self.rounds[0]
+                .proof_sizes
+                .iter()
+                .map(|(mix, _)| mix)
+                .enumerate()
+            {
+                let sizes: Vec<usize> = self
+                    .rounds
+                    .iter()
+                    .filter_map(|r| r.proof_sizes.get(i).map(|(_, size)| *size))
+                    .collect();
+                let mean = sizes.iter().sum::<usize>() as f64 / sizes.len().max(1) as f64;
+                println!(
+                    "  Proof mix {:.2}: {:.0} bytes per proof of {} keys, {:.1} per key",
+                    mix,
+                    mean,
+                    self.phase.batch_size,
+                    mean / self.phase.batch_size.max(1) as f64
+                );
+            }
+        }
+        let debts: Vec<u64> = self
+            .rounds
+            .iter()
+            .filter_map(|r| r.compaction_debt)
+            .collect();
+        if !debts.is_empty() {
+            println!(
+                "  Compaction paused during rounds, pending bytes at re-enable: mean {}, max {}",
+                debts.iter().sum::<u64>() / debts.len() as u64,
+                debts.iter().max().unwrap()
+            );
+        }
+        let physical_ops: usize = self.rounds.iter().map(|r| r.physical_ops).sum();
+        if physical_ops > 0 {
+            let throttled: Duration = self.rounds.iter().map(|r| r.throttled).sum();
+            let share = throttled.as_secs_f64() / self.elapsed().as_secs_f64().max(f64::EPSILON);
+            println!(
+                "  IOPS: {:.0} achieved, waited {:?} for the limit ({:.1}% of round time), {}",
+                physical_ops as f64 / self.elapsed().as_secs_f64().max(f64::EPSILON),
+                throttled,
+                share * 100.0,
+                if share >= 0.5 {
+                    "the limit was the bottleneck"
+                } else {
+                    "the limit was not the bottleneck"
+                }
+            );
+        }
+        let changes: Vec<String> = self
+            .rounds
+            .iter()
+            .enumerate()
+            .flat_map(|(i, r)| {
+                r.changes
+                    .iter()
+                    .map(move |setting| format!("{} before round {}", setting, i + 1))
+            })
+            .collect();
+        if !changes.is_empty() {
+            println!("  Scheduled changes: {}", changes.join(", "));
+        }
+        let commits: usize = self.rounds.iter().map(|r| r.commits).sum();
+        if commits > self.rounds.len() {
+            println!(
+                "  Split commits: {} transactions over {} rounds, staged peak: {} bytes",
+                commits,
+                self.rounds.len(),
+                self.rounds
+                    .iter()
+                    .map(|r| r.staged_bytes)
+                    .max()
+                    .unwrap_or(0)
+            );
+        }
+        // A balanced tree over n leaves needs about log2(n) branch reads per
+        // update, TrieStore reads fewer but larger values than that
+        let leaves = self.rounds.last().map(|r| r.leaves).unwrap_or(0);
+        if self.phase.kind.writes() && ops > 0 && leaves > 1 {
+            let actual = reads as f64 / ops as f64;
+            let expected = (leaves as f64).log2();
+            println!(
+                "  Reads per update: {:.1}, log2(leaves): {:.1}, ratio: {:.2}",
+                actual,
+                expected,
+                actual / expected
+            );
+        }
+        if self.rounds.len() > 1 {
+            println!(
+                "  Round p50: {:?}, p90: {:?}, p99: {:?}, max: {:?}",
+                self.percentile(50),
+                self.percentile(90),
+                self.percentile(99),
+                self.percentile(100)
+            );
+            let mut sizes: Vec<usize> = self.rounds.iter().map(|r| r.ops).collect();
+            sizes.sort_unstable();
+            if sizes[0] != sizes[sizes.len() - 1] {
+                let rank = |p: usize| sizes[(p * sizes.len()).div_ceil(100).max(1) - 1];
+                println!(
+                    "  Round sizes: min {}, p50 {}, p90 {}, max {}, mean {:.1}",
+                    sizes[0],
+                    rank(50),
+                    rank(90),
+                    sizes[sizes.len() - 1],
+                    ops as f64 / sizes.len() as f64
+                );
+            }
+        }
+    }
+
+    // Prints how the first round of this phase compares to the rest of it,
+    // which shows effects carried over from the previous phase.
+    pub fn print_transition(&self, previous: &PhaseReport) {
+        if let Some(first) = self.rounds.first() {
+            let mean = self.mean_elapsed();
+            println!(
+                "  After {}: first round took {:?} ({:.2}x phase mean)",
+                previous.phase,
+                first.elapsed,
+                first.elapsed.as_secs_f64() / mean.as_secs_f64().max(f64::EPSILON)
+            );
+        }
+    }
+}
+
+// Sums of the update and root() timings of rounds measured with --time-root
+fn root_timing(rounds: &[RoundReport]) -> Option<(Duration, Duration)> {
+    let update: Option<Duration> = rounds.iter().map(|r| r.update_elapsed).sum();
+    let root: Option<Duration> = rounds.iter().map(|r| r.root_elapsed).sum();
+    update.zip(root)
+}
+
+// Running sums of the I/O counters over every round of the run
+#[derive(Clone, Copy, Debug, Default)]
+struct Totals {
+    reads: usize,
+    writes: usize,
+    bytes: usize,
+}
+
+impl Totals {
+    fn add(&mut self, round: &RoundReport) {
+        self.reads += round.reads;
+        self.writes += round.writes;
+        self.bytes += round.written_bytes;
+    }
+}
+
+// Prints one aggregated line for a window of consecutive rounds, totals being
+// the sums after the window and printed the sums at the previous line
+fn print_rounds(
+    first: usize,
+    rounds: &[RoundReport],
+    mode: StatsMode,
+    totals: &Totals,
+    printed: &Totals,
+) {
+    let elapsed: Duration = rounds.iter().map(|r| r.elapsed).sum();
+    let stats = match mode {
+        StatsMode::Cumulative => format!(
+            "total reads: {}, total writes: {}, total bytes: {}",
+            totals.reads, totals.writes, totals.bytes
+        ),
+        StatsMode::Delta => format!(
+            "reads: +{}, writes: +{}, bytes: +{}",
+            totals.reads - printed.reads,
+            totals.writes - printed.writes,
+            totals.bytes - printed.bytes
+        ),
+        StatsMode::PerRound => format!(
+            "reads/round: {}, writes/round: {}, bytes/round: {}",
+            (totals.reads - printed.reads) / rounds.len(),
+            (totals.writes - printed.writes) / rounds.len(),
+            (totals.bytes - printed.bytes) / rounds.len()
+        ),
+    };
+    let root_timing = match root_timing(rounds) {
+        Some((update, root)) => format!(
+            ", update: {:?}, root: {:?}",
+            update / rounds.len() as u32,
+            root / rounds.len() as u32
+        ),
+        None => String::new(),
+    };
+    let replica = match rounds.last().unwrap().replica_queue {
+        Some(queued) => {
+            let applied: usize = rounds.iter().map(|r| r.replica_applied).sum();
+            let apply: Duration = rounds.iter().map(|r| r.replica_apply).sum();
+            format!(
+                ", replica queue: {}, replica apply: {:?}/batch",
+                queued,
+                apply / applied.max(1) as u32
+            )
+        }
+        None => String::new(),
+    };
+    let staged = match rounds.iter().map(|r| r.staged_bytes).max() {
+        Some(staged) if staged > 0 => format!(", staged peak: {}", staged),
+        _ => String::new(),
+    };
+    println!(
+        "  Rounds {}-{}: mean {:?}{}, {}{}{}, leaves: {}",
+        first + 1,
+        first + rounds.len(),
+        elapsed / rounds.len() as u32,
+        root_timing,
+        stats,
+        staged,
+        replica,
+        rounds.last().unwrap().leaves_label()
+    );
+}
+
+// Receives every round as soon as it completes
+pub trait ReportSink {
+    fn round(&mut self, phase: &Phase, index: usize, round: &RoundReport);
+}
+
+pub struct Runner<'a> {
+    config: &'a Config,
+    store: &'a GwStore,
+    seeds: SeedTree,
+    phases_run: usize,
+    // Rounds started across all phases, numbers the leaf history records
+    rounds_run: u64,
+    // Identifies the round in progress in failure messages
+    round_label: String,
+    round_seeds: Option<SeedTree>,
+    root: H256,
+    // Keys currently present in the tree, used as the sampling pool for
+    // delete and read phases
+    keys: Vec<H256>,
+    leaves: LeafCounter,
+    // Key proven by every round of the current prove-same phase
+    proof_key: Option<H256>,
+    // Roots committed by the rounds so far, each once, for prove-history
+    past_roots: Vec<H256>,
+    sinks: Vec<Box<dyn ReportSink + 'a>>,
+    totals: Totals,
+    // Totals at the last progress line, for --stats-mode delta
+    printed: Totals,
+    // Shadow of everything the run committed, with --check-contract
+    contract: Option<Rc<RefCell<ContractModel>>>,
+    replica: Option<Replica>,
+    // Key every --rewrite-storm key shares all but its lowest byte with
+    storm_prefix: Option<H256>,
+    // Counter the next key of --sorted-keys is made of
+    next_sorted: u64,
+    // Settings --at can change during the run
+    batch_size: Option<usize>,
+    verbose: bool,
+    // Pairs of --keys-file, replacing the generated ones
+    corpus: Option<Corpus>,
+    // Rate of physical store operations with --iops-limit
+    iops: Option<Rc<RefCell<TokenBucket>>>,
+    // Database whose automatic compactions are off while rounds are timed
+    paused_db: Option<RocksDB>,
+    // Database whose tombstones, L0 files, compaction backlog, block cache
+    // tickers and size are sampled after every round
+    stats_db: Option<RocksDB>,
+    // Block cache hits and misses since the database was opened, at the
+    // last sample
+    cache_tickers: Option<(u64, u64)>,
+    // Trie blob reads of the rounds, with --cache-advisor
+    trace: Option<Rc<RefCell<AccessTrace>>>,
+    // Committed writes of the rounds, with --dump-writes
+    dump: Option<Rc<RefCell<WriteDump>>>,
+    // Root of every committed round, with --emit-roots
+    roots: Option<File>,
+    // Every round with its roots, with --lineage
+    lineage: Option<LineageFile>,
+    // Accesses outside the columns of the tree, with --guard-columns
+    // --tolerate
+    column_violations: Rc<Cell<usize>>,
+    // Where the correctness monitors report, see --on-violation
+    monitor: Rc<Monitor>,
+    // Fingerprint of the run, with which every round leaves a checkpoint
+    // in the database directory for --resume
+    checkpoint: Option<String>,
+    // Rounds of the next phase a resumed run already did
+    resume_round: usize,
+}
+
+impl<'a> Runner<'a> {
+    pub fn new(config: &'a Config, store: &'a GwStore, seeds: SeedTree) -> Self {
+        let storm_prefix = config
+            .rewrite_storm
+            .then(|| random_h256(&mut seeds.child("rewrite-storm").rng()));
+        Self {
+            config,
+            store,
+            seeds,
+            phases_run: 0,
+            rounds_run: 0,
+            round_label: String::new(),
+            round_seeds: None,
+            root: H256::default(),
+            keys: vec![],
+            leaves: LeafCounter::new(config.exact_leaf_limit),
+            proof_key: None,
+            past_roots: vec![],
+            sinks: vec![],
+            totals: Totals::default(),
+            printed: Totals::default(),
+            contract: config.check_contract.then(|| {
+                // A database holding nothing yet lets the model treat every
+                // key it has not seen as absent
+                let tx = handles::track(handles::TRANSACTION, store.begin_transaction());
+                let empty = [config.branch_col, config.leaf_col]
+                    .iter()
+                    .all(|col| tx.get_iter(*col, IteratorMode::Start).next().is_none());
+                Rc::new(RefCell::new(ContractModel::new(empty)))
+            }),
+            replica: config
+                .replicate_to
+                .as_ref()
+                .map(|(kind, path)| Replica::start(config, *kind, path.clone())),
+            storm_prefix,
+            next_sorted: 0,
+            batch_size: None,
+            verbose: config.verbose,
+            corpus: None,
+            iops: config
+                .iops_limit
+                .map(|limit| Rc::new(RefCell::new(TokenBucket::new(limit)))),
+            paused_db: None,
+            stats_db: None,
+            cache_tickers: None,
+            trace: config
+                .cache_advisor
+                .then(|| Rc::new(RefCell::new(AccessTrace::new()))),
+            dump: config.dump_writes.as_ref().map(|path| {
+                let dump = WriteDump::create(path)
+                    .unwrap_or_else(|err| panic!("Cannot create {}: {}", path.display(), err));
+                Rc::new(RefCell::new(dump))
+            }),
+            roots: config.emit_roots.as_ref().map(|path| {
+                File::create(path)
+                    .unwrap_or_else(|err| panic!("Cannot create {}: {}", path.display(), err))
+            }),
+            lineage: config.lineage.as_ref().map(|path| {
+                LineageFile::create(path)
+                    .unwrap_or_else(|err| panic!("Cannot create {}: {}", path.display(), err))
+            }),
+            column_violations: Rc::default(),
+            monitor: Rc::new(Monitor::new(config.on_violation)),
+            checkpoint: None,
+            resume_round: 0,
+        }
+    }
+
+    pub fn root(&self) -> H256 {
+        self.root
+    }
+
+    // A transaction on the store, registered with --debug-handles
+    fn begin(&self) -> Tracked<StoreTransaction> {
+        handles::track(handles::TRANSACTION, self.store.begin_transaction())
+    }
+
+    // Every key in the tree with its leaf, read back from the store
+    pub(crate) fn leaves(&self) -> Vec<(H256, H256)> {
+        let tx = self.begin();
+        let tx = self.plain(&tx);
+        let store = self.trie_store(&tx);
+        self.keys
+            .iter()
+            .map(|key| {
+                (
+                    *key,
+                    store.get_leaf(key).unwrap().unwrap_or_else(H256::zero),
+                )
+            })
+            .collect()
+    }
+
+    // Waits for the replica of --replicate-to to catch up and checks its
+    // root, then trims the leaf history to --history-retention
+    pub fn finish(&mut self) {
+        let horizon = self
+            .config
+            .history_retention
+            .map(|retention| self.rounds_run.saturating_sub(retention));
+        if let Some(replica) = self.replica.take() {
+            replica.finish(self.root, horizon);
+        }
+        if let Some(horizon) = horizon {
+            let tx = self.store.begin_transaction();
+            let trimmed = history::trim(&tx, self.config.history_col, horizon)
+                .and_then(|trimmed| tx.commit().map(|_| trimmed).map_err(|err| err.to_string()))
+                .unwrap_or_else(|err| panic!("Cannot trim the leaf history: {}", err));
+            println!(
+                "Trimmed {} leaf history records, history-get answers from round {} on",
+                trimmed, horizon
+            );
+        }
+        if let Some(dump) = &self.dump {
+            dump.borrow_mut().flush().unwrap();
+        }
+    }
+
+    pub fn set_corpus(&mut self, corpus: Corpus) {
+        self.corpus = Some(corpus);
+    }
+
+    // Turns the automatic compactions of db off for every round from now on
+    pub fn pause_compaction(&mut self, db: RocksDB) {
+        self.paused_db = Some(db);
+    }
+
+    // Samples the tombstones, L0 files, pending compaction bytes, block
+    // cache hits and misses and size of db after every round from now on
+    pub fn track_db_stats(&mut self, db: RocksDB) {
+        self.cache_tickers = footprint::block_cache_tickers(&db, self.config);
+        self.stats_db = Some(db);
+    }
+
+    // Transactions whose writes went to the dump of --dump-writes
+    pub fn dumped_transactions(&self) -> Option<usize> {
+        self.dump.as_ref().map(|dump| dump.borrow().transactions)
+    }
+
+    pub fn access_trace(&self) -> Option<Rc<RefCell<AccessTrace>>> {
+        self.trace.clone()
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn ReportSink + 'a>) {
+        self.sinks.push(sink);
+    }
+
+    // Checkpoints every round from now on, see resume.rs
+    pub fn checkpoint(&mut self, fingerprint: String) {
+        self.checkpoint = Some(fingerprint);
+    }
+
+    // Goes on from a checkpoint of the run that wrote the store, with round
+    // rounds of the phase-th phase done. The pool of keys is read back from
+    // the leaf column, in key order rather than the order the run wrote
+    // them, so rounds sampling it by index, deletes and reads, pick other
+    // keys than the interrupted run would have.
+    pub fn resume(&mut self, checkpoint: &Checkpoint, phase: usize, round: usize) {
+        self.root = checkpoint.root;
+        self.phases_run = phase;
+        self.resume_round = round;
+        self.rounds_run = checkpoint.rounds_run;
+        self.next_sorted = checkpoint.next_sorted;
+        self.past_roots = vec![checkpoint.root];
+        let tx = self.begin();
+        self.keys = tx
+            .get_iter(self.config.leaf_col, IteratorMode::Start)
+            .map(|(key, _)| {
+                let mut bytes = [0u8; 32];
+                bytes.copy_from_slice(&key);
+                H256::from(bytes)
+            })
+            .collect();
+        drop(tx);
+        let leaves = self.leaves();
+        self.leaves.record(&leaves);
+        // Settings --at changed in the rounds done stay changed
+        for change in &self.config.schedule {
+            if change.round <= self.rounds_run {
+                match change.setting {
+                    Setting::BatchSize(size) => self.batch_size = Some(size),
+                    Setting::Verbose(verbose) => self.verbose = verbose,
+                }
+            }
+        }
+    }
+
+    // Operations and throttled time of the --iops-limit bucket so far
+    fn iops_snapshot(&self) -> (usize, Duration) {
+        self.iops
+            .as_ref()
+            .map_or((0, Duration::default()), |bucket| {
+                let bucket = bucket.borrow();
+                (bucket.ops, bucket.throttled)
+            })
+    }
+
+    // The transaction behind the --iops-limit token bucket, the
+    // --cache-advisor trace and the --dump-writes dump. Wraps a transaction
+    // just begun, whose writes start a new transaction of the dump
+    fn limited<'b>(&self, tx: &'b StoreTransaction) -> LimitedTx<'b> {
+        if let Some(dump) = &self.dump {
+            dump.borrow_mut().begin();
+        }
+        let tx = TraceStore::new(
+            IopsLimitedStore::new(tx, self.iops.clone()),
+            self.trace.clone(),
+            self.config.branch_col,
+        );
+        self.guard(DumpStore::new(tx, self.dump.clone()))
+    }
+
+    // A transaction outside of the rounds, neither limited, traced nor
+    // dumped
+    fn plain<'b>(&self, tx: &'b StoreTransaction) -> LimitedTx<'b> {
+        let tx = TraceStore::new(
+            IopsLimitedStore::new(tx, None),
+            None,
+            self.config.branch_col,
+        );
+        self.guard(DumpStore::new(tx, None))
+    }
+
+    // Restricts tx to the columns of the tree with --guard-columns
+    fn guard<'b>(&self, tx: RoundTx<'b>) -> LimitedTx<'b> {
+        let allowed = self
+            .config
+            .guard_columns
+            .then(|| guard::allowed_columns(self.config));
+        let store = ColumnGuardStore::new(tx, allowed);
+        if self.config.tolerate {
+            store.tolerate(self.column_violations.clone())
+        } else {
+            store
+        }
+    }
+
+    fn trie_store<'b>(&self, tx: &'b LimitedTx<'b>) -> TrieStore<'b, LimitedTx<'b>> {
+        let store = TrieStore::with_layout(tx, self.config.trie_layout)
+            .with_columns(self.config.branch_col, self.config.leaf_col)
+            .molecule_wrap(self.config.trie_molecule_wrap)
+            .truncate_leaf_keys(self.config.leaf_key_bytes);
+        let store = match self.storm_prefix {
+            Some(prefix) => store.watch_trie(&BranchKey::new(0, prefix.parent_path(0))),
+            None => store,
+        };
+        let store = if self.config.strict_leaf {
+            store.strict_leaves()
+        } else {
+            store
+        };
+        if self.config.leaf_history {
+            store.with_history(self.config.history_col, self.rounds_run)
+        } else {
+            store
+        }
+    }
+
+    pub fn run_phase(&mut self, phase: &Phase) -> PhaseReport {
+        let phase_seeds = self.seeds.child(&format!("phase-{}", self.phases_run));
+        self.phases_run += 1;
+        self.run_seeded_phase(phase, phase_seeds)
+    }
+
+    // Runs an extra phase, e.g. the reads around a compaction, on the seed
+    // stream of label. The phases after it draw the same keys as without it.
+    pub fn run_probe(&mut self, phase: &Phase, label: &str) -> PhaseReport {
+        let probe_seeds = self.seeds.child(label);
+        self.run_seeded_phase(phase, probe_seeds)
+    }
+
+    fn run_seeded_phase(&mut self, phase: &Phase, phase_seeds: SeedTree) -> PhaseReport {
+        // Progress lines would scroll the dashboard away
+        let report_every = if self.config.tui {
+            0
+        } else {
+            self.config.report_every
+        };
+        let mut rounds = vec![];
+        let mut reported = 0;
+        self.proof_key = None;
+        if self.config.drop_caches == Some(DropCaches::BetweenPhases) {
+            self.drop_caches();
+        }
+        let first_round = std::mem::take(&mut self.resume_round);
+        for round in first_round..phase.rounds {
+            if stop_requested() {
+                break;
+            }
+            if self.config.drop_caches == Some(DropCaches::BetweenRounds) {
+                self.drop_caches();
+            }
+            let round_seeds = phase_seeds.child(&format!("round-{}", round));
+            let mut rng = round_seeds.rng();
+            // Drawn from a seed of its own, so the keys of the round stay
+            // the same whatever the distribution
+            let drawn_size = self
+                .config
+                .round_sizes
+                .filter(|_| matches!(phase.kind, PhaseKind::Update | PhaseKind::Delete))
+                .map(|sizes| sizes.sample(&mut round_seeds.child("round-size").rng()));
+            self.round_label = format!("phase {} round {}", phase, round + 1);
+            self.round_seeds = Some(round_seeds);
+            self.rounds_run += 1;
+            self.monitor.start_round(self.rounds_run);
+            let changes = self.apply_schedule();
+            let batch_size = self.batch_size.or(drawn_size).unwrap_or(phase.batch_size);
+            let root_before = self.root;
+            let iops_before = self.iops_snapshot();
+            let violations_before = self.column_violations.get();
+            let serde_before = serde_nanos();
+            if let Some(db) = &self.paused_db {
+                compaction::set_auto_compactions(db, self.config, false);
+            }
+            // Load rounds initialize the tree the other phases measure
+            let mut report = if phase.kind == PhaseKind::Load {
+                markers::phase_init(|| self.run_round(phase.kind, &mut rng, batch_size))
+            } else {
+                markers::phase_round(|| self.run_round(phase.kind, &mut rng, batch_size))
+            };
+            report.changes = changes;
+            report.violations = self.monitor.take();
+            report.init = phase.kind == PhaseKind::Load;
+            report.serde_elapsed = Duration::from_nanos(serde_nanos() - serde_before);
+            if let Some(db) = &self.paused_db {
+                report.compaction_debt = compaction::pending_bytes(db, self.config);
+                compaction::set_auto_compactions(db, self.config, true);
+            }
+            let (ops, throttled) = self.iops_snapshot();
+            report.physical_ops = ops - iops_before.0;
+            report.throttled = throttled - iops_before.1;
+            report.column_violations = self.column_violations.get() - violations_before;
+            if let Some(db) = &self.stats_db {
+                report.tombstones = compaction::tombstones(db, self.config);
+                report.l0_files = compaction::l0_files(db, self.config);
+                report.pending_compaction_bytes = compaction::pending_bytes(db, self.config);
+                let tickers = footprint::block_cache_tickers(db, self.config);
+                if let (Some((hits, misses)), Some((last_hits, last_misses))) =
+                    (tickers, self.cache_tickers)
+                {
+                    report.block_cache_hits = Some(hits.saturating_sub(last_hits));
+                    report.block_cache_misses = Some(misses.saturating_sub(last_misses));
+                }
+                self.cache_tickers = tickers;
+                report.db_bytes = Some(footprint::dir_size(&self.config.db_path));
+            }
+            // Load and update rounds insert fresh random keys, so an unchanged
+            // root means the writes were lost
+            let inserts = matches!(phase.kind, PhaseKind::Load | PhaseKind::Update);
+            if self.config.assert_root_changes
+                && inserts
+                && report.ops > 0
+                && !report.rolled_back
+                && self.root == root_before
+            {
+                panic!(
+                    "{}: root {:?} did not change after inserting {} keys",
+                    self.round_label, self.root, report.ops
+                );
+            }
+            // Write amplification regressions, e.g. trie writes no longer
+            // coalesced, show up as more writes per updated key
+            let writes_per_update = report.writes as f64 / report.ops.max(1) as f64;
+            match self.config.max_writes_per_update {
+                Some(max) if phase.kind.writes() && report.ops > 0 && writes_per_update > max => {
+                    panic!(
+                        "{}: {} writes for {} updated keys, {:.2} per update is over --max-writes-per-update {}",
+                        self.round_label, report.writes, report.ops, writes_per_update, max
+                    );
+                }
+                _ => {}
+            }
+            if let Some(replica) = &mut self.replica {
+                let (applied, apply) = replica.drain();
+                report.replica_applied = applied;
+                report.replica_apply = apply;
+            }
+            report.leaves = self.leaves.count();
+            report.leaves_estimated = self.leaves.is_estimate();
+            if !report.rolled_back && !self.past_roots.contains(&self.root) {
+                self.past_roots.push(self.root);
+            }
+            // Written through at once, a crashed run leaves every round it
+            // committed in the file
+            if let Some(out) = &mut self.roots {
+                if !report.rolled_back {
+                    writeln!(out, "{}", hex(&self.root))
+                        .and_then(|_| out.flush())
+                        .unwrap_or_else(|err| panic!("Cannot write --emit-roots: {}", err));
+                }
+            }
+            let transactions = self.dumped_transactions().unwrap_or(0);
+            if let Some(lineage) = &mut self.lineage {
+                let entry = Entry {
+                    round: self.rounds_run,
+                    parent_root: root_before,
+                    new_root: self.root,
+                    transactions,
+                };
+                lineage
+                    .record(&entry)
+                    .unwrap_or_else(|err| panic!("Cannot write --lineage: {}", err));
+            }
+            if let Some(fingerprint) = &self.checkpoint {
+                let checkpoint = Checkpoint {
+                    fingerprint: fingerprint.clone(),
+                    phases_run: self.phases_run,
+                    round: round + 1,
+                    rounds_run: self.rounds_run,
+                    root: self.root,
+                    next_sorted: self.next_sorted,
+                };
+                resume::write(&self.config.db_path, &checkpoint)
+                    .unwrap_or_else(|err| panic!("Cannot write the checkpoint: {}", err));
+            }
+            rounds.push(report);
+            let report = rounds.last().unwrap();
+            for sink in &mut self.sinks {
+                sink.round(phase, round, report);
+            }
+            self.totals.add(report);
+            if report_every > 0 && rounds.len() - reported == report_every {
+                self.print_rounds(first_round + reported, &rounds[reported..]);
+                reported = rounds.len();
+            }
+        }
+        if report_every > 1 && reported < rounds.len() {
+            self.print_rounds(first_round + reported, &rounds[reported..]);
+        }
+        PhaseReport {
+            phase: phase.clone(),
+            rounds,
+        }
+    }
+
+    fn run_round(
+        &mut self,
+        kind: PhaseKind,
+        rng: &mut ChaCha20Rng,
+        batch_size: usize,
+    ) -> RoundReport {
+        match kind {
+            PhaseKind::Load => self.load_round(rng, batch_size),
+            PhaseKind::Update => self.update_round(rng, batch_size),
+            PhaseKind::Delete => self.delete_round(rng, batch_size),
+            PhaseKind::Read => self.read_round(rng, batch_size),
+            PhaseKind::ProveSame => self.prove_same_round(rng, batch_size),
+            PhaseKind::ProveUpdate => self.prove_update_round(rng, batch_size),
+            PhaseKind::ProveMix => self.prove_mix_round(rng, batch_size),
+            PhaseKind::ProveHistory => self.prove_history_round(rng, batch_size),
+        }
+    }
+
+    // Applies the --at changes scheduled for the round about to start and
+    // logs each of them
+    fn apply_schedule(&mut self) -> Vec<Setting> {
+        let changes: Vec<Setting> = self
+            .config
+            .schedule
+            .iter()
+            .filter(|change| change.round == self.rounds_run)
+            .map(|change| change.setting)
+            .collect();
+        for setting in &changes {
+            match setting {
+                Setting::BatchSize(size) => self.batch_size = Some(*size),
+                Setting::Verbose(verbose) => self.verbose = *verbose,
+            }
+            if !self.config.tui {
+                println!(
+                    "  Round {} of the run ({}): set {}",
+                    self.rounds_run, self.round_label, setting
+                );
+            }
+        }
+        changes
+    }
+
+    // Runs the phase the last run_phase call ran once more on the same
+    // seeds, so a read phase reads the same keys again
+    pub fn rerun_phase(&mut self, phase: &Phase) -> PhaseReport {
+        self.phases_run -= 1;
+        self.run_phase(phase)
+    }
+
+    // Drops the OS page cache of the database files, then reads the root trie
+    // twice to show how much colder the first read got
+    fn drop_caches(&self) {
+        let files = drop_os_cache(&self.config.db_path);
+        let tx = self.begin();
+        let tx = self.plain(&tx);
+        let store = self.trie_store(&tx);
+        let key = BranchKey::new(u8::MAX, H256::zero());
+        let start = Instant::now();
+        store.get_branch(&key).unwrap();
+        let cold = start.elapsed();
+        let start = Instant::now();
+        store.get_branch(&key).unwrap();
+        let warm = start.elapsed();
+        if !self.config.tui {
+            println!(
+                "  Dropped the page cache of {} SST files, root trie read {:?} cold, {:?} warm",
+                files, cold, warm
+            );
+        }
+    }
+
+    // The round's TrieStore behind the --check-contract and --fault-rate
+    // decorators
+    fn faulty_store<'b>(&self, tx: &'b LimitedTx<'b>) -> BenchStore<'b> {
+        let rng = match &self.round_seeds {
+            Some(seeds) => seeds.child("faults").rng(),
+            None => self.seeds.child("faults").rng(),
+        };
+        let store = ContractCheckStore::new(self.trie_store(tx), self.contract.clone())
+            .with_monitor(self.monitor.clone());
+        FaultyStore::new(store, self.config.fault_rate, rng)
+    }
+
+    // Keeps the update and root() timings of a round when --time-root is set
+    fn root_timing(
+        &self,
+        update_elapsed: Duration,
+        root_elapsed: Duration,
+    ) -> (Option<Duration>, Option<Duration>) {
+        if self.config.time_root {
+            (Some(update_elapsed), Some(root_elapsed))
+        } else {
+            (None, None)
+        }
+    }
+
+    fn print_rounds(&mut self, first: usize, rounds: &[RoundReport]) {
+        print_rounds(
+            first,
+            rounds,
+            self.config.stats_mode,
+            &self.totals,
+            &self.printed,
+        );
+        self.printed = self.totals;
+    }
+
+    // Generates a fresh key, either raw random bytes or the blake2b hash of a
+    // random preimage when --key-preimage-size is set.
+    fn random_key(&self, rng: &mut ChaCha20Rng, hash_elapsed: &mut Duration) -> H256 {
+        random_key(rng, self.config.key_preimage_size, hash_elapsed)
+    }
+
+    fn random_pairs(
+        &mut self,
+        rng: &mut ChaCha20Rng,
+        count: usize,
+    ) -> (Vec<(H256, H256)>, Duration) {
+        if let Some(prefix) = self.storm_prefix {
+            return (self.storm_pairs(rng, prefix, count), Duration::default());
+        }
+        if let Some(corpus) = &mut self.corpus {
+            let pairs = corpus.take(count);
+            self.keys.extend(pairs.iter().map(|(key, _)| *key));
+            return (pairs, Duration::default());
+        }
+        if self.config.sorted_keys {
+            let pairs = self.sorted_pairs(rng, count);
+            self.keys.extend(pairs.iter().map(|(key, _)| *key));
+            return (pairs, Duration::default());
+        }
+        let (pairs, hash_elapsed) = random_pairs(rng, count, self.config.key_preimage_size);
+        self.keys.extend(pairs.iter().map(|(key, _)| *key));
+        (pairs, hash_elapsed)
+    }
+
+    // Pairs whose keys differ from prefix in the lowest byte only, so that
+    // the top branch of every update lands in the same rounded trie and each
+    // update rewrites it. Rounds reuse the same keys, only new ones join the
+    // pool.
+    fn storm_pairs(
+        &mut self,
+        rng: &mut ChaCha20Rng,
+        prefix: H256,
+        count: usize,
+    ) -> Vec<(H256, H256)> {
+        let mut pairs = vec![];
+        for i in 0..count {
+            let mut key: [u8; 32] = prefix.into();
+            key[0] = i as u8;
+            let key = H256::from(key);
+            if !self.keys.contains(&key) {
+                self.keys.push(key);
+            }
+            pairs.push((key, random_h256(rng)));
+        }
+        pairs
+    }
+
+    // Pairs whose keys are the next values of a counter, little endian in
+    // the lowest bytes, which is ascending in the order of the tree: the
+    // keys of a round are neighbours and land in the tries the previous
+    // round wrote last
+    fn sorted_pairs(&mut self, rng: &mut ChaCha20Rng, count: usize) -> Vec<(H256, H256)> {
+        (0..count)
+            .map(|_| {
+                let mut key = [0u8; 32];
+                key[..8].copy_from_slice(&self.next_sorted.to_le_bytes());
+                self.next_sorted += 1;
+                (H256::from(key), random_h256(rng))
+            })
+            .collect()
+    }
+
+    // Generates the pairs of a load round on --init-threads threads. Every
+    // thread jumps its copy of the stream to the start of its shard, so the
+    // pairs, and the root, are the same as with serial generation. The
+    // serial generation is run as well to check that and to time both.
+    fn parallel_pairs(
+        &mut self,
+        rng: &mut ChaCha20Rng,
+        count: usize,
+    ) -> (Vec<(H256, H256)>, RoundReport) {
+        let threads = self.config.init_threads;
+        let preimage_size = self.config.key_preimage_size;
+        let key_words = if preimage_size == 0 {
+            8
+        } else {
+            (preimage_size as u128).div_ceil(4)
+        };
+        let pair_words = key_words + 8;
+        let base = rng.get_word_pos();
+        let shard = count.div_ceil(threads);
+
+        let start = Instant::now();
+        let shards: Vec<(Vec<(H256, H256)>, Duration)> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|i| {
+                    let mut rng = rng.clone();
+                    let first = (i * shard).min(count);
+                    let last = ((i + 1) * shard).min(count);
+                    rng.set_word_pos(base + first as u128 * pair_words);
+                    scope.spawn(move || random_pairs(&mut rng, last - first, preimage_size))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let gen_elapsed = start.elapsed();
+        let mut pairs = Vec::with_capacity(count);
+        let mut hash_elapsed = Duration::default();
+        for (shard_pairs, shard_hash_elapsed) in shards {
+            pairs.extend(shard_pairs);
+            hash_elapsed += shard_hash_elapsed;
+        }
+
+        let start = Instant::now();
+        let (serial_pairs, _) = random_pairs(rng, count, preimage_size);
+        let serial_gen_elapsed = start.elapsed();
+        if pairs != serial_pairs {
+            panic!(
+                "{}: parallel key generation differs from serial",
+                self.round_label
+            );
+        }
+        self.keys.extend(pairs.iter().map(|(key, _)| *key));
+
+        let report = RoundReport {
+            hash_elapsed,
+            gen_elapsed,
+            serial_gen_elapsed,
+            ..Default::default()
+        };
+        (pairs, report)
+    }
+
+    fn load_round(&mut self, rng: &mut ChaCha20Rng, count: usize) -> RoundReport {
+        let pool_size = self.keys.len();
+        let (pairs, gen_report) = if self.config.init_threads > 1
+            && self.storm_prefix.is_none()
+            && self.corpus.is_none()
+            && !self.config.sorted_keys
+        {
+            self.parallel_pairs(rng, count)
+        } else {
+            let start = Instant::now();
+            let (pairs, hash_elapsed) = self.random_pairs(rng, count);
+            let gen_report = RoundReport {
+                hash_elapsed,
+                gen_elapsed: start.elapsed(),
+                ..Default::default()
+            };
+            (pairs, gen_report)
+        };
+        let sample = pairs.clone();
+        let report = self.write_pairs(pairs, sample, true);
+        if report.rolled_back {
+            self.keys.truncate(pool_size + report.ops);
+        }
+        RoundReport {
+            hash_elapsed: gen_report.hash_elapsed,
+            gen_elapsed: gen_report.gen_elapsed,
+            serial_gen_elapsed: gen_report.serial_gen_elapsed,
+            ..report
+        }
+    }
+
+    fn update_round(&mut self, rng: &mut ChaCha20Rng, batch_size: usize) -> RoundReport {
+        let pool_size = self.keys.len();
+        let (pairs, hash_elapsed) = self.random_pairs(rng, batch_size);
+        let report = self.apply_round(pairs);
+        if report.rolled_back {
+            self.keys.truncate(pool_size + report.ops);
+        }
+        RoundReport {
+            hash_elapsed,
+            ..report
+        }
+    }
+
+    fn delete_round(&mut self, rng: &mut ChaCha20Rng, batch_size: usize) -> RoundReport {
+        let mut pairs = vec![];
+        while pairs.len() < batch_size && !self.keys.is_empty() {
+            let index = rng.next_u64() as usize % self.keys.len();
+            pairs.push((self.keys.swap_remove(index), H256::zero()));
+        }
+        let deleted: Vec<H256> = pairs.iter().map(|(key, _)| *key).collect();
+        let report = self.apply_round(pairs);
+        if report.rolled_back {
+            self.keys.extend(&deleted[report.ops..]);
+        }
+        report
+    }
+
+    fn apply_round(&mut self, pairs: Vec<(H256, H256)>) -> RoundReport {
+        let sample = self.sample_pairs(&pairs);
+        self.write_pairs(pairs, sample, false)
+    }
+
+    // Writes the pairs in one transaction, one update call per key for load
+    // rounds, or update_all over --init-chunk keys at a time, and a single
+    // update_all otherwise, and commits. With
+    // --split-commits the round is committed early whenever the transaction
+    // has staged --max-staged-bytes, update_all then being fed SPLIT_CHUNK
+    // keys at a time, and goes on in a new transaction from the committed
+    // root. --smt-lifetime also feeds SPLIT_CHUNK keys at a time, per-chunk
+    // building the tree again from its root after every chunk. A store
+    // error, e.g. injected by --fault-rate, rolls back the current
+    // transaction by dropping it; ops is then the number of pairs committed
+    // before, and the caller puts the rest of its key pool back.
+    fn write_pairs(
+        &mut self,
+        pairs: Vec<(H256, H256)>,
+        sample: Vec<(H256, H256)>,
+        load: bool,
+    ) -> RoundReport {
+        let one_by_one = load && self.config.init_chunk.is_none();
+        let split_at = self
+            .config
+            .max_staged_bytes
+            .filter(|_| self.config.split_commits);
+        let chunk = match split_at {
+            _ if one_by_one => 1,
+            _ if load => self.config.init_chunk.unwrap(),
+            // Policies of --smt-lifetime are compared over the same chunks
+            None if self.config.smt_lifetime.is_none() => pairs.len().max(1),
+            _ => SPLIT_CHUNK,
+        };
+        let rebuild_per_chunk = self.config.smt_lifetime == Some(SmtLifetime::PerChunk);
+        let (conversions, converted_bytes) = conversion_stats();
+        let start = Instant::now();
+        let mut report = RoundReport::default();
+        let mut update_elapsed = Duration::default();
+        let mut root_elapsed = Duration::default();
+        let mut expected = vec![];
+        let mut committed = 0;
+        loop {
+            let tx = self.begin();
+            let limited = self.limited(&tx);
+            let mut smt = BenchSMT::new(self.root, self.faulty_store(&limited));
+            let mut applied = committed;
+            let mut result = Ok(());
+            let update_start = Instant::now();
+            for chunk in pairs[committed..].chunks(chunk) {
+                result = if one_by_one {
+                    chunk
+                        .iter()
+                        .try_for_each(|(key, value)| smt.update(*key, *value).map(|_| ()))
+                } else {
+                    smt.update_all(chunk.to_vec()).map(|_| ())
+                };
+                if result.is_err() {
+                    break;
+                }
+                applied += chunk.len();
+                if split_at.is_some_and(|max| smt.store().inner().inner().staged_bytes() >= max) {
+                    break;
+                }
+                if rebuild_per_chunk && applied < pairs.len() {
+                    let rebuild_start = Instant::now();
+                    let root = *smt.root();
+                    smt = BenchSMT::new(root, smt.take_store());
+                    report.rebuild_elapsed += rebuild_start.elapsed();
+                    report.smt_rebuilds += 1;
+                }
+            }
+            update_elapsed += update_start.elapsed();
+            report.faults += smt.store().faults();
+            if let Err(err) = result {
+                if self.verbose {
+                    println!(
+                        "{}: rolled back after {}, {} of {} keys committed",
+                        self.round_label,
+                        err,
+                        committed,
+                        pairs.len()
+                    );
+                }
+                return RoundReport {
+                    ops: committed,
+                    elapsed: start.elapsed(),
+                    rolled_back: true,
+                    ..report
+                };
+            }
+            let root_start = Instant::now();
+            self.root = *smt.root();
+            root_elapsed += root_start.elapsed();
+            self.leaves.record(&pairs[committed..applied]);
+            let contract = smt.store().inner();
+            let store = contract.inner();
+            report.reads += store.reads();
+            report.writes += store.writes();
+            report.written_bytes += store.written_bytes();
+            report.staged_bytes = report.staged_bytes.max(store.staged_bytes());
+            report.storm_writes += store.watched_writes();
+            report.storm_bytes += store.watched_bytes();
+            report.strict_violations += store.strict_violations();
+            report.leaf_collisions += store.leaf_collisions();
+            if let Some(history) = store.history() {
+                report.history_reads += history.reads;
+                report.history_writes += history.writes;
+                report.history_bytes += history.bytes;
+            }
+            // The last transaction sees the whole round
+            if applied == pairs.len() {
+                expected = self.sample_written(store, &sample);
+            }
+            markers::phase_commit(|| {
+                contract.commit_model();
+                tx.commit().unwrap();
+                if let Some(dump) = &self.dump {
+                    dump.borrow_mut().commit().unwrap();
+                }
+            });
+            report.commits += 1;
+            if let Some(replica) = &mut self.replica {
+                let (wait, queued) =
+                    replica.send(self.rounds_run, pairs[committed..applied].to_vec());
+                report.replica_wait += wait;
+                report.replica_queue = Some(queued);
+            }
+            committed = applied;
+            if committed == pairs.len() {
+                break;
+            }
+        }
+        let elapsed = start.elapsed();
+        let (update_elapsed, root_elapsed) = self.root_timing(update_elapsed, root_elapsed);
+        let (end_conversions, end_converted_bytes) = conversion_stats();
+        report.conversions = end_conversions - conversions;
+        report.converted_bytes = end_converted_bytes - converted_bytes;
+        if let Some(max) = self.config.max_staged_bytes {
+            if report.staged_bytes > max && !self.config.split_commits && !self.config.tui {
+                eprintln!(
+                    "Warning: {} staged {} bytes in one transaction, over --max-staged-bytes {}",
+                    self.round_label, report.staged_bytes, max
+                );
+            }
+        }
+
+        RoundReport {
+            ops: pairs.len(),
+            elapsed,
+            verify_elapsed: markers::phase_verify(|| self.verify_commit(&expected)),
+            update_elapsed,
+            root_elapsed,
+            ..report
+        }
+    }
+
+    // Picks the pairs whose persistence is verified after commit
+    fn sample_pairs(&self, pairs: &[(H256, H256)]) -> Vec<(H256, H256)> {
+        let count = self.config.verify_commit.min(pairs.len());
+        if count == 0 {
+            return vec![];
+        }
+        let mut rng = self.round_seeds.as_ref().unwrap().child("verify").rng();
+        (0..count)
+            .map(|_| pairs[rng.next_u64() as usize % pairs.len()])
+            .collect()
+    }
+
+    // Records what the round's transaction holds for each sampled leaf and
+    // the lowest branch on its path, to compare against after commit.
+    fn sample_written<S: Store<H256>>(
+        &self,
+        store: &S,
+        pairs: &[(H256, H256)],
+    ) -> Vec<(H256, Option<H256>, Option<BranchNode>)> {
+        if self.config.verify_commit == 0 {
+            return vec![];
+        }
+        let sample = if pairs.len() > self.config.verify_commit {
+            self.sample_pairs(pairs)
+        } else {
+            pairs.to_vec()
+        };
+        sample
+            .into_iter()
+            .map(|(key, value)| {
+                let leaf = if value.is_zero() { None } else { Some(value) };
+                let branch = store
+                    .get_branch(&BranchKey::new(0, key.parent_path(0)))
+                    .unwrap();
+                (key, leaf, branch)
+            })
+            .collect()
+    }
+
+    // Re-reads sampled leaves and branches through a fresh transaction after
+    // the round committed, panicking on any mismatch.
+    fn verify_commit(&self, expected: &[(H256, Option<H256>, Option<BranchNode>)]) -> Duration {
+        debug_assert_eq!(markers::current(), Marker::Verify);
+        if expected.is_empty() {
+            return Duration::default();
+        }
+        // Decoding for verification is not part of the round
+        let serde = serde_nanos();
+        let start = Instant::now();
+        let tx = self.begin();
+        let tx = self.plain(&tx);
+        let store = self.trie_store(&tx);
+        for (key, leaf, branch) in expected {
+            let actual_leaf = store.get_leaf(key).unwrap();
+            if &actual_leaf != leaf {
+                self.monitor.raise(
+                    violations::COMMIT_VERIFY,
+                    Some(hex(key)),
+                    format!(
+                        "Commit verification failed in {}: leaf {:?} read back {:?}, expected {:?}",
+                        self.round_label, key, actual_leaf, leaf
+                    ),
+                );
+            }
+            let branch_key = BranchKey::new(0, key.parent_path(0));
+            let actual_branch = store.get_branch(&branch_key).unwrap();
+            if &actual_branch != branch {
+                self.monitor.raise(
+                    violations::COMMIT_VERIFY,
+                    Some(hex(key)),
+                    format!(
+                        "Commit verification failed in {}: branch {:?} of leaf {:?} read back {:?}, expected {:?}",
+                        self.round_label, branch_key, key, actual_branch, branch
+                    ),
+                );
+            }
+        }
+        let elapsed = start.elapsed();
+        rewind_serde_nanos(serde);
+        elapsed
+    }
+
+    fn read_round(&mut self, rng: &mut ChaCha20Rng, batch_size: usize) -> RoundReport {
+        let mut hash_elapsed = Duration::default();
+        let mut failed_ops = 0;
+        let start = Instant::now();
+        let tx = self.begin();
+        let limited = self.limited(&tx);
+        let store = self.faulty_store(&limited);
+        for _ in 0..batch_size {
+            // Nothing was written in this run, e.g. when it starts with a read
+            // phase, so probe random keys instead
+            let key = if self.keys.is_empty() {
+                self.random_key(rng, &mut hash_elapsed)
+            } else {
+                self.keys[rng.next_u64() as usize % self.keys.len()]
+            };
+            if store.get_leaf(&key).is_err() {
+                failed_ops += 1;
+            }
+        }
+
+        RoundReport {
+            ops: batch_size,
+            reads: store.inner().inner().reads(),
+            writes: store.inner().inner().writes(),
+            elapsed: start.elapsed(),
+            hash_elapsed,
+            faults: store.faults(),
+            failed_ops,
+            ..Default::default()
+        }
+    }
+
+    fn prove_same_round(&mut self, rng: &mut ChaCha20Rng, batch_size: usize) -> RoundReport {
+        let mut hash_elapsed = Duration::default();
+        let key = match self.proof_key {
+            Some(key) => key,
+            None => {
+                let key = if self.keys.is_empty() {
+                    self.random_key(rng, &mut hash_elapsed)
+                } else {
+                    self.keys[rng.next_u64() as usize % self.keys.len()]
+                };
+                self.proof_key = Some(key);
+                key
+            }
+        };
+        let mut failed_ops = 0;
+        let start = Instant::now();
+        let tx = self.begin();
+        let limited = self.limited(&tx);
+        let tree = BenchSMT::new(self.root, self.faulty_store(&limited));
+        for _ in 0..batch_size {
+            if tree.merkle_proof(vec![key]).is_err() {
+                failed_ops += 1;
+            }
+        }
+
+        RoundReport {
+            ops: batch_size,
+            reads: tree.store().inner().inner().reads(),
+            writes: tree.store().inner().inner().writes(),
+            elapsed: start.elapsed(),
+            hash_elapsed,
+            faults: tree.store().faults(),
+            failed_ops,
+            ..Default::default()
+        }
+    }
+
+    // Models a prover keeping proofs across small state changes: proves
+    // batch_size keys, updates for each of them the key differing only in
+    // the lowest byte, which shares all but the bottom trie of its path, and
+    // proves the same keys again against the new root.
+    fn prove_update_round(&mut self, rng: &mut ChaCha20Rng, batch_size: usize) -> RoundReport {
+        let mut hash_elapsed = Duration::default();
+        let keys: Vec<H256> = (0..batch_size)
+            .map(|_| {
+                if self.keys.is_empty() {
+                    self.random_key(rng, &mut hash_elapsed)
+                } else {
+                    self.keys[rng.next_u64() as usize % self.keys.len()]
+                }
+            })
+            .collect();
+        let start = Instant::now();
+        let (proof_reads, proof_failures) = self.prove_keys(&keys);
+
+        let pool_size = self.keys.len();
+        let pairs: Vec<(H256, H256)> = keys
+            .iter()
+            .map(|key| {
+                let mut nearby: [u8; 32] = (*key).into();
+                nearby[0] ^= (rng.next_u32() as u8).max(1);
+                (H256::from(nearby), random_h256(rng))
+            })
+            .collect();
+        self.keys.extend(pairs.iter().map(|(key, _)| *key));
+        let report = self.apply_round(pairs);
+        if report.rolled_back {
+            self.keys.truncate(pool_size + report.ops);
+            return RoundReport {
+                hash_elapsed,
+                elapsed: start.elapsed(),
+                ..report
+            };
+        }
+
+        let (reproof_reads, reproof_failures) = self.prove_keys(&keys);
+        RoundReport {
+            elapsed: start.elapsed(),
+            hash_elapsed,
+            failed_ops: proof_failures + reproof_failures,
+            proof_reads,
+            reproof_reads,
+            ..report
+        }
+    }
+
+    // Models the proofs of Godwoken challenges, which cover existing and
+    // absent keys in one CompiledMerkleProof: for every --proof-mix share,
+    // proves batch_size keys of which that share is absent and verifies the
+    // proof against the root with the zero values of the absent keys.
+    fn prove_mix_round(&mut self, rng: &mut ChaCha20Rng, batch_size: usize) -> RoundReport {
+        if self.keys.is_empty() {
+            panic!(
+                "{}: prove-mix needs keys written earlier in the run",
+                self.round_label
+            );
+        }
+        let mut hash_elapsed = Duration::default();
+        let mut failed_ops = 0;
+        let mut proof_sizes = vec![];
+        let start = Instant::now();
+        let tx = self.begin();
+        let limited = self.limited(&tx);
+        let tree = BenchSMT::new(self.root, self.faulty_store(&limited));
+        for mix in &self.config.proof_mix {
+            let absent = (batch_size as f64 * mix).round() as usize;
+            let mut leaves = vec![];
+            for i in 0..batch_size {
+                let key = if i < absent {
+                    self.random_key(rng, &mut hash_elapsed)
+                } else {
+                    self.keys[rng.next_u64() as usize % self.keys.len()]
+                };
+                match tree.get(&key) {
+                    Ok(value) => leaves.push((key, value)),
+                    Err(_) => failed_ops += 1,
+                }
+            }
+            leaves.sort_unstable_by_key(|(key, _)| *key);
+            leaves.dedup_by_key(|(key, _)| *key);
+            if leaves.is_empty() {
+                continue;
+            }
+            match prove_leaves(&tree, &leaves) {
+                Ok(Some(size)) => proof_sizes.push((*mix, size)),
+                Ok(None) => {
+                    // Proving the classes on their own tells which store path
+                    // broke: absent keys end in empty subtrees, existing ones
+                    // in stored leaves
+                    let broken: Vec<&str> = [("existing", false), ("absent", true)]
+                        .iter()
+                        .filter(|(_, zero)| {
+                            let class: Vec<(H256, H256)> = leaves
+                                .iter()
+                                .filter(|(_, value)| value.is_zero() == *zero)
+                                .cloned()
+                                .collect();
+                            !class.is_empty() && prove_leaves(&tree, &class) == Ok(None)
+                        })
+                        .map(|(class, _)| *class)
+                        .collect();
+                    self.monitor.raise(
+                        violations::PROOF,
+                        None,
+                        format!(
+                            "{}: proof of {} keys with share {} absent failed to verify against root {:?}, broken class: {}",
+                            self.round_label,
+                            leaves.len(),
+                            mix,
+                            self.root,
+                            if broken.is_empty() {
+                                "neither on its own, only the combination".to_string()
+                            } else {
+                                broken.join(" and ")
+                            }
+                        ),
+                    );
+                }
+                Err(_) => failed_ops += 1,
+            }
+        }
+
+        RoundReport {
+            ops: batch_size * self.config.proof_mix.len(),
+            reads: tree.store().inner().inner().reads(),
+            writes: tree.store().inner().inner().writes(),
+            elapsed: start.elapsed(),
+            hash_elapsed,
+            faults: tree.store().faults(),
+            failed_ops,
+            proof_sizes,
+            ..Default::default()
+        }
+    }
+
+    // Models a client asking for proofs against a root it saw earlier:
+    // proves batch_size keys on the current root and on a root picked among
+    // those earlier rounds committed, and verifies every proof against the
+    // root it was generated on. The trie store rewrites the tries of a path
+    // in place, so proofs on a past root read today's branches.
+    fn prove_history_round(&mut self, rng: &mut ChaCha20Rng, batch_size: usize) -> RoundReport {
+        let past: Vec<H256> = self
+            .past_roots
+            .iter()
+            .filter(|root| **root != self.root)
+            .cloned()
+            .collect();
+        if self.keys.is_empty() || past.is_empty() {
+            panic!(
+                "{}: prove-history needs keys and a root committed by an earlier round",
+                self.round_label
+            );
+        }
+        let past_root = past[rng.next_u64() as usize % past.len()];
+        let keys: Vec<H256> = (0..batch_size)
+            .map(|_| self.keys[rng.next_u64() as usize % self.keys.len()])
+            .collect();
+        let start = Instant::now();
+        let (proof_reads, failed, stale) = self.prove_at(self.root, &keys);
+        if stale > 0 {
+            self.monitor.raise(
+                violations::PROOF,
+                None,
+                format!(
+                    "{}: {} proofs on the current root {:?} failed to verify against it",
+                    self.round_label, stale, self.root
+                ),
+            );
+        }
+        let (reproof_reads, past_failed, stale_proofs) = self.prove_at(past_root, &keys);
+
+        RoundReport {
+            ops: batch_size,
+            reads: proof_reads + reproof_reads,
+            elapsed: start.elapsed(),
+            failed_ops: failed + past_failed,
+            proof_reads,
+            reproof_reads,
+            stale_proofs,
+            ..Default::default()
+        }
+    }
+
+    // Generates a compiled proof of every key on root in a fresh transaction
+    // and verifies it against root with the value the key holds now.
+    // Returns the store reads it took, the failed proofs and the proofs that
+    // did not verify.
+    fn prove_at(&self, root: H256, keys: &[H256]) -> (usize, usize, usize) {
+        let tx = self.begin();
+        let limited = self.limited(&tx);
+        let tree = BenchSMT::new(root, self.faulty_store(&limited));
+        let (mut failed, mut stale) = (0, 0);
+        for key in keys {
+            let leaf = match tree.get(key) {
+                Ok(value) => (*key, value),
+                Err(_) => {
+                    failed += 1;
+                    continue;
+                }
+            };
+            match prove_leaves(&tree, &[leaf]) {
+                Ok(Some(_)) => {}
+                Ok(None) => stale += 1,
+                Err(_) => failed += 1,
+            }
+        }
+        (tree.store().inner().inner().reads(), failed, stale)
+    }
+
+    // Generates a proof of every key on the current root in a fresh
+    // transaction, returning the store reads it took and the failed proofs
+    fn prove_keys(&self, keys: &[H256]) -> (usize, usize) {
+        let tx = self.begin();
+        let limited = self.limited(&tx);
+        let tree = BenchSMT::new(self.root, self.faulty_store(&limited));
+        let failed = keys
+            .iter()
+            .filter(|key| tree.merkle_proof(vec![**key]).is_err())
+            .count();
+        (tree.store().inner().inner().reads(), failed)
+    }
+}
+
+// Builds and compiles one proof of the sorted leaves and verifies it against
+// the root of tree. Returns the compiled bytes, None when verification
+// failed, or an error when the store could not produce the proof.
+fn prove_leaves(tree: &BenchSMT, leaves: &[(H256, H256)]) -> Result<Option<usize>, String> {
+    let keys: Vec<H256> = leaves.iter().map(|(key, _)| *key).collect();
+    let compiled = tree
+        .merkle_proof(keys.clone())
+        .and_then(|proof| proof.compile(keys))
+        .map_err(|err| err.to_string())?;
+    match compiled.verify::<Blake2bHasher>(tree.root(), leaves.to_vec()) {
+        Ok(true) => Ok(Some(compiled.0.len())),
+        _ => Ok(None),
+    }
+}
+
+fn random_key(rng: &mut ChaCha20Rng, preimage_size: usize, hash_elapsed: &mut Duration) -> H256 {
+    if preimage_size == 0 {
+        return random_h256(rng);
+    }
+    let mut preimage = vec![0u8; preimage_size];
+    rng.fill_bytes(&mut preimage);
+    let start = Instant::now();
+    let mut key = [0u8; 32];
+    let mut hasher = new_blake2b();
+    hasher.update(&preimage);
+    hasher.finalize(&mut key);
+    *hash_elapsed += start.elapsed();
+    key.into()
+}
+
+pub(crate) fn random_pairs(
+    rng: &mut ChaCha20Rng,
+    count: usize,
+    preimage_size: usize,
+) -> (Vec<(H256, H256)>, Duration) {
+    let mut hash_elapsed = Duration::default();
+    let mut pairs = vec![];
+    for _ in 0..count {
+        let key = random_key(rng, preimage_size, &mut hash_elapsed);
+        let value = random_h256(rng);
+        pairs.push((key, value));
+    }
+    (pairs, hash_elapsed)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::analysis;
+    use std::sync::{Mutex, MutexGuard};
+
+    // Runs move the process-wide phase marker and register their
+    // transactions while --debug-handles is on, so tests running one or
+    // reading those take turns
+    pub(crate) fn serial() -> MutexGuard<'static, ()> {
+        static SERIAL: Mutex<()> = Mutex::new(());
+        SERIAL.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    // Committing a round in several transactions must end at the same root as
+    // committing it at once, for both the per-key load path and update_all.
+    #[test]
+    fn split_commits() -> Result<(), String> {
+        let _serial = serial();
+        let phases = [
+            Phase::new(PhaseKind::Load, 2, 300),
+            Phase::new(PhaseKind::Update, 2, 300),
+            Phase::new(PhaseKind::Delete, 1, 200),
+        ];
+        let mut roots = vec![];
+        for split_commits in [false, true] {
+            let config = Config {
+                report_every: 0,
+                max_staged_bytes: Some(16 << 10).filter(|_| split_commits),
+                split_commits,
+                ..Default::default()
+            };
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let mut runner = Runner::new(&config, &store, SeedTree::new(0, false));
+            let mut commits = 0;
+            for phase in &phases {
+                let report = runner.run_phase(phase);
+                commits += report.rounds.iter().map(|r| r.commits).sum::<usize>();
+            }
+            let rounds: usize = phases.iter().map(|p| p.rounds).sum();
+            if split_commits && commits <= rounds {
+                return Err(format!("{} rounds were never split", rounds));
+            }
+            roots.push(runner.root());
+        }
+        if roots[0] != roots[1] {
+            return Err(format!(
+                "split root {:?} differs from {:?}",
+                roots[1], roots[0]
+            ));
+        }
+        Ok(())
+    }
+
+    // Building the tree again after every chunk of a round, once per round or
+    // without --smt-lifetime must end with the same root
+    #[test]
+    fn smt_lifetimes() -> Result<(), String> {
+        let _serial = serial();
+        let phases = [
+            Phase::new(PhaseKind::Load, 1, 100),
+            Phase::new(PhaseKind::Update, 2, 150),
+            Phase::new(PhaseKind::Delete, 1, 100),
+        ];
+        let mut roots = vec![];
+        for lifetime in [
+            None,
+            Some(SmtLifetime::PerRound),
+            Some(SmtLifetime::PerChunk),
+        ] {
+            let config = Config {
+                report_every: 0,
+                smt_lifetime: lifetime,
+                ..Default::default()
+            };
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let mut runner = Runner::new(&config, &store, SeedTree::new(0, false));
+            let mut rebuilds = 0;
+            for phase in &phases {
+                let report = runner.run_phase(phase);
+                rebuilds += report.rounds.iter().map(|r| r.smt_rebuilds).sum::<usize>();
+            }
+            if (lifetime == Some(SmtLifetime::PerChunk)) != (rebuilds > 0) {
+                return Err(format!("{} rebuilds with {:?}", rebuilds, lifetime));
+            }
+            roots.push((lifetime, runner.root()));
+        }
+        if roots.iter().any(|(_, root)| *root != roots[0].1) {
+            return Err(format!("roots differ: {:?}", roots));
+        }
+        Ok(())
+    }
+
+    // Load rounds applied with one update per key and with update_all over
+    // --init-chunk keys at a time must end with the same root, every load
+    // round flagged as init and no other round.
+    #[test]
+    fn init_chunks() -> Result<(), String> {
+        let _serial = serial();
+        let phases = [
+            Phase::new(PhaseKind::Load, 2, 100),
+            Phase::new(PhaseKind::Update, 2, 50),
+        ];
+        let mut roots = vec![];
+        for init_chunk in [None, Some(1), Some(30), Some(1_000)] {
+            let config = Config {
+                report_every: 0,
+                init_chunk,
+                ..Default::default()
+            };
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let mut runner = Runner::new(&config, &store, SeedTree::new(0, false));
+            let reports: Vec<PhaseReport> = phases.iter().map(|p| runner.run_phase(p)).collect();
+            for report in &reports {
+                let load = report.phase.kind == PhaseKind::Load;
+                if report.rounds.iter().any(|r| r.init != load) {
+                    return Err(format!(
+                        "{} rounds flagged init wrongly with --init-chunk {:?}",
+                        report.phase, init_chunk
+                    ));
+                }
+            }
+            let cost = analysis::init_cost(&reports).ok_or("no init cost")?;
+            if cost.rounds != 2 || cost.keys != 200 {
+                return Err(format!(
+                    "init cost of {} rounds and {} keys, expected 2 and 200",
+                    cost.rounds, cost.keys
+                ));
+            }
+            roots.push((init_chunk, runner.root()));
+        }
+        if roots.iter().any(|(_, root)| *root != roots[0].1) {
+            return Err(format!("roots differ: {:?}", roots));
+        }
+        Ok(())
+    }
+
+    // Reading through a probe between two phases must leave the keys of the
+    // later phases, and so the final root, as they are without it
+    #[test]
+    fn probes_keep_phase_seeds() -> Result<(), String> {
+        let _serial = serial();
+        let config = Config {
+            report_every: 0,
+            ..Default::default()
+        };
+        let mut roots = vec![];
+        for probe in [false, true] {
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let mut runner = Runner::new(&config, &store, SeedTree::new(0, false));
+            runner.run_phase(&Phase::new(PhaseKind::Load, 1, 100));
+            runner.run_phase(&Phase::new(PhaseKind::Delete, 1, 50));
+            if probe {
+                let read = Phase::new(PhaseKind::Read, 1, 20);
+                runner.run_probe(&read, "probe");
+                runner.run_probe(&read, "probe");
+            }
+            runner.run_phase(&Phase::new(PhaseKind::Update, 2, 50));
+            roots.push(runner.root());
+        }
+        if roots[0] != roots[1] {
+            return Err(format!(
+                "root {:?} after a probe, {:?} without",
+                roots[1], roots[0]
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{
+    churn,
+    config::{Config, OutputFormat},
+    markdown,
+    phase::{Phase, PhaseKind},
+    runner::Runner,
+    seed::SeedTree,
+};
+use gw_db::{
+    iter::{Direction, IteratorMode},
+    schema::Col,
+};
+use gw_store::{traits::KVStore, Store as GwStore};
+use rand_chacha::rand_core::RngCore;
+use std::time::{Duration, Instant};
+
+// Keys inserted per update_all while growing the tree to a scan size
+const GROW_BATCH: usize = 50_000;
+// Random ranges scanned at every size, their results summed
+const RANGE_SCANS: usize = 10;
+
+// What one pass of ordered iteration read
+#[derive(Default)]
+pub(crate) struct Scan {
+    pub(crate) entries: usize,
+    bytes: usize,
+    elapsed: Duration,
+}
+
+impl Scan {
+    fn add(&mut self, other: Scan) {
+        self.entries += other.entries;
+        self.bytes += other.bytes;
+        self.elapsed += other.elapsed;
+    }
+
+    fn entries_per_sec(&self) -> f64 {
+        self.entries as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    fn mib_per_sec(&self) -> f64 {
+        self.bytes as f64 / (1 << 20) as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+// The first 8 bytes of a key as a big endian number, shorter keys padded
+// with zeros, which orders keys like RocksDB compares them
+pub(crate) fn prefix(key: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    let n = key.len().min(8);
+    bytes[..n].copy_from_slice(&key[..n]);
+    u64::from_be_bytes(bytes)
+}
+
+// Iterates col in key order from the first key at or after from, or from
+// the start, up to the first key whose prefix reaches end. The iterator
+// yields values either way; with values the bytes count them and every
+// value is read, without only the keys are.
+pub(crate) fn scan(
+    store: &impl KVStore,
+    col: Col,
+    from: Option<u64>,
+    end: Option<u64>,
+    values: bool,
+) -> Scan {
+    let start_key = from.map(|from| from.to_be_bytes());
+    let mode = match &start_key {
+        Some(key) => IteratorMode::From(key, Direction::Forward),
+        None => IteratorMode::Start,
+    };
+    let mut result = Scan::default();
+    let mut checksum = 0u8;
+    let start = Instant::now();
+    for (key, value) in store
+        .get_iter(col, mode)
+        .skip_while(|(key, _)| from.is_some_and(|from| prefix(key) < from))
+        .take_while(|(key, _)| end.is_none_or(|end| prefix(key) < end))
+    {
+        result.entries += 1;
+        result.bytes += key.len();
+        checksum ^= key.last().copied().unwrap_or(0);
+        if values {
+            result.bytes += value.len();
+            checksum ^= value.iter().fold(0, |acc, byte| acc ^ byte);
+        }
+    }
+    result.elapsed = start.elapsed();
+    std::hint::black_box(checksum);
+    result
+}
+
+// The scans timed at one tree size
+struct Row {
+    leaves: usize,
+    keys: Scan,
+    keys_values: Scan,
+    range: Scan,
+}
+
+// Grows an empty tree to every --scan-sizes size in batches of GROW_BATCH
+// keys, and at each times a full ordered iteration of the leaf column,
+// keys only and keys and values, then RANGE_SCANS iterations of random
+// contiguous ranges of --scan-range-width of the key space.
+pub fn run(config: &Config, store: &GwStore) {
+    churn::require_empty(config, store, "--workload scan");
+    let config = Config {
+        report_every: 0,
+        ..config.clone()
+    };
+    let width = (config.scan_range_width * u64::MAX as f64) as u64;
+    println!(
+        "Scan: leaf column {} in key order at {} tree sizes, ranges of {:.4}% of the key space",
+        config.leaf_col,
+        config.scan_sizes.len(),
+        config.scan_range_width * 100.0
+    );
+    let seeds = SeedTree::new(config.seed, config.verbose);
+    let mut rng = seeds.child("scan").rng();
+    let mut runner = Runner::new(&config, store, seeds);
+    let mut leaves = 0;
+    let mut rows = vec![];
+    for &size in &config.scan_sizes {
+        while leaves < size {
+            let batch = GROW_BATCH.min(size - leaves);
+            let report = runner.run_phase(&Phase::new(PhaseKind::Update, 1, batch));
+            leaves += report
+                .rounds
+                .iter()
+                .filter(|r| !r.rolled_back)
+                .map(|r| r.ops)
+                .sum::<usize>();
+        }
+        let tx = store.begin_transaction();
+        let keys = scan(&tx, config.leaf_col, None, None, false);
+        let keys_values = scan(&tx, config.leaf_col, None, None, true);
+        if keys.entries != leaves || keys_values.entries != leaves {
+            panic!(
+                "Scanned {} and {} leaves of a tree of {}",
+                keys.entries, keys_values.entries, leaves
+            );
+        }
+        let mut range = Scan::default();
+        for _ in 0..RANGE_SCANS {
+            let from = rng.next_u64() % (u64::MAX - width).max(1);
+            range.add(scan(
+                &tx,
+                config.leaf_col,
+                Some(from),
+                Some(from + width),
+                true,
+            ));
+        }
+        drop(tx);
+        println!(
+            "  {} leaves: keys {:.0} entries/s, keys and values {:.0} entries/s, ranges {:.1} entries per scan",
+            leaves,
+            keys.entries_per_sec(),
+            keys_values.entries_per_sec(),
+            range.entries as f64 / RANGE_SCANS as f64
+        );
+        rows.push(Row {
+            leaves,
+            keys,
+            keys_values,
+            range,
+        });
+    }
+    runner.finish();
+
+    let header: Vec<String> = [
+        "Leaves",
+        "Keys/s",
+        "Keys MiB/s",
+        "K+V/s",
+        "K+V MiB/s",
+        "Range entries",
+        "Range/s",
+        "Range MiB/s",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let rows: Vec<Vec<String>> = rows
+        .iter()
+        .map(|r| {
+            vec![
+                r.leaves.to_string(),
+                format!("{:.0}", r.keys.entries_per_sec()),
+                format!("{:.1}", r.keys.mib_per_sec()),
+                format!("{:.0}", r.keys_values.entries_per_sec()),
+                format!("{:.1}", r.keys_values.mib_per_sec()),
+                format!("{:.1}", r.range.entries as f64 / RANGE_SCANS as f64),
+                format!("{:.0}", r.range.entries_per_sec()),
+                format!("{:.1}", r.range.mib_per_sec()),
+            ]
+        })
+        .collect();
+    println!();
+    if config.output == OutputFormat::Markdown {
+        markdown::print_preamble(&config);
+        markdown::print_table(&header, &rows);
+        return;
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        println!(
+            "{}",
+            row.iter().map(|c| format!("{:>15}", c)).collect::<String>()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::tests::serial;
+
+    // Ordered iteration of the leaf column must see every leaf of the tree,
+    // and a range scan exactly the leaves whose keys fall in the range.
+    #[test]
+    fn leaf_scan() -> Result<(), String> {
+        let _serial = serial();
+        let config = Config {
+            report_every: 0,
+            ..Default::default()
+        };
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let mut runner = Runner::new(&config, &store, SeedTree::new(0, false));
+        runner.run_phase(&Phase::new(PhaseKind::Update, 2, 60));
+        let prefixes: Vec<u64> = runner
+            .leaves()
+            .iter()
+            .map(|(key, _)| prefix(key.as_slice()))
+            .collect();
+        let tx = store.begin_transaction();
+        for values in [false, true] {
+            let entries = scan(&tx, config.leaf_col, None, None, values).entries;
+            if entries != prefixes.len() {
+                return Err(format!(
+                    "full scan saw {} of {} leaves",
+                    entries,
+                    prefixes.len()
+                ));
+            }
+        }
+        for (from, end) in [(0, u64::MAX / 4), (u64::MAX / 3, u64::MAX / 2), (7, 8)] {
+            let expected = prefixes.iter().filter(|p| (from..end).contains(*p)).count();
+            let entries = scan(&tx, config.leaf_col, Some(from), Some(end), true).entries;
+            if entries != expected {
+                return Err(format!(
+                    "range {:x}..{:x} scanned {} leaves, it holds {}",
+                    from, end, entries, expected
+                ));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+use std::str::FromStr;
+
+// The options --at may change while the bench runs. Everything else decides
+// how the stored tree or the key stream looks and stays fixed for the run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Setting {
+    // Batch size of every later round, replacing the phases' own
+    BatchSize(usize),
+    Verbose(bool),
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Setting::BatchSize(size) => write!(f, "batch_size:{}", size),
+            Setting::Verbose(verbose) => write!(f, "verbose:{}", verbose),
+        }
+    }
+}
+
+// A setting applied before the given round, rounds counted from 1 across
+// all phases
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScheduledChange {
+    pub round: u64,
+    pub setting: Setting,
+}
+
+// Parses "round=R,set=<option>:<value>"
+impl FromStr for ScheduledChange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut round = None;
+        let mut set = None;
+        for part in s.split(',') {
+            match part.trim().split_once('=') {
+                Some(("round", value)) => {
+                    round = Some(
+                        value
+                            .parse::<u64>()
+                            .map_err(|err| format!("Invalid round in {}: {}", s, err))?,
+                    )
+                }
+                Some(("set", value)) => set = Some(value),
+                _ => return Err(format!("Unknown part {} of --at {}", part, s)),
+            }
+        }
+        let round = round
+            .filter(|round| *round > 0)
+            .ok_or_else(|| format!("--at {} needs round=R with R from 1", s))?;
+        let (name, value) = set
+            .and_then(|set| set.split_once(':'))
+            .ok_or_else(|| format!("--at {} needs set=<option>:<value>", s))?;
+        let setting = match name {
+            "batch_size" => Setting::BatchSize(
+                value
+                    .parse()
+                    .ok()
+                    .filter(|size| *size > 0)
+                    .ok_or_else(|| format!("Invalid batch size in --at {}", s))?,
+            ),
+            "verbose" => Setting::Verbose(
+                value
+                    .parse()
+                    .map_err(|_| format!("Invalid verbose value in --at {}", s))?,
+            ),
+            _ => {
+                return Err(format!(
+                    "--at can change batch_size or verbose, not {}",
+                    name
+                ))
+            }
+        };
+        Ok(ScheduledChange { round, setting })
+    }
+}
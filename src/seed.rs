@@ -0,0 +1,129 @@
+use gw_common::blake2b::new_blake2b;
+use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+// With --audit-seeds, every stream label derived during this process, in
+// derivation order, and how often each path was derived. Nothing is kept
+// otherwise, as a run derives streams for every round.
+static AUDIT: AtomicBool = AtomicBool::new(false);
+static DERIVED_LABELS: Mutex<Vec<(String, [u8; 32])>> = Mutex::new(Vec::new());
+static DERIVED_PATHS: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+pub fn enable_audit() {
+    AUDIT.store(true, Ordering::SeqCst);
+}
+
+// A tree of labeled random streams. Each child seed is derived as
+// blake2b(parent_seed || label), so a stream only depends on its position in
+// the tree, never on how much randomness sibling streams consumed.
+#[derive(Clone)]
+pub struct SeedTree {
+    seed: [u8; 32],
+    path: String,
+    verbose: bool,
+}
+
+impl SeedTree {
+    pub fn new(seed: u64, verbose: bool) -> Self {
+        let mut root = [0u8; 32];
+        let mut hasher = new_blake2b();
+        hasher.update(&seed.to_le_bytes());
+        hasher.finalize(&mut root);
+        Self {
+            seed: root,
+            path: "root".to_string(),
+            verbose,
+        }
+    }
+
+    pub fn child(&self, label: &str) -> Self {
+        let mut seed = [0u8; 32];
+        let mut hasher = new_blake2b();
+        hasher.update(&self.seed);
+        hasher.update(label.as_bytes());
+        hasher.finalize(&mut seed);
+        let path = format!("{}/{}", self.path, label);
+
+        if self.verbose {
+            println!("Derived seed stream {}", path);
+        }
+        if AUDIT.load(Ordering::SeqCst) {
+            DERIVED_LABELS.lock().unwrap().push((path.clone(), seed));
+            *DERIVED_PATHS
+                .lock()
+                .unwrap()
+                .entry(path.clone())
+                .or_default() += 1;
+        }
+        Self {
+            seed,
+            path,
+            verbose: self.verbose,
+        }
+    }
+
+    pub fn rng(&self) -> ChaCha20Rng {
+        ChaCha20Rng::from_seed(self.seed)
+    }
+}
+
+// Streams derived more than once under the same parent with the same
+// label, which hand identical randomness to whoever derived them
+pub fn reused_paths() -> Vec<(String, usize)> {
+    DERIVED_PATHS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, n)| **n > 1)
+        .map(|(path, n)| (path.clone(), *n))
+        .collect()
+}
+
+// Prints every derived stream with its seed, so the stream structure of two
+// runs can be compared with a plain diff, then the streams derived twice.
+pub fn print_audit() {
+    println!("Seed streams:");
+    for (path, seed) in DERIVED_LABELS.lock().unwrap().iter() {
+        let hex: String = seed.iter().map(|b| format!("{:02x}", b)).collect();
+        println!("  {} {}", path, hex);
+    }
+    for (path, n) in reused_paths() {
+        eprintln!("Warning: seed stream {} derived {} times", path, n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_chacha::rand_core::RngCore;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn derived_values_are_pinned() {
+        let x = SeedTree::new(0, false).child("x");
+        assert_eq!(
+            hex(&x.seed),
+            "e9ed0f7da2e5b15e042c14a9ee701600742652c238e009bed03d28dee9bdf3cd"
+        );
+        let mut bytes = [0u8; 16];
+        x.rng().fill_bytes(&mut bytes);
+        assert_eq!(hex(&bytes), "29fcfca57cef0872c8b09c613c3ed39e");
+    }
+
+    #[test]
+    fn reused_labels_are_reported() {
+        enable_audit();
+        let root = SeedTree::new(1, false);
+        root.child("reuse-once");
+        root.child("reuse-twice");
+        root.clone().child("reuse-twice");
+        let reused = reused_paths();
+        assert!(!reused.iter().any(|(path, _)| path == "root/reuse-once"));
+        assert!(reused.contains(&("root/reuse-twice".to_string(), 2)));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{
+    config::Config, path::parse_h256, random_h256, seed::SeedTree, trie::TrieStore, TrieSmt,
+};
+use gw_store::{transaction::StoreTransaction, Store as GwStore};
+use sparse_merkle_tree::{
+    blake2b::Blake2bHasher, default_store::DefaultStore, SparseMerkleTree, H256,
+};
+use std::time::{Duration, Instant};
+
+type ReferenceSMT = SparseMerkleTree<Blake2bHasher, H256, DefaultStore<H256>>;
+
+const PAIRS: usize = 64;
+
+// Roots of the self-test pairs under the blake2b hasher, after inserting
+// them and after removing every other one. Pinned, so a regression of the
+// hasher or of sparse_merkle_tree fails the self-test as well.
+const INSERT_ROOT: &str = "d47fb55fbfdefd1cfc8114eb05b4df475ae26d6de345e8fdede8fac878480817";
+const REMOVED_ROOT: &str = "629055a54ac9d8219c6b893d307f671b66adc257c178d3ce5cfd422eefc6b951";
+
+// Runs the configured TrieStore end to end in a temp database before the
+// bench starts: inserts a fixed set of pairs, reads them back through a new
+// transaction, removes half of them and reads again. Roots are compared
+// against the pinned ones, then against the smt crate's in-memory
+// DefaultStore fed the same pairs.
+pub fn run(config: &Config) -> Result<Duration, String> {
+    let start = Instant::now();
+    let mut rng = SeedTree::new(0, false).child("self-test").rng();
+    let pairs: Vec<(H256, H256)> = (0..PAIRS)
+        .map(|_| (random_h256(&mut rng), random_h256(&mut rng)))
+        .collect();
+    let removed: Vec<(H256, H256)> = pairs
+        .iter()
+        .step_by(2)
+        .map(|(key, _)| (*key, H256::zero()))
+        .collect();
+
+    let expected_root = parse_h256(INSERT_ROOT)?;
+    let expected_removed_root = parse_h256(REMOVED_ROOT)?;
+    let mut reference = ReferenceSMT::default();
+    reference
+        .update_all(pairs.clone())
+        .map_err(|err| format!("reference tree: {}", err))?;
+    let reference_root = *reference.root();
+    reference
+        .update_all(removed.clone())
+        .map_err(|err| format!("reference tree: {}", err))?;
+    let reference_removed_root = *reference.root();
+
+    let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+
+    let tx = store.begin_transaction();
+    let mut smt = TrieSmt::new(H256::zero(), trie_store(config, &tx));
+    smt.update_all(pairs.clone())
+        .map_err(|err| format!("insert failed: {}", err))?;
+    if *smt.root() != expected_root {
+        return Err(format!(
+            "root after insert is {:?}, expected {:?}",
+            smt.root(),
+            expected_root
+        ));
+    }
+    if reference_root != expected_root {
+        return Err(format!(
+            "reference root after insert is {:?}, expected {:?}",
+            reference_root, expected_root
+        ));
+    }
+    tx.commit().map_err(|err| err.to_string())?;
+
+    let tx = store.begin_transaction();
+    let mut smt = TrieSmt::new(expected_root, trie_store(config, &tx));
+    for (key, value) in &pairs {
+        let stored = smt.get(key).map_err(|err| format!("get failed: {}", err))?;
+        if stored != *value {
+            return Err(format!(
+                "committed leaf {:?} reads back as {:?}",
+                key, stored
+            ));
+        }
+    }
+    smt.update_all(removed.clone())
+        .map_err(|err| format!("remove failed: {}", err))?;
+    if *smt.root() != expected_removed_root {
+        return Err(format!(
+            "root after removal is {:?}, expected {:?}",
+            smt.root(),
+            expected_removed_root
+        ));
+    }
+    if reference_removed_root != expected_removed_root {
+        return Err(format!(
+            "reference root after removal is {:?}, expected {:?}",
+            reference_removed_root, expected_removed_root
+        ));
+    }
+    tx.commit().map_err(|err| err.to_string())?;
+
+    let tx = store.begin_transaction();
+    let smt = TrieSmt::new(expected_removed_root, trie_store(config, &tx));
+    for (key, _) in &removed {
+        let stored = smt.get(key).map_err(|err| format!("get failed: {}", err))?;
+        if !stored.is_zero() {
+            return Err(format!("removed leaf {:?} reads back as {:?}", key, stored));
+        }
+    }
+    Ok(start.elapsed())
+}
+
+fn trie_store<'a>(config: &Config, tx: &'a StoreTransaction) -> TrieStore<'a, StoreTransaction> {
+    TrieStore::with_layout(tx, config.trie_layout)
+        .with_columns(config.branch_col, config.leaf_col)
+        .molecule_wrap(config.trie_molecule_wrap)
+        .truncate_leaf_keys(config.leaf_key_bytes)
+}
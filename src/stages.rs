@@ -0,0 +1,41 @@
+use std::time::{Duration, Instant};
+
+// Wall time of every stage of a bench run, from the start of the process.
+// Each stage ends where the next one starts, so the stages add up to the
+// whole run.
+pub struct StageTimer {
+    start: Instant,
+    last: Instant,
+    stages: Vec<(String, Duration)>,
+}
+
+impl StageTimer {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+            stages: vec![],
+        }
+    }
+
+    // Ends the stage running since the previous lap under label
+    pub fn lap(&mut self, label: impl Into<String>) {
+        let now = Instant::now();
+        self.stages.push((label.into(), now - self.last));
+        self.last = now;
+    }
+
+    pub fn print(&self) {
+        let total = self.last - self.start;
+        println!("Stage times, {:?} in total:", total);
+        for (label, elapsed) in &self.stages {
+            println!(
+                "  {:<24} {:>14.3?} {:>6.1}%",
+                label,
+                elapsed,
+                elapsed.as_secs_f64() * 100.0 / total.as_secs_f64().max(f64::EPSILON)
+            );
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{
+    config::{Config, OutputFormat, Suite},
+    grid::{base_args, run_cell, CellResult},
+    markdown,
+};
+use std::fs;
+use std::path::PathBuf;
+
+// A scenario of a suite: options added after the command line, so they
+// override the ones given there, and what it characterizes
+pub struct Scenario {
+    pub name: &'static str,
+    pub about: &'static str,
+    pub args: &'static [&'static str],
+}
+
+const STANDARD: &[Scenario] = &[
+    Scenario {
+        name: "insert-only",
+        about: "fresh random keys only, the tree growing every round",
+        args: &["--phases", "load:20000;update:20x1000"],
+    },
+    Scenario {
+        name: "delete-heavy",
+        about: "a tree loaded once, then emptied a batch at a time",
+        args: &["--phases", "load:20000;delete:20x800"],
+    },
+    Scenario {
+        name: "hot-key",
+        about: "the same 256 keys rewritten every round, all under one trie",
+        args: &["--rewrite-storm", "--phases", "load:256;update:50x256"],
+    },
+    Scenario {
+        name: "sorted",
+        about: "ascending keys, every round writing next to the previous one",
+        args: &["--sorted-keys", "--phases", "load:20000;update:20x1000"],
+    },
+    Scenario {
+        name: "max-depth-proof",
+        about: "proofs of keys sharing all but their lowest byte, the deepest paths",
+        args: &[
+            "--rewrite-storm",
+            "--phases",
+            "load:256;prove-update:20x100",
+        ],
+    },
+];
+
+pub fn scenarios(suite: Suite) -> &'static [Scenario] {
+    match suite {
+        Suite::Standard => STANDARD,
+    }
+}
+
+fn metric<'a>(metrics: &'a [(&'static str, String)], name: &str) -> &'a str {
+    metrics
+        .iter()
+        .find(|(metric, _)| *metric == name)
+        .map_or("", |(_, value)| value)
+}
+
+fn mib(bytes: &str) -> String {
+    bytes.parse::<f64>().map_or(String::new(), |bytes| {
+        format!("{:.1}", bytes / (1 << 20) as f64)
+    })
+}
+
+// Runs every scenario of the suite in turn, each on a fresh database in a
+// directory next to --db-path, and prints one report of all of them. A
+// failing scenario is reported as such instead of stopping the suite.
+pub fn run(config: &Config, suite: Suite) {
+    let mut dir = config.db_path.as_os_str().to_owned();
+    dir.push("-suite");
+    let dir = PathBuf::from(dir);
+    if dir.exists() {
+        panic!(
+            "{} already exists, --suite needs a fresh directory",
+            dir.display()
+        );
+    }
+    fs::create_dir_all(&dir).unwrap();
+    let scenarios = scenarios(suite);
+    println!(
+        "Suite {}: {} scenarios in {}",
+        format!("{:?}", suite).to_lowercase(),
+        scenarios.len(),
+        dir.display()
+    );
+    let base = base_args();
+    let mut results: Vec<CellResult> = vec![];
+    for (i, scenario) in scenarios.iter().enumerate() {
+        println!(
+            "  Scenario {}/{} {}: {}",
+            i + 1,
+            scenarios.len(),
+            scenario.name,
+            scenario.about
+        );
+        let path = dir.join(scenario.name);
+        let mut args = base.clone();
+        args.extend(scenario.args.iter().map(|arg| arg.to_string()));
+        args.extend(["--db-path".to_string(), path.display().to_string()]);
+        let result = run_cell(args, &path);
+        match &result {
+            Ok(metrics) => println!(
+                "    {} ops in {}s, {} ops/s",
+                metric(metrics, "ops"),
+                metric(metrics, "elapsed_secs"),
+                metric(metrics, "ops_per_sec")
+            ),
+            Err(err) => println!("    failed: {}", err),
+        }
+        results.push(result);
+    }
+
+    let header: Vec<String> = [
+        "Scenario",
+        "Ops",
+        "Ops/s",
+        "Mean round (s)",
+        "Reads",
+        "Writes",
+        "Written MiB",
+        "Disk MiB",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let rows: Vec<Vec<String>> = scenarios
+        .iter()
+        .zip(&results)
+        .map(|(scenario, result)| {
+            let mut row = vec![scenario.name.to_string()];
+            match result {
+                Ok(metrics) => row.extend([
+                    metric(metrics, "ops").to_string(),
+                    metric(metrics, "ops_per_sec").to_string(),
+                    metric(metrics, "mean_round_secs").to_string(),
+                    metric(metrics, "reads").to_string(),
+                    metric(metrics, "writes").to_string(),
+                    mib(metric(metrics, "written_bytes")),
+                    mib(metric(metrics, "disk_bytes")),
+                ]),
+                Err(_) => row.extend((1..header.len()).map(|_| "failed".to_string())),
+            }
+            row
+        })
+        .collect();
+    println!();
+    if config.output == OutputFormat::Markdown {
+        markdown::print_preamble(config);
+        markdown::print_table(&header, &rows);
+        return;
+    }
+    println!(
+        "{:<18}{}",
+        header[0],
+        header[1..]
+            .iter()
+            .map(|h| format!("{:>16}", h))
+            .collect::<String>()
+    );
+    for row in rows {
+        println!(
+            "  {:<16}{}",
+            row[0],
+            row[1..]
+                .iter()
+                .map(|c| format!("{:>16}", c))
+                .collect::<String>()
+        );
+    }
+}
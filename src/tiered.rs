@@ -0,0 +1,265 @@
+use crate::{
+    batching::open, config::Config, dot::hex, footprint::dir_size, seed::SeedTree, trie::TrieStore,
+};
+use gw_db::{
+    error::Error,
+    iter::{DBIter, IteratorMode},
+    schema::Col,
+};
+use gw_store::traits::KVStore;
+use sparse_merkle_tree::H256;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// Branches below height go to the database at slow, the rest of the tree
+// to the one at fast, see TieredStore
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TierSplit {
+    pub height: u8,
+    pub fast: PathBuf,
+    pub slow: PathBuf,
+}
+
+// Parses "height=H:FAST:SLOW"
+impl FromStr for TierSplit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let usage = || format!("Invalid tier split {}, expected height=H:FAST:SLOW", s);
+        let rest = s.strip_prefix("height=").ok_or_else(usage)?;
+        let (height, rest) = rest.split_once(':').ok_or_else(usage)?;
+        let (fast, slow) = rest.split_once(':').ok_or_else(usage)?;
+        if fast.is_empty() || slow.is_empty() {
+            return Err(usage());
+        }
+        let height = height
+            .parse()
+            .map_err(|err| format!("Invalid tier split height {}: {}", height, err))?;
+        Ok(Self {
+            height,
+            fast: PathBuf::from(fast),
+            slow: PathBuf::from(slow),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tier {
+    Fast,
+    Slow,
+}
+
+// What one tier was asked for, the bytes counting keys and values
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TierStats {
+    pub gets: usize,
+    pub puts: usize,
+    pub deletes: usize,
+    pub read_bytes: usize,
+    pub written_bytes: usize,
+}
+
+impl TierStats {
+    pub fn ops(&self) -> usize {
+        self.gets + self.puts + self.deletes
+    }
+
+    fn add(&mut self, other: &TierStats) {
+        self.gets += other.gets;
+        self.puts += other.puts;
+        self.deletes += other.deletes;
+        self.read_bytes += other.read_bytes;
+        self.written_bytes += other.written_bytes;
+    }
+}
+
+// KVStore routing the branch column by height between two stores: a
+// branch key packed by TrieStore starts with the top height of its trie,
+// so a trie goes to the slow tier when all of its heights are below the
+// split, and to the fast one otherwise. Every other column, the leaves
+// included, goes to the fast tier.
+pub struct TieredStore<'a, Fast: KVStore, Slow: KVStore> {
+    fast: &'a Fast,
+    slow: &'a Slow,
+    branch_col: Col,
+    height: u8,
+    stats: RefCell<[TierStats; 2]>,
+}
+
+impl<'a, Fast: KVStore, Slow: KVStore> TieredStore<'a, Fast, Slow> {
+    pub fn new(fast: &'a Fast, slow: &'a Slow, branch_col: Col, height: u8) -> Self {
+        Self {
+            fast,
+            slow,
+            branch_col,
+            height,
+            stats: RefCell::default(),
+        }
+    }
+
+    pub fn tier(&self, col: Col, key: &[u8]) -> Tier {
+        match key.first() {
+            Some(height) if col == self.branch_col && *height < self.height => Tier::Slow,
+            _ => Tier::Fast,
+        }
+    }
+
+    pub fn stats(&self, tier: Tier) -> TierStats {
+        self.stats.borrow()[tier as usize]
+    }
+
+    fn count(&self, tier: Tier, f: impl FnOnce(&mut TierStats)) {
+        f(&mut self.stats.borrow_mut()[tier as usize]);
+    }
+}
+
+impl<'a, Fast: KVStore, Slow: KVStore> KVStore for TieredStore<'a, Fast, Slow> {
+    fn get(&self, col: Col, key: &[u8]) -> Option<Box<[u8]>> {
+        let tier = self.tier(col, key);
+        let value = match tier {
+            Tier::Fast => self.fast.get(col, key),
+            Tier::Slow => self.slow.get(col, key),
+        };
+        self.count(tier, |stats| {
+            stats.gets += 1;
+            stats.read_bytes += key.len() + value.as_ref().map_or(0, |value| value.len());
+        });
+        value
+    }
+
+    fn get_iter(&self, col: Col, mode: IteratorMode) -> DBIter<'_> {
+        // The branch column is split over both stores, one iterator cannot
+        // walk it in order
+        if col == self.branch_col {
+            panic!("TieredStore cannot iterate the branch column over two tiers");
+        }
+        self.fast.get_iter(col, mode)
+    }
+
+    fn insert_raw(&self, col: Col, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let tier = self.tier(col, key);
+        self.count(tier, |stats| {
+            stats.puts += 1;
+            stats.written_bytes += key.len() + value.len();
+        });
+        match tier {
+            Tier::Fast => self.fast.insert_raw(col, key, value),
+            Tier::Slow => self.slow.insert_raw(col, key, value),
+        }
+    }
+
+    fn delete(&self, col: Col, key: &[u8]) -> Result<(), Error> {
+        let tier = self.tier(col, key);
+        self.count(tier, |stats| {
+            stats.deletes += 1;
+            stats.written_bytes += key.len();
+        });
+        match tier {
+            Tier::Fast => self.fast.delete(col, key),
+            Tier::Slow => self.slow.delete(col, key),
+        }
+    }
+}
+
+// Runs the load, update, delete and read phases of --phases on a tree
+// split over the two databases of --tier-split, each round a transaction
+// on both, committed together. Reports per phase and per tier the ops and
+// bytes each tier was asked for, and the size each database ends with, to
+// size the two tiers.
+pub fn run(config: &Config) {
+    let split = config
+        .tier_split
+        .as_ref()
+        .expect("--experiment tiered requires --tier-split");
+    for path in [&split.fast, &split.slow] {
+        if path.exists() {
+            panic!(
+                "{} already exists, --experiment tiered needs fresh directories",
+                path.display()
+            );
+        }
+    }
+    println!(
+        "Tiered: branches from height {} up and leaves in {}, lower branches in {}",
+        split.height,
+        split.fast.display(),
+        split.slow.display()
+    );
+
+    let fast = open(config, &split.fast);
+    let slow = open(config, &split.slow);
+    let mut rng = SeedTree::new(config.seed, config.verbose)
+        .child("tiered")
+        .rng();
+    let mut keys: Vec<(H256, H256)> = vec![];
+    let mut root = H256::zero();
+    let mut totals = [TierStats::default(); 2];
+    for phase in &config.phases {
+        if !phase.leaves_only() {
+            println!(
+                "  {}: skipped, only load, update, delete and read run on the tiers",
+                phase
+            );
+            continue;
+        }
+        let mut elapsed = Duration::default();
+        let mut stats = [TierStats::default(); 2];
+        for _ in 0..phase.rounds {
+            let pairs = phase.round_pairs(&mut keys, &mut rng);
+            let start = Instant::now();
+            let (fast_tx, slow_tx) = (fast.begin_transaction(), slow.begin_transaction());
+            let tiered = TieredStore::new(&fast_tx, &slow_tx, config.branch_col, split.height);
+            let trie = TrieStore::with_layout(&tiered, config.trie_layout)
+                .with_columns(config.branch_col, config.leaf_col)
+                .molecule_wrap(config.trie_molecule_wrap)
+                .truncate_leaf_keys(config.leaf_key_bytes);
+            root = phase.apply(trie, root, &pairs);
+            fast_tx.commit().unwrap();
+            slow_tx.commit().unwrap();
+            elapsed += start.elapsed();
+            for tier in [Tier::Fast, Tier::Slow] {
+                stats[tier as usize].add(&tiered.stats(tier));
+            }
+        }
+        println!(
+            "  {}: {:?} per round",
+            phase,
+            elapsed / phase.rounds.max(1) as u32
+        );
+        print_tiers(&stats, "    ");
+        for (total, stats) in totals.iter_mut().zip(&stats) {
+            total.add(stats);
+        }
+    }
+    drop(fast);
+    drop(slow);
+
+    println!("  All phases, ending with root {}:", hex(&root));
+    print_tiers(&totals, "    ");
+    let sizes = [dir_size(&split.fast), dir_size(&split.slow)];
+    println!(
+        "  On disk: fast {:.1} MiB, slow {:.1} MiB ({:.1}% slow)",
+        sizes[0] as f64 / (1 << 20) as f64,
+        sizes[1] as f64 / (1 << 20) as f64,
+        sizes[1] as f64 * 100.0 / (sizes[0] + sizes[1]).max(1) as f64
+    );
+}
+
+fn print_tiers(stats: &[TierStats; 2], indent: &str) {
+    let ops = (stats[0].ops() + stats[1].ops()).max(1) as f64;
+    for (name, stats) in ["fast", "slow"].iter().zip(stats) {
+        println!(
+            "{}{}: {} gets, {} puts, {} deletes ({:.1}% of ops), {:.1} MiB read, {:.1} MiB written",
+            indent,
+            name,
+            stats.gets,
+            stats.puts,
+            stats.deletes,
+            stats.ops() as f64 * 100.0 / ops,
+            stats.read_bytes as f64 / (1 << 20) as f64,
+            stats.written_bytes as f64 / (1 << 20) as f64
+        );
+    }
+}
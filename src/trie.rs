@@ -1,4 +1,5 @@
-use crate::utils::*;
+use crate::{history::LeafHistory, utils::*};
+use gw_db::schema::Col;
 use gw_store::traits::KVStore;
 use gw_types::{packed, prelude::*};
 use sparse_merkle_tree::{
@@ -8,33 +9,145 @@ use sparse_merkle_tree::{
     tree::{BranchKey, BranchNode},
     H256,
 };
+use std::borrow::Cow;
 use std::cell::Cell;
+use std::str::FromStr;
 
 const BYTE_SIZE: usize = 8;
-const NODES_PER_TRIE: usize = (1 << BYTE_SIZE) - 1;
+pub(crate) const NODES_PER_TRIE: usize = (1 << BYTE_SIZE) - 1;
 const MERGE_VALUE_SIZE: usize = 32 + 32 + 2;
-const NODE_SIZE: usize = MERGE_VALUE_SIZE * 2;
-const TRIE_SIZE: usize = NODES_PER_TRIE * NODE_SIZE;
+pub(crate) const NODE_SIZE: usize = MERGE_VALUE_SIZE * 2;
+// A single version byte recording the layout precedes the nodes
+const HEADER_SIZE: usize = 1;
+pub(crate) const TRIE_SIZE: usize = HEADER_SIZE + NODES_PER_TRIE * NODE_SIZE;
+// A merge operand sets one slot of a trie: the layout version, the slot and
+// the node
+const OPERAND_SIZE: usize = 2 + NODE_SIZE;
+// A blob wrapped in a molecule Bytes is preceded by its length as a 4 byte
+// little-endian number
+pub(crate) const WRAP_HEADER_SIZE: usize = 4;
+// Bytes of a leaf key, what leaves are stored under unless truncated
+pub(crate) const LEAF_KEY_SIZE: usize = 32;
 
-struct BranchTrie {
+// Order in which the 255 nodes of a trie are laid out within the blob. Each
+// layout is stored as a distinct version byte, so a blob can only be read
+// back with the layout that wrote it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrieLayout {
+    // Breadth-first from the top of the trie, children of i at 2i+1 and 2i+2
+    #[default]
+    Heap,
+    // Depth-first pre-order, so a node's subtree is contiguous
+    Dfs,
+    // Breadth-first from the bottom of the trie, lowest height first
+    Level,
+}
+
+impl TrieLayout {
+    pub const ALL: [TrieLayout; 3] = [TrieLayout::Heap, TrieLayout::Dfs, TrieLayout::Level];
+
+    fn version(self) -> u8 {
+        match self {
+            TrieLayout::Heap => 1,
+            TrieLayout::Dfs => 2,
+            TrieLayout::Level => 3,
+        }
+    }
+
+    fn from_version(version: u8) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|layout| layout.version() == version)
+    }
+
+    // Maps a node, given by its inner height and its position among the
+    // nodes of that height, to its slot in the blob.
+    pub fn index(self, inner_height: u8, position: usize) -> usize {
+        let depth = BYTE_SIZE - 1 - inner_height as usize;
+        match self {
+            TrieLayout::Heap => (1 << depth) - 1 + position,
+            TrieLayout::Dfs => {
+                // Every step down skips the node itself, and stepping right
+                // also skips the whole left subtree.
+                (0..depth)
+                    .map(|level| {
+                        let right = (position >> (depth - 1 - level)) & 1;
+                        1 + right * ((1 << (BYTE_SIZE - 1 - level)) - 1)
+                    })
+                    .sum()
+            }
+            TrieLayout::Level => {
+                (1 << BYTE_SIZE) - (1 << (BYTE_SIZE - inner_height as usize)) + position
+            }
+        }
+    }
+}
+
+impl FromStr for TrieLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "heap" => Ok(TrieLayout::Heap),
+            "dfs" => Ok(TrieLayout::Dfs),
+            "level" => Ok(TrieLayout::Level),
+            _ => Err(format!("Unknown trie layout: {}", s)),
+        }
+    }
+}
+
+pub(crate) struct BranchTrie {
     data: Vec<u8>,
     rounded_path: BranchKey,
+    layout: TrieLayout,
 }
 
 impl BranchTrie {
-    fn empty(rounded_path: BranchKey) -> Self {
+    pub(crate) fn empty(rounded_path: BranchKey, layout: TrieLayout) -> Self {
+        let mut data = vec![0u8; TRIE_SIZE];
+        data[0] = layout.version();
         BranchTrie {
-            data: vec![0u8; TRIE_SIZE],
+            data,
             rounded_path,
+            layout,
         }
     }
 
-    fn get_branch(&self, branch_key: &BranchKey) -> Result<Option<BranchNode>, SMTError> {
+    // data is a stored blob, either written whole or materialized by the
+    // merge operator from the slot operands of --trie-merge-op, which leaves
+    // the same blob
+    fn load(data: &[u8], rounded_path: BranchKey, layout: TrieLayout) -> Result<Self, SMTError> {
+        if data.len() != TRIE_SIZE {
+            return Err(SMTError::Store("corrupted trie".to_string()));
+        }
+        if data[0] != layout.version() {
+            return Err(SMTError::Store(format!(
+                "trie layout version {} does not match {:?}",
+                data[0], layout
+            )));
+        }
+        Ok(BranchTrie {
+            data: data.to_vec(),
+            rounded_path,
+            layout,
+        })
+    }
+
+    pub(crate) fn get_branch(
+        &self,
+        branch_key: &BranchKey,
+    ) -> Result<Option<BranchNode>, SMTError> {
         let index = self.calculate_index(branch_key);
+        // The tree never stores a branch with two zero children, so an all
+        // zero slot is one that was never written or has been removed
+        if self.is_empty_slot(index) {
+            return Ok(None);
+        }
         Ok(Some(self.load_branch_node(index)))
     }
 
-    fn insert_branch(
+    pub(crate) fn insert_branch(
         &mut self,
         branch_key: &BranchKey,
         branch: &BranchNode,
@@ -45,26 +158,53 @@ impl BranchTrie {
     }
 
     fn remove_branch(&mut self, branch_key: &BranchKey) -> Result<bool, SMTError> {
-        let index = self.calculate_index(branch_key);
-        let offset = index * NODE_SIZE;
+        let offset = node_offset(self.calculate_index(branch_key));
         self.data[offset..offset + NODE_SIZE].fill(0);
-        // TODO: we return true if current Trie contains no valid branches. For now
-        // we always return false but this is an optimization that can be used to reduce
-        // storage.
-        Ok(false)
+        // Returns true when no branch is left, so the whole trie can be
+        // deleted instead of keeping a blob of zeros around
+        Ok(self.data[HEADER_SIZE..].iter().all(|b| *b == 0))
+    }
+
+    // Checks every slot the way load_merge_value cannot: each child's tag
+    // is 0 for a value or 1 for a merge with zero, and a value child has a
+    // zero count and zero bits of zero, bytes a value never uses. A blob of
+    // the right length holding garbage fails here instead of loading as
+    // wrong branches.
+    fn validate(&self) -> Result<(), SMTError> {
+        for index in 0..NODES_PER_TRIE {
+            for (child, side) in [(0, "left"), (1, "right")] {
+                let offset = node_offset(index) + child * MERGE_VALUE_SIZE;
+                let value = &self.data[offset..offset + MERGE_VALUE_SIZE];
+                let error = match value[0] {
+                    0 if value[1] != 0 => format!("a value with zero count {}", value[1]),
+                    0 if value[2 + 32..].iter().any(|b| *b != 0) => {
+                        "a value with non-zero padding".to_string()
+                    }
+                    0 | 1 => continue,
+                    tag => format!("tag {}", tag),
+                };
+                return Err(SMTError::Store(format!(
+                    "trie slot {} {} child holds {}",
+                    index, side, error
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_empty_slot(&self, index: usize) -> bool {
+        let offset = node_offset(index);
+        self.data[offset..offset + NODE_SIZE]
+            .iter()
+            .all(|b| *b == 0)
     }
 
     fn calculate_index(&self, branch_key: &BranchKey) -> usize {
-        let index_byte =
-            branch_key.node_key.as_slice()[self.rounded_path.height as usize / BYTE_SIZE];
-        let inner_height: u8 = branch_key.height % BYTE_SIZE as u8;
-        let base_index: usize = (1 << (8 - inner_height - 1)) - 1;
-        let index = index_byte >> (inner_height + 1);
-        base_index as usize + index as usize
+        calculate_index(self.layout, &self.rounded_path, branch_key)
     }
 
     fn load_branch_node(&self, index: usize) -> BranchNode {
-        let offset = index * NODE_SIZE;
+        let offset = node_offset(index);
         BranchNode {
             left: self.load_merge_value(offset),
             right: self.load_merge_value(offset + MERGE_VALUE_SIZE),
@@ -92,44 +232,211 @@ impl BranchTrie {
     }
 
     fn save_branch_node(&mut self, index: usize, branch: &BranchNode) {
-        let offset = index * NODE_SIZE;
-        self.save_merge_value(offset, &branch.left);
-        self.save_merge_value(offset + MERGE_VALUE_SIZE, &branch.right);
+        let offset = node_offset(index);
+        save_node(&mut self.data[offset..offset + NODE_SIZE], branch);
     }
+}
 
-    fn save_merge_value(&mut self, offset: usize, merge_value: &MergeValue) {
-        match merge_value {
-            MergeValue::Value(value) => {
-                self.data[offset] = 0;
-                self.save_h256(offset + 2, value);
-            }
-            MergeValue::MergeWithZero {
-                base_node,
-                zero_bits,
-                zero_count,
-            } => {
-                self.data[offset] = 1;
-                self.data[offset + 1] = *zero_count;
-                self.save_h256(offset + 2, base_node);
-                self.save_h256(offset + 2 + 32, zero_bits);
-            }
+// Writes branch into the NODE_SIZE bytes of its slot
+fn save_node(node: &mut [u8], branch: &BranchNode) {
+    let (left, right) = node.split_at_mut(MERGE_VALUE_SIZE);
+    save_merge_value(left, &branch.left);
+    save_merge_value(right, &branch.right);
+}
+
+fn save_merge_value(data: &mut [u8], merge_value: &MergeValue) {
+    match merge_value {
+        MergeValue::Value(value) => {
+            data[0] = 0;
+            data[1] = 0;
+            data[2..2 + 32].copy_from_slice(value.as_slice());
+            // Clears what a MergeWithZero saved here before left behind
+            data[2 + 32..].fill(0);
+        }
+        MergeValue::MergeWithZero {
+            base_node,
+            zero_bits,
+            zero_count,
+        } => {
+            data[0] = 1;
+            data[1] = *zero_count;
+            data[2..2 + 32].copy_from_slice(base_node.as_slice());
+            data[2 + 32..].copy_from_slice(zero_bits.as_slice());
         }
     }
+}
+
+// The merge operand setting slot index of a trie of layout to branch, or
+// clearing it without one
+pub(crate) fn slot_operand(
+    layout: TrieLayout,
+    index: usize,
+    branch: Option<&BranchNode>,
+) -> Vec<u8> {
+    let mut operand = vec![0u8; OPERAND_SIZE];
+    operand[0] = layout.version();
+    operand[1] = index as u8;
+    if let Some(branch) = branch {
+        save_node(&mut operand[2..], branch);
+    }
+    operand
+}
+
+// The blob the merge operands of a trie leave, applied in order on top of
+// existing, the stored blob if there is one, or on an empty trie of the
+// layout of the first operand. None when the blob or an operand is
+// malformed, which RocksDB turns into a corruption error.
+pub(crate) fn materialize<'o>(
+    existing: Option<&[u8]>,
+    operands: impl IntoIterator<Item = &'o [u8]>,
+) -> Option<Vec<u8>> {
+    let rounded_path = BranchKey::new(0, H256::zero());
+    let mut trie = match existing {
+        Some(blob) => {
+            let data = unwrap_blob(blob);
+            let layout = TrieLayout::from_version(*data.first()?)?;
+            Some(BranchTrie::load(data, rounded_path.clone(), layout).ok()?)
+        }
+        None => None,
+    };
+    for operand in operands {
+        if operand.len() != OPERAND_SIZE || operand[1] as usize >= NODES_PER_TRIE {
+            return None;
+        }
+        let layout = TrieLayout::from_version(operand[0])?;
+        let trie = trie.get_or_insert_with(|| BranchTrie::empty(rounded_path.clone(), layout));
+        if trie.layout != layout {
+            return None;
+        }
+        let offset = node_offset(operand[1] as usize);
+        trie.data[offset..offset + NODE_SIZE].copy_from_slice(&operand[2..]);
+    }
+    trie.map(|trie| trie.data)
+}
+
+// Slot of a branch within the trie at rounded_path, which must be the
+// rounded key of the branch
+pub(crate) fn calculate_index(
+    layout: TrieLayout,
+    rounded_path: &BranchKey,
+    branch_key: &BranchKey,
+) -> usize {
+    let index_byte = branch_key.node_key.as_slice()[rounded_path.height as usize / BYTE_SIZE];
+    let inner_height: u8 = branch_key.height % BYTE_SIZE as u8;
+    // At inner height 7 no bit of the byte is part of the position, and a
+    // plain shift by 8 would overflow
+    let position = index_byte.checked_shr(inner_height as u32 + 1).unwrap_or(0);
+    layout.index(inner_height, position as usize)
+}
+
+// The blob as a molecule Bytes, for scanners expecting every value of the
+// branch column to be a molecule structure
+pub(crate) fn wrap_blob(data: &[u8]) -> Vec<u8> {
+    data.pack().as_slice().to_vec()
+}
+
+// The blob of a stored trie, wrapped or not. A blob starts with a layout
+// version, and the length a wrapped one starts with never does, so blobs
+// written before --trie-molecule-wrap still read back.
+pub(crate) fn unwrap_blob(data: &[u8]) -> &[u8] {
+    if data
+        .first()
+        .is_none_or(|b| TrieLayout::ALL.iter().any(|l| l.version() == *b))
+    {
+        return data;
+    }
+    match packed::BytesReader::from_slice(data) {
+        Ok(bytes) => bytes.raw_data(),
+        Err(_) => data,
+    }
+}
+
+pub(crate) fn node_offset(index: usize) -> usize {
+    HEADER_SIZE + index * NODE_SIZE
+}
+
+// Checks a stored trie blob, wrapped or not, of the given layout down to
+// every slot, see BranchTrie::validate.
+pub(crate) fn validate_trie(data: &[u8], layout: TrieLayout) -> Result<(), SMTError> {
+    let rounded_path = BranchKey::new(0, H256::zero());
+    BranchTrie::load(unwrap_blob(data), rounded_path, layout)?.validate()
+}
+
+// Number of branches a stored trie blob holds. A blob holding none should
+// have been deleted by remove_branch.
+pub(crate) fn trie_occupancy(data: &[u8]) -> usize {
+    let data = unwrap_blob(data);
+    (0..NODES_PER_TRIE)
+        .filter(|index| {
+            let offset = node_offset(*index);
+            data.get(offset..offset + NODE_SIZE)
+                .is_some_and(|node| node.iter().any(|b| *b != 0))
+        })
+        .count()
+}
 
-    fn save_h256(&mut self, offset: usize, h: &H256) {
-        self.data[offset..offset + 32].copy_from_slice(h.as_slice());
+// Branches a stored trie blob holds, in slot order. The slot offsets are the
+// same for every layout.
+pub(crate) fn trie_branches(data: &[u8]) -> Vec<BranchNode> {
+    let data = unwrap_blob(data);
+    if data.len() != TRIE_SIZE {
+        return vec![];
     }
+    let trie = BranchTrie {
+        data: data.to_vec(),
+        rounded_path: BranchKey::new(0, H256::zero()),
+        layout: TrieLayout::default(),
+    };
+    (0..NODES_PER_TRIE)
+        .filter(|index| !trie.is_empty_slot(*index))
+        .map(|index| trie.load_branch_node(index))
+        .collect()
 }
 
 pub struct TrieStore<'a, DB: KVStore> {
     store: &'a DB,
+    layout: TrieLayout,
+    branch_col: Col,
+    leaf_col: Col,
+    history: Option<LeafHistory>,
 
     reads: Cell<usize>,
     writes: usize,
+    // Bytes of keys and values passed to insert_raw
+    written_bytes: usize,
+    // Bytes of keys and values buffered in the transaction, deleted keys
+    // included. Stores live for one transaction, so this restarts at commit.
+    staged_bytes: usize,
+    // Packed key of the trie whose rewrites are counted, see watch_trie
+    watched: Option<Vec<u8>>,
+    watched_writes: usize,
+    watched_bytes: usize,
+    // With strict_leaves, inserts replacing a leaf by a different value,
+    // counted instead of failing so a run reports all of them
+    strict: bool,
+    strict_violations: usize,
+    // Whether blobs are written wrapped in a molecule Bytes
+    wrap: bool,
+    // Bytes of its key a leaf is stored under, see truncate_leaf_keys, and
+    // the inserts whose truncated key held another leaf
+    leaf_key_bytes: usize,
+    leaf_collisions: usize,
+    // Whether changed slots are written as merge operands, see
+    // merge_operands
+    merge_operands: bool,
     // cache: Cell<Option<BranchTrie>>,
 }
 
-fn round_branch_key(branch_key: &BranchKey) -> BranchKey {
+// What the truncated key of a leaf holds
+enum LeafSlot {
+    Free,
+    // The leaf of the key looked up
+    Own(H256),
+    // The leaf of another key with the same first bytes
+    Taken,
+}
+
+pub(crate) fn round_branch_key(branch_key: &BranchKey) -> BranchKey {
     let rounded_height = (((branch_key.height as usize) / BYTE_SIZE + 1) * BYTE_SIZE - 1) as u8;
     BranchKey::new(
         rounded_height,
@@ -139,20 +446,182 @@ fn round_branch_key(branch_key: &BranchKey) -> BranchKey {
 
 impl<'a, DB: KVStore> TrieStore<'a, DB> {
     pub fn new(store: &'a DB) -> Self {
+        Self::with_layout(store, TrieLayout::default())
+    }
+
+    pub fn with_layout(store: &'a DB, layout: TrieLayout) -> Self {
         Self {
             store,
+            layout,
+            branch_col: 0,
+            leaf_col: 1,
+            history: None,
             reads: Cell::default(),
             writes: 0,
+            written_bytes: 0,
+            staged_bytes: 0,
+            watched: None,
+            watched_writes: 0,
+            watched_bytes: 0,
+            strict: false,
+            strict_violations: 0,
+            wrap: false,
+            leaf_key_bytes: LEAF_KEY_SIZE,
+            leaf_collisions: 0,
+            merge_operands: false,
+        }
+    }
+
+    // Counts the writes to the trie holding branch_key separately
+    pub fn watch_trie(mut self, branch_key: &BranchKey) -> Self {
+        let rounded_key = pack_key(&round_branch_key(branch_key));
+        self.watched = Some(rounded_key.as_slice().to_vec());
+        self
+    }
+
+    pub fn watched_writes(&self) -> usize {
+        self.watched_writes
+    }
+
+    pub fn watched_bytes(&self) -> usize {
+        self.watched_bytes
+    }
+
+    fn count_watched(&mut self, packed_rounded_key: &[u8], bytes: usize) {
+        if self.watched.as_deref() == Some(packed_rounded_key) {
+            self.watched_writes += 1;
+            self.watched_bytes += bytes;
+        }
+    }
+
+    // Reads every leaf before inserting it and counts the inserts replacing
+    // an existing leaf by a different value, which a workload of fresh keys
+    // never does
+    pub fn strict_leaves(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    pub fn strict_violations(&self) -> usize {
+        self.strict_violations
+    }
+
+    // Writes every blob wrapped in a molecule Bytes when wrap is set. Blobs
+    // read back either way.
+    pub fn molecule_wrap(mut self, wrap: bool) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    // Stores every leaf under the first bytes of its key, with the rest of
+    // the key in front of the leaf to tell keys sharing them apart. A leaf
+    // whose truncated key holds another leaf is stored under its full key,
+    // which lookups missing the truncated key probe. None keeps full keys.
+    pub fn truncate_leaf_keys(mut self, bytes: Option<usize>) -> Self {
+        self.leaf_key_bytes = bytes.unwrap_or(LEAF_KEY_SIZE);
+        self
+    }
+
+    pub fn leaf_collisions(&self) -> usize {
+        self.leaf_collisions
+    }
+
+    // Writes every changed slot as a small operand, see slot_operand, in
+    // place of the whole blob, and inserts a branch without reading its
+    // trie first. The store must turn the inserts into the branch column
+    // into merges for the operator of merge_op, which materializes the blob.
+    pub fn merge_operands(mut self) -> Self {
+        self.merge_operands = true;
+        self
+    }
+
+    // What the truncated key of leaf_key holds
+    fn truncated_slot(&self, leaf_key: &H256) -> Result<LeafSlot, SMTError> {
+        let bytes = self.leaf_key_bytes;
+        self.reads.set(self.reads.get() + 1);
+        match self.store.get(self.leaf_col, &leaf_key.as_slice()[..bytes]) {
+            None => Ok(LeafSlot::Free),
+            Some(slice) if slice.len() == 2 * LEAF_KEY_SIZE - bytes => {
+                let (rest, leaf) = slice.split_at(LEAF_KEY_SIZE - bytes);
+                if rest == &leaf_key.as_slice()[bytes..] {
+                    let mut own = [0u8; 32];
+                    own.copy_from_slice(leaf);
+                    Ok(LeafSlot::Own(H256::from(own)))
+                } else {
+                    Ok(LeafSlot::Taken)
+                }
+            }
+            Some(_) => Err(SMTError::Store("get corrupted leaf".to_string())),
+        }
+    }
+
+    // The leaf stored under the full leaf_key
+    fn full_key_leaf(&self, leaf_key: &H256) -> Result<Option<H256>, SMTError> {
+        self.reads.set(self.reads.get() + 1);
+        match self.store.get(self.leaf_col, leaf_key.as_slice()) {
+            Some(slice) if 32 == slice.len() => {
+                let mut leaf = [0u8; 32];
+                leaf.copy_from_slice(slice.as_ref());
+                Ok(Some(H256::from(leaf)))
+            }
+            Some(_) => Err(SMTError::Store("get corrupted leaf".to_string())),
+            None => Ok(None),
+        }
+    }
+
+    // The value a trie blob is stored as
+    fn encode<'b>(&self, data: &'b [u8]) -> Cow<'b, [u8]> {
+        if self.wrap {
+            Cow::Owned(time_serde(|| wrap_blob(data)))
+        } else {
+            Cow::Borrowed(data)
         }
     }
 
-    pub fn clear_stats(&mut self) {
-        self.reads.set(0);
-        self.writes = 0;
+    // Reads and writes tries and leaves in the given columns instead of the
+    // default 0 and 1.
+    pub fn with_columns(mut self, branch_col: Col, leaf_col: Col) -> Self {
+        self.branch_col = branch_col;
+        self.leaf_col = leaf_col;
+        self
+    }
+
+    // Records the previous value of every changed leaf in history_col, see
+    // LeafHistory.
+    pub fn with_history(mut self, history_col: Col, round: u64) -> Self {
+        self.history = Some(LeafHistory::new(history_col, round));
+        self
     }
 
-    pub fn stats(&self) {
-        println!("Reads: {}, writes: {}", self.reads.get(), self.writes);
+    pub fn history(&self) -> Option<&LeafHistory> {
+        self.history.as_ref()
+    }
+
+    pub fn reads(&self) -> usize {
+        self.reads.get()
+    }
+
+    pub fn writes(&self) -> usize {
+        self.writes
+    }
+
+    pub fn written_bytes(&self) -> usize {
+        self.written_bytes
+    }
+
+    // Includes the leaf history records written in the same transaction
+    pub fn staged_bytes(&self) -> usize {
+        self.staged_bytes + self.history.as_ref().map_or(0, |history| history.bytes)
+    }
+
+    fn write_operand(&mut self, packed_rounded_key: &[u8], operand: &[u8]) -> Result<(), SMTError> {
+        self.writes += 1;
+        self.count_watched(packed_rounded_key, operand.len());
+        self.written_bytes += packed_rounded_key.len() + operand.len();
+        self.staged_bytes += packed_rounded_key.len() + operand.len();
+        self.store
+            .insert_raw(self.branch_col, packed_rounded_key, operand)
+            .map_err(|err| SMTError::Store(format!("insert error {}", err)))
     }
 }
 
@@ -163,32 +632,26 @@ impl<'a, DB: KVStore> Store<H256> for TrieStore<'a, DB> {
 
         self.reads.set(self.reads.get() + 1);
         // TODO: cache
-        let trie = match self.store.get(0, packed_rounded_key.as_slice()) {
+        let trie = match self
+            .store
+            .get(self.branch_col, packed_rounded_key.as_slice())
+        {
             Some(slice) => {
-                if slice.len() != TRIE_SIZE {
-                    return Err(SMTError::Store("corrupted trie".to_string()));
-                }
-                BranchTrie {
-                    data: slice.to_vec(),
-                    rounded_path: rounded_key,
-                }
+                time_serde(|| BranchTrie::load(unwrap_blob(&slice), rounded_key, self.layout))?
             }
             None => return Ok(None),
         };
 
-        trie.get_branch(branch_key)
+        time_serde(|| trie.get_branch(branch_key))
     }
 
     fn get_leaf(&self, leaf_key: &H256) -> Result<Option<H256>, SMTError> {
-        self.reads.set(self.reads.get() + 1);
-        match self.store.get(1, leaf_key.as_slice()) {
-            Some(slice) if 32 == slice.len() => {
-                let mut leaf = [0u8; 32];
-                leaf.copy_from_slice(slice.as_ref());
-                Ok(Some(H256::from(leaf)))
-            }
-            Some(_) => Err(SMTError::Store("get corrupted leaf".to_string())),
-            None => Ok(None),
+        if self.leaf_key_bytes == LEAF_KEY_SIZE {
+            return self.full_key_leaf(leaf_key);
+        }
+        match self.truncated_slot(leaf_key)? {
+            LeafSlot::Own(leaf) => Ok(Some(leaf)),
+            LeafSlot::Free | LeafSlot::Taken => self.full_key_leaf(leaf_key),
         }
     }
 
@@ -196,35 +659,72 @@ impl<'a, DB: KVStore> Store<H256> for TrieStore<'a, DB> {
         let rounded_key = round_branch_key(&branch_key);
         let packed_rounded_key: packed::SMTBranchKey = pack_key(&rounded_key);
 
+        if self.merge_operands {
+            let index = calculate_index(self.layout, &rounded_key, &branch_key);
+            let operand = slot_operand(self.layout, index, Some(&branch));
+            return self.write_operand(packed_rounded_key.as_slice(), &operand);
+        }
         self.reads.set(self.reads.get() + 1);
         // TODO: cache
-        let mut trie = match self.store.get(0, packed_rounded_key.as_slice()) {
+        let mut trie = match self
+            .store
+            .get(self.branch_col, packed_rounded_key.as_slice())
+        {
             Some(slice) => {
-                if slice.len() != TRIE_SIZE {
-                    return Err(SMTError::Store("corrupted trie".to_string()));
-                }
-                BranchTrie {
-                    data: slice.to_vec(),
-                    rounded_path: rounded_key,
-                }
+                time_serde(|| BranchTrie::load(unwrap_blob(&slice), rounded_key, self.layout))?
             }
-            None => BranchTrie::empty(rounded_key),
+            None => BranchTrie::empty(rounded_key, self.layout),
         };
 
-        trie.insert_branch(&branch_key, &branch)?;
+        time_serde(|| trie.insert_branch(&branch_key, &branch))?;
+        let blob = self.encode(&trie.data);
         self.writes += 1;
+        self.count_watched(packed_rounded_key.as_slice(), blob.len());
+        self.written_bytes += packed_rounded_key.as_slice().len() + blob.len();
+        self.staged_bytes += packed_rounded_key.as_slice().len() + blob.len();
         self.store
-            .insert_raw(0, packed_rounded_key.as_slice(), trie.data.as_slice())
+            .insert_raw(self.branch_col, packed_rounded_key.as_slice(), &blob)
             .map_err(|err| SMTError::Store(format!("insert error {}", err)))?;
 
         Ok(())
     }
 
     fn insert_leaf(&mut self, leaf_key: H256, leaf: H256) -> Result<(), SMTError> {
+        if self.strict {
+            self.reads.set(self.reads.get() + 1);
+            if let Some(existing) = self.store.get(self.leaf_col, leaf_key.as_slice()) {
+                if existing.as_ref() != leaf.as_slice() {
+                    self.strict_violations += 1;
+                }
+            }
+        }
+        if let Some(history) = &mut self.history {
+            history.record(self.store, self.leaf_col, &leaf_key)?;
+        }
         self.writes += 1;
-        self.store
-            .insert_raw(1, leaf_key.as_slice(), leaf.as_slice())
-            .map_err(|err| SMTError::Store(format!("insert error {}", err)))?;
+        self.written_bytes += 64;
+        self.staged_bytes += 64;
+        let bytes = self.leaf_key_bytes;
+        // A key stays where it is, so no lookup finds a stale copy of it
+        let truncated = bytes < LEAF_KEY_SIZE
+            && match self.truncated_slot(&leaf_key)? {
+                LeafSlot::Own(_) => true,
+                LeafSlot::Taken => {
+                    self.leaf_collisions += 1;
+                    false
+                }
+                LeafSlot::Free => self.full_key_leaf(&leaf_key)?.is_none(),
+            };
+        let result = if truncated {
+            let mut value = leaf_key.as_slice()[bytes..].to_vec();
+            value.extend_from_slice(leaf.as_slice());
+            self.store
+                .insert_raw(self.leaf_col, &leaf_key.as_slice()[..bytes], &value)
+        } else {
+            self.store
+                .insert_raw(self.leaf_col, leaf_key.as_slice(), leaf.as_slice())
+        };
+        result.map_err(|err| SMTError::Store(format!("insert error {}", err)))?;
 
         Ok(())
     }
@@ -235,28 +735,35 @@ impl<'a, DB: KVStore> Store<H256> for TrieStore<'a, DB> {
 
         self.reads.set(self.reads.get() + 1);
         // TODO: cache
-        let mut trie = match self.store.get(0, packed_rounded_key.as_slice()) {
+        let mut trie = match self
+            .store
+            .get(self.branch_col, packed_rounded_key.as_slice())
+        {
             Some(slice) => {
-                if slice.len() != TRIE_SIZE {
-                    return Err(SMTError::Store("corrupted trie".to_string()));
-                }
-                BranchTrie {
-                    data: slice.to_vec(),
-                    rounded_path: rounded_key,
-                }
+                time_serde(|| BranchTrie::load(unwrap_blob(&slice), rounded_key, self.layout))?
             }
-            None => BranchTrie::empty(rounded_key),
+            None => return Ok(()),
         };
 
         let should_remove = trie.remove_branch(branch_key)?;
+        if self.merge_operands && !should_remove {
+            let index = trie.calculate_index(branch_key);
+            let operand = slot_operand(self.layout, index, None);
+            return self.write_operand(packed_rounded_key.as_slice(), &operand);
+        }
         self.writes += 1;
         if should_remove {
+            self.staged_bytes += packed_rounded_key.as_slice().len();
             self.store
-                .delete(0, packed_rounded_key.as_slice())
+                .delete(self.branch_col, packed_rounded_key.as_slice())
                 .map_err(|err| SMTError::Store(format!("delete error {}", err)))?;
         } else {
+            let blob = self.encode(&trie.data);
+            self.count_watched(packed_rounded_key.as_slice(), blob.len());
+            self.written_bytes += packed_rounded_key.as_slice().len() + blob.len();
+            self.staged_bytes += packed_rounded_key.as_slice().len() + blob.len();
             self.store
-                .insert_raw(0, packed_rounded_key.as_slice(), trie.data.as_slice())
+                .insert_raw(self.branch_col, packed_rounded_key.as_slice(), &blob)
                 .map_err(|err| SMTError::Store(format!("insert error {}", err)))?;
         }
 
@@ -264,10 +771,609 @@ impl<'a, DB: KVStore> Store<H256> for TrieStore<'a, DB> {
     }
 
     fn remove_leaf(&mut self, leaf_key: &H256) -> Result<(), SMTError> {
+        if let Some(history) = &mut self.history {
+            history.record(self.store, self.leaf_col, leaf_key)?;
+        }
+        let bytes = self.leaf_key_bytes;
+        let key = if bytes < LEAF_KEY_SIZE
+            && matches!(self.truncated_slot(leaf_key)?, LeafSlot::Own(_))
+        {
+            &leaf_key.as_slice()[..bytes]
+        } else {
+            leaf_key.as_slice()
+        };
+        self.staged_bytes += key.len();
         self.store
-            .delete(1, leaf_key.as_slice())
+            .delete(self.leaf_col, key)
             .map_err(|err| SMTError::Store(format!("delete error {}", err)))?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    // trie.rs is also built into tests/roots.rs, so these tests only use the
+    // stores and external crates
+    use super::*;
+    use crate::old::CountingStore;
+    use gw_db::iter::IteratorMode;
+    use gw_store::Store as GwStore;
+    use rand_chacha::{
+        rand_core::{RngCore, SeedableRng},
+        ChaCha20Rng,
+    };
+    use sparse_merkle_tree::{blake2b::Blake2bHasher, SparseMerkleTree};
+    use std::collections::{BTreeMap, BTreeSet};
+
+    type CountingSmt<'a, DB> = SparseMerkleTree<Blake2bHasher, H256, CountingStore<'a, DB>>;
+    type TrieSmt<'a, DB> = SparseMerkleTree<Blake2bHasher, H256, TrieStore<'a, DB>>;
+
+    fn random_h256(rng: &mut ChaCha20Rng) -> H256 {
+        let mut buf = [0u8; 32];
+        rng.fill_bytes(&mut buf);
+        buf.into()
+    }
+
+    // Keys and values of a column, in key order
+    type Entries = Vec<(Box<[u8]>, Box<[u8]>)>;
+
+    fn column(store: &GwStore, col: u8) -> Entries {
+        let tx = store.begin_transaction();
+        tx.get_iter(col, IteratorMode::Start).collect()
+    }
+
+    // Every layout must map the 255 nodes of a trie onto distinct slots, produce
+    // the same root, and refuse blobs written by another layout.
+    #[test]
+    fn layouts() -> Result<(), String> {
+        for layout in TrieLayout::ALL {
+            let mut seen = [false; 255];
+            for inner_height in 0..8u8 {
+                for position in 0..(1usize << (7 - inner_height)) {
+                    let index = layout.index(inner_height, position);
+                    if index >= seen.len() || seen[index] {
+                        return Err(format!(
+                            "{:?} maps height {} position {} to taken slot {}",
+                            layout, inner_height, position, index
+                        ));
+                    }
+                    seen[index] = true;
+                }
+            }
+        }
+
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        let pairs: Vec<(H256, H256)> = (0..50)
+            .map(|_| (random_h256(&mut rng), random_h256(&mut rng)))
+            .collect();
+        let mut roots = vec![];
+        for layout in TrieLayout::ALL {
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let tx = store.begin_transaction();
+            let mut smt = TrieSmt::new(H256::zero(), TrieStore::with_layout(&tx, layout));
+            smt.update_all(pairs.clone())
+                .map_err(|err| format!("{:?}: {}", layout, err))?;
+            roots.push((layout, *smt.root()));
+
+            let other = TrieLayout::ALL
+                .iter()
+                .find(|other| **other != layout)
+                .cloned()
+                .unwrap();
+            let key = BranchKey::new(255, H256::zero());
+            if TrieStore::with_layout(&tx, other).get_branch(&key).is_ok() {
+                return Err(format!("{:?} read a blob written by {:?}", other, layout));
+            }
+        }
+        if roots.iter().any(|(_, root)| *root != roots[0].1) {
+            return Err(format!("roots differ: {:?}", roots));
+        }
+        Ok(())
+    }
+
+    // Every branch key rounding to one trie must get a slot of its own below
+    // 255, or two branches would overwrite each other. Enumerates all of them,
+    // for the bottom, a middle and the top trie of a random path: at inner
+    // height h the bits of the trie's byte above h are free, those below are
+    // zero, and the bytes above the trie's byte are the shared path.
+    #[test]
+    fn slot_indices() -> Result<(), String> {
+        let mut rng = ChaCha20Rng::seed_from_u64(2);
+        let path: [u8; 32] = random_h256(&mut rng).into();
+        for layout in TrieLayout::ALL {
+            for byte in [0usize, 15, 31] {
+                let rounded_height = (byte * 8 + 7) as u8;
+                let mut node_key = [0u8; 32];
+                node_key[byte + 1..].copy_from_slice(&path[byte + 1..]);
+                let rounded = BranchKey::new(rounded_height, H256::from(node_key));
+                let mut seen: Vec<Option<BranchKey>> = vec![None; 255];
+                for inner_height in 0..8u8 {
+                    for bits in 0..(1u16 << (7 - inner_height)) {
+                        node_key[byte] = (bits << (inner_height + 1)) as u8;
+                        let key =
+                            BranchKey::new(byte as u8 * 8 + inner_height, H256::from(node_key));
+                        if round_branch_key(&key) != rounded {
+                            return Err(format!("{:?} does not round to {:?}", key, rounded));
+                        }
+                        let index = calculate_index(layout, &rounded, &key);
+                        if index >= seen.len() {
+                            return Err(format!(
+                                "{:?} puts {:?} at slot {}, past the last one",
+                                layout, key, index
+                            ));
+                        }
+                        if let Some(other) = &seen[index] {
+                            return Err(format!(
+                                "{:?} puts {:?} and {:?} both at slot {}",
+                                layout, other, key, index
+                            ));
+                        }
+                        seen[index] = Some(key);
+                    }
+                }
+                if seen.iter().any(|slot| slot.is_none()) {
+                    return Err(format!(
+                        "{:?} leaves slots of the trie at height {} unused",
+                        layout, rounded_height
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Heights 248 to 255 share the topmost trie blob, indexed by the last byte
+    // of the node key. Branches at every one of those heights and for last
+    // bytes around the bit boundaries must read back after commit, and trees
+    // whose two leaves merge in that blob must get CountingStore's root.
+    #[test]
+    fn top_trie_heights() -> Result<(), String> {
+        let map_err = |err: sparse_merkle_tree::error::Error| err.to_string();
+        let last_bytes = [0x00u8, 0x01, 0x7f, 0x80, 0xfe, 0xff];
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let tx = store.begin_transaction();
+        let mut trie = TrieStore::new(&tx);
+        let mut expected = vec![];
+        for height in 248..=255u8 {
+            for last_byte in last_bytes {
+                let mut key = [0x33u8; 32];
+                key[31] = last_byte;
+                let branch_key = BranchKey::new(height, H256::from(key).parent_path(height));
+                let branch = BranchNode {
+                    left: MergeValue::Value(H256::from([height; 32])),
+                    right: MergeValue::Value(H256::from([last_byte; 32])),
+                };
+                trie.insert_branch(branch_key.clone(), branch.clone())
+                    .map_err(map_err)?;
+                // Keys equal above the height share the node, the last write wins
+                expected.retain(|(k, _): &(BranchKey, BranchNode)| *k != branch_key);
+                expected.push((branch_key, branch));
+            }
+        }
+        tx.commit().map_err(|err| err.to_string())?;
+        let tx = store.begin_transaction();
+        let trie = TrieStore::new(&tx);
+        for (key, branch) in &expected {
+            let loaded = trie.get_branch(key).map_err(map_err)?;
+            if loaded.as_ref() != Some(branch) {
+                return Err(format!(
+                    "branch {:?} read back as {:?}, expected {:?}",
+                    key, loaded, branch
+                ));
+            }
+        }
+
+        // The leaves differ only in one bit of the last byte, so they merge at
+        // 248 + that bit
+        for bit in 0..8u8 {
+            let mut key = [0x33u8; 32];
+            key[31] = 0;
+            let first = H256::from(key);
+            key[31] = 1 << bit;
+            let second = H256::from(key);
+            let pairs = vec![
+                (first, H256::from([1u8; 32])),
+                (second, H256::from([2u8; 32])),
+            ];
+            let counting_db = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let counting_tx = counting_db.begin_transaction();
+            let mut counting = CountingSmt::new(H256::zero(), CountingStore::new(&counting_tx));
+            counting.update_all(pairs.clone()).map_err(map_err)?;
+            let trie_db = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let trie_tx = trie_db.begin_transaction();
+            let mut trie = TrieSmt::new(H256::zero(), TrieStore::new(&trie_tx));
+            trie.update_all(pairs.clone()).map_err(map_err)?;
+            if trie.root() != counting.root() {
+                return Err(format!(
+                    "merge at height {}: root {:?}, CountingStore has {:?}",
+                    248 + bit,
+                    trie.root(),
+                    counting.root()
+                ));
+            }
+            for (key, value) in &pairs {
+                if trie.get(key).map_err(map_err)? != *value {
+                    return Err(format!(
+                        "merge at height {}: leaf {:?} lost",
+                        248 + bit,
+                        key
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // After remove_leaf or remove_branch the key must read back as None, for both
+    // stores. TrieStore must also keep the other branches of a partially emptied
+    // trie and delete the blob once its last branch is gone.
+    #[test]
+    fn removal_visibility() -> Result<(), String> {
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let tx = store.begin_transaction();
+        check_removal(&mut CountingStore::new(&tx))
+            .map_err(|err| format!("CountingStore: {}", err))?;
+
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let tx = store.begin_transaction();
+        let mut trie = TrieStore::new(&tx);
+        check_removal(&mut trie).map_err(|err| format!("TrieStore: {}", err))?;
+
+        let node_key = H256::from([0x5au8; 32]);
+        let first = BranchKey::new(1, node_key.parent_path(1));
+        let second = BranchKey::new(2, node_key.parent_path(2));
+        let branch = BranchNode {
+            left: MergeValue::Value(H256::from([1u8; 32])),
+            right: MergeValue::Value(H256::zero()),
+        };
+        let map_err = |err: sparse_merkle_tree::error::Error| err.to_string();
+        trie.insert_branch(first.clone(), branch.clone())
+            .map_err(map_err)?;
+        trie.insert_branch(second.clone(), branch.clone())
+            .map_err(map_err)?;
+        trie.remove_branch(&first).map_err(map_err)?;
+        if trie.get_branch(&first).map_err(map_err)?.is_some() {
+            return Err("TrieStore: removed slot still reports a branch".to_string());
+        }
+        if trie.get_branch(&second).map_err(map_err)? != Some(branch) {
+            return Err("TrieStore: removal cleared a sibling slot".to_string());
+        }
+        trie.remove_branch(&second).map_err(map_err)?;
+        let rounded = pack_key(&round_branch_key(&second));
+        if tx.get(0, rounded.as_slice()).is_some() {
+            return Err("TrieStore: empty trie blob was not deleted".to_string());
+        }
+        Ok(())
+    }
+
+    fn check_removal<S: Store<H256>>(store: &mut S) -> Result<(), String> {
+        let map_err = |err: sparse_merkle_tree::error::Error| err.to_string();
+        let leaf_key = H256::from([9u8; 32]);
+        store
+            .insert_leaf(leaf_key, H256::from([1u8; 32]))
+            .map_err(map_err)?;
+        store.remove_leaf(&leaf_key).map_err(map_err)?;
+        if let Some(leaf) = store.get_leaf(&leaf_key).map_err(map_err)? {
+            return Err(format!("removed leaf reads back as {:?}", leaf));
+        }
+
+        let key = BranchKey::new(200, H256::from([3u8; 32]).parent_path(200));
+        let branch = BranchNode {
+            left: MergeValue::Value(H256::from([1u8; 32])),
+            right: MergeValue::Value(H256::from([2u8; 32])),
+        };
+        store.insert_branch(key.clone(), branch).map_err(map_err)?;
+        store.remove_branch(&key).map_err(map_err)?;
+        if let Some(branch) = store.get_branch(&key).map_err(map_err)? {
+            return Err(format!("removed branch reads back as {:?}", branch));
+        }
+        Ok(())
+    }
+
+    // Pins the --trie-molecule-wrap encoding external scanners rely on: every
+    // branch column value is a molecule Bytes, a 4 byte little-endian length
+    // and then the unwrapped blob, byte for byte. Trees written either way must
+    // read back with the other setting.
+    #[test]
+    fn molecule_wrapped_tries() -> Result<(), String> {
+        if wrap_blob(&[1, 2, 3]) != [3, 0, 0, 0, 1, 2, 3] {
+            return Err(format!("[1, 2, 3] wrapped as {:?}", wrap_blob(&[1, 2, 3])));
+        }
+        // TRIE_SIZE is 0x837d, followed by the version byte of the heap layout
+        let golden = [0x7d, 0x83, 0x00, 0x00, 0x01];
+
+        let mut rng = ChaCha20Rng::seed_from_u64(3);
+        let pairs: Vec<(H256, H256)> = (0..20)
+            .map(|_| (random_h256(&mut rng), random_h256(&mut rng)))
+            .collect();
+        let mut contents = vec![];
+        let mut stores = vec![];
+        for wrap in [false, true] {
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let tx = store.begin_transaction();
+            let mut smt = TrieSmt::new(H256::zero(), TrieStore::new(&tx).molecule_wrap(wrap));
+            smt.update_all(pairs.clone())
+                .map_err(|err| err.to_string())?;
+            let root = *smt.root();
+            tx.commit().map_err(|err| err.to_string())?;
+            contents.push(column(&store, 0));
+            stores.push((store, root));
+        }
+        let (raw, wrapped) = (&contents[0], &contents[1]);
+        if raw.len() != wrapped.len() || raw.is_empty() {
+            return Err(format!(
+                "{} tries unwrapped, {} wrapped",
+                raw.len(),
+                wrapped.len()
+            ));
+        }
+        for ((raw_key, raw_blob), (key, blob)) in raw.iter().zip(wrapped) {
+            if raw_key != key || blob.len() != TRIE_SIZE + 4 || blob[..5] != golden {
+                return Err(format!(
+                    "trie {:?} stored as {} bytes starting with {:?}",
+                    key,
+                    blob.len(),
+                    &blob[..5.min(blob.len())]
+                ));
+            }
+            if blob[4..] != raw_blob[..] || unwrap_blob(blob) != &raw_blob[..] {
+                return Err(format!("trie {:?} wraps a different blob", key));
+            }
+            if trie_branches(blob).len() != trie_branches(raw_blob).len() {
+                return Err(format!("trie {:?} lists other branches wrapped", key));
+            }
+        }
+
+        // Each tree extended by a store with the other setting, so both kinds
+        // of blob are read, rewritten and read again
+        let more: Vec<(H256, H256)> = (0..10)
+            .map(|_| (random_h256(&mut rng), random_h256(&mut rng)))
+            .collect();
+        let mut roots = vec![];
+        for ((store, root), wrap) in stores.iter().zip([true, false]) {
+            let tx = store.begin_transaction();
+            let mut smt = TrieSmt::new(*root, TrieStore::new(&tx).molecule_wrap(wrap));
+            for (key, value) in &pairs {
+                if smt.get(key).map_err(|err| err.to_string())? != *value {
+                    return Err(format!("leaf {:?} lost reading with wrap {}", key, wrap));
+                }
+            }
+            smt.update_all(more.clone())
+                .map_err(|err| err.to_string())?;
+            roots.push(*smt.root());
+        }
+        if roots[0] != roots[1] {
+            return Err(format!("roots differ after mixing: {:?}", roots));
+        }
+        Ok(())
+    }
+
+    // Every blob a tree of inserts, updates and deletes leaves behind must
+    // validate, and
+    // a blob with a single byte of a slot changed to something no child is
+    // saved as must not: a tag other than 0 or 1, a zero count or padding on a
+    // value child.
+    #[test]
+    fn blob_validation() -> Result<(), String> {
+        let mut rng = ChaCha20Rng::seed_from_u64(4);
+        let keys: Vec<H256> = (0..40).map(|_| random_h256(&mut rng)).collect();
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let tx = store.begin_transaction();
+        let mut smt = TrieSmt::new(H256::zero(), TrieStore::new(&tx));
+        for (keys, delete) in [
+            (&keys[..], false),
+            (&keys[..20], false),
+            (&keys[10..], true),
+        ] {
+            let pairs = keys
+                .iter()
+                .map(|key| {
+                    (
+                        *key,
+                        if delete {
+                            H256::zero()
+                        } else {
+                            random_h256(&mut rng)
+                        },
+                    )
+                })
+                .collect();
+            smt.update_all(pairs).map_err(|err| err.to_string())?;
+        }
+        tx.commit().map_err(|err| err.to_string())?;
+        let layout = TrieLayout::default();
+        let blobs = column(&store, 0);
+        for (key, blob) in &blobs {
+            validate_trie(blob, layout).map_err(|err| format!("trie {:?}: {}", key, err))?;
+        }
+
+        let blob = &blobs.first().ok_or("no trie was written")?.1;
+        let slot = (0..NODES_PER_TRIE)
+            .map(node_offset)
+            .find(|offset| blob[*offset..*offset + NODE_SIZE].iter().any(|b| *b != 0))
+            .ok_or("the first trie is empty")?;
+        // A value child when the tag is 0, its zero count and the last byte of
+        // its padding
+        let value_child = if blob[slot] == 0 {
+            slot
+        } else {
+            slot + NODE_SIZE / 2
+        };
+        let mut corruptions = vec![("tag 2", slot, 2)];
+        if blob[value_child] == 0 {
+            corruptions.push(("value zero count", value_child + 1, 7));
+            corruptions.push(("value padding", value_child + NODE_SIZE / 2 - 1, 1));
+        }
+        for (name, offset, byte) in corruptions {
+            let mut corrupted = blob.to_vec();
+            corrupted[offset] = byte;
+            if validate_trie(&corrupted, layout).is_ok() {
+                return Err(format!("a blob with {} at byte {} validated", name, offset));
+            }
+        }
+        if validate_trie(&blob[..TRIE_SIZE - 1], layout).is_ok() {
+            return Err("a short blob validated".to_string());
+        }
+        Ok(())
+    }
+
+    // Leaves stored under 2 bytes of their key, ten of them sharing those
+    // bytes, must read, delete and insert again like leaves stored under their
+    // full key, giving the same roots. Every insert finding another key's leaf
+    // under its truncated key must be counted as a collision and stored under
+    // its full key.
+    #[test]
+    fn truncated_leaf_keys() -> Result<(), String> {
+        const BYTES: usize = 2;
+        let mut rng = ChaCha20Rng::seed_from_u64(5);
+        let pairs: Vec<(H256, H256)> = (0..30)
+            .map(|i| {
+                let mut key = [0u8; 32];
+                key.copy_from_slice(random_h256(&mut rng).as_slice());
+                if i < 10 {
+                    key[..BYTES].copy_from_slice(&[7, 7]);
+                }
+                (H256::from(key), random_h256(&mut rng))
+            })
+            .collect();
+        let prefixes: BTreeSet<&[u8]> = pairs
+            .iter()
+            .map(|(key, _)| &key.as_slice()[..BYTES])
+            .collect();
+        let collided = pairs.len() - prefixes.len();
+
+        let full = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let truncated = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let update = |store: &GwStore, bytes, root, pairs: &[(H256, H256)]| {
+            let tx = store.begin_transaction();
+            let mut smt = TrieSmt::new(root, TrieStore::new(&tx).truncate_leaf_keys(bytes));
+            smt.update_all(pairs.to_vec())
+                .map_err(|err| err.to_string())?;
+            let updated = (*smt.root(), smt.store().leaf_collisions());
+            tx.commit().map_err(|err| err.to_string())?;
+            Ok::<_, String>(updated)
+        };
+        let mut expected: BTreeMap<H256, H256> = BTreeMap::new();
+        let mut roots = (H256::zero(), H256::zero());
+        let mut step = |name: &str, pairs: &[(H256, H256)]| {
+            let (full_root, _) = update(&full, None, roots.0, pairs)?;
+            let (root, collisions) = update(&truncated, Some(BYTES), roots.1, pairs)?;
+            if root != full_root {
+                return Err(format!("{}: roots differ from full keys", name));
+            }
+            roots = (full_root, root);
+            expected.extend(pairs.iter().cloned());
+            let tx = truncated.begin_transaction();
+            let smt = TrieSmt::new(root, TrieStore::new(&tx).truncate_leaf_keys(Some(BYTES)));
+            for (key, value) in &expected {
+                if smt.get(key).map_err(|err| err.to_string())? != *value {
+                    return Err(format!("{}: leaf {:?} reads back wrong", name, key));
+                }
+            }
+            Ok(collisions)
+        };
+
+        let collisions = step("insert", &pairs)?;
+        if collisions != collided {
+            return Err(format!(
+                "{} collisions counted for {} keys sharing truncated keys",
+                collisions, collided
+            ));
+        }
+        let leaves = column(&truncated, 1);
+        let short = leaves.iter().filter(|(key, _)| key.len() == BYTES).count();
+        if short != prefixes.len() || leaves.len() != pairs.len() {
+            return Err(format!(
+                "{} leaves stored, {} under truncated keys, for {} keys with {} truncated keys",
+                leaves.len(),
+                short,
+                pairs.len(),
+                prefixes.len()
+            ));
+        }
+        // The key owning [7, 7], and another one stored under its full key
+        let owner = leaves
+            .iter()
+            .find(|(key, _)| key[..] == [7, 7])
+            .and_then(|(_, value)| {
+                pairs.iter().find(|(key, _)| {
+                    key.as_slice()[..BYTES] == [7, 7]
+                        && key.as_slice()[BYTES..] == value[..32 - BYTES]
+                })
+            })
+            .ok_or("no leaf of the shared truncated key found under it")?
+            .0;
+        let other = pairs[..10].iter().find(|(key, _)| *key != owner).unwrap().0;
+
+        step(
+            "delete",
+            &[(owner, H256::zero()), (other, random_h256(&mut rng))],
+        )?;
+        step("insert again", &[(owner, random_h256(&mut rng))])?;
+        Ok(())
+    }
+
+    // Materializing the slot operands of every branch of a trie must leave
+    // the blob rewriting it does, for every layout, and load back. An
+    // operand clearing a slot must leave it empty, and an operand of another
+    // layout or of the wrong size must fail the merge.
+    #[test]
+    fn slot_operands() -> Result<(), String> {
+        let mut rng = ChaCha20Rng::seed_from_u64(9);
+        let pairs: Vec<(H256, H256)> = (0..30)
+            .map(|_| (random_h256(&mut rng), random_h256(&mut rng)))
+            .collect();
+        let top = BranchKey::new(255, H256::zero());
+        for layout in TrieLayout::ALL {
+            let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            let tx = store.begin_transaction();
+            let mut smt = TrieSmt::new(H256::zero(), TrieStore::with_layout(&tx, layout));
+            smt.update_all(pairs.clone())
+                .map_err(|err| err.to_string())?;
+            let blob = tx
+                .get(0, pack_key(&top).as_slice())
+                .ok_or("no top trie was written")?;
+            let trie =
+                BranchTrie::load(&blob, top.clone(), layout).map_err(|err| err.to_string())?;
+            let operands: Vec<Vec<u8>> = (0..NODES_PER_TRIE)
+                .filter(|index| !trie.is_empty_slot(*index))
+                .map(|index| slot_operand(layout, index, Some(&trie.load_branch_node(index))))
+                .collect();
+            let materialized = materialize(None, operands.iter().map(Vec::as_slice))
+                .ok_or_else(|| format!("{:?}: the operands did not materialize", layout))?;
+            if materialized[..] != blob[..] {
+                return Err(format!(
+                    "{:?}: the operands materialized another blob",
+                    layout
+                ));
+            }
+
+            let clear = slot_operand(layout, trie.calculate_index(&top), None);
+            let cleared = materialize(Some(&materialized), [clear.as_slice()])
+                .ok_or_else(|| format!("{:?}: clearing a slot did not materialize", layout))?;
+            let cleared =
+                BranchTrie::load(&cleared, top.clone(), layout).map_err(|err| err.to_string())?;
+            if cleared
+                .get_branch(&top)
+                .map_err(|err| err.to_string())?
+                .is_some()
+            {
+                return Err(format!("{:?}: a cleared slot still holds a branch", layout));
+            }
+
+            let other = TrieLayout::ALL
+                .iter()
+                .find(|other| **other != layout)
+                .cloned()
+                .unwrap();
+            let foreign = slot_operand(other, 0, None);
+            if materialize(Some(&materialized), [foreign.as_slice()]).is_some()
+                || materialize(None, [&operands[0][..NODE_SIZE]]).is_some()
+            {
+                return Err(format!("{:?}: a malformed operand materialized", layout));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use crate::{
+    phase::Phase,
+    runner::{request_stop, ReportSink, RoundReport},
+};
+use std::collections::VecDeque;
+use std::io::{stdin, stdout, BufRead, Write};
+use std::thread;
+use std::time::Duration;
+
+const HISTORY: usize = 60;
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// Live dashboard redrawn in place after every round using plain ANSI escapes.
+// It only renders data carried by RoundReport.
+pub struct Dashboard {
+    latencies: VecDeque<Duration>,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        // Terminal stays in line mode, so stopping takes 'q' followed by
+        // Enter, or Ctrl-C as without the dashboard
+        thread::spawn(|| {
+            for line in stdin().lock().lines() {
+                match line {
+                    Ok(line) if line.trim() == "q" => {
+                        request_stop();
+                        break;
+                    }
+                    Ok(_) => (),
+                    Err(_) => break,
+                }
+            }
+        });
+        Self {
+            latencies: VecDeque::with_capacity(HISTORY),
+        }
+    }
+
+    fn sparkline(&self) -> String {
+        let max = self.latencies.iter().max().cloned().unwrap_or_default();
+        self.latencies
+            .iter()
+            .map(|latency| {
+                let ratio = latency.as_secs_f64() / max.as_secs_f64().max(f64::EPSILON);
+                BARS[((ratio * (BARS.len() - 1) as f64).round() as usize).min(BARS.len() - 1)]
+            })
+            .collect()
+    }
+}
+
+impl ReportSink for Dashboard {
+    fn round(&mut self, phase: &Phase, index: usize, round: &RoundReport) {
+        if self.latencies.len() == HISTORY {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(round.elapsed);
+
+        let throughput = round.ops as f64 / round.elapsed.as_secs_f64().max(f64::EPSILON);
+        let mut out = stdout();
+        // Move the cursor home and clear the screen before redrawing
+        write!(out, "\x1b[H\x1b[2J").unwrap();
+        writeln!(out, "Phase {}  round {}/{}", phase, index + 1, phase.rounds).unwrap();
+        writeln!(out, "Latency    {} {:?}", self.sparkline(), round.elapsed).unwrap();
+        writeln!(out, "Throughput {:.0} ops/s", throughput).unwrap();
+        writeln!(out, "Reads {}  writes {}", round.reads, round.writes).unwrap();
+        match (round.block_cache_hits, round.block_cache_misses) {
+            (Some(hits), Some(misses)) if hits + misses > 0 => writeln!(
+                out,
+                "Cache hit rate {:.1}% ({} hits, {} misses)",
+                hits as f64 * 100.0 / (hits + misses) as f64,
+                hits,
+                misses
+            ),
+            (Some(_), Some(_)) => writeln!(out, "Cache hit rate n/a, no block reads"),
+            _ => writeln!(out, "Cache hit rate n/a, RocksDB statistics are off"),
+        }
+        .unwrap();
+        match round.db_bytes {
+            Some(bytes) => writeln!(out, "DB size {:.1} MiB", bytes as f64 / (1 << 20) as f64),
+            None => writeln!(out, "DB size n/a"),
+        }
+        .unwrap();
+        writeln!(out, "Type q and Enter to stop after this round").unwrap();
+        out.flush().unwrap();
+    }
+}
@@ -4,22 +4,117 @@ use sparse_merkle_tree::{
     tree::{BranchKey, BranchNode},
     H256,
 };
+#[cfg(any(feature = "conversion-stats", feature = "serde-timing"))]
+use std::cell::Cell;
+#[cfg(feature = "serde-timing")]
+use std::time::Instant;
+
+// Size of a packed SMTBranchKey, the height byte and the node key
+pub const PACKED_KEY_SIZE: usize = 33;
+
+#[cfg(feature = "conversion-stats")]
+thread_local! {
+    // Conversions done by this thread and the bytes they copied
+    static CONVERSIONS: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+// Records one conversion copying bytes, with the conversion-stats feature
+#[inline]
+fn count_conversion(_bytes: usize) {
+    #[cfg(feature = "conversion-stats")]
+    CONVERSIONS.with(|conversions| {
+        let (calls, bytes) = conversions.get();
+        conversions.set((calls + 1, bytes + _bytes));
+    });
+}
+
+// Conversions counted on the calling thread so far and the bytes they
+// copied, always zero without the conversion-stats feature
+pub fn conversion_stats() -> (usize, usize) {
+    #[cfg(feature = "conversion-stats")]
+    return CONVERSIONS.with(|conversions| conversions.get());
+    #[cfg(not(feature = "conversion-stats"))]
+    (0, 0)
+}
+
+#[cfg(feature = "serde-timing")]
+thread_local! {
+    // Nanoseconds this thread spent encoding and decoding branches and keys
+    static SERDE_NANOS: Cell<u64> = const { Cell::new(0) };
+}
+
+// Runs f, adding the time it took to the serialization time of the thread
+// with the serde-timing feature
+#[inline]
+pub fn time_serde<T>(f: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "serde-timing")]
+    {
+        let start = Instant::now();
+        let result = f();
+        let nanos = start.elapsed().as_nanos() as u64;
+        SERDE_NANOS.with(|total| total.set(total.get() + nanos));
+        result
+    }
+    #[cfg(not(feature = "serde-timing"))]
+    f()
+}
+
+// Serialization time of the calling thread so far in nanoseconds, always
+// zero without the serde-timing feature
+pub fn serde_nanos() -> u64 {
+    #[cfg(feature = "serde-timing")]
+    return SERDE_NANOS.with(|total| total.get());
+    #[cfg(not(feature = "serde-timing"))]
+    0
+}
+
+// Sets the serialization time back to an earlier serde_nanos, dropping
+// what was timed since
+pub fn rewind_serde_nanos(_nanos: u64) {
+    #[cfg(feature = "serde-timing")]
+    SERDE_NANOS.with(|total| total.set(_nanos));
+}
+
+fn h256_bytes(value: &H256) -> [u8; 32] {
+    count_conversion(32);
+    (*value).into()
+}
+
+fn pack_byte32(value: [u8; 32]) -> packed::Byte32 {
+    count_conversion(32);
+    value.pack()
+}
 
 // Simulating Pack/Unpack trait impls
 pub fn pack_key(key: &BranchKey) -> packed::SMTBranchKey {
-    let height = key.height.into();
-    let node_key: [u8; 32] = key.node_key.into();
+    time_serde(|| {
+        let height = key.height.into();
+        let node_key = h256_bytes(&key.node_key);
 
-    packed::SMTBranchKey::new_builder()
-        .height(height)
-        .node_key(node_key.pack())
-        .build()
+        let packed = packed::SMTBranchKey::new_builder()
+            .height(height)
+            .node_key(pack_byte32(node_key))
+            .build();
+        count_conversion(PACKED_KEY_SIZE);
+        packed
+    })
+}
+
+// The bytes of pack_key written straight from the key, without going
+// through [u8; 32] and the molecule builder
+pub fn pack_key_direct(key: &BranchKey) -> [u8; PACKED_KEY_SIZE] {
+    let mut packed = [0u8; PACKED_KEY_SIZE];
+    packed[0] = key.height;
+    packed[1..].copy_from_slice(key.node_key.as_slice());
+    count_conversion(PACKED_KEY_SIZE);
+    packed
 }
 
 pub fn unpack_h256(value: &packed::Byte32Reader) -> H256 {
-    let ptr = value.as_slice().as_ptr() as *const [u8; 32];
-    let r = unsafe { *ptr };
-    r.into()
+    count_conversion(32);
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(value.as_slice());
+    buf.into()
 }
 
 pub fn unpack_merge_value(value: &packed::SMTMergeValueReader) -> MergeValue {
@@ -38,11 +133,12 @@ pub fn unpack_merge_value(value: &packed::SMTMergeValueReader) -> MergeValue {
 }
 
 pub fn pack_merge_value(value: &MergeValue) -> packed::SMTMergeValue {
-    match value {
+    let packed = match value {
         MergeValue::Value(value) => {
             let smt_value = packed::SMTValue::new_builder()
-                .value(Into::<[u8; 32]>::into(*value).pack())
+                .value(pack_byte32(h256_bytes(value)))
                 .build();
+            count_conversion(smt_value.as_slice().len());
 
             packed::SMTMergeValue::new_builder()
                 .set(packed::SMTMergeValueUnion::SMTValue(smt_value))
@@ -54,10 +150,11 @@ pub fn pack_merge_value(value: &MergeValue) -> packed::SMTMergeValue {
             zero_count,
         } => {
             let merge_with_zero = packed::SMTMergeWithZero::new_builder()
-                .base_node(Into::<[u8; 32]>::into(*base_node).pack())
-                .zero_bits(Into::<[u8; 32]>::into(*zero_bits).pack())
+                .base_node(pack_byte32(h256_bytes(base_node)))
+                .zero_bits(pack_byte32(h256_bytes(zero_bits)))
                 .zero_count(Into::<packed::Byte>::into(*zero_count))
                 .build();
+            count_conversion(merge_with_zero.as_slice().len());
 
             packed::SMTMergeValue::new_builder()
                 .set(packed::SMTMergeValueUnion::SMTMergeWithZero(
@@ -65,19 +162,77 @@ pub fn pack_merge_value(value: &MergeValue) -> packed::SMTMergeValue {
                 ))
                 .build()
         }
-    }
+    };
+    count_conversion(packed.as_slice().len());
+    packed
 }
 
 pub fn pack_branch(branch: &BranchNode) -> packed::SMTBranchNode {
-    packed::SMTBranchNode::new_builder()
-        .left(pack_merge_value(&branch.left))
-        .right(pack_merge_value(&branch.right))
-        .build()
+    time_serde(|| {
+        let packed = packed::SMTBranchNode::new_builder()
+            .left(pack_merge_value(&branch.left))
+            .right(pack_merge_value(&branch.right))
+            .build();
+        count_conversion(packed.as_slice().len());
+        packed
+    })
 }
 
 pub fn unpack_branch(branch: &packed::SMTBranchNodeReader) -> BranchNode {
-    BranchNode {
+    time_serde(|| BranchNode {
         left: unpack_merge_value(&branch.left()),
         right: unpack_merge_value(&branch.right()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The packed key must be the height byte followed by the 32 node_key bytes,
+    // for every boundary height, and pack_key_direct must write the same bytes.
+    #[test]
+    fn pack_key_round_trip() -> Result<(), String> {
+        let mut node_key = [0u8; 32];
+        for (i, b) in node_key.iter_mut().enumerate() {
+            *b = i as u8 ^ 0xa5;
+        }
+        for height in [0u8, 127, 255] {
+            let key = BranchKey::new(height, H256::from(node_key));
+            let packed = pack_key(&key);
+            let slice = packed.as_slice();
+            if slice.len() != 33 || slice[0] != height || slice[1..] != node_key[..] {
+                return Err(format!("height {} packed as {:?}", height, slice));
+            }
+            let reader = packed::SMTBranchKeyReader::from_slice_should_be_ok(slice);
+            let unpacked = BranchKey::new(reader.height().into(), unpack_h256(&reader.node_key()));
+            if unpacked != key {
+                return Err(format!("height {} unpacked as {:?}", height, unpacked));
+            }
+            if pack_key_direct(&key) != slice {
+                return Err(format!("height {} packed directly differs", height));
+            }
+        }
+        Ok(())
+    }
+
+    // unpack_h256 must read the 32 bytes of a Byte32 wherever they sit in
+    // memory, at every alignment.
+    #[test]
+    fn unaligned_unpack_h256() -> Result<(), String> {
+        let buf: Vec<u8> = (0..40u8).collect();
+        for offset in 0..8 {
+            let bytes = &buf[offset..offset + 32];
+            let reader = packed::Byte32Reader::from_slice_should_be_ok(bytes);
+            let unpacked = unpack_h256(&reader);
+            if unpacked.as_slice() != bytes {
+                return Err(format!(
+                    "offset {} unpacked as {:?}",
+                    offset,
+                    unpacked.as_slice()
+                ));
+            }
+        }
+        Ok(())
     }
 }
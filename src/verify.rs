@@ -0,0 +1,124 @@
+use crate::{
+    config::{Config, OutputFormat},
+    markdown, random_h256,
+    seed::SeedTree,
+};
+use gw_common::blake2b::new_blake2b;
+use rand_chacha::rand_core::RngCore;
+use sparse_merkle_tree::{
+    blake2b::Blake2bHasher, default_store::DefaultStore, SparseMerkleTree, H256,
+};
+use std::time::{Duration, Instant};
+
+type MemorySMT = SparseMerkleTree<Blake2bHasher, H256, DefaultStore<H256>>;
+
+// Leaves whose proofs are verified for every value size
+const PROOFS: usize = 1000;
+
+fn hash_preimage(preimage: &[u8]) -> H256 {
+    let mut value = [0u8; 32];
+    let mut hasher = new_blake2b();
+    hasher.update(preimage);
+    hasher.finalize(&mut value);
+    value.into()
+}
+
+// Measures what verifying a single-leaf proof costs when the verifier holds
+// the raw record and has to hash it into the leaf value first. The tree
+// lives in memory, only verification is timed, and for each of
+// --value-sizes the proven leaves get values hashed from preimages of that
+// size, so the sibling hashing stays the same across sizes.
+pub fn value_size_sweep(config: &Config) {
+    let seeds = SeedTree::new(config.seed, config.verbose).child("verify-sizes");
+    let mut rng = seeds.rng();
+    let pairs: Vec<(H256, H256)> = (0..config.verify_leaves.max(PROOFS))
+        .map(|_| (random_h256(&mut rng), random_h256(&mut rng)))
+        .collect();
+    let mut smt = MemorySMT::default();
+    smt.update_all(pairs.clone()).unwrap();
+    let keys: Vec<H256> = pairs.iter().take(PROOFS).map(|(key, _)| *key).collect();
+
+    let markdown = config.output == OutputFormat::Markdown;
+    let header = [
+        "value size",
+        "preimage hash",
+        "proof verify",
+        "total",
+        "hash share",
+    ];
+    let mut rows = vec![];
+    if markdown {
+        markdown::print_preamble(config);
+    } else {
+        println!(
+            "Verifying {} proofs in a tree of {} leaves",
+            PROOFS,
+            pairs.len()
+        );
+        println!(
+            "{:>12}{:>16}{:>16}{:>16}{:>12}",
+            header[0], header[1], header[2], header[3], header[4]
+        );
+    }
+    for size in &config.value_sizes {
+        let preimages: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|_| {
+                let mut preimage = vec![0u8; *size];
+                rng.fill_bytes(&mut preimage);
+                preimage
+            })
+            .collect();
+        let updates = keys
+            .iter()
+            .zip(&preimages)
+            .map(|(key, preimage)| (*key, hash_preimage(preimage)))
+            .collect();
+        smt.update_all(updates).unwrap();
+        let root = *smt.root();
+
+        let mut hash_elapsed = Duration::default();
+        let mut verify_elapsed = Duration::default();
+        for (key, preimage) in keys.iter().zip(&preimages) {
+            let proof = smt.merkle_proof(vec![*key]).unwrap();
+            let start = Instant::now();
+            let value = hash_preimage(preimage);
+            hash_elapsed += start.elapsed();
+            let start = Instant::now();
+            let valid = proof.verify::<Blake2bHasher>(&root, vec![(*key, value)]);
+            verify_elapsed += start.elapsed();
+            if !valid.unwrap_or(false) {
+                panic!("Proof of {:?} with a {} byte value failed", key, size);
+            }
+        }
+        let total = hash_elapsed + verify_elapsed;
+        let row = vec![
+            size.to_string(),
+            format!("{:.2?}", hash_elapsed / PROOFS as u32),
+            format!("{:.2?}", verify_elapsed / PROOFS as u32),
+            format!("{:.2?}", total / PROOFS as u32),
+            format!(
+                "{:.1}%",
+                hash_elapsed.as_secs_f64() * 100.0 / total.as_secs_f64().max(f64::EPSILON)
+            ),
+        ];
+        if markdown {
+            rows.push(row);
+        } else {
+            println!(
+                "{:>12}{:>16}{:>16}{:>16}{:>12}",
+                row[0], row[1], row[2], row[3], row[4]
+            );
+        }
+    }
+    if markdown {
+        let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+        markdown::print_table(&header, &rows);
+        println!();
+        println!(
+            "{} proofs verified in a tree of {} leaves",
+            PROOFS,
+            pairs.len()
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{
+    config::OnViolation,
+    handles,
+    phase::Phase,
+    runner::{ReportSink, RoundReport},
+};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+// Monitors a violation comes from
+pub const CONTRACT: &str = "contract";
+pub const COMMIT_VERIFY: &str = "commit-verify";
+pub const PROOF: &str = "proof";
+
+// Violations tallied by the process so far, by monitor, with the first of
+// each, for the summary at exit
+static TALLIED: Mutex<BTreeMap<&'static str, (usize, String)>> = Mutex::new(BTreeMap::new());
+
+// A correctness monitor that fired, kept with --on-violation tally
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub kind: &'static str,
+    // Run-wide number of the round, as in --lineage
+    pub round: u64,
+    pub key: Option<String>,
+    pub details: String,
+}
+
+impl Violation {
+    pub(crate) fn to_json(&self) -> String {
+        let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+        format!(
+            "{{\"type\": \"{}\", \"round\": {}, \"key\": {}, \"details\": \"{}\"}}",
+            self.kind,
+            self.round,
+            self.key
+                .as_ref()
+                .map_or("null".to_string(), |key| format!("\"{}\"", escape(key))),
+            escape(&self.details)
+        )
+    }
+}
+
+// Shared by the runner and the stores it wraps. In abort mode raise panics
+// with the details, as every monitor did before --on-violation; in tally
+// mode it records the violation for the round in progress and the run
+// goes on.
+pub struct Monitor {
+    tally: bool,
+    round: Cell<u64>,
+    pending: RefCell<Vec<Violation>>,
+}
+
+impl Monitor {
+    pub fn new(mode: OnViolation) -> Self {
+        Self {
+            tally: mode == OnViolation::Tally,
+            round: Cell::new(0),
+            pending: RefCell::default(),
+        }
+    }
+
+    pub fn start_round(&self, round: u64) {
+        self.round.set(round);
+    }
+
+    pub fn raise(&self, kind: &'static str, key: Option<String>, details: String) {
+        if !self.tally {
+            panic!("{}", details);
+        }
+        TALLIED
+            .lock()
+            .unwrap()
+            .entry(kind)
+            .or_insert_with(|| (0, details.clone()))
+            .0 += 1;
+        self.pending.borrow_mut().push(Violation {
+            kind,
+            round: self.round.get(),
+            key,
+            details,
+        });
+    }
+
+    // The violations raised since the last call
+    pub fn take(&self) -> Vec<Violation> {
+        self.pending.take()
+    }
+}
+
+// The ndjson file of --violations-out: every violation of a round as a
+// line, written through as the round completes
+pub struct ViolationLog {
+    out: BufWriter<File>,
+}
+
+impl ViolationLog {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+        })
+    }
+}
+
+impl ReportSink for ViolationLog {
+    fn round(&mut self, _phase: &Phase, _index: usize, round: &RoundReport) {
+        for violation in &round.violations {
+            writeln!(self.out, "{}", violation.to_json())
+                .unwrap_or_else(|err| panic!("Cannot write --violations-out: {}", err));
+        }
+        self.out
+            .flush()
+            .unwrap_or_else(|err| panic!("Cannot write --violations-out: {}", err));
+    }
+}
+
+// Created in main right after the handles::ExitCheck, so it is dropped
+// just before it: at exit with --on-violation tally, prints the violations
+// of the run by monitor and fails the process if there were any, reporting
+// the live handles first as the exit skips the check of handles
+pub struct ExitCheck;
+
+impl Drop for ExitCheck {
+    fn drop(&mut self) {
+        let tallied = TALLIED.lock().unwrap();
+        if tallied.is_empty() {
+            return;
+        }
+        eprintln!(
+            "Violations: {} tallied, the numbers of this run are not to be trusted",
+            tallied.values().map(|(n, _)| n).sum::<usize>()
+        );
+        for (kind, (n, first)) in tallied.iter() {
+            eprintln!("  {}: {}, first: {}", kind, n, first);
+        }
+        if handles::enabled() {
+            handles::report("at exit");
+        }
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contract::{ContractCheckStore, ContractModel},
+        phase::PhaseKind,
+        random_h256,
+        seed::SeedTree,
+        trie::TrieStore,
+    };
+    use gw_store::Store as GwStore;
+    use sparse_merkle_tree::{traits::Store, H256};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    // A leaf written behind the back of ContractCheckStore must abort the read
+    // by default, and with a tally monitor be recorded for the round instead
+    // and reach the ndjson log of the round as one line.
+    #[test]
+    fn tally() -> Result<(), String> {
+        let map_err = |err: sparse_merkle_tree::error::Error| err.to_string();
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let tx = store.begin_transaction();
+        let key = random_h256(&mut SeedTree::new(0, false).child("violation-tally").rng());
+        TrieStore::new(&tx)
+            .insert_leaf(key, H256::from([1u8; 32]))
+            .map_err(map_err)?;
+        let checked = |mode| {
+            let model = Rc::new(RefCell::new(ContractModel::new(true)));
+            let monitor = Rc::new(Monitor::new(mode));
+            monitor.start_round(7);
+            let store = ContractCheckStore::new(TrieStore::new(&tx), Some(model))
+                .with_monitor(monitor.clone());
+            (store, monitor)
+        };
+
+        let (store, _) = checked(OnViolation::Abort);
+        if panic::catch_unwind(AssertUnwindSafe(|| store.get_leaf(&key))).is_ok() {
+            return Err("abort mode read a stray leaf without panicking".to_string());
+        }
+        let (store, monitor) = checked(OnViolation::Tally);
+        store.get_leaf(&key).map_err(map_err)?;
+        store.get_leaf(&key).map_err(map_err)?;
+        let tallied = monitor.take();
+        if tallied.len() != 2 || tallied.iter().any(|v| v.kind != CONTRACT || v.round != 7) {
+            return Err(format!("two stray reads in round 7 tallied {:?}", tallied));
+        }
+        if !monitor.take().is_empty() {
+            return Err("violations tallied again after being taken".to_string());
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "smt-bench-violations-{}.ndjson",
+            std::process::id()
+        ));
+        let mut log = ViolationLog::create(&path).map_err(|err| err.to_string())?;
+        let round = RoundReport {
+            violations: tallied.clone(),
+            ..Default::default()
+        };
+        log.round(&Phase::new(PhaseKind::Read, 1, 1), 0, &round);
+        drop(log);
+        let written = std::fs::read_to_string(&path).map_err(|err| err.to_string())?;
+        std::fs::remove_file(&path).map_err(|err| err.to_string())?;
+        let expected: String = tallied.iter().map(|v| v.to_json() + "\n").collect();
+        if written != expected || !expected.contains("\"type\": \"contract\", \"round\": 7") {
+            return Err(format!("violation log holds {:?}", written));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,395 @@
+use crate::{config::Config, results::write_varint, store_config};
+use gw_db::{
+    error::Error,
+    iter::{DBIter, IteratorMode},
+    schema::Col,
+    RocksDB,
+};
+use gw_store::{traits::KVStore, Store as GwStore};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// A write dump is MAGIC and a format version byte, then in version 1 one
+// record per store write: a tag byte, PUT followed by the column byte, the
+// key and the value, DELETE by the column byte and the key, and COMMIT
+// alone closing the transaction the records since the previous one belong
+// to. Keys and values are a varint length and bytes, varints unsigned
+// LEB128 as in results files.
+const MAGIC: &[u8; 7] = b"SMTWRT\0";
+const VERSION: u8 = 1;
+const PUT: u8 = 0;
+const DELETE: u8 = 1;
+const COMMIT: u8 = 2;
+
+pub enum Record {
+    Put(Col, Vec<u8>, Vec<u8>),
+    Delete(Col, Vec<u8>),
+}
+
+// Writes of the committed transactions of a run, for --dump-writes. The
+// writes of the open transaction are held back until it commits, so those
+// of a transaction rolled back or dropped never reach the file.
+pub struct WriteDump {
+    out: BufWriter<File>,
+    pending: Vec<u8>,
+    pub transactions: usize,
+}
+
+impl WriteDump {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        Ok(Self {
+            out,
+            pending: vec![],
+            transactions: 0,
+        })
+    }
+
+    // Drops the writes of a transaction that did not commit
+    pub fn begin(&mut self) {
+        self.pending.clear();
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        write_varint(&mut self.pending, bytes.len() as u64).unwrap();
+        self.pending.extend_from_slice(bytes);
+    }
+
+    pub fn put(&mut self, col: Col, key: &[u8], value: &[u8]) {
+        self.pending.extend_from_slice(&[PUT, col]);
+        self.bytes(key);
+        self.bytes(value);
+    }
+
+    pub fn delete(&mut self, col: Col, key: &[u8]) {
+        self.pending.extend_from_slice(&[DELETE, col]);
+        self.bytes(key);
+    }
+
+    pub fn commit(&mut self) -> io::Result<()> {
+        self.out.write_all(&self.pending)?;
+        self.out.write_all(&[COMMIT])?;
+        self.pending.clear();
+        self.transactions += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// KVStore decorator adding every insert and delete that went through to a
+// WriteDump, passing everything through unchanged without one
+pub struct DumpStore<DB: KVStore> {
+    inner: DB,
+    dump: Option<Rc<RefCell<WriteDump>>>,
+}
+
+impl<DB: KVStore> DumpStore<DB> {
+    pub fn new(inner: DB, dump: Option<Rc<RefCell<WriteDump>>>) -> Self {
+        Self { inner, dump }
+    }
+}
+
+impl<DB: KVStore> KVStore for DumpStore<DB> {
+    fn get(&self, col: Col, key: &[u8]) -> Option<Box<[u8]>> {
+        self.inner.get(col, key)
+    }
+
+    fn get_iter(&self, col: Col, mode: IteratorMode) -> DBIter<'_> {
+        self.inner.get_iter(col, mode)
+    }
+
+    fn insert_raw(&self, col: Col, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.inner.insert_raw(col, key, value)?;
+        if let Some(dump) = &self.dump {
+            dump.borrow_mut().put(col, key, value);
+        }
+        Ok(())
+    }
+
+    fn delete(&self, col: Col, key: &[u8]) -> Result<(), Error> {
+        self.inner.delete(col, key)?;
+        if let Some(dump) = &self.dump {
+            dump.borrow_mut().delete(col, key);
+        }
+        Ok(())
+    }
+}
+
+// Transactions of a write dump decoded one at a time, so a dump of any
+// size is read in bounded memory
+pub struct Transactions {
+    input: BufReader<File>,
+}
+
+impl Transactions {
+    fn byte(&mut self) -> Result<u8, String> {
+        let mut byte = [0u8; 1];
+        self.input
+            .read_exact(&mut byte)
+            .map_err(|err| err.to_string())?;
+        Ok(byte[0])
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, String> {
+        let mut len = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            len |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                let mut bytes = vec![];
+                (&mut self.input)
+                    .take(len)
+                    .read_to_end(&mut bytes)
+                    .map_err(|err| err.to_string())?;
+                if bytes.len() as u64 != len {
+                    return Err(format!("{} of {} bytes before the end", bytes.len(), len));
+                }
+                return Ok(bytes);
+            }
+        }
+        Err("varint too long".to_string())
+    }
+
+    // The records of the next transaction, None at the end of the dump
+    fn read_v1(&mut self) -> Result<Option<Vec<Record>>, String> {
+        let mut records = vec![];
+        loop {
+            let at_end = self
+                .input
+                .fill_buf()
+                .map_err(|err| err.to_string())?
+                .is_empty();
+            if at_end && records.is_empty() {
+                return Ok(None);
+            }
+            if at_end {
+                return Err(format!("{} records after the last commit", records.len()));
+            }
+            match self.byte()? {
+                PUT => {
+                    let col = self.byte()?;
+                    let key = self.bytes()?;
+                    records.push(Record::Put(col, key, self.bytes()?));
+                }
+                DELETE => {
+                    let col = self.byte()?;
+                    records.push(Record::Delete(col, self.bytes()?));
+                }
+                COMMIT => return Ok(Some(records)),
+                tag => return Err(format!("unknown record tag {}", tag)),
+            }
+        }
+    }
+}
+
+impl Iterator for Transactions {
+    type Item = Result<Vec<Record>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_v1().transpose()
+    }
+}
+
+// Opens a write dump of any version for reading its transactions
+pub fn open(path: &Path) -> Result<Transactions, String> {
+    let file = File::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut transactions = Transactions {
+        input: BufReader::new(file),
+    };
+    let mut magic = [0u8; MAGIC.len()];
+    transactions
+        .input
+        .read_exact(&mut magic)
+        .map_err(|_| format!("{} is not a write dump", path.display()))?;
+    if &magic != MAGIC {
+        return Err(format!("{} is not a write dump", path.display()));
+    }
+    match transactions.byte()? {
+        1 => Ok(transactions),
+        version => Err(format!(
+            "{} has format version {}, this build reads up to {}",
+            path.display(),
+            version,
+            VERSION
+        )),
+    }
+}
+
+// Decodes a write dump of any version into its transactions
+pub fn read(path: &Path) -> Result<Vec<Vec<Record>>, String> {
+    open(path)?.collect()
+}
+
+#[derive(Default)]
+pub struct Replay {
+    pub transactions: usize,
+    pub puts: usize,
+    pub deletes: usize,
+    pub bytes: usize,
+    pub elapsed: Duration,
+    // Longest transaction, commit included
+    pub slowest: Duration,
+}
+
+// Applies the transactions to store one after the other, each in its own
+// store transaction, and times them. Decoding is done before, so only the
+// store is timed.
+pub fn replay(store: &GwStore, transactions: &[Vec<Record>]) -> Replay {
+    let mut replay = Replay::default();
+    let start = Instant::now();
+    for records in transactions {
+        let tx_start = Instant::now();
+        let tx = store.begin_transaction();
+        for record in records {
+            match record {
+                Record::Put(col, key, value) => {
+                    tx.insert_raw(*col, key, value).unwrap();
+                    replay.puts += 1;
+                    replay.bytes += key.len() + value.len();
+                }
+                Record::Delete(col, key) => {
+                    tx.delete(*col, key).unwrap();
+                    replay.deletes += 1;
+                    replay.bytes += key.len();
+                }
+            }
+        }
+        tx.commit().unwrap();
+        replay.transactions += 1;
+        replay.slowest = replay.slowest.max(tx_start.elapsed());
+    }
+    replay.elapsed = start.elapsed();
+    replay
+}
+
+// Replays the write dump given to replay-writes against a fresh database at
+// --db-path, opened with the options of the command line
+pub fn run(config: &Config) {
+    let path = config
+        .input_file
+        .as_ref()
+        .expect("replay-writes requires a write dump");
+    let transactions = read(path).unwrap_or_else(|err| {
+        eprintln!("Cannot read the write dump: {}", err);
+        std::process::exit(1);
+    });
+    if config.db_path.exists() {
+        panic!(
+            "{} already exists, replay-writes needs a fresh database",
+            config.db_path.display()
+        );
+    }
+    let db = RocksDB::open(
+        &store_config(config, config.db_path.clone()),
+        config.columns,
+    );
+    let store = GwStore::new(db);
+    let replay = replay(&store, &transactions);
+    let secs = replay.elapsed.as_secs_f64().max(f64::EPSILON);
+    println!(
+        "Replayed {} transactions, {} puts and {} deletes of {:.1} MiB, into {} in {:?}",
+        replay.transactions,
+        replay.puts,
+        replay.deletes,
+        replay.bytes as f64 / (1 << 20) as f64,
+        config.db_path.display(),
+        replay.elapsed
+    );
+    println!(
+        "  {:.0} writes/s, {:.1} MiB/s, mean transaction {:?}, slowest {:?}",
+        (replay.puts + replay.deletes) as f64 / secs,
+        replay.bytes as f64 / (1 << 20) as f64 / secs,
+        replay.elapsed / replay.transactions.max(1) as u32,
+        replay.slowest
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        phase::{Phase, PhaseKind},
+        runner::{tests::serial, Runner},
+        seed::SeedTree,
+    };
+
+    // Keys and values of a column, in key order
+    type Entries = Vec<(Box<[u8]>, Box<[u8]>)>;
+
+    fn store_contents(store: &GwStore, columns: u32) -> Vec<Entries> {
+        let tx = store.begin_transaction();
+        (0..columns as u8)
+            .map(|col| tx.get_iter(col, IteratorMode::Start).collect())
+            .collect()
+    }
+
+    // Replaying the write dump of a run with injected faults on a fresh
+    // database must rebuild the database of the run exactly, the writes of the
+    // transactions rolled back included nowhere. A truncated dump and one of a
+    // later version must be refused.
+    #[test]
+    fn write_dump_replay() -> Result<(), String> {
+        let _serial = serial();
+        let path =
+            std::env::temp_dir().join(format!("smt-bench-writes-{}.bin", std::process::id()));
+        let config = Config {
+            report_every: 0,
+            fault_rate: 0.00005,
+            dump_writes: Some(path.clone()),
+            ..Default::default()
+        };
+        let store = GwStore::open_tmp().map_err(|err| err.to_string())?;
+        let mut runner = Runner::new(&config, &store, SeedTree::new(0, false));
+        let mut rolled_back = 0;
+        for phase in [
+            Phase::new(PhaseKind::Load, 2, 20),
+            Phase::new(PhaseKind::Update, 2, 10),
+            Phase::new(PhaseKind::Delete, 1, 5),
+        ] {
+            let report = runner.run_phase(&phase);
+            rolled_back += report.rounds.iter().filter(|r| r.rolled_back).count();
+        }
+        runner.finish();
+        let data = std::fs::read(&path).map_err(|err| err.to_string())?;
+        let outcome = (|| {
+            let transactions = read(&path).map_err(|err| format!("written dump: {}", err))?;
+            let replayed = GwStore::open_tmp().map_err(|err| err.to_string())?;
+            replay(&replayed, &transactions);
+            let (expected, actual) = (
+                store_contents(&store, config.columns),
+                store_contents(&replayed, config.columns),
+            );
+            if let Some(col) = (0..expected.len()).find(|col| expected[*col] != actual[*col]) {
+                return Err(format!(
+                    "column {} holds {} entries after the replay, {} after the run ({} rounds rolled back)",
+                    col,
+                    actual[col].len(),
+                    expected[col].len(),
+                    rolled_back
+                ));
+            }
+            std::fs::write(&path, &data[..data.len() - 2]).map_err(|err| err.to_string())?;
+            if read(&path).is_ok() {
+                return Err("a truncated dump was read".to_string());
+            }
+            let mut later = data.clone();
+            later[7] += 1;
+            std::fs::write(&path, &later).map_err(|err| err.to_string())?;
+            if read(&path).is_ok() {
+                return Err("a dump of a later version was read".to_string());
+            }
+            Ok(())
+        })();
+        let _ = std::fs::remove_file(&path);
+        outcome
+    }
+}
@@ -0,0 +1,99 @@
+// The bench is a binary crate, so the stores are pulled in by path
+#[path = "../src/history.rs"]
+#[allow(dead_code)]
+mod history;
+#[path = "../src/old.rs"]
+#[allow(dead_code)]
+mod old;
+#[path = "../src/trie.rs"]
+#[allow(dead_code)]
+mod trie;
+#[path = "../src/utils.rs"]
+#[allow(dead_code)]
+mod utils;
+
+use gw_db::iter::IteratorMode;
+use gw_store::{traits::KVStore, Store as GwStore};
+use old::CountingStore;
+use sparse_merkle_tree::{blake2b::Blake2bHasher, merge::MergeValue, SparseMerkleTree, H256};
+use std::collections::BTreeMap;
+use trie::{trie_branches, TrieStore};
+
+type CountingSmt<'a, DB> = SparseMerkleTree<Blake2bHasher, H256, CountingStore<'a, DB>>;
+type TrieSmt<'a, DB> = SparseMerkleTree<Blake2bHasher, H256, TrieStore<'a, DB>>;
+
+// Key with the given bytes set and the rest zero
+fn fixed_key(bytes: &[(usize, u8)]) -> H256 {
+    let mut key = [0u8; 32];
+    for (i, b) in bytes {
+        key[*i] = *b;
+    }
+    key.into()
+}
+
+// Both stores must reach byte-identical roots on a hardcoded dataset,
+// independent of any RNG: neighbouring keys, keys splitting at the top and
+// at the bottom of the tree, an overwrite, and deletions that leave
+// branches merged with zero. After the deletions both roots must also be
+// the root of a fresh tree of the remaining pairs.
+#[test]
+fn fixed_dataset_roots() {
+    let keys = [
+        fixed_key(&[(31, 0x01)]),
+        fixed_key(&[(31, 0x02)]),
+        fixed_key(&[(31, 0x03)]),
+        fixed_key(&[(0, 0x80)]),
+        fixed_key(&[(0, 0x80), (31, 0x80)]),
+        fixed_key(&[(15, 0x10)]),
+        fixed_key(&[(0, 0x01), (31, 0x01)]),
+        H256::from([0xffu8; 32]),
+    ];
+    let value = |i: usize| H256::from([i as u8 + 1; 32]);
+    let steps: Vec<Vec<(H256, H256)>> = vec![
+        keys.iter()
+            .enumerate()
+            .map(|(i, key)| (*key, value(i)))
+            .collect(),
+        vec![(keys[2], value(20))],
+        vec![(keys[1], H256::zero()), (keys[4], H256::zero())],
+    ];
+
+    let counting_db = GwStore::open_tmp().unwrap();
+    let counting_tx = counting_db.begin_transaction();
+    let mut counting = CountingSmt::new(H256::zero(), CountingStore::new(&counting_tx));
+    let trie_db = GwStore::open_tmp().unwrap();
+    let trie_tx = trie_db.begin_transaction();
+    let mut trie = TrieSmt::new(H256::zero(), TrieStore::new(&trie_tx));
+    let mut expected = BTreeMap::new();
+    for (i, pairs) in steps.into_iter().enumerate() {
+        counting.update_all(pairs.clone()).unwrap();
+        trie.update_all(pairs.clone()).unwrap();
+        assert_eq!(trie.root(), counting.root(), "step {}", i);
+        for (key, value) in pairs {
+            if value.is_zero() {
+                expected.remove(&key);
+            } else {
+                expected.insert(key, value);
+            }
+        }
+    }
+
+    let fresh_db = GwStore::open_tmp().unwrap();
+    let fresh_tx = fresh_db.begin_transaction();
+    let mut fresh = TrieSmt::new(H256::zero(), TrieStore::new(&fresh_tx));
+    fresh.update_all(expected.into_iter().collect()).unwrap();
+    assert_eq!(fresh.root(), trie.root(), "root after the deletions");
+
+    let merged_with_zero = trie_tx
+        .get_iter(0, IteratorMode::Start)
+        .flat_map(|(_, blob)| trie_branches(&blob))
+        .any(|branch| {
+            [branch.left, branch.right]
+                .iter()
+                .any(|child| matches!(child, MergeValue::MergeWithZero { .. }))
+        });
+    assert!(
+        merged_with_zero,
+        "no branch merged with zero after the deletions"
+    );
+}